@@ -1,15 +1,32 @@
 import { invoke } from '@tauri-apps/api/core'
+import { listen, type UnlistenFn } from '@tauri-apps/api/event'
 
 export interface WebdavSettings {
   url: string
   username: string
   password: string
+  remote_folder: string
 }
 
 export interface WebdavBackup {
   filename: string
   size: number
   modified: string
+  incremental: boolean // JSON diff of changed config tables, restored on top of its baseline
+}
+
+// backup://progress event, emitted while export/import reads, writes, uploads or downloads a file
+export interface BackupProgress {
+  operation: 'read' | 'write' | 'upload' | 'download'
+  file: string
+  bytes: number
+  total: number | null // null when the WebDAV server sends no Content-Length
+  done: boolean
+}
+
+// Returns the unlisten function
+export const onBackupProgress = async (handler: (progress: BackupProgress) => void): Promise<UnlistenFn> => {
+  return await listen<BackupProgress>('backup://progress', event => handler(event.payload))
 }
 
 export const getWebdavSettings = async (): Promise<{ data: WebdavSettings }> => {
@@ -22,7 +39,7 @@ export const updateWebdavSettings = async (data: Partial<WebdavSettings>): Promi
   return { data: result }
 }
 
-export const testWebdavConnection = async (data: WebdavSettings): Promise<{ data: { success: boolean } }> => {
+export const testWebdavConnection = async (data: Omit<WebdavSettings, 'remote_folder'>): Promise<{ data: { success: boolean } }> => {
   const success = await invoke<boolean>('test_webdav_connection', {
     url: data.url,
     username: data.username,
@@ -36,15 +53,33 @@ export const exportToLocal = async (): Promise<Blob> => {
   return new Blob([new Uint8Array(data)], { type: 'application/octet-stream' })
 }
 
-export const importFromLocal = async (file: File): Promise<{ data: { success: boolean; message: string } }> => {
+export const importFromLocal = async (file: File, restart = true): Promise<{ data: { success: boolean; message: string } }> => {
   const arrayBuffer = await file.arrayBuffer()
   const data = Array.from(new Uint8Array(arrayBuffer))
-  await invoke('import_from_local', { data })
+  await invoke('import_from_local', { data, restart })
   return { data: { success: true, message: 'Database imported successfully' } }
 }
 
-export const exportToWebdav = async (): Promise<{ data: { success: boolean; filename: string } }> => {
-  const filename = await invoke<string>('export_to_webdav')
+export type ConfigSection = 'providers' | 'settings' | 'mcps' | 'prompts' | 'skills'
+
+// Encrypted config bundle (contains API keys); empty sections = all.
+// Admin token, GitHub token and WebDAV password are only included with includeSecrets
+export const exportConfigEncrypted = async (passphrase: string, sections: ConfigSection[] = [], includeSecrets = false): Promise<Blob> => {
+  const data = await invoke<number[]>('export_config_encrypted', { passphrase, sections, includeSecrets })
+  return new Blob([new Uint8Array(data)], { type: 'application/octet-stream' })
+}
+
+// replace: clear the bundle's sections first; merge: overwrite matching rows only (providers match by name + CLI type)
+export const importConfigEncrypted = async (file: File, passphrase: string, mode: 'replace' | 'merge' = 'merge'): Promise<{ data: { tables: number } }> => {
+  const arrayBuffer = await file.arrayBuffer()
+  const data = Array.from(new Uint8Array(arrayBuffer))
+  const tables = await invoke<number>('import_config_encrypted', { data, passphrase, mode })
+  return { data: { tables } }
+}
+
+// Returns an empty filename when incremental and nothing changed since the last backup
+export const exportToWebdav = async (incremental = false): Promise<{ data: { success: boolean; filename: string } }> => {
+  const filename = await invoke<string>('export_to_webdav', { incremental })
   return { data: { success: true, filename } }
 }
 
@@ -53,8 +88,8 @@ export const listWebdavBackups = async (): Promise<{ data: { backups: WebdavBack
   return { data: { backups } }
 }
 
-export const importFromWebdav = async (filename: string): Promise<{ data: { success: boolean; message: string } }> => {
-  await invoke('import_from_webdav', { filename })
+export const importFromWebdav = async (filename: string, restart = true): Promise<{ data: { success: boolean; message: string } }> => {
+  await invoke('import_from_webdav', { filename, restart })
   return { data: { success: true, message: 'Database imported successfully' } }
 }
 
@@ -4,7 +4,9 @@ import type {
   RequestLogDetail,
   SystemLogListResponse,
   GatewaySettings,
-  GatewaySettingsUpdate
+  GatewaySettingsUpdate,
+  LogSettings,
+  LogSettingsUpdate
 } from '@/types/models'
 
 export interface RequestLogQuery {
@@ -12,6 +14,7 @@ export interface RequestLogQuery {
   page_size?: number
   cli_type?: string
   provider_name?: string
+  client_ip?: string
 }
 
 export interface SystemLogQuery {
@@ -20,6 +23,8 @@ export interface SystemLogQuery {
   level?: string
   event_type?: string
   provider_name?: string
+  start_time?: number // unix seconds
+  end_time?: number
 }
 
 export const logsApi = {
@@ -28,7 +33,7 @@ export const logsApi = {
     return { data: { debug_log: !!data.debug_log } as GatewaySettings }
   },
   updateSettings: async (data: GatewaySettingsUpdate) => {
-    await invoke('update_gateway_settings', { debugLog: data.debug_log })
+    await invoke('update_gateway_settings', { input: { debug_log: data.debug_log } })
     return { data: null }
   },
 
@@ -36,7 +41,8 @@ export const logsApi = {
     const data = await invoke<RequestLogListResponse>('get_request_logs', {
       page: params.page,
       pageSize: params.page_size,
-      cliType: params.cli_type
+      cliType: params.cli_type,
+      clientIp: params.client_ip
     })
     return { data }
   },
@@ -48,6 +54,14 @@ export const logsApi = {
     await invoke('clear_request_logs')
     return { data: null }
   },
+  deleteRequestLog: async (id: number) => {
+    const data = await invoke<number>('delete_request_log', { id })
+    return { data }
+  },
+  deleteRequestLogs: async (ids: number[]) => {
+    const data = await invoke<number>('delete_request_logs', { ids })
+    return { data }
+  },
 
   listSystemLogs: async (params: SystemLogQuery) => {
     const data = await invoke<SystemLogListResponse>('get_system_logs', {
@@ -55,12 +69,32 @@ export const logsApi = {
       pageSize: params.page_size,
       level: params.level,
       eventType: params.event_type,
-      providerName: params.provider_name
+      providerName: params.provider_name,
+      startTime: params.start_time,
+      endTime: params.end_time
+    })
+    return { data }
+  },
+  exportSystemLogs: async (format: 'json' | 'csv', params: Omit<SystemLogQuery, 'page' | 'page_size'> = {}) => {
+    const data = await invoke<string>('export_system_logs', {
+      format,
+      eventType: params.event_type,
+      startTime: params.start_time,
+      endTime: params.end_time
     })
     return { data }
   },
   clearSystemLogs: async (before_timestamp?: number) => {
     await invoke('clear_system_logs')
     return { data: null }
+  },
+
+  getLogSettings: async () => {
+    const data = await invoke<LogSettings>('get_log_settings')
+    return { data }
+  },
+  updateLogSettings: async (input: LogSettingsUpdate) => {
+    const data = await invoke<LogSettings>('update_log_settings', { input })
+    return { data }
   }
 }
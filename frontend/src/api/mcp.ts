@@ -1,5 +1,5 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { Mcp, McpCreate, McpUpdate } from '@/types/models'
+import type { Mcp, McpConflict, McpCreate, McpUpdate } from '@/types/models'
 
 // 后端返回的 cli_flags 格式
 type McpCliFlagBackend = { cli_type: string; enabled: boolean }
@@ -41,5 +41,9 @@ export const mcpApi = {
   delete: async (id: number) => {
     await invoke('delete_mcp', { id })
     return { data: null }
+  },
+  findConflicts: async () => {
+    const data = await invoke<McpConflict[]>('find_mcp_conflicts')
+    return { data }
   }
 }
@@ -1,9 +1,10 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { Provider, ProviderCreate, ProviderUpdate } from '@/types/models'
+import type { Provider, ProviderCreate, ProviderUpdate, ProviderTestResult, RoutingExplanation } from '@/types/models'
 
 export const providersApi = {
-  list: async (cliType?: string): Promise<{ data: Provider[] }> => {
-    const data = await invoke<Provider[]>('get_providers', { cliType })
+  // search: 按名称或备注过滤（不区分大小写）
+  list: async (cliType?: string, withStats?: boolean, search?: string): Promise<{ data: Provider[] }> => {
+    const data = await invoke<Provider[]>('get_providers', { cliType, withStats, search })
     return { data }
   },
   get: async (id: number): Promise<{ data: Provider }> => {
@@ -14,6 +15,11 @@ export const providersApi = {
     const result = await invoke<Provider>('create_provider', { input: data })
     return { data: result }
   },
+  // 批量创建：任一失败则整体回滚
+  bulkCreate: async (data: ProviderCreate[]): Promise<{ data: Provider[] }> => {
+    const result = await invoke<Provider[]>('bulk_create_providers', { input: data })
+    return { data: result }
+  },
   update: async (id: number, data: ProviderUpdate): Promise<{ data: Provider }> => {
     const result = await invoke<Provider>('update_provider', { id, input: data })
     return { data: result }
@@ -22,16 +28,62 @@ export const providersApi = {
     await invoke('delete_provider', { id })
     return { data: null }
   },
-  reorder: async (ids: number[]) => {
-    await invoke('reorder_providers', { ids })
+  reorder: async (ids: number[], cliType?: string) => {
+    await invoke('reorder_providers', { ids, cliType })
+    return { data: null }
+  },
+  // 故障转移顺序（ids 第一个最先尝试），不影响展示顺序
+  reorderPriority: async (ids: number[], cliType?: string) => {
+    await invoke('reorder_provider_priority', { ids, cliType })
     return { data: null }
   },
   resetFailures: async (id: number) => {
     await invoke('reset_provider_failures', { id })
     return { data: null }
   },
+  // 先测试连通性，通过后才清除失败计数和黑名单
+  recover: async (id: number): Promise<{ data: ProviderTestResult }> => {
+    const data = await invoke<ProviderTestResult>('recover_provider', { id })
+    return { data }
+  },
   unblacklist: async (id: number) => {
     await invoke('reset_provider_failures', { id })
     return { data: null }
+  },
+  pause: async (id: number) => {
+    await invoke('pause_provider', { id })
+    return { data: null }
+  },
+  resume: async (id: number) => {
+    await invoke('resume_provider', { id })
+    return { data: null }
+  },
+  testConnection: async (id: number): Promise<{ data: ProviderTestResult }> => {
+    const data = await invoke<ProviderTestResult>('test_provider_connection', { id })
+    return { data }
+  },
+  // 上游服务商提供的模型列表（后端短暂缓存）
+  listModels: async (id: number): Promise<{ data: string[] }> => {
+    const data = await invoke<string[]>('list_provider_models', { id })
+    return { data }
+  },
+  // 路由 dry-run：不会真正发送请求
+  explainRouting: async (
+    cliType: string,
+    model?: string,
+    path?: string,
+    options?: { method?: string; body?: string; pin?: string; force?: boolean }
+  ): Promise<{ data: RoutingExplanation }> => {
+    const data = await invoke<RoutingExplanation>('explain_routing', { cliType, model, path, ...options })
+    return { data }
+  },
+  // 用服务商的 User-Agent 覆盖预览一批 UA：[输入, 改写后的 UA, 生效的服务商 id]
+  previewUseragentRewrites: async (id: number, inputs: string[]): Promise<{ data: [string, string | null, number | null][] }> => {
+    const data = await invoke<[string, string | null, number | null][]>('preview_useragent_rewrites', { providerId: id, inputs })
+    return { data }
+  },
+  testAll: async (clearBlacklist?: boolean): Promise<{ data: ProviderTestResult[] }> => {
+    const data = await invoke<ProviderTestResult[]>('test_all_providers', { clearBlacklist })
+    return { data }
   }
 }
@@ -16,6 +16,7 @@ export interface SessionInfo {
   first_message: string
   git_branch: string
   summary: string
+  parse_failed: boolean // file exists but no line could be parsed
 }
 
 export interface SessionMessage {
@@ -24,6 +25,11 @@ export interface SessionMessage {
   timestamp?: number
 }
 
+export interface SessionMessages {
+  messages: SessionMessage[]
+  parse_warnings: number // lines skipped because they failed to parse
+}
+
 export interface PaginatedResponse<T> {
   items: T[]
   total: number
@@ -51,8 +57,8 @@ export const sessionsApi = {
     return { data }
   },
 
-  getSessionMessages: async (cliType: string, projectName: string, sessionId: string): Promise<{ data: SessionMessage[] }> => {
-    const data = await invoke<SessionMessage[]>('get_session_messages', {
+  getSessionMessages: async (cliType: string, projectName: string, sessionId: string): Promise<{ data: SessionMessages }> => {
+    const data = await invoke<SessionMessages>('get_session_messages', {
       cliType,
       projectName,
       sessionId
@@ -65,8 +71,38 @@ export const sessionsApi = {
     return { data: null }
   },
 
+  // 原始会话文件（CLI 原生格式），用于跨机器迁移
+  exportSessionRaw: async (cliType: string, projectName: string, sessionId: string): Promise<{ data: Uint8Array }> => {
+    const data = await invoke<number[]>('export_session_raw', { cliType, projectName, sessionId })
+    return { data: new Uint8Array(data) }
+  },
+
+  // 会话源文件的绝对路径（排查解析问题时查看原始文件）
+  getSessionFilePath: async (cliType: string, projectName: string, sessionId: string): Promise<{ data: string }> => {
+    const data = await invoke<string>('get_session_file_path', { cliType, projectName, sessionId })
+    return { data }
+  },
+
+  // 在系统文件管理器中定位会话源文件，返回其路径
+  openSessionFile: async (cliType: string, projectName: string, sessionId: string): Promise<{ data: string }> => {
+    const data = await invoke<string>('open_session_file', { cliType, projectName, sessionId })
+    return { data }
+  },
+
+  // 返回写入的会话 ID；Codex 会话按记录的 cwd 归组，projectName 不生效
+  importSessionRaw: async (cliType: string, projectName: string, bytes: Uint8Array, overwrite?: boolean): Promise<{ data: string }> => {
+    const data = await invoke<string>('import_session_raw', { cliType, projectName, bytes: Array.from(bytes), overwrite })
+    return { data }
+  },
+
+  // 清空并重建 Gemini 项目 hash→路径缓存，返回解析成功的项目数
+  rebuildGeminiPathCache: async (): Promise<{ data: number }> => {
+    const data = await invoke<number>('rebuild_gemini_path_cache')
+    return { data }
+  },
+
   deleteProject: async (cliType: string, projectName: string) => {
-    await invoke('delete_project', { cliType, projectName })
-    return { data: null }
+    const data = await invoke<number>('delete_project', { cliType, projectName })
+    return { data }
   }
 }
@@ -1,31 +1,45 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { AllSettings, GatewaySettingsUpdate, TimeoutSettingsUpdate, CliSettingsUpdate, SystemStatus } from '@/types/models'
+import type { AllSettings, LogBodyFormat, UpstreamCompression, GatewaySettingsUpdate, TimeoutSettingsUpdate, CliSettingsUpdate, ConfigFileInfo, SystemStatus, ActiveRequest, ConcurrencyStatus, DatabaseCompactResult, SchemaReport, StorageUsage } from '@/types/models'
 
 export const settingsApi = {
   getAll: async () => {
-    const [gateway, timeouts, claudeCode, codex, gemini, status] = await Promise.all([
-      invoke<{ debug_log: number }>('get_gateway_settings'),
-      invoke<{ stream_first_byte_timeout: number; stream_idle_timeout: number; non_stream_timeout: number }>('get_timeout_settings'),
-      invoke<{ cli_type: string; enabled: boolean; default_json_config: string }>('get_cli_settings', { cliType: 'claude_code' }),
-      invoke<{ cli_type: string; enabled: boolean; default_json_config: string }>('get_cli_settings', { cliType: 'codex' }),
-      invoke<{ cli_type: string; enabled: boolean; default_json_config: string }>('get_cli_settings', { cliType: 'gemini' }),
+    const [all, status] = await Promise.all([
+      invoke<{
+        gateway: { debug_log: number; shadow_provider_id: number | null; shadow_sample_rate: number; log_sample_rate: number; log_body_format: LogBodyFormat; log_body_max_depth: number; log_body_max_array_items: number; log_body_max_string_chars: number; validate_on_enable: number; max_concurrency: number; shutdown_grace_secs: number; allow_force_provider: number; admin_api_enabled: number; upstream_compression: UpstreamCompression; retry_after_max_secs: number; key_expiry_warn_days: number; auto_pause_expired_keys: number }
+        timeouts: { stream_first_byte_timeout: number; stream_idle_timeout: number; non_stream_timeout: number }
+        cli_settings: { cli_type: string; enabled: boolean; default_json_config: string }[]
+      }>('get_all_settings'),
       invoke<SystemStatus>('get_system_status')
     ])
     return {
       data: {
-        gateway: { debug_log: !!gateway.debug_log },
-        timeouts,
-        cli_settings: {
-          claude_code: claudeCode,
-          codex: codex,
-          gemini: gemini
+        gateway: {
+          debug_log: !!all.gateway.debug_log,
+          shadow_provider_id: all.gateway.shadow_provider_id,
+          shadow_sample_rate: all.gateway.shadow_sample_rate,
+          log_sample_rate: all.gateway.log_sample_rate,
+          log_body_format: all.gateway.log_body_format,
+          log_body_max_depth: all.gateway.log_body_max_depth,
+          log_body_max_array_items: all.gateway.log_body_max_array_items,
+          log_body_max_string_chars: all.gateway.log_body_max_string_chars,
+          validate_on_enable: !!all.gateway.validate_on_enable,
+          max_concurrency: all.gateway.max_concurrency,
+          shutdown_grace_secs: all.gateway.shutdown_grace_secs,
+          allow_force_provider: !!all.gateway.allow_force_provider,
+          admin_api_enabled: !!all.gateway.admin_api_enabled,
+          upstream_compression: all.gateway.upstream_compression,
+          retry_after_max_secs: all.gateway.retry_after_max_secs,
+          key_expiry_warn_days: all.gateway.key_expiry_warn_days,
+          auto_pause_expired_keys: !!all.gateway.auto_pause_expired_keys
         },
+        timeouts: all.timeouts,
+        cli_settings: Object.fromEntries(all.cli_settings.map(c => [c.cli_type, c])),
         status
       } as AllSettings
     }
   },
   updateGateway: async (data: GatewaySettingsUpdate) => {
-    await invoke('update_gateway_settings', { debugLog: data.debug_log })
+    await invoke('update_gateway_settings', { input: data })
     return { data: null }
   },
   updateTimeouts: async (data: TimeoutSettingsUpdate) => {
@@ -36,8 +50,52 @@ export const settingsApi = {
     await invoke('update_cli_settings', { cliType, input: data })
     return { data: null }
   },
+  getCliConfigFiles: async (cliType: string) => {
+    const data = await invoke<ConfigFileInfo[]>('get_cli_config_files', { cliType })
+    return { data }
+  },
   getStatus: async () => {
     const data = await invoke<SystemStatus>('get_system_status')
     return { data }
+  },
+  listActiveRequests: async () => {
+    const data = await invoke<ActiveRequest[]>('list_active_requests')
+    return { data }
+  },
+  // 当前并发数（全局及各服务商）
+  getConcurrencyStatus: async () => {
+    const data = await invoke<ConcurrencyStatus>('get_concurrency_status')
+    return { data }
+  },
+  cancelRequest: async (requestId: string) => {
+    await invoke('cancel_request', { requestId })
+    return { data: null }
+  },
+  compactDatabases: async () => {
+    const data = await invoke<DatabaseCompactResult[]>('compact_databases')
+    return { data }
+  },
+  // 数据目录磁盘占用（数据库、skills、仓库 ZIP 缓存、日志）
+  getStorageUsage: async () => {
+    const data = await invoke<StorageUsage>('get_storage_usage')
+    return { data }
+  },
+  verifySchema: async () => {
+    const data = await invoke<SchemaReport[]>('verify_schema')
+    return { data }
+  },
+  // 按期望结构修复数据库（会删除多余的表/列），返回修复后的检查结果
+  migrateSchema: async () => {
+    const data = await invoke<SchemaReport[]>('migrate_schema')
+    return { data }
+  },
+  // 上次运行崩溃时写入的 crash.log 末尾若干行，没有时为空字符串
+  getCrashLog: async (limitLines?: number) => {
+    const data = await invoke<string>('get_crash_log', { limitLines })
+    return { data }
+  },
+  clearCrashLog: async () => {
+    await invoke('clear_crash_log')
+    return { data: null }
   }
 }
@@ -1,5 +1,6 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { SkillRepo, SkillRepoCreate, DiscoverableSkill, InstalledSkill } from '@/types/models'
+import { listen, type UnlistenFn } from '@tauri-apps/api/event'
+import type { SkillRepo, SkillRepoCreate, DiscoverableSkill, InstalledSkill, SkillDownloadProgress, SkillInstallProgress, SkillReconcileReport, SkillRepairActions } from '@/types/models'
 
 // 后端返回的 cli_flags 格式
 type SkillCliFlagBackend = { cli_type: string; enabled: boolean }
@@ -48,12 +49,37 @@ export const skillsApi = {
     return await invoke<DiscoverableSkill[]>('refresh_repo_skills', { owner, name, branch })
   },
 
+  // 离线目录：导入 DiscoverableSkill 数组 JSON，返回导入条数
+  importCatalog: async (json: string, replace: boolean = false): Promise<number> => {
+    return await invoke<number>('import_skill_catalog', { json, replace })
+  },
+
+  getCatalog: async (): Promise<DiscoverableSkill[]> => {
+    return await invoke<DiscoverableSkill[]>('get_skill_catalog')
+  },
+
+  // 仓库 ZIP 下载进度（发现/刷新/安装时触发），返回取消监听函数
+  onDownloadProgress: async (handler: (progress: SkillDownloadProgress) => void): Promise<UnlistenFn> => {
+    return await listen<SkillDownloadProgress>('skill-download://progress', event => handler(event.payload))
+  },
+
   // ==================== Skill 安装/卸载 ====================
-  install: async (skill: DiscoverableSkill, reinstall: boolean = false): Promise<InstalledSkill> => {
-    const result = await invoke<InstalledSkillBackend>('install_skill', { skill, reinstall })
+  // zipPath: 本地仓库 ZIP（GitHub 归档格式），提供时不访问网络
+  install: async (skill: DiscoverableSkill, reinstall: boolean = false, installAs?: string, zipPath?: string): Promise<InstalledSkill> => {
+    const result = await invoke<InstalledSkillBackend>('install_skill', { skill, reinstall, installAs, zipPath })
     return transformInstalledSkill(result)
   },
 
+  // 按 skill key 取消进行中的安装；被取消的 install 调用以 'Skill installation cancelled' 失败
+  cancelInstall: async (key: string): Promise<void> => {
+    await invoke('cancel_skill_install', { key })
+  },
+
+  // 安装进度（download / extract / done / cancelled），返回取消监听函数
+  onInstallProgress: async (handler: (progress: SkillInstallProgress) => void): Promise<UnlistenFn> => {
+    return await listen<SkillInstallProgress>('skill-install://progress', event => handler(event.payload))
+  },
+
   uninstall: async (id: number): Promise<void> => {
     await invoke('uninstall_skill', { id })
   },
@@ -67,4 +93,18 @@ export const skillsApi = {
   toggleCli: async (id: number, cliType: string, enabled: boolean): Promise<void> => {
     await invoke('toggle_skill_cli', { id, cliType, enabled })
   },
+
+  rename: async (id: number, newDirectory: string): Promise<void> => {
+    await invoke('rename_skill', { id, newDirectory })
+  },
+
+  // 检查记录与 SSOT 目录是否一致
+  reconcile: async (): Promise<SkillReconcileReport> => {
+    return await invoke<SkillReconcileReport>('reconcile_skills')
+  },
+
+  // 删除孤立记录 / 重新登记孤立目录，返回处理的数量
+  repair: async (actions: SkillRepairActions): Promise<{ removed: number; registered: number }> => {
+    return await invoke<{ removed: number; registered: number }>('repair_skills', { actions })
+  },
 }
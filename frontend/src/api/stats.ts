@@ -1,19 +1,35 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { DailyStats, ProviderStats } from '@/types/models'
+import type { CliHealth, DailyStats, LatencyBucket, ProviderStats } from '@/types/models'
 
 export const statsApi = {
-  getDaily: async (params?: { start_date?: string; end_date?: string; cli_type?: string; provider_name?: string }): Promise<{ data: DailyStats[] }> => {
+  getCliHealth: async (): Promise<{ data: CliHealth[] }> => {
+    const data = await invoke<CliHealth[]>('get_cli_health')
+    return { data }
+  },
+  getDaily: async (params?: { start_date?: string; end_date?: string; cli_type?: string; provider_name?: string; timezone?: string }): Promise<{ data: DailyStats[] }> => {
     const data = await invoke<DailyStats[]>('get_daily_stats', {
       startDate: params?.start_date,
       endDate: params?.end_date,
-      cliType: params?.cli_type
+      cliType: params?.cli_type,
+      timezone: params?.timezone
     })
     return { data }
   },
-  getProviders: async (params?: { start_date?: string; end_date?: string }): Promise<{ data: ProviderStats[] }> => {
+  getProviders: async (params?: { start_date?: string; end_date?: string; timezone?: string }): Promise<{ data: ProviderStats[] }> => {
     const data = await invoke<ProviderStats[]>('get_provider_stats', {
       startDate: params?.start_date,
-      endDate: params?.end_date
+      endDate: params?.end_date,
+      timezone: params?.timezone
+    })
+    return { data }
+  },
+  // start/end are unix seconds; defaults to the last 24 hours
+  getLatencySeries: async (params: { provider_name: string; bucket: 'minute' | 'hour'; start?: number; end?: number }): Promise<{ data: LatencyBucket[] }> => {
+    const data = await invoke<LatencyBucket[]>('get_provider_latency_series', {
+      providerName: params.provider_name,
+      bucket: params.bucket,
+      start: params.start,
+      end: params.end
     })
     return { data }
   }
@@ -41,7 +41,8 @@ export const useProviderStore = defineStore('providers', () => {
   }
 
   async function reorderProviders(ids: number[]) {
-    await providersApi.reorder(ids)
+    const uiStore = useUiStore()
+    await providersApi.reorder(ids, uiStore.providersActiveCliType)
     await fetchProviders()
   }
 
@@ -8,6 +8,7 @@ export const useSessionStore = defineStore('sessions', () => {
   const projects = ref<ProjectInfo[]>([])
   const sessions = ref<SessionInfo[]>([])
   const messages = ref<SessionMessage[]>([])
+  const parseWarnings = ref(0)
   const loading = ref(false)
   const currentProject = ref<string>('')
   const currentProjectInfo = ref<ProjectInfo | null>(null)
@@ -71,10 +72,12 @@ export const useSessionStore = defineStore('sessions', () => {
       const uiStore = useUiStore()
       const type = cliType || uiStore.sessionsActiveCliType
       const { data } = await sessionsApi.getSessionMessages(type, projectName, sessionId)
-      messages.value = data
+      messages.value = data.messages
+      parseWarnings.value = data.parse_warnings
     } catch (error: any) {
       console.error('Failed to fetch messages:', error)
       messages.value = []
+      parseWarnings.value = 0
     } finally {
       loading.value = false
     }
@@ -91,13 +94,15 @@ export const useSessionStore = defineStore('sessions', () => {
   async function deleteProject(projectName: string, cliType?: CliType) {
     const uiStore = useUiStore()
     const type = cliType || uiStore.sessionsActiveCliType
-    await sessionsApi.deleteProject(type, projectName)
+    const { data } = await sessionsApi.deleteProject(type, projectName)
     projects.value = projects.value.filter(p => p.name !== projectName)
     projectTotal.value = Math.max(0, projectTotal.value - 1)
+    return data
   }
 
   function clearMessages() {
     messages.value = []
+    parseWarnings.value = 0
     currentSession.value = ''
   }
 
@@ -113,6 +118,7 @@ export const useSessionStore = defineStore('sessions', () => {
     projects,
     sessions,
     messages,
+    parseWarnings,
     loading,
     currentProject,
     currentProjectInfo,
@@ -7,23 +7,79 @@ export interface ModelMap {
   source_model: string
   target_model: string
   enabled: boolean
+  cli_type?: CliType | null
+  // 估算输入 token 超过阈值时改用 large_target_model，两者需同时设置
+  large_input_tokens?: number | null
+  large_target_model?: string | null
+}
+
+// JSON Patch (RFC 6902) operation, used for per-provider body transforms.
+// A transform applies all-or-nothing: every operation must be valid for every body
+// (e.g. `remove` of an optional field skips the whole transform for bodies without it)
+export interface JsonPatchOperation {
+  op: 'add' | 'remove' | 'replace' | 'move' | 'copy' | 'test'
+  path: string
+  value?: unknown
+  from?: string
 }
 
 export interface Provider {
   id: number
   cli_type: CliType
+  cli_types: CliType[]
   name: string
   base_url: string
   api_key: string
   enabled: boolean
+  paused: boolean
   failure_threshold: number
   blacklist_minutes: number
   consecutive_failures: number
   blacklisted_until: number | null
   sort_order: number
+  priority: number // failover order (lower first), independent of display sort_order
   custom_useragent: string | null
+  auth_header_name: string | null
+  auth_scheme: string | null
+  upstream_proxy: string | null // credentials masked
+  strip_headers: string[]
+  header_overrides: Record<string, string>
+  inject_system_prompt: string | null
+  request_transform: JsonPatchOperation[] | null // applied to the forwarded request body
+  response_transform: JsonPatchOperation[] | null // applied to successful non-streaming responses
+  key_expires_at: number | null // unix seconds, informational only
+  expires_in_days: number | null // whole days left, negative once expired
+  force_non_stream: boolean
+  max_concurrency: number // 0 = unlimited
+  notes: string | null
+  accept_invalid_certs: boolean // insecure: upstream certificate verification disabled
+  custom_ca_pem: string | null
   model_maps: ModelMap[]
   is_blacklisted: boolean
+  blacklist_remaining_secs: number | null
+  score: ProviderScore | null
+  connection_warning: string | null // only set by create/update when validate_on_enable is on
+  last_used_ts: number | null
+  requests_24h: number | null
+}
+
+export interface ProviderTestResult {
+  provider_id: number
+  provider_name: string
+  cli_type: CliType
+  reachable: boolean
+  success: boolean
+  status_code: number | null
+  latency_ms: number
+  error: string | null
+  unblacklisted: boolean
+}
+
+export interface ProviderScore {
+  success_rate: number
+  avg_latency_ms: number
+  samples: number
+  score: number
 }
 
 export interface ProviderCreate {
@@ -35,6 +91,22 @@ export interface ProviderCreate {
   failure_threshold?: number
   blacklist_minutes?: number
   custom_useragent?: string
+  auth_header_name?: string
+  auth_scheme?: string
+  upstream_proxy?: string // http(s)://[user:pass@]host:port, empty for direct
+  strip_headers?: string[] // removed before forwarding; empty clears
+  header_overrides?: Record<string, string> // set after auth injection; empty clears
+  inject_system_prompt?: string // prepended to the client's system prompt; empty clears
+  request_transform?: JsonPatchOperation[] // empty array clears
+  response_transform?: JsonPatchOperation[] // empty array clears
+  key_expires_at?: number // unix seconds; 0 clears on update
+  force_non_stream?: boolean // send streaming requests upstream as non-streaming, replayed to the client as SSE
+  max_concurrency?: number // in-flight request limit, 0 = unlimited
+  notes?: string // free-text notes, searchable; empty clears
+  accept_invalid_certs?: boolean // insecure: skip upstream certificate verification
+  custom_ca_pem?: string // extra trusted root certificates (PEM); empty clears
+  priority?: number // failover order, lower first; new providers go last when omitted
+  cli_types?: CliType[]
   model_maps?: ModelMap[]
 }
 
@@ -46,12 +118,71 @@ export interface ProviderUpdate {
   failure_threshold?: number
   blacklist_minutes?: number
   custom_useragent?: string
+  auth_header_name?: string
+  auth_scheme?: string
+  upstream_proxy?: string // http(s)://[user:pass@]host:port, empty for direct
+  strip_headers?: string[] // removed before forwarding; empty clears
+  header_overrides?: Record<string, string> // set after auth injection; empty clears
+  inject_system_prompt?: string // prepended to the client's system prompt; empty clears
+  request_transform?: JsonPatchOperation[] // empty array clears
+  response_transform?: JsonPatchOperation[] // empty array clears
+  key_expires_at?: number // unix seconds; 0 clears on update
+  force_non_stream?: boolean // send streaming requests upstream as non-streaming, replayed to the client as SSE
+  max_concurrency?: number // in-flight request limit, 0 = unlimited
+  notes?: string // free-text notes, searchable; empty clears
+  accept_invalid_certs?: boolean // insecure: skip upstream certificate verification
+  custom_ca_pem?: string // extra trusted root certificates (PEM); empty clears
+  priority?: number // failover order, lower first; new providers go last when omitted
+  cli_types?: CliType[]
   model_maps?: ModelMap[]
 }
 
 // Settings types
 export interface GatewaySettings {
   debug_log: boolean
+  shadow_provider_id: number | null
+  shadow_sample_rate: number
+  // 成功请求写入请求日志的采样率；错误总是记录，统计总量以聚合表为准
+  log_sample_rate: number
+  // 日志 JSON body 的存储格式与折叠限制（0 表示不限制）
+  log_body_format: LogBodyFormat
+  log_body_max_depth: number
+  log_body_max_array_items: number
+  log_body_max_string_chars: number
+  // 启用服务商时检测连通性（失败只提示，不阻止保存）
+  validate_on_enable: boolean
+  // 全局同时转发的请求上限，0 表示不限制
+  max_concurrency: number
+  // 退出时等待进行中请求完成的最长秒数（0-300）
+  shutdown_grace_secs: number
+  // 允许请求头 x-ccg-force 绕过暂停/拉黑，强制使用 x-ccg-provider 指定的服务商
+  allow_force_provider: boolean
+  // 是否已设置 /admin HTTP 管理接口的 Bearer 令牌（令牌本身不返回），未设置时管理接口关闭
+  admin_api_enabled: boolean
+  // 上游压缩响应：passthrough 转发客户端的 Accept-Encoding，日志与用量解析使用解压后的内容；
+  // identity 要求上游返回未压缩内容（用于网关无法解压的编码）
+  upstream_compression: UpstreamCompression
+  // 上游返回 429 且带 Retry-After 时，服务商拉黑到该时间（不超过此秒数）；0 表示忽略 Retry-After
+  retry_after_max_secs: number
+  // API Key 过期前多少天开始每日提醒（系统日志 + Webhook），0 表示只在过期后提醒
+  key_expiry_warn_days: number
+  // Key 过期后自动暂停该服务商
+  auto_pause_expired_keys: boolean
+}
+
+export type UpstreamCompression = 'passthrough' | 'identity'
+
+export type LogBodyFormat = 'raw' | 'compact' | 'pretty'
+
+// Retention in days; 0 keeps logs forever
+export interface LogSettings {
+  request_log_retention_days: number
+  system_log_retention_days: number
+}
+
+export interface LogSettingsUpdate {
+  request_log_retention_days?: number
+  system_log_retention_days?: number
 }
 
 export interface TimeoutSettings {
@@ -72,8 +203,29 @@ export interface AllSettings {
   cli_settings: Record<string, CliSettings>
 }
 
+// Only the provided fields are updated
 export interface GatewaySettingsUpdate {
   debug_log?: boolean
+  max_timeout_override_ms?: number
+  sse_keepalive_ms?: number
+  body_capture_policy?: string
+  routing_strategy?: string
+  shadow_provider_id?: number // 0 clears the shadow provider
+  shadow_sample_rate?: number
+  log_sample_rate?: number // 0.0 ~ 1.0
+  log_body_format?: LogBodyFormat
+  log_body_max_depth?: number // 0 = unlimited
+  log_body_max_array_items?: number // 0 = unlimited
+  log_body_max_string_chars?: number // 0 = unlimited
+  validate_on_enable?: boolean
+  max_concurrency?: number // 0 = unlimited
+  shutdown_grace_secs?: number // 0-300
+  allow_force_provider?: boolean
+  admin_token?: string // 至少 16 个字符，空字符串关闭管理接口
+  upstream_compression?: UpstreamCompression
+  retry_after_max_secs?: number // 0-86400, 0 ignores Retry-After
+  key_expiry_warn_days?: number // 0-365
+  auto_pause_expired_keys?: boolean
 }
 
 export interface TimeoutSettingsUpdate {
@@ -87,6 +239,31 @@ export interface CliSettingsUpdate {
   default_json_config?: string
 }
 
+// Structured command error (provider commands so far); branch on code, show message
+export type AppErrorCode = 'not_found' | 'validation' | 'conflict' | 'db' | 'io' | 'upstream' | 'internal'
+
+export interface AppError {
+  code: AppErrorCode
+  message: string
+}
+
+// update_cli_settings rejects with this when the config doesn't parse
+export interface ConfigValidationError {
+  message: string
+  line: number | null
+  column: number | null
+}
+
+export interface ConfigFileInfo {
+  name: string
+  path: string
+  usages: ('gateway' | 'mcp' | 'prompt')[]
+  exists: boolean
+  has_backup: boolean
+  size: number | null
+  modified_at: number | null
+}
+
 export interface SystemStatus {
   status: 'running' | 'stopped'
   port: number
@@ -94,6 +271,107 @@ export interface SystemStatus {
   version: string
 }
 
+export interface CliHealth {
+  cli_type: string
+  total_providers: number
+  enabled_providers: number
+  paused_providers: number
+  blacklisted_providers: number
+  available_providers: number
+  serveable: boolean
+}
+
+// Routing dry-run: nothing is sent upstream
+export interface RoutingExplanation {
+  cli_type: CliType
+  method: string
+  routing_strategy: string
+  // Provider pinned via x-ccg-provider; load balancing is skipped
+  pinned: boolean
+  source_model: string | null
+  // Most likely pick; only deterministic with the ordered strategy
+  selected_provider_id: number | null
+  selected_provider_name: string | null
+  target_model: string | null
+  matched_model_map: string | null
+  upstream_url: string | null
+  candidates: RoutingCandidate[]
+}
+
+export interface RoutingCandidate {
+  provider_id: number
+  provider_name: string
+  sort_order: number
+  priority: number
+  eligible: boolean
+  skip_reason: 'cli_type_not_served' | 'disabled' | 'paused' | 'blacklisted' | 'concurrency_saturated' | null
+  blacklist_remaining_secs: number | null
+  score: number | null
+  selection_probability: number
+  target_model: string | null
+  matched_model_map: string | null
+}
+
+export interface DatabaseCompactResult {
+  database: 'main' | 'log'
+  path: string
+  size_before: number
+  size_after: number
+  reclaimed: number
+}
+
+export interface StorageCategory {
+  name: 'main_db' | 'log_db' | 'skills' | 'skill_repo_cache' | 'logs'
+  path: string
+  bytes: number
+  items: number // DB files incl. -wal/-shm, or top-level entries of the directory
+}
+
+export interface StorageUsage {
+  data_dir: string
+  categories: StorageCategory[]
+  total_bytes: number
+}
+
+export interface SchemaReport {
+  database: 'main' | 'log'
+  version: number
+  expected_version: number
+  ok: boolean
+  missing_tables: string[]
+  unexpected_tables: string[] // dropped by migrateSchema
+  missing_columns: string[] // table.column
+  unexpected_columns: string[] // table.column
+  changed_tables: string[] // rebuilt by migrateSchema
+}
+
+export interface ActiveRequest {
+  request_id: string
+  cli_type: string
+  provider_name: string
+  model_id: string | null
+  client_method: string
+  client_path: string
+  streaming: boolean
+  started_at: number
+  elapsed_ms: number
+  cancelling: boolean
+}
+
+export interface ProviderConcurrency {
+  provider_id: number
+  provider_name: string
+  cli_type: string
+  in_flight: number
+  max_concurrency: number // 0 = unlimited
+}
+
+export interface ConcurrencyStatus {
+  in_flight: number
+  max_concurrency: number // 0 = unlimited
+  providers: ProviderConcurrency[]
+}
+
 // MCP types
 export interface CliFlags {
   claude_code: boolean
@@ -123,11 +401,19 @@ export interface McpUpdate {
   cli_flags?: CliFlags
 }
 
+// 名称冲突的 MCP（同名或仅大小写/首尾空白不同）
+export interface McpConflict {
+  name: string
+  mcps: { id: number; name: string; updated_at: number }[]
+}
+
 // Prompt types
 export interface Prompt {
   id: number
   name: string
   content: string
+  // null = 全局；否则写入该项目的 .claude/CLAUDE.md（仅 Claude Code）
+  project_path: string | null
   enabled: boolean
   cli_flags: Record<string, boolean>
 }
@@ -135,6 +421,7 @@ export interface Prompt {
 export interface PromptCreate {
   name: string
   content: string
+  project_path?: string
   enabled?: boolean
   cli_flags?: CliFlags
 }
@@ -142,6 +429,8 @@ export interface PromptCreate {
 export interface PromptUpdate {
   name?: string
   content?: string
+  // 空字符串表示改回全局
+  project_path?: string
   enabled?: boolean
   cli_flags?: CliFlags
 }
@@ -151,6 +440,8 @@ export interface SkillRepo {
   owner: string
   name: string
   branch: string
+  skill_count: number | null       // 最近一次扫描到的 skill 数量
+  last_refreshed_ts: number | null // 最近一次扫描时间
 }
 
 export interface SkillRepoCreate {
@@ -158,6 +449,24 @@ export interface SkillRepoCreate {
   branch?: string
 }
 
+// skill-download://progress 事件
+export interface SkillDownloadProgress {
+  owner: string
+  name: string
+  bytes: number
+  total: number | null // 服务端未提供 Content-Length 时为 null
+  resumed_from: number
+  done: boolean
+}
+
+// skill-install://progress 事件；下载阶段的字节进度见 SkillDownloadProgress
+export interface SkillInstallProgress {
+  key: string
+  phase: 'download' | 'extract' | 'done' | 'cancelled'
+  current: number      // extract 阶段为已解压的条目数
+  total: number | null // extract 阶段为待解压的条目总数
+}
+
 export interface DiscoverableSkill {
   key: string
   name: string
@@ -167,6 +476,10 @@ export interface DiscoverableSkill {
   repo_owner: string
   repo_name: string
   repo_branch: string
+  version: string | null
+  author: string | null
+  license: string | null
+  tags: string[]
 }
 
 export interface InstalledSkill {
@@ -181,6 +494,23 @@ export interface InstalledSkill {
   installed_at: number
   cli_flags: Record<string, boolean>
   exists_on_disk: boolean // skill 文件是否存在于本地
+  version: string | null
+  author: string | null
+  license: string | null
+  tags: string[]
+}
+
+// Skill 记录与 SSOT 目录的一致性检查结果
+export interface SkillReconcileReport {
+  // 有记录但 SSOT 目录不存在
+  orphaned_rows: { id: number; name: string; directory: string }[]
+  // SSOT 中没有记录的目录；没有 SKILL.md 的不能重新登记
+  orphaned_directories: { directory: string; has_skill_md: boolean; name: string | null; description: string | null }[]
+}
+
+export interface SkillRepairActions {
+  remove_rows?: number[]           // 删除的记录 id
+  register_directories?: string[]  // 重新登记的目录
 }
 
 // Stats types
@@ -205,6 +535,14 @@ export interface ProviderStats {
   total_tokens: number
 }
 
+// One time bucket of a provider's successful-request latency
+export interface LatencyBucket {
+  bucket_start: number // unix seconds
+  request_count: number
+  avg_ms: number
+  p95_ms: number
+}
+
 // Log types
 export interface RequestLogListItem {
   id: number
@@ -218,6 +556,8 @@ export interface RequestLogListItem {
   output_tokens: number
   client_method: string
   client_path: string
+  is_shadow: boolean
+  client_ip: string | null // remote address of the connection
 }
 
 export interface RequestLogDetail extends RequestLogListItem {
@@ -229,6 +569,8 @@ export interface RequestLogDetail extends RequestLogListItem {
   provider_headers: string | null
   provider_body: string | null
   error_message: string | null
+  error_kind: string | null
+  selection_reason: string | null
 }
 
 export interface RequestLogListResponse {
@@ -243,6 +585,7 @@ export interface SystemLogItem {
   created_at: number
   event_type: string
   message: string
+  source: string | null // file:line of the recording call site
 }
 
 export interface SystemLogListResponse {
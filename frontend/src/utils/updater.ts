@@ -134,3 +134,26 @@ export async function checkForUpdatesNotification(): Promise<void> {
     console.error('检查更新失败:', error)
   }
 }
+
+export interface UpdateDiagnosis {
+  url: string
+  token_configured: boolean
+  reachable: boolean // false 表示没有收到 HTTP 响应（DNS/TLS/代理/超时）
+  status: number | null
+  elapsed_ms: number
+  rate_limit: number | null
+  rate_limit_remaining: number | null
+  rate_limit_reset: number | null // unix 秒
+  rate_limited: boolean
+  current_version: string
+  latest_tag: string | null
+  update_available: boolean | null
+  error: string | null
+}
+
+/**
+ * 诊断检查更新失败的原因（单次请求，不重试）
+ */
+export async function diagnoseUpdateConnectivity(): Promise<UpdateDiagnosis> {
+  return await invoke<UpdateDiagnosis>('diagnose_update_connectivity')
+}
@@ -1,34 +1,38 @@
 use axum::{
     body::Body,
-    extract::{Path, Query, State},
+    extract::{ConnectInfo, Path, Query, State},
     http::{Response, StatusCode},
     Json,
 };
 use bytes::Bytes;
-use futures_util::StreamExt;
+use futures_util::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::{mpsc, Mutex};
-use flate2::read::GzDecoder;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Mutex};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
 use std::io::Read;
 
 use super::AppState;
 use crate::db::models::{
-    Provider, ProviderCreate, ProviderResponse, ProviderUpdate,
-    GatewaySettings, TimeoutSettings, TimeoutSettingsUpdate,
-    RequestLogItem, RequestLogDetail, RequestLogInfo, PaginatedLogs,
+    ActiveRequestInfo, ProviderCreate, ProviderResponse, ProviderUpdate,
+    GatewaySettings, GatewaySettingsUpdate, TimeoutSettings, TimeoutSettingsUpdate,
+    RequestLogItem, RequestLogDetail, RequestLogInfo, PaginatedLogs, BodyCapturePolicy, LogBodyShape, UpstreamCompression, ERROR_KIND_CLIENT_CANCELLED, ERROR_KIND_CONCURRENCY_LIMITED, ERROR_KIND_NO_PROVIDER_AVAILABLE, ERROR_KIND_RATE_LIMITED, ERROR_KIND_REQUEST_CANCELLED,
     SystemLogItem, SystemLogListResponse,
-    DailyStats,
+    CliSettingsUpdate, DailyStats, ProviderStatsResponse,
     SystemStatus,
 };
+use crate::error::AppError;
+use crate::services::active_requests::{ActiveRequestGuard, REQUEST_ID_HEADER, RESPONSE_REQUEST_ID_HEADER};
+use crate::services::concurrency::{ConcurrencyPermit, QUEUE_TIMEOUT};
 use crate::services::proxy::{
-    apply_body_model_mapping, apply_url_model_mapping, apply_useragent_override,
-    detect_cli_type, filter_headers, is_streaming, parse_token_usage, set_auth_header,
-    CliType, TimeoutConfig, TokenUsage,
+    apply_body_model_mapping, apply_provider_auth, apply_url_model_mapping, apply_useragent_override,
+    detect_cli_type, filter_headers, FORCE_PROVIDER_HEADER, FORWARDED_MARKER_HEADER, is_streaming, non_stream_request, non_stream_response_to_sse, parse_streaming_token_usage, parse_token_usage, protocol_error_body, set_auth_header, PIN_PROVIDER_HEADER,
+    CliType, ClientOptions, TimeoutConfig, TokenUsage,
 };
-use crate::services::routing::select_provider;
-use crate::services::{provider as provider_service, stats as stats_service};
+use crate::services::routing::{get_provider_for_cli, random_unit, select_pinned_provider, select_provider, ProviderWithMaps, RoutingStrategy, SelectionReason};
+use crate::services::{provider as provider_service, stats as stats_service, webhook as webhook_service};
 
 // Common query params
 #[derive(Debug, Deserialize)]
@@ -42,6 +46,8 @@ pub struct PaginatedQuery {
 #[derive(Debug, Deserialize)]
 pub struct ProviderQuery {
     pub cli_type: Option<String>,
+    pub with_stats: Option<bool>,
+    pub search: Option<String>,
 }
 
 fn default_page() -> i64 {
@@ -71,12 +77,65 @@ fn db_error(e: impl std::fmt::Display) -> (StatusCode, Json<ErrorResponse>) {
     error_response(e.to_string())
 }
 
+// Status for errors from the logic shared with the Tauri commands
+fn command_error(e: AppError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match e {
+        AppError::NotFound(_) => StatusCode::NOT_FOUND,
+        AppError::Validation(_) => StatusCode::BAD_REQUEST,
+        AppError::Conflict(_) => StatusCode::CONFLICT,
+        AppError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        AppError::Db(_) | AppError::Io(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(ErrorResponse { error: e.to_string() }))
+}
+
+/// Guards the `/admin` routes with `Authorization: Bearer <gateway_settings.admin_token>`.
+/// The admin API stays off until a token is configured.
+pub async fn require_admin_token(
+    State(db): State<sqlx::SqlitePool>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<Response<Body>, (StatusCode, Json<ErrorResponse>)> {
+    let token: Option<String> = sqlx::query_scalar("SELECT admin_token FROM gateway_settings WHERE id = 1")
+        .fetch_optional(&db)
+        .await
+        .map_err(db_error)?
+        .flatten();
+    let Some(token) = token else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse { error: "Admin API is disabled; set admin_token in gateway settings".to_string() }),
+        ));
+    };
+
+    let provided = req
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .unwrap_or_default();
+    if !constant_time_eq(provided.as_bytes(), token.as_bytes()) {
+        tracing::warn!(path = %req.uri().path(), "Rejected admin API request with a missing or wrong token");
+        return Err((StatusCode::UNAUTHORIZED, Json(ErrorResponse { error: "Invalid admin token".to_string() })));
+    }
+
+    Ok(next.run(req).await)
+}
+
+// Compare without short-circuiting so response timing does not reveal the matching prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 // Catch-all proxy handler - forwards any non-API request to the appropriate provider
 pub async fn proxy_handler_catchall(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(client_addr): ConnectInfo<std::net::SocketAddr>,
     req: axum::http::Request<Body>,
 ) -> Result<Response<Body>, StatusCode> {
     let start_time = Instant::now();
+    let client_ip = client_addr.ip().to_canonical().to_string();
     let method = req.method().clone();
     let headers = req.headers().clone();
     let uri = req.uri().clone();
@@ -88,6 +147,16 @@ pub async fn proxy_handler_catchall(
         uri.path().to_string()
     };
 
+    // A request we forwarded ourselves came back: the provider base_url points at the gateway
+    if headers.contains_key(FORWARDED_MARKER_HEADER) {
+        tracing::error!(path = %full_path, "Proxy loop detected");
+        return Ok(Response::builder()
+            .status(StatusCode::LOOP_DETECTED)
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"error": "Proxy loop detected: provider base_url points at the gateway"}"#))
+            .unwrap());
+    }
+
     // Detect CLI type from User-Agent
     let cli_type = detect_cli_type(&headers);
 
@@ -104,11 +173,59 @@ pub async fn proxy_handler_catchall(
     };
 
     // Store client body for logging (truncate if too large)
-    let client_body_str = truncate_body(&body_bytes);
+    let client_body_str = body_text(&body_bytes);
+
+    let (max_timeout_override_ms, sse_keepalive_ms, body_capture_policy, routing_strategy, shadow_provider_id, shadow_sample_rate, log_sample_rate, log_body_format, log_body_max_depth, log_body_max_array_items, log_body_max_string_chars, max_concurrency, allow_force_provider, upstream_compression) =
+        sqlx::query_as::<_, (i64, i64, String, String, Option<i64>, f64, f64, String, i64, i64, i64, i64, i64, String)>(
+            "SELECT max_timeout_override_ms, sse_keepalive_ms, body_capture_policy, routing_strategy, shadow_provider_id, shadow_sample_rate, log_sample_rate, log_body_format, log_body_max_depth, log_body_max_array_items, log_body_max_string_chars, max_concurrency, allow_force_provider, upstream_compression FROM gateway_settings WHERE id = 1",
+        )
+        .fetch_one(&state.db)
+        .await
+        .unwrap_or((600_000, 0, "all".to_string(), "ordered".to_string(), None, 0.0, 1.0, "raw".to_string(), 0, 0, 0, 0, 0, "passthrough".to_string()));
+    let routing_strategy: RoutingStrategy = routing_strategy.parse().unwrap_or_default();
+    let upstream_compression: UpstreamCompression = upstream_compression.parse().unwrap_or_default();
+    let body_shape = LogBodyShape::from_settings(&log_body_format, log_body_max_depth, log_body_max_array_items, log_body_max_string_chars);
+
+    // A client can pin a provider for debugging; forcing past pause/blacklist needs allow_force_provider
+    let pin = headers
+        .get(PIN_PROVIDER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    let force_requested = headers
+        .get(FORCE_PROVIDER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "" | "0" | "false"));
+    if force_requested && allow_force_provider == 0 {
+        tracing::warn!("Ignoring {} header: allow_force_provider is disabled", FORCE_PROVIDER_HEADER);
+    }
+    let force = force_requested && allow_force_provider != 0;
+    let pinned = match pin {
+        Some(pin) => match select_pinned_provider(&state.db, cli_type.as_str(), pin, force).await {
+            Ok(Some(selected)) => {
+                tracing::info!(pin = %pin, provider = %selected.provider.name, forced = force, "Using pinned provider");
+                let reason = if force { SelectionReason::PinnedForced } else { SelectionReason::Pinned };
+                Some((selected, reason))
+            }
+            Ok(None) => {
+                tracing::warn!(pin = %pin, forced = force, "Pinned provider is not available, using normal routing");
+                None
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to load pinned provider");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        },
+        None => None,
+    };
 
-    // Select provider based on CLI type
-    let provider_with_maps = match select_provider(&state.db, cli_type.as_str()).await {
-        Ok(Some(p)) => p,
+    // Select provider based on CLI type and routing strategy
+    let selected = match pinned {
+        Some(pinned) => Ok(Some(pinned)),
+        None => select_provider(&state.db, cli_type.as_str(), routing_strategy, &state.scores, &state.concurrency).await,
+    };
+    let (provider_with_maps, selection_reason) = match selected {
+        Ok(Some(selected)) => selected,
         Ok(None) => {
             tracing::warn!(cli_type = %cli_type, "No available provider");
             // Log system event
@@ -117,10 +234,40 @@ pub async fn proxy_handler_catchall(
                 "no_provider_available",
                 &format!("CLI 类型 {} 没有可用的服务商", cli_type),
             ).await;
+            webhook_service::notify_once(
+                &state.db,
+                "no_provider_available",
+                cli_type.as_str(),
+                None,
+                &format!("CLI 类型 {} 没有可用的服务商", cli_type),
+            );
+            let message = "All upstream providers are currently unavailable";
+            let _ = stats_service::record_request_log(
+                &state.log_db,
+                cli_type.as_str(),
+                "",
+                None,
+                Some(StatusCode::SERVICE_UNAVAILABLE.as_u16()),
+                start_time.elapsed().as_millis() as i64,
+                0,
+                0,
+                method.as_str(),
+                &full_path,
+                Some(RequestLogInfo {
+                    client_headers: Some(client_headers_json),
+                    client_body: Some(client_body_str),
+                    error_message: Some(message.to_string()),
+                    error_kind: Some(ERROR_KIND_NO_PROVIDER_AVAILABLE.to_string()),
+                    body_capture: body_capture_policy.parse().unwrap_or_default(),
+                    body_shape,
+                    client_ip: Some(client_ip),
+                    ..Default::default()
+                }),
+            ).await;
             return Ok(Response::builder()
                 .status(StatusCode::SERVICE_UNAVAILABLE)
                 .header("content-type", "application/json")
-                .body(Body::from(r#"{"error": "No available provider configured"}"#))
+                .body(Body::from(protocol_error_body(cli_type, 503, ERROR_KIND_NO_PROVIDER_AVAILABLE, message)))
                 .unwrap());
         }
         Err(e) => {
@@ -132,9 +279,44 @@ pub async fn proxy_handler_catchall(
     let provider = &provider_with_maps.provider;
     let provider_id = provider.id;
     let provider_name = provider.name.clone();
+    let response_transform = provider.response_transform.clone();
+
+    // Every eligible provider was saturated (or the global limit is hit): queue briefly for a slot
+    let Some(permit) = state.concurrency.acquire(provider_id, provider.max_concurrency, max_concurrency, QUEUE_TIMEOUT).await else {
+        tracing::warn!(provider = %provider_name, cli_type = %cli_type, "Timed out waiting for a concurrency slot");
+        let message = "Too many concurrent requests, timed out waiting for a free slot";
+        let _ = stats_service::record_request_log(
+            &state.log_db,
+            cli_type.as_str(),
+            &provider_name,
+            None,
+            Some(StatusCode::SERVICE_UNAVAILABLE.as_u16()),
+            start_time.elapsed().as_millis() as i64,
+            0,
+            0,
+            method.as_str(),
+            &full_path,
+            Some(RequestLogInfo {
+                client_headers: Some(client_headers_json),
+                client_body: Some(client_body_str),
+                error_message: Some(message.to_string()),
+                error_kind: Some(ERROR_KIND_CONCURRENCY_LIMITED.to_string()),
+                body_capture: body_capture_policy.parse().unwrap_or_default(),
+                body_shape,
+                selection_reason: Some(selection_reason.as_str().to_string()),
+                client_ip: Some(client_ip),
+                ..Default::default()
+            }),
+        ).await;
+        return Ok(Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("content-type", "application/json")
+            .body(Body::from(protocol_error_body(cli_type, 503, ERROR_KIND_CONCURRENCY_LIMITED, message)))
+            .unwrap());
+    };
 
     // Get timeout settings
-    let timeouts = match sqlx::query_as::<_, (i64, i64, i64)>(
+    let mut timeouts = match sqlx::query_as::<_, (i64, i64, i64)>(
         "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout FROM timeout_settings WHERE id = 1",
     )
     .fetch_one(&state.db)
@@ -144,23 +326,312 @@ pub async fn proxy_handler_catchall(
         Err(_) => TimeoutConfig::default(),
     };
 
+    // Per-request timeout override from the client (bounded by gateway settings)
+    timeouts.apply_header_override(&headers, max_timeout_override_ms);
+
+    // SSE heartbeats while waiting for the first byte (0 = disabled)
+    let keepalive = (sse_keepalive_ms > 0).then(|| Duration::from_millis(sse_keepalive_ms as u64));
+
     // Check if streaming
     let streaming = is_streaming(&body_bytes, &full_path, cli_type);
+    // Providers with broken streaming get a non-streaming request, replayed to the client as SSE
+    let downgrade_stream = streaming && provider.force_non_stream != 0;
+    let effective_timeout = if streaming && !downgrade_stream { timeouts.idle_timeout } else { timeouts.non_stream_timeout };
+    let (upstream_body, upstream_path) = if downgrade_stream {
+        tracing::info!(provider = %provider_name, cli_type = %cli_type, "Downgrading streaming request to non-streaming");
+        non_stream_request(&body_bytes, &full_path, cli_type)
+    } else {
+        (body_bytes.clone(), full_path.clone())
+    };
+
+    // Direct providers share the pooled client; proxied or custom-TLS ones get their own client
+    let client = match state.upstream_clients.get(provider.id, &ClientOptions::for_provider(provider)) {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(provider = %provider_name, error = %e, "Failed to build upstream client");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let UpstreamRequest {
+        builder: request_builder,
+        upstream_url,
+        forward_headers: forward_headers_json,
+        forward_body: forward_body_str,
+        model_id,
+    } = build_upstream_request(&client, &provider_with_maps, cli_type, &method, &headers, &upstream_path, &upstream_body, upstream_compression);
+
+    // Mirror a sample of traffic to the shadow provider (diagnostics only)
+    if let Some(shadow_id) = shadow_provider_id.filter(|&id| id != provider_id) {
+        if shadow_sample_rate > 0.0 && random_unit() < shadow_sample_rate {
+            spawn_shadow_request(
+                state.clone(),
+                shadow_id,
+                cli_type,
+                method.clone(),
+                headers.clone(),
+                full_path.clone(),
+                body_bytes.clone(),
+                timeouts.non_stream_timeout,
+                body_capture_policy.parse().unwrap_or_default(),
+                body_shape,
+                upstream_compression,
+            );
+        }
+    }
+
+    // Build log info
+    let cancel_log_info = RequestLogInfo {
+        client_headers: Some(client_headers_json.clone()),
+        client_body: Some(client_body_str.clone()),
+        forward_url: Some(upstream_url.clone()),
+        effective_timeout_ms: Some(effective_timeout.as_millis() as i64),
+        body_capture: body_capture_policy.parse().unwrap_or_default(),
+        body_shape,
+        selection_reason: Some(selection_reason.as_str().to_string()),
+        client_ip: Some(client_ip.clone()),
+        ..Default::default()
+    };
+    let log_info = RequestLogInfo {
+        client_headers: Some(client_headers_json),
+        client_body: Some(client_body_str),
+        forward_url: Some(upstream_url.clone()),
+        forward_headers: Some(forward_headers_json),
+        forward_body: Some(forward_body_str),
+        effective_timeout_ms: Some(effective_timeout.as_millis() as i64),
+        body_capture: body_capture_policy.parse().unwrap_or_default(),
+        log_sample_rate: Some(log_sample_rate),
+        body_shape,
+        selection_reason: Some(selection_reason.as_str().to_string()),
+        client_ip: Some(client_ip),
+        ..Default::default()
+    };
+
+    // Track the request so it can be listed and cancelled; cancelling drops the upstream future
+    let request_id = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let active = state.active_requests.register(ActiveRequestInfo {
+        request_id,
+        cli_type: cli_type.as_str().to_string(),
+        provider_name: provider_name.clone(),
+        model_id: model_id.clone(),
+        client_method: method.to_string(),
+        client_path: full_path.clone(),
+        streaming,
+        started_at: chrono::Utc::now().timestamp(),
+        elapsed_ms: 0,
+        cancelling: false,
+    });
+
+    // Execute request
+    let execute = async {
+        if streaming && !downgrade_stream {
+            handle_streaming_request(
+                request_builder,
+                &state,
+                provider_id,
+                &provider_name,
+                cli_type,
+                model_id.as_deref(),
+                method.as_ref(),
+                &full_path,
+                start_time,
+                timeouts,
+                keepalive,
+                log_info,
+                active.cancel_signal(),
+            )
+            .await
+        } else {
+            let response = handle_non_streaming_request(
+                request_builder,
+                &state,
+                provider_id,
+                &provider_name,
+                cli_type,
+                model_id.as_deref(),
+                method.as_ref(),
+                &full_path,
+                start_time,
+                timeouts,
+                response_transform.as_deref(),
+                log_info,
+            )
+            .await?;
+            if downgrade_stream {
+                Ok(replay_as_sse(response, cli_type).await)
+            } else {
+                Ok(response)
+            }
+        }
+    };
+
+    let mut response = tokio::select! {
+        res = execute => res?,
+        _ = active.cancelled() => {
+            tracing::info!(request_id = %active.request_id(), provider = %provider_name, "Request cancelled");
+            let message = "Request cancelled by the gateway";
+            let _ = stats_service::record_request_log(
+                &state.log_db,
+                cli_type.as_str(),
+                &provider_name,
+                model_id.as_deref(),
+                Some(CLIENT_CLOSED_REQUEST),
+                start_time.elapsed().as_millis() as i64,
+                0,
+                0,
+                method.as_str(),
+                &full_path,
+                Some(RequestLogInfo {
+                    error_message: Some(message.to_string()),
+                    error_kind: Some(ERROR_KIND_REQUEST_CANCELLED.to_string()),
+                    ..cancel_log_info
+                }),
+            ).await;
+            return Ok(Response::builder()
+                .status(CLIENT_CLOSED_REQUEST)
+                .header("content-type", "application/json")
+                .header(RESPONSE_REQUEST_ID_HEADER, active.request_id())
+                .body(Body::from(protocol_error_body(cli_type, CLIENT_CLOSED_REQUEST, ERROR_KIND_REQUEST_CANCELLED, message)))
+                .unwrap());
+        }
+    };
+
+    if let Ok(value) = axum::http::HeaderValue::from_str(active.request_id()) {
+        response.headers_mut().insert(RESPONSE_REQUEST_ID_HEADER, value);
+    }
+    if streaming {
+        // The stream outlives this handler: keep the request active and its slot held until the body finishes
+        let body = std::mem::replace(response.body_mut(), Body::empty());
+        *response.body_mut() = Body::from_stream(cancellable_body(body, active, permit));
+    }
+    Ok(response)
+}
+
+/// Re-emit a successful non-streaming response as SSE for a client that asked to stream.
+/// Errors and bodies the converter does not recognise are passed through unchanged.
+async fn replay_as_sse(response: Response<Body>, cli_type: CliType) -> Response<Body> {
+    if !response.status().is_success() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Non-streaming bodies are already fully buffered in memory
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let content_encoding = parts.headers.get("content-encoding").and_then(|v| v.to_str().ok());
+    let decompressed = maybe_decompress(&bytes, content_encoding);
+    let Some(sse) = non_stream_response_to_sse(&decompressed, cli_type) else {
+        tracing::warn!(cli_type = %cli_type, "Could not replay non-streaming response as SSE, passing it through");
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    for name in ["content-encoding", "content-length", "transfer-encoding"] {
+        parts.headers.remove(name);
+    }
+    parts.headers.insert(
+        axum::http::header::CONTENT_TYPE,
+        axum::http::HeaderValue::from_static("text/event-stream"),
+    );
+    Response::from_parts(parts, Body::from(sse))
+}
+
+/// Nginx-style status for a request cancelled before it completed, by the gateway or the client
+const CLIENT_CLOSED_REQUEST: u16 = 499;
+
+/// Forward a streaming body until it ends or the request is cancelled.
+/// Holds the guard so the request stays listed while streaming, and the concurrency permit
+/// so the slot is not freed early.
+fn cancellable_body(body: Body, active: ActiveRequestGuard, permit: ConcurrencyPermit) -> ByteStream {
+    let mut inner = body.into_data_stream();
+    Box::pin(async_stream::stream! {
+        let _permit = permit;
+        loop {
+            let next = tokio::select! {
+                item = inner.next() => Some(item),
+                _ = active.cancelled() => None,
+            };
+            match next {
+                Some(Some(Ok(chunk))) => yield Ok::<Bytes, std::io::Error>(chunk),
+                Some(Some(Err(e))) => {
+                    yield Err(std::io::Error::other(e));
+                    break;
+                }
+                Some(None) => break,
+                None => {
+                    // Dropping the inner stream aborts the upstream request
+                    tracing::info!(request_id = %active.request_id(), "Streaming request cancelled");
+                    yield Ok(Bytes::from_static(b"event: error\ndata: {\"error\": \"Request cancelled by the gateway\"}\n\n"));
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// A request ready to send to a provider, plus what gets logged about it
+struct UpstreamRequest {
+    builder: reqwest::RequestBuilder,
+    upstream_url: String,
+    forward_headers: String,
+    forward_body: String,
+    model_id: Option<String>,
+}
+
+/// Build the upstream request for a provider: model mapping, URL, auth, User-Agent override,
+/// the loop-detection marker and the provider's header rules
+#[allow(clippy::too_many_arguments)]
+fn build_upstream_request(
+    client: &reqwest::Client,
+    provider_with_maps: &ProviderWithMaps,
+    cli_type: CliType,
+    method: &axum::http::Method,
+    headers: &axum::http::HeaderMap,
+    full_path: &str,
+    body_bytes: &[u8],
+    compression: UpstreamCompression,
+) -> UpstreamRequest {
+    let provider = &provider_with_maps.provider;
 
     // Apply model mapping and extract model info
     let (final_body, final_path, source_model, target_model) = match cli_type {
         CliType::Gemini => {
-            let mapping = apply_url_model_mapping(&provider_with_maps, &full_path, &provider_with_maps.model_maps);
-            (body_bytes.clone(), mapping.path, mapping.source_model, mapping.target_model)
+            let mapping = apply_url_model_mapping(provider_with_maps, full_path, body_bytes, &provider_with_maps.model_maps);
+            (body_bytes.to_vec(), mapping.path, mapping.source_model, mapping.target_model)
         }
         _ => {
-            let mapping = apply_body_model_mapping(&provider_with_maps, &body_bytes, &full_path);
+            let mapping = apply_body_model_mapping(provider_with_maps, body_bytes, full_path);
             (mapping.body, mapping.path, mapping.source_model, mapping.target_model)
         }
     };
 
+    // Per-provider system prompt, merged ahead of the client's own
+    let final_body = match provider.inject_system_prompt.as_deref()
+        .and_then(|prompt| crate::services::proxy::inject_system_prompt(&final_body, cli_type, prompt))
+    {
+        Some(body) => {
+            tracing::info!(provider = %provider.name, cli_type = %cli_type, "Injected provider system prompt");
+            body
+        }
+        None => final_body,
+    };
+
+    // Per-provider JSON Patch fixups, applied last so they see the body as forwarded
+    let final_body = match crate::services::proxy::apply_json_patch(&final_body, provider.request_transform.as_deref()) {
+        Some(body) => {
+            tracing::debug!(provider = %provider.name, cli_type = %cli_type, "Applied provider request transform");
+            body
+        }
+        None => final_body,
+    };
+
     // Use target model if mapped, otherwise use source model
-    let model_id = target_model.clone().or(source_model.clone());
+    let model_id = target_model.or(source_model);
 
     // Build upstream URL: base_url + original_path
     // e.g., base_url="https://api.example.com/v1", path="/responses" -> "https://api.example.com/v1/responses"
@@ -168,8 +639,24 @@ pub async fn proxy_handler_catchall(
     let upstream_url = format!("{}{}", base_url, final_path);
 
     // Prepare headers - filter hop-by-hop headers and set auth
-    let mut req_headers = filter_headers(&headers);
-    set_auth_header(&mut req_headers, &provider.api_key, cli_type);
+    // (request_url may carry the key as a query parameter, so only upstream_url is logged)
+    let mut req_headers = filter_headers(headers);
+    let request_url = match apply_provider_auth(
+        &mut req_headers,
+        &upstream_url,
+        &provider.api_key,
+        cli_type,
+        provider.auth_header_name.as_deref(),
+        provider.auth_scheme.as_deref(),
+    ) {
+        Ok(url) => url,
+        Err(e) => {
+            tracing::warn!(provider = %provider.name, error = %e, "Invalid provider auth settings, using CLI default");
+            set_auth_header(&mut req_headers, &provider.api_key, cli_type);
+            upstream_url.clone()
+        }
+    };
+    req_headers.insert(FORWARDED_MARKER_HEADER, reqwest::header::HeaderValue::from_static("1"));
 
     // Apply User-Agent override (per-provider)
     let _original_ua = apply_useragent_override(&mut req_headers, provider.custom_useragent.as_deref());
@@ -182,73 +669,140 @@ pub async fn proxy_handler_catchall(
         );
     }
 
+    if compression == UpstreamCompression::Identity {
+        req_headers.insert(reqwest::header::ACCEPT_ENCODING, reqwest::header::HeaderValue::from_static("identity"));
+    }
+
+    // Per-provider strip/override rules get the final say
+    crate::services::proxy::apply_header_rules(
+        &mut req_headers,
+        provider.strip_headers.as_deref(),
+        provider.header_overrides.as_deref(),
+    );
+
     // Serialize forward headers for logging (mask sensitive headers)
-    let forward_headers_json = serialize_reqwest_headers(&req_headers);
-    let forward_body_str = truncate_body(&final_body);
+    let forward_headers = serialize_reqwest_headers(&req_headers);
+    let forward_body = body_text(&final_body);
 
     // Create HTTP client request
-    let client = reqwest::Client::new();
     let request_builder = match method.as_str() {
-        "GET" => client.get(&upstream_url),
-        "POST" => client.post(&upstream_url),
-        "PUT" => client.put(&upstream_url),
-        "DELETE" => client.delete(&upstream_url),
-        "PATCH" => client.patch(&upstream_url),
+        "GET" => client.get(&request_url),
+        "POST" => client.post(&request_url),
+        "PUT" => client.put(&request_url),
+        "DELETE" => client.delete(&request_url),
+        "PATCH" => client.patch(&request_url),
         _ => client.request(
             reqwest::Method::from_bytes(method.as_str().as_bytes()).unwrap_or(reqwest::Method::GET),
-            &upstream_url,
+            &request_url,
         ),
     };
 
     let request_builder = request_builder.headers(req_headers);
-    let request_builder = if !final_body.is_empty() {
+    let builder = if !final_body.is_empty() {
         request_builder.body(final_body)
     } else {
         request_builder
     };
 
-    // Build log info
-    let log_info = RequestLogInfo {
-        client_headers: Some(client_headers_json),
-        client_body: Some(client_body_str),
-        forward_url: Some(upstream_url.clone()),
-        forward_headers: Some(forward_headers_json),
-        forward_body: Some(forward_body_str),
-        ..Default::default()
-    };
+    UpstreamRequest { builder, upstream_url, forward_headers, forward_body, model_id }
+}
 
-    // Execute request
-    if streaming {
-        handle_streaming_request(
-            request_builder,
-            &state,
-            provider_id,
-            &provider_name,
-            cli_type,
-            model_id.as_deref(),
-            method.as_ref(),
-            &full_path,
-            start_time,
-            timeouts,
-            log_info,
-        )
+/// Send a copy of the request to the shadow provider in the background.
+/// The response is read to completion for latency/token comparison and then discarded.
+/// Only a request log flagged `is_shadow` is written: no usage stats, failure counts or
+/// routing scores are touched.
+#[allow(clippy::too_many_arguments)]
+fn spawn_shadow_request(
+    state: Arc<AppState>,
+    shadow_provider_id: i64,
+    cli_type: CliType,
+    method: axum::http::Method,
+    headers: axum::http::HeaderMap,
+    full_path: String,
+    body_bytes: Vec<u8>,
+    timeout: Duration,
+    body_capture: BodyCapturePolicy,
+    body_shape: LogBodyShape,
+    compression: UpstreamCompression,
+) {
+    tokio::spawn(async move {
+        let shadow = match get_provider_for_cli(&state.db, shadow_provider_id, cli_type.as_str()).await {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                tracing::debug!(shadow_provider_id, cli_type = %cli_type, "Shadow provider missing or does not serve this CLI type");
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to load shadow provider");
+                return;
+            }
+        };
+
+        let client = match state.upstream_clients.get(shadow.provider.id, &ClientOptions::for_provider(&shadow.provider)) {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!(provider = %shadow.provider.name, error = %e, "Failed to build shadow upstream client");
+                return;
+            }
+        };
+
+        let start_time = Instant::now();
+        let request = build_upstream_request(&client, &shadow, cli_type, &method, &headers, &full_path, &body_bytes, compression);
+        let mut log_info = RequestLogInfo {
+            client_headers: Some(serialize_headers(&headers)),
+            client_body: Some(body_text(&body_bytes)),
+            forward_url: Some(request.upstream_url),
+            forward_headers: Some(request.forward_headers),
+            forward_body: Some(request.forward_body),
+            effective_timeout_ms: Some(timeout.as_millis() as i64),
+            is_shadow: true,
+            body_capture,
+            body_shape,
+            selection_reason: Some(SelectionReason::Shadow.as_str().to_string()),
+            ..Default::default()
+        };
+
+        let mut status_code = None;
+        let mut usage = TokenUsage::default();
+        match tokio::time::timeout(timeout, async {
+            let response = request.builder.send().await?;
+            let status = response.status();
+            let resp_headers = response.headers().clone();
+            let bytes = response.bytes().await?;
+            Ok::<_, reqwest::Error>((status, resp_headers, bytes))
+        })
         .await
-    } else {
-        handle_non_streaming_request(
-            request_builder,
-            &state,
-            provider_id,
-            &provider_name,
-            cli_type,
-            model_id.as_deref(),
-            method.as_ref(),
+        {
+            Ok(Ok((status, resp_headers, bytes))) => {
+                status_code = Some(status.as_u16());
+                log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers));
+                let content_encoding = resp_headers.get("content-encoding").and_then(|v| v.to_str().ok());
+                let body = maybe_decompress(&bytes, content_encoding);
+                parse_token_usage(&body, cli_type, &mut usage);
+                for line in String::from_utf8_lossy(&body).lines() {
+                    parse_streaming_token_usage(line, cli_type, &mut usage);
+                }
+                log_info.provider_body = Some(body_text(&body));
+            }
+            Ok(Err(e)) => log_info.error_message = Some(format!("Upstream error: {}", e.without_url())),
+            Err(_) => log_info.error_message = Some("Request timeout".to_string()),
+        }
+
+        let _ = stats_service::record_request_log(
+            &state.log_db,
+            cli_type.as_str(),
+            &shadow.provider.name,
+            request.model_id.as_deref(),
+            status_code,
+            start_time.elapsed().as_millis() as i64,
+            usage.input_tokens,
+            usage.output_tokens,
+            method.as_str(),
             &full_path,
-            start_time,
-            timeouts,
-            log_info,
+            Some(log_info),
         )
-        .await
-    }
+        .await;
+    });
 }
 
 fn serialize_headers(headers: &axum::http::HeaderMap) -> String {
@@ -273,32 +827,34 @@ fn serialize_reqwest_headers(headers: &reqwest::header::HeaderMap) -> String {
     serde_json::to_string(&map).unwrap_or_default()
 }
 
-fn truncate_body(body: &[u8]) -> String {
-    const MAX_SIZE: usize = 100 * 1024; // 100KB
-    let s = String::from_utf8_lossy(body);
-    if s.len() > MAX_SIZE {
-        let mut end = MAX_SIZE;
-        while end > 0 && !s.is_char_boundary(end) {
-            end -= 1;
-        }
-        format!("{}...[truncated]", &s[..end])
-    } else {
-        s.to_string()
-    }
+// Bodies are logged in full here; record_request_log shapes them and then applies the size cap
+fn body_text(body: &[u8]) -> String {
+    String::from_utf8_lossy(body).into_owned()
 }
 
-/// Decompress gzip data if needed
+/// Decode a gzip/deflate body for logging and usage parsing. Other encodings, and bodies
+/// that fail to decode, are returned as-is.
 fn maybe_decompress(body: &[u8], content_encoding: Option<&str>) -> Vec<u8> {
-    if let Some(encoding) = content_encoding {
-        if encoding.to_lowercase().contains("gzip") {
-            let mut decoder = GzDecoder::new(body);
-            let mut decompressed = Vec::new();
-            if decoder.read_to_end(&mut decompressed).is_ok() {
-                return decompressed;
-            }
-        }
+    let Some(encoding) = content_encoding.map(|e| e.trim().to_ascii_lowercase()) else {
+        return body.to_vec();
+    };
+    let decoded = match encoding.as_str() {
+        "gzip" | "x-gzip" => decode_all(GzDecoder::new(body)),
+        // HTTP deflate is zlib-wrapped, but some servers send raw deflate
+        "deflate" => decode_all(ZlibDecoder::new(body)).or_else(|| decode_all(DeflateDecoder::new(body))),
+        _ => None,
+    };
+    decoded.unwrap_or_else(|| body.to_vec())
+}
+
+fn decode_all(mut decoder: impl Read) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    match decoder.read_to_end(&mut decoded) {
+        Ok(_) => Some(decoded),
+        // Truncated body (collection cap or client disconnect): keep what decoded before the cut
+        Err(_) if !decoded.is_empty() => Some(decoded),
+        Err(_) => None,
     }
-    body.to_vec()
 }
 
 async fn handle_streaming_request(
@@ -312,42 +868,53 @@ async fn handle_streaming_request(
     client_path: &str,
     start_time: Instant,
     timeouts: TimeoutConfig,
-    mut log_info: RequestLogInfo,
+    keepalive: Option<Duration>,
+    log_info: RequestLogInfo,
+    cancel_requested: watch::Receiver<bool>,
 ) -> Result<Response<Body>, StatusCode> {
+    let ctx = StreamContext {
+        state: state.clone(),
+        provider_id,
+        provider_name: provider_name.to_string(),
+        cli_type,
+        model_id: model_id.map(|s| s.to_string()),
+        client_method: client_method.to_string(),
+        client_path: client_path.to_string(),
+        start_time,
+        cancel_requested,
+    };
+
     // Send request with timeout for first byte
-    let response = match tokio::time::timeout(
-        timeouts.first_byte_timeout,
-        request_builder.send(),
-    )
-    .await
-    {
+    let send_future = tokio::time::timeout(timeouts.first_byte_timeout, request_builder.send());
+    let send_result = match keepalive {
+        Some(interval) => {
+            // Upstream answered within one interval: respond exactly as without keepalive.
+            // Otherwise commit to an SSE response and send heartbeats while waiting.
+            let mut send_task = tokio::spawn(send_future);
+            tokio::select! {
+                res = &mut send_task => res.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+                _ = tokio::time::sleep(interval) => {
+                    let stream = keepalive_stream(ctx, send_task, interval, timeouts.idle_timeout, log_info);
+                    return Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header("content-type", "text/event-stream")
+                        .header("cache-control", "no-cache")
+                        .header("X-CCG-Provider", provider_name)
+                        .body(Body::from_stream(stream))
+                        .unwrap());
+                }
+            }
+        }
+        None => send_future.await,
+    };
+
+    let response = match send_result {
         Ok(Ok(resp)) => resp,
         Ok(Err(e)) => {
+            // The URL may carry the API key (query-parameter auth), so it is never reported
+            let e = e.without_url();
             tracing::error!(error = %e, "Upstream request failed");
-            if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
-                if was_blacklisted {
-                    let _ = stats_service::record_system_log(
-                        &state.log_db,
-                        "provider_blacklisted",
-                        &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
-                    ).await;
-                }
-            }
-            log_info.error_message = Some(format!("Upstream error: {}", e));
-            record_request_stats(
-                state,
-                cli_type,
-                provider_name,
-                model_id,
-                None,
-                start_time.elapsed().as_millis() as i64,
-                0,
-                0,
-                client_method,
-                client_path,
-                Some(log_info),
-            )
-            .await;
+            record_stream_failure(&ctx, log_info, format!("Upstream error: {}", e)).await;
             return Ok(Response::builder()
                 .status(StatusCode::BAD_GATEWAY)
                 .header("content-type", "application/json")
@@ -356,30 +923,7 @@ async fn handle_streaming_request(
         }
         Err(_) => {
             tracing::error!("First byte timeout");
-            if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
-                if was_blacklisted {
-                    let _ = stats_service::record_system_log(
-                        &state.log_db,
-                        "provider_blacklisted",
-                        &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
-                    ).await;
-                }
-            }
-            log_info.error_message = Some("First byte timeout".to_string());
-            record_request_stats(
-                state,
-                cli_type,
-                provider_name,
-                model_id,
-                None,
-                start_time.elapsed().as_millis() as i64,
-                0,
-                0,
-                client_method,
-                client_path,
-                Some(log_info),
-            )
-            .await;
+            record_stream_failure(&ctx, log_info, "First byte timeout".to_string()).await;
             return Ok(Response::builder()
                 .status(StatusCode::GATEWAY_TIMEOUT)
                 .header("content-type", "application/json")
@@ -388,42 +932,176 @@ async fn handle_streaming_request(
         }
     };
 
-    let status = response.status();
-    let resp_headers = response.headers().clone();
-
-    // Store provider response info
-    log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers));
-
     // Build response headers
     let mut builder = Response::builder()
-        .status(StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::OK));
+        .status(StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::OK));
 
-    for (name, value) in resp_headers.iter() {
+    for (name, value) in response.headers().iter() {
         if let Ok(header_name) = axum::http::HeaderName::from_bytes(name.as_str().as_bytes()) {
             if let Ok(header_value) = axum::http::HeaderValue::from_bytes(value.as_bytes()) {
                 builder = builder.header(header_name, header_value);
             }
         }
     }
-    builder = builder.header("X-CCG-Provider", provider_name);
+    builder = builder.header("X-CCG-Provider", provider_name);
+
+    let stream = stream_upstream_body(ctx, response, timeouts.idle_timeout, log_info);
+    Ok(builder
+        .body(Body::from_stream(stream))
+        .unwrap())
+}
+
+/// Request context shared by the streaming paths (owned so it can move into background tasks)
+struct StreamContext {
+    state: Arc<AppState>,
+    provider_id: i64,
+    provider_name: String,
+    cli_type: CliType,
+    model_id: Option<String>,
+    client_method: String,
+    client_path: String,
+    start_time: Instant,
+    /// Set by cancel_request; tells a gateway cancel apart from a client disconnect
+    cancel_requested: watch::Receiver<bool>,
+}
+
+type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+/// Aborts a spawned task when dropped (e.g. together with a response stream the client abandoned)
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Status recorded for a finished stream: an abandoned stream is a failed request, not a 200
+fn stream_log_status(upstream_status: StatusCode, client_cancelled: bool) -> u16 {
+    if client_cancelled {
+        CLIENT_CLOSED_REQUEST
+    } else {
+        upstream_status.as_u16()
+    }
+}
+
+/// Record a streaming request that failed before any upstream data was received
+async fn record_stream_failure(ctx: &StreamContext, mut log_info: RequestLogInfo, error_message: String) {
+    if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&ctx.state.db, ctx.provider_id).await {
+        if was_blacklisted {
+            let _ = stats_service::record_system_log(
+                &ctx.state.log_db,
+                "provider_blacklisted",
+                &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
+            ).await;
+            webhook_service::notify_once(&ctx.state.db, "provider_blacklisted", &prov_name, Some(&prov_name), "连续失败次数达到阈值");
+        }
+    }
+    log_info.error_message = Some(error_message);
+    record_request_stats(
+        &ctx.state,
+        ctx.provider_id,
+        ctx.cli_type,
+        &ctx.provider_name,
+        ctx.model_id.as_deref(),
+        None,
+        ctx.start_time.elapsed().as_millis() as i64,
+        0,
+        0,
+        &ctx.client_method,
+        &ctx.client_path,
+        Some(log_info),
+    )
+    .await;
+}
+
+/// Count a failed upstream response against the provider. A 429 with a usable Retry-After
+/// blacklists the provider until exactly that time (capped by retry_after_max_secs) instead of
+/// going through the failure threshold and blacklist_minutes.
+async fn record_response_failure(
+    state: &AppState,
+    provider_id: i64,
+    status: StatusCode,
+    resp_headers: &reqwest::header::HeaderMap,
+    log_info: &mut RequestLogInfo,
+) {
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        if let Some(secs) = rate_limit_retry_after(&state.db, resp_headers).await {
+            log_info.error_kind = Some(ERROR_KIND_RATE_LIMITED.to_string());
+            log_info.error_message = Some(format!("Rate limited by upstream, Retry-After: {}s", secs));
+            if let Ok(prov_name) = provider_service::record_rate_limited(&state.db, provider_id, secs).await {
+                let _ = stats_service::record_system_log(
+                    &state.log_db,
+                    "provider_blacklisted",
+                    &format!("服务商 {} 被上游限流，按 Retry-After 加入黑名单 {} 秒", prov_name, secs),
+                ).await;
+                webhook_service::notify_once(&state.db, "provider_blacklisted", &prov_name, Some(&prov_name), &format!("上游限流，{} 秒后重试", secs));
+            }
+            return;
+        }
+    }
 
-    // Create streaming body
-    let is_success = status.is_success();
+    if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
+        if was_blacklisted {
+            let _ = stats_service::record_system_log(
+                &state.log_db,
+                "provider_blacklisted",
+                &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
+            ).await;
+            webhook_service::notify_once(&state.db, "provider_blacklisted", &prov_name, Some(&prov_name), "连续失败次数达到阈值");
+        }
+    }
+}
+
+/// Retry-After of a 429 in seconds, capped by gateway_settings.retry_after_max_secs;
+/// None when the header is missing or unreadable, or the setting is 0 (Retry-After ignored)
+async fn rate_limit_retry_after(db: &sqlx::SqlitePool, resp_headers: &reqwest::header::HeaderMap) -> Option<i64> {
+    let secs = resp_headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| provider_service::parse_retry_after(v, chrono::Utc::now()))?;
+    let max_secs: i64 = sqlx::query_scalar("SELECT retry_after_max_secs FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(0);
+    (max_secs > 0).then(|| secs.min(max_secs))
+}
+
+/// What a relayed upstream stream produced, readable once the client side stops polling it
+struct RelayedStream {
+    chunks: Arc<Mutex<Vec<Bytes>>>,
+    ended: mpsc::Receiver<()>,
+}
+
+impl RelayedStream {
+    /// Wait for the relay to stop. Returns the collected chunks and whether the stream ran to
+    /// its end (completed, failed or timed out) rather than being dropped early.
+    async fn finish(mut self) -> (Vec<Bytes>, bool) {
+        // 客户端断开或请求被取消时 axum 丢弃 stream，上游响应随之被 drop（连接关闭，不再继续读取），
+        // sender 被 drop 而没有发送通知，recv 返回 None
+        let completed = self.ended.recv().await.is_some();
+        let chunks = self.chunks.lock().await.clone();
+        (chunks, completed)
+    }
+}
 
+/// Forward the upstream body with the idle timeout, collecting it for the log
+fn relay_upstream(cli_type: CliType, response: reqwest::Response, idle_timeout: Duration) -> (ByteStream, RelayedStream) {
     // 使用共享状态收集chunks，确保即使stream被提前终止也能记录日志
     // 优化：只存储原始chunks，后台任务再解析（避免重复解析）
     let collected_chunks = Arc::new(Mutex::new(Vec::<Bytes>::new()));
     let collected_chunks_for_stream = collected_chunks.clone();
     
     // 创建channel用于通知stream结束
-    let (stream_end_tx, mut stream_end_rx) = mpsc::channel::<()>(1);
+    let (stream_end_tx, stream_end_rx) = mpsc::channel::<()>(1);
 
     // 收集完整内容的上限（10MB），用于解析token；存储时再截断到100KB
     const MAX_COLLECT_SIZE: usize = 10 * 1024 * 1024;
 
     let stream = async_stream::stream! {
         let mut byte_stream = response.bytes_stream();
-        let idle_timeout = timeouts.idle_timeout;
         let mut chunk_count = 0usize;
         let mut total_bytes = 0usize;
         let mut collected_bytes = 0usize;
@@ -454,7 +1132,7 @@ async fn handle_streaming_request(
                 Ok(Some(Err(e))) => {
                     tracing::error!(
                         "[{}] Stream error after {} chunks, {} bytes: {}",
-                        cli_type, chunk_count, total_bytes, e
+                        cli_type, chunk_count, total_bytes, e.without_url()
                     );
                     break;
                 }
@@ -487,25 +1165,65 @@ async fn handle_streaming_request(
         let _ = stream_end_tx.send(()).await;
     };
 
+    (Box::pin(stream), RelayedStream { chunks: collected_chunks, ended: stream_end_rx })
+}
+
+/// Mark the log of a stream that stopped before completing: a cancel_request call is logged
+/// as request_cancelled, anything else means the client went away
+fn mark_stream_cancelled(log_info: &mut RequestLogInfo, cancel_requested: bool) {
+    let (kind, message) = if cancel_requested {
+        (ERROR_KIND_REQUEST_CANCELLED, "Request cancelled by the gateway")
+    } else {
+        (ERROR_KIND_CLIENT_CANCELLED, "Client disconnected before the stream completed")
+    };
+    log_info.error_kind = Some(kind.to_string());
+    log_info.error_message = Some(message.to_string());
+}
+
+/// Wrap the upstream body: forward chunks with the idle timeout and record the log once the stream ends
+fn stream_upstream_body(
+    ctx: StreamContext,
+    response: reqwest::Response,
+    idle_timeout: Duration,
+    mut log_info: RequestLogInfo,
+) -> ByteStream {
+    let cli_type = ctx.cli_type;
+    let status = response.status();
+    let resp_headers = response.headers().clone();
+
+    // Store provider response info
+    log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers));
+
+    let is_success = status.is_success();
+    let (stream, relayed) = relay_upstream(cli_type, response, idle_timeout);
+
     // Spawn后台任务记录日志 - 等待stream结束通知或超时
-    let log_state = state.clone();
-    let log_provider_name = provider_name.to_string();
-    let log_model_id = model_id.map(|s| s.to_string());
-    let log_client_method = client_method.to_string();
-    let log_client_path = client_path.to_string();
-    let log_provider_id = provider_id;
+    let log_state = ctx.state.clone();
+    let log_provider_name = ctx.provider_name;
+    let log_model_id = ctx.model_id;
+    let log_client_method = ctx.client_method;
+    let log_client_path = ctx.client_path;
+    let log_provider_id = ctx.provider_id;
     let log_status = status;
-    let log_resp_headers = resp_headers.clone();
+    let log_resp_headers = resp_headers;
     let log_is_success = is_success;
+    let start_time = ctx.start_time;
+    let cancel_requested = ctx.cancel_requested;
     
     tokio::spawn(async move {
         // 等待stream结束通知（已验证可靠，无需超时兜底）
-        let _ = stream_end_rx.recv().await;
+        let (chunks, completed) = relayed.finish().await;
+        let cancelled = !completed;
         tracing::debug!("[{}] Received stream end notification", cli_type);
-        
-        // 读取收集的chunks
-        let chunks = collected_chunks.lock().await.clone();
-        drop(collected_chunks);  // 立即释放Arc引用
+        // The flag stays readable after the request's guard (the sender) is dropped
+        let cancel_requested = *cancel_requested.borrow();
+        if cancelled {
+            tracing::warn!(
+                "[{}] Stream stopped before completing ({}), upstream request cancelled",
+                cli_type,
+                if cancel_requested { "cancel_request" } else { "client disconnected" }
+            );
+        }
         
         // 一次性解析（避免重复解析，提升性能）
         let full_body: Vec<u8> = chunks.iter().flat_map(|c| c.iter()).copied().collect();
@@ -516,12 +1234,17 @@ async fn handle_streaming_request(
             cli_type, chunk_count, full_body.len()
         );
         
+        // 压缩的响应先解压，日志和 token 解析都使用解压后的内容（客户端收到的仍是原始编码）
+        let content_encoding = log_resp_headers.get("content-encoding")
+            .and_then(|v| v.to_str().ok());
+        let decompressed_body = maybe_decompress(&full_body, content_encoding);
+
         // 解析token usage
         let mut usage = TokenUsage::default();
-        if !full_body.is_empty() {
+        if !decompressed_body.is_empty() {
             // SSE 格式需要逐行解析，不能直接解析整个body
             // 注意：流式响应可能有多个usage更新，应该使用最后一个值
-            let body_str = String::from_utf8_lossy(&full_body);
+            let body_str = String::from_utf8_lossy(&decompressed_body);
             for line in body_str.lines() {
                 if line.starts_with("data:") {
                     // 提取 data: 后面的 JSON
@@ -542,15 +1265,17 @@ async fn handle_streaming_request(
         );
         
         // Update log info with response body
-        let content_encoding = log_resp_headers.get("content-encoding")
-            .and_then(|v| v.to_str().ok());
-        let decompressed_body = maybe_decompress(&full_body, content_encoding);
         let mut final_log_info = log_info;
-        final_log_info.provider_body = Some(truncate_body(&decompressed_body));
+        final_log_info.provider_body = Some(body_text(&decompressed_body));
+        if cancelled {
+            mark_stream_cancelled(&mut final_log_info, cancel_requested);
+        }
         
         // Record stats
         let elapsed = start_time.elapsed().as_millis() as i64;
-        if log_is_success {
+        if cancelled {
+            // Not the provider's fault: leave its failure counters untouched
+        } else if log_is_success {
             if let Ok(had_failures) = provider_service::record_success(&log_state.db, log_provider_id).await {
                 if had_failures {
                     let _ = stats_service::record_system_log(
@@ -560,22 +1285,17 @@ async fn handle_streaming_request(
                     ).await;
                 }
             }
-        } else if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&log_state.db, log_provider_id).await {
-            if was_blacklisted {
-                let _ = stats_service::record_system_log(
-                    &log_state.log_db,
-                    "provider_blacklisted",
-                    &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
-                ).await;
-            }
+        } else {
+            record_response_failure(&log_state, log_provider_id, log_status, &log_resp_headers, &mut final_log_info).await;
         }
         
         record_request_stats(
             &log_state,
+            log_provider_id,
             cli_type,
             &log_provider_name,
             log_model_id.as_deref(),
-            Some(log_status.as_u16()),
+            Some(stream_log_status(log_status, cancelled)),
             elapsed,
             usage.input_tokens,
             usage.output_tokens,
@@ -587,9 +1307,104 @@ async fn handle_streaming_request(
         tracing::info!("[{}] Delayed log recording completed", cli_type);
     });
 
-    Ok(builder
-        .body(Body::from_stream(stream))
-        .unwrap())
+    Box::pin(stream)
+}
+
+/// SSE heartbeat comment sent while waiting for the upstream
+const SSE_PING: &[u8] = b": ping\n\n";
+
+/// Send `: ping` comments every `interval` until `pending` finishes, then forward the stream
+/// `then` builds from its result. Heartbeats stop before the first real byte, so they never
+/// interleave with events. Dropping the stream (client gone) aborts `pending`.
+fn heartbeat_then<T, S, F>(mut pending: tokio::task::JoinHandle<T>, interval: Duration, then: F) -> ByteStream
+where
+    T: Send + 'static,
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+    F: FnOnce(Result<T, tokio::task::JoinError>) -> S + Send + 'static,
+{
+    // Dropping a JoinHandle doesn't cancel the task: abort the upstream request if the client goes away
+    let _abort_guard = AbortOnDrop(pending.abort_handle());
+
+    Box::pin(async_stream::stream! {
+        let _abort_guard = _abort_guard;
+        let ping = Bytes::from_static(SSE_PING);
+        yield Ok::<Bytes, std::io::Error>(ping.clone());
+
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await; // first tick completes immediately
+        let result = loop {
+            let finished = tokio::select! {
+                res = &mut pending => Some(res),
+                _ = ticker.tick() => None,
+            };
+            match finished {
+                Some(res) => break res,
+                None => yield Ok::<Bytes, std::io::Error>(ping.clone()),
+            }
+        };
+
+        let mut rest = std::pin::pin!(then(result));
+        while let Some(item) = rest.next().await {
+            yield item;
+        }
+    })
+}
+
+/// SSE response used once the keepalive interval elapsed before the upstream answered.
+/// Sends `: ping` comments until the upstream responds, then forwards its data untouched.
+fn keepalive_stream(
+    ctx: StreamContext,
+    send_task: tokio::task::JoinHandle<Result<reqwest::Result<reqwest::Response>, tokio::time::error::Elapsed>>,
+    interval: Duration,
+    idle_timeout: Duration,
+    log_info: RequestLogInfo,
+) -> ByteStream {
+    heartbeat_then(send_task, interval, move |result| async_stream::stream! {
+        match result {
+            Ok(Ok(Ok(response))) => {
+                let status = response.status();
+                let content_encoding = response.headers().get("content-encoding")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|s| s.to_string());
+                let mut body = stream_upstream_body(ctx, response, idle_timeout, log_info);
+                if status.is_success() {
+                    while let Some(item) = body.next().await {
+                        yield item;
+                    }
+                } else {
+                    // Status line is already committed, so report the upstream error as an SSE event
+                    let mut error_body = Vec::new();
+                    while let Some(Ok(chunk)) = body.next().await {
+                        error_body.extend_from_slice(&chunk);
+                    }
+                    let error_body = maybe_decompress(&error_body, content_encoding.as_deref());
+                    let payload = serde_json::json!({
+                        "error": format!("Upstream HTTP {}", status.as_u16()),
+                        "body": String::from_utf8_lossy(&error_body),
+                    });
+                    yield Ok::<Bytes, std::io::Error>(Bytes::from(format!("event: error\ndata: {}\n\n", payload)));
+                }
+            }
+            Ok(Ok(Err(e))) => {
+                // The URL may carry the API key (query-parameter auth), so it is never reported
+                let e = e.without_url();
+                tracing::error!(error = %e, "Upstream request failed");
+                record_stream_failure(&ctx, log_info, format!("Upstream error: {}", e)).await;
+                let payload = serde_json::json!({ "error": format!("Upstream error: {}", e) });
+                yield Ok::<Bytes, std::io::Error>(Bytes::from(format!("event: error\ndata: {}\n\n", payload)));
+            }
+            Ok(Err(_)) => {
+                tracing::error!("First byte timeout");
+                record_stream_failure(&ctx, log_info, "First byte timeout".to_string()).await;
+                yield Ok::<Bytes, std::io::Error>(Bytes::from("event: error\ndata: {\"error\": \"First byte timeout\"}\n\n"));
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Upstream request task failed");
+                record_stream_failure(&ctx, log_info, format!("Upstream request task failed: {}", e)).await;
+                yield Ok::<Bytes, std::io::Error>(Bytes::from("event: error\ndata: {\"error\": \"Internal error\"}\n\n"));
+            }
+        }
+    })
 }
 
 async fn handle_non_streaming_request(
@@ -603,6 +1418,7 @@ async fn handle_non_streaming_request(
     client_path: &str,
     start_time: Instant,
     timeouts: TimeoutConfig,
+    response_transform: Option<&str>,
     mut log_info: RequestLogInfo,
 ) -> Result<Response<Body>, StatusCode> {
     // Send request with timeout
@@ -614,6 +1430,8 @@ async fn handle_non_streaming_request(
     {
         Ok(Ok(resp)) => resp,
         Ok(Err(e)) => {
+            // The URL may carry the API key (query-parameter auth), so it is never reported
+            let e = e.without_url();
             tracing::error!(error = %e, "Upstream request failed");
             if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
                 if was_blacklisted {
@@ -622,11 +1440,13 @@ async fn handle_non_streaming_request(
                         "provider_blacklisted",
                         &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
                     ).await;
+                    webhook_service::notify_once(&state.db, "provider_blacklisted", &prov_name, Some(&prov_name), "连续失败次数达到阈值");
                 }
             }
             log_info.error_message = Some(format!("Upstream error: {}", e));
             record_request_stats(
                 state,
+                provider_id,
                 cli_type,
                 provider_name,
                 model_id,
@@ -654,11 +1474,13 @@ async fn handle_non_streaming_request(
                         "provider_blacklisted",
                         &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
                     ).await;
+                    webhook_service::notify_once(&state.db, "provider_blacklisted", &prov_name, Some(&prov_name), "连续失败次数达到阈值");
                 }
             }
             log_info.error_message = Some("Request timeout".to_string());
             record_request_stats(
                 state,
+                provider_id,
                 cli_type,
                 provider_name,
                 model_id,
@@ -690,6 +1512,7 @@ async fn handle_non_streaming_request(
     let body_bytes = match response.bytes().await {
         Ok(bytes) => bytes,
         Err(e) => {
+            let e = e.without_url();
             tracing::error!(error = %e, "Failed to read response body");
             if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
                 if was_blacklisted {
@@ -698,11 +1521,13 @@ async fn handle_non_streaming_request(
                         "provider_blacklisted",
                         &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
                     ).await;
+                    webhook_service::notify_once(&state.db, "provider_blacklisted", &prov_name, Some(&prov_name), "连续失败次数达到阈值");
                 }
             }
             log_info.error_message = Some(format!("Failed to read response body: {}", e));
             record_request_stats(
                 state,
+                provider_id,
                 cli_type,
                 provider_name,
                 model_id,
@@ -725,7 +1550,18 @@ async fn handle_non_streaming_request(
     let decompressed_body = maybe_decompress(&body_bytes, content_encoding);
 
     // Store response body for logging (use decompressed version)
-    log_info.provider_body = Some(truncate_body(&decompressed_body));
+    log_info.provider_body = Some(body_text(&decompressed_body));
+
+    // Provider response transform (successful non-streaming responses only; streams pass through).
+    // Logging and usage keep the provider's original body.
+    let transformed_body = if is_success {
+        crate::services::proxy::apply_json_patch(&decompressed_body, response_transform)
+    } else {
+        None
+    };
+    if transformed_body.is_some() {
+        tracing::debug!(provider = %provider_name, cli_type = %cli_type, "Applied provider response transform");
+    }
 
     // Parse token usage (use decompressed body)
     let mut usage = TokenUsage::default();
@@ -742,20 +1578,15 @@ async fn handle_non_streaming_request(
                 ).await;
             }
         }
-    } else if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
-        if was_blacklisted {
-            let _ = stats_service::record_system_log(
-                &state.log_db,
-                "provider_blacklisted",
-                &format!("服务商 {} 因连续失败已被加入黑名单", prov_name),
-            ).await;
-        }
+    } else {
+        record_response_failure(state, provider_id, status, &resp_headers, &mut log_info).await;
     }
 
     // Record stats
     let elapsed = start_time.elapsed().as_millis() as i64;
     record_request_stats(
         state,
+        provider_id,
         cli_type,
         provider_name,
         model_id,
@@ -774,6 +1605,12 @@ async fn handle_non_streaming_request(
         .status(StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::OK));
 
     for (name, value) in resp_headers.iter() {
+        // A transformed body is sent uncompressed, so the original framing no longer applies
+        if transformed_body.is_some()
+            && matches!(name.as_str(), "content-encoding" | "content-length" | "transfer-encoding")
+        {
+            continue;
+        }
         if let Ok(header_name) = axum::http::HeaderName::from_bytes(name.as_str().as_bytes()) {
             if let Ok(header_value) = axum::http::HeaderValue::from_bytes(value.as_bytes()) {
                 builder = builder.header(header_name, header_value);
@@ -782,11 +1619,16 @@ async fn handle_non_streaming_request(
     }
     builder = builder.header("X-CCG-Provider", provider_name);
 
-    Ok(builder.body(Body::from(body_bytes)).unwrap())
+    let body = match transformed_body {
+        Some(body) => Body::from(body),
+        None => Body::from(body_bytes),
+    };
+    Ok(builder.body(body).unwrap())
 }
 
 async fn record_request_stats(
     state: &Arc<AppState>,
+    provider_id: i64,
     cli_type: CliType,
     provider_name: &str,
     model_id: Option<&str>,
@@ -801,6 +1643,12 @@ async fn record_request_stats(
     // Derive success from status_code (200-299 = success)
     let success = status_code.map(|code| (200..300).contains(&code)).unwrap_or(false);
 
+    // Feed the adaptive routing score (client cancellations say nothing about the provider)
+    let cancelled = log_info.as_ref().and_then(|i| i.error_kind.as_deref()) == Some(ERROR_KIND_CLIENT_CANCELLED);
+    if !cancelled {
+        state.scores.record(provider_id, success, elapsed_ms);
+    }
+
     // Record to request_logs
     let _ = stats_service::record_request_log(
         &state.log_db,
@@ -817,7 +1665,7 @@ async fn record_request_stats(
     )
     .await;
 
-    // Record to usage_daily
+    // Record to usage_daily / usage_buckets
     let _ = stats_service::record_request(
         &state.log_db,
         provider_name,
@@ -834,66 +1682,37 @@ pub async fn list_providers(
     State(state): State<Arc<AppState>>,
     Query(query): Query<ProviderQuery>,
 ) -> Result<Json<Vec<ProviderResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    let providers = if let Some(ct) = query.cli_type {
-        sqlx::query_as::<_, Provider>(
-            "SELECT * FROM providers WHERE cli_type = ? ORDER BY sort_order, id",
-        )
-        .bind(&ct)
-        .fetch_all(&state.db)
-        .await
-    } else {
-        sqlx::query_as::<_, Provider>("SELECT * FROM providers ORDER BY sort_order, id")
-            .fetch_all(&state.db)
-            .await
-    };
-
-    providers
-        .map(|ps| Json(ps.into_iter().map(ProviderResponse::from).collect()))
-        .map_err(db_error)
+    crate::commands::get_providers_impl(
+        &state.db,
+        &state.log_db,
+        &state.scores,
+        query.cli_type,
+        query.with_stats.unwrap_or(false),
+        query.search,
+    )
+    .await
+    .map(Json)
+    .map_err(command_error)
 }
 
 pub async fn get_provider_handler(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
 ) -> Result<Json<ProviderResponse>, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
-        .bind(id)
-        .fetch_optional(&state.db)
+    crate::commands::get_provider_impl(&state.db, &state.scores, id)
         .await
-        .map_err(db_error)?
-        .map(ProviderResponse::from)
         .map(Json)
-        .ok_or_else(|| error_response("Provider not found"))
+        .map_err(command_error)
 }
 
 pub async fn create_provider_handler(
     State(state): State<Arc<AppState>>,
     Json(input): Json<ProviderCreate>,
 ) -> Result<Json<ProviderResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let now = chrono::Utc::now().timestamp();
-    let cli_type = input.cli_type.unwrap_or_else(|| "claude_code".to_string());
-
-    let result = sqlx::query(
-        r#"
-        INSERT INTO providers (cli_type, name, base_url, api_key, enabled, failure_threshold, blacklist_minutes, consecutive_failures, sort_order, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?, 0, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM providers), ?, ?)
-        "#,
-    )
-    .bind(&cli_type)
-    .bind(&input.name)
-    .bind(&input.base_url)
-    .bind(&input.api_key)
-    .bind(input.enabled.unwrap_or(true) as i64)
-    .bind(input.failure_threshold.unwrap_or(3))
-    .bind(input.blacklist_minutes.unwrap_or(10))
-    .bind(now)
-    .bind(now)
-    .execute(&state.db)
-    .await
-    .map_err(db_error)?;
-
-    let id = result.last_insert_rowid();
-    get_provider_handler(State(state), Path(id)).await
+    crate::commands::create_provider_impl(&state.db, &state.log_db, &state.scores, &state.config, input)
+        .await
+        .map(Json)
+        .map_err(command_error)
 }
 
 pub async fn update_provider_handler(
@@ -901,93 +1720,30 @@ pub async fn update_provider_handler(
     Path(id): Path<i64>,
     Json(input): Json<ProviderUpdate>,
 ) -> Result<Json<ProviderResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let now = chrono::Utc::now().timestamp();
-    let mut updates = vec!["updated_at = ?".to_string()];
-    let mut has_updates = false;
-
-    if input.name.is_some() {
-        updates.push("name = ?".to_string());
-        has_updates = true;
-    }
-    if input.base_url.is_some() {
-        updates.push("base_url = ?".to_string());
-        has_updates = true;
-    }
-    if input.api_key.is_some() {
-        updates.push("api_key = ?".to_string());
-        has_updates = true;
-    }
-    if input.enabled.is_some() {
-        updates.push("enabled = ?".to_string());
-        has_updates = true;
-    }
-    if input.failure_threshold.is_some() {
-        updates.push("failure_threshold = ?".to_string());
-        has_updates = true;
-    }
-    if input.blacklist_minutes.is_some() {
-        updates.push("blacklist_minutes = ?".to_string());
-        has_updates = true;
-    }
-
-    if !has_updates {
-        return get_provider_handler(State(state), Path(id)).await;
-    }
-
-    let query = format!("UPDATE providers SET {} WHERE id = ?", updates.join(", "));
-    let mut q = sqlx::query(&query).bind(now);
-
-    if let Some(ref name) = input.name {
-        q = q.bind(name);
-    }
-    if let Some(ref base_url) = input.base_url {
-        q = q.bind(base_url);
-    }
-    if let Some(ref api_key) = input.api_key {
-        q = q.bind(api_key);
-    }
-    if let Some(enabled) = input.enabled {
-        q = q.bind(enabled as i64);
-    }
-    if let Some(failure_threshold) = input.failure_threshold {
-        q = q.bind(failure_threshold);
-    }
-    if let Some(blacklist_minutes) = input.blacklist_minutes {
-        q = q.bind(blacklist_minutes);
-    }
-
-    q.bind(id)
-        .execute(&state.db)
+    crate::commands::update_provider_impl(&state.db, &state.log_db, &state.scores, &state.config, id, input)
         .await
-        .map_err(db_error)?;
-
-    get_provider_handler(State(state), Path(id)).await
+        .map(Json)
+        .map_err(command_error)
 }
 
 pub async fn delete_provider_handler(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query("DELETE FROM providers WHERE id = ?")
-        .bind(id)
-        .execute(&state.db)
+    crate::commands::delete_provider_impl(&state.db, &state.log_db, &state.upstream_clients, id)
         .await
-        .map_err(db_error)?;
+        .map_err(command_error)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn reorder_providers_handler(
     State(state): State<Arc<AppState>>,
+    Query(query): Query<ProviderQuery>,
     Json(ids): Json<Vec<i64>>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    for (idx, id) in ids.iter().enumerate() {
-        sqlx::query("UPDATE providers SET sort_order = ? WHERE id = ?")
-            .bind(idx as i64)
-            .bind(id)
-            .execute(&state.db)
-            .await
-            .map_err(db_error)?;
-    }
+    crate::services::provider::reorder(&state.db, &ids, query.cli_type.as_deref())
+        .await
+        .map_err(command_error)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -1003,27 +1759,84 @@ pub async fn reset_provider_failures_handler(
     Ok(StatusCode::NO_CONTENT)
 }
 
-// Settings
-#[derive(Debug, Deserialize)]
-pub struct GatewaySettingsUpdate {
-    pub debug_log: bool,
+// CLI settings: toggle the CLI and/or save its default config
+pub async fn update_cli_settings_handler(
+    State(state): State<Arc<AppState>>,
+    Path(cli_type): Path<String>,
+    Json(input): Json<CliSettingsUpdate>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let changed = crate::commands::update_cli_settings_impl(&state.db, &state.log_db, cli_type, input)
+        .await
+        .map_err(|e| {
+            let error = match (e.line, e.column) {
+                (Some(line), Some(column)) => format!("{} (line {}, column {})", e.message, line, column),
+                _ => e.message,
+            };
+            (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }))
+        })?;
+    if let Some(changed) = changed {
+        use tauri::Emitter;
+        let _ = state.app.emit("cli-enabled-changed", changed);
+    }
+    Ok(StatusCode::NO_CONTENT)
 }
 
+// Settings
 #[derive(Debug, Serialize)]
 pub struct GatewaySettingsResponse {
     pub debug_log: bool,
+    pub max_timeout_override_ms: i64,
+    pub sse_keepalive_ms: i64,
+    pub body_capture_policy: String,
+    pub routing_strategy: String,
+    pub shadow_provider_id: Option<i64>,
+    pub shadow_sample_rate: f64,
+    pub log_sample_rate: f64,
+    pub log_body_format: String,
+    pub log_body_max_depth: i64,
+    pub log_body_max_array_items: i64,
+    pub log_body_max_string_chars: i64,
+    pub validate_on_enable: bool,
+    pub max_concurrency: i64,
+    pub shutdown_grace_secs: i64,
+    pub allow_force_provider: bool,
+    pub admin_api_enabled: bool,
+    pub upstream_compression: String,
+    pub retry_after_max_secs: i64,
+    pub key_expiry_warn_days: i64,
+    pub auto_pause_expired_keys: bool,
 }
 
 pub async fn get_gateway_settings(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<GatewaySettingsResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let settings = sqlx::query_as::<_, GatewaySettings>("SELECT debug_log FROM gateway_settings WHERE id = 1")
+    let settings = sqlx::query_as::<_, GatewaySettings>("SELECT *, admin_token IS NOT NULL AS admin_api_enabled FROM gateway_settings WHERE id = 1")
         .fetch_one(&state.db)
         .await
         .map_err(db_error)?;
 
     Ok(Json(GatewaySettingsResponse {
         debug_log: settings.debug_log != 0,
+        max_timeout_override_ms: settings.max_timeout_override_ms,
+        sse_keepalive_ms: settings.sse_keepalive_ms,
+        body_capture_policy: settings.body_capture_policy,
+        routing_strategy: settings.routing_strategy,
+        shadow_provider_id: settings.shadow_provider_id,
+        shadow_sample_rate: settings.shadow_sample_rate,
+        log_sample_rate: settings.log_sample_rate,
+        log_body_format: settings.log_body_format,
+        log_body_max_depth: settings.log_body_max_depth,
+        log_body_max_array_items: settings.log_body_max_array_items,
+        log_body_max_string_chars: settings.log_body_max_string_chars,
+        validate_on_enable: settings.validate_on_enable != 0,
+        max_concurrency: settings.max_concurrency,
+        shutdown_grace_secs: settings.shutdown_grace_secs,
+        allow_force_provider: settings.allow_force_provider != 0,
+        admin_api_enabled: settings.admin_api_enabled != 0,
+        upstream_compression: settings.upstream_compression,
+        retry_after_max_secs: settings.retry_after_max_secs,
+        key_expiry_warn_days: settings.key_expiry_warn_days,
+        auto_pause_expired_keys: settings.auto_pause_expired_keys != 0,
     }))
 }
 
@@ -1031,13 +1844,9 @@ pub async fn update_gateway_settings_handler(
     State(state): State<Arc<AppState>>,
     Json(input): Json<GatewaySettingsUpdate>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    let now = chrono::Utc::now().timestamp();
-    sqlx::query("UPDATE gateway_settings SET debug_log = ?, updated_at = ? WHERE id = 1")
-        .bind(input.debug_log as i64)
-        .bind(now)
-        .execute(&state.db)
+    crate::services::settings::update_gateway(&state.db, &input)
         .await
-        .map_err(db_error)?;
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -1094,7 +1903,7 @@ pub async fn get_request_logs(
 
     let (items, total) = if let Some(ct) = query.cli_type {
         let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs WHERE cli_type = ? ORDER BY id DESC LIMIT ? OFFSET ?",
+            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, is_shadow, client_ip FROM request_logs WHERE cli_type = ? ORDER BY id DESC LIMIT ? OFFSET ?",
         )
         .bind(&ct)
         .bind(page_size)
@@ -1112,7 +1921,7 @@ pub async fn get_request_logs(
         (items, total.0)
     } else {
         let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs ORDER BY id DESC LIMIT ? OFFSET ?",
+            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, is_shadow, client_ip FROM request_logs ORDER BY id DESC LIMIT ? OFFSET ?",
         )
         .bind(page_size)
         .bind(offset)
@@ -1151,7 +1960,7 @@ pub async fn get_request_log_detail(
     Path(id): Path<i64>,
 ) -> Result<Json<RequestLogDetail>, (StatusCode, Json<ErrorResponse>)> {
     sqlx::query_as::<_, RequestLogDetail>(
-        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, error_message FROM request_logs WHERE id = ?",
+        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, error_message, effective_timeout_ms, error_kind, is_shadow, selection_reason, client_ip FROM request_logs WHERE id = ?",
     )
     .bind(id)
     .fetch_optional(&state.log_db)
@@ -1169,6 +1978,8 @@ pub struct SystemLogQuery {
     #[serde(default = "default_page_size")]
     pub page_size: i64,
     pub event_type: Option<String>,
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
 }
 
 pub async fn get_system_logs_handler(
@@ -1188,23 +1999,41 @@ pub async fn get_system_logs_handler(
         sql.push_str(" AND event_type = ?");
         count_sql.push_str(" AND event_type = ?");
     }
+    if query.start_time.is_some() {
+        sql.push_str(" AND created_at >= ?");
+        count_sql.push_str(" AND created_at >= ?");
+    }
+    if query.end_time.is_some() {
+        sql.push_str(" AND created_at <= ?");
+        count_sql.push_str(" AND created_at <= ?");
+    }
 
     sql.push_str(" ORDER BY id DESC LIMIT ? OFFSET ?");
-    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql)
-        .bind(page_size)
-        .bind(offset);
+    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql);
 
     if let Some(ref et) = query.event_type {
         q = q.bind(et);
     }
+    if let Some(st) = query.start_time {
+        q = q.bind(st);
+    }
+    if let Some(et) = query.end_time {
+        q = q.bind(et);
+    }
 
-    let items = q.fetch_all(pool).await.map_err(db_error)?;
+    let items = q.bind(page_size).bind(offset).fetch_all(pool).await.map_err(db_error)?;
 
     // Get total count
     let mut count_q = sqlx::query_as::<_, (i64,)>(&count_sql);
     if let Some(ref et) = query.event_type {
         count_q = count_q.bind(et);
     }
+    if let Some(st) = query.start_time {
+        count_q = count_q.bind(st);
+    }
+    if let Some(et) = query.end_time {
+        count_q = count_q.bind(et);
+    }
     let (total,) = count_q.fetch_one(pool).await.map_err(db_error)?;
 
     Ok(Json(SystemLogListResponse {
@@ -1231,41 +2060,29 @@ pub struct StatsQuery {
     pub start_date: Option<String>,
     pub end_date: Option<String>,
     pub cli_type: Option<String>,
+    pub provider_name: Option<String>,
+    pub timezone: Option<String>,
 }
 
 pub async fn get_daily_stats(
     State(state): State<Arc<AppState>>,
     Query(query): Query<StatsQuery>,
 ) -> Result<Json<Vec<DailyStats>>, (StatusCode, Json<ErrorResponse>)> {
-    let pool = &state.log_db;
-
-    let mut sql = "SELECT * FROM usage_daily WHERE 1=1".to_string();
-    if query.start_date.is_some() {
-        sql.push_str(" AND usage_date >= ?");
-    }
-    if query.end_date.is_some() {
-        sql.push_str(" AND usage_date <= ?");
-    }
-    if query.cli_type.is_some() {
-        sql.push_str(" AND cli_type = ?");
-    }
-    sql.push_str(" ORDER BY usage_date DESC");
-
-    let mut q = sqlx::query_as::<_, DailyStats>(&sql);
-    if let Some(ref sd) = query.start_date {
-        q = q.bind(sd);
-    }
-    if let Some(ref ed) = query.end_date {
-        q = q.bind(ed);
-    }
-    if let Some(ref ct) = query.cli_type {
-        q = q.bind(ct);
-    }
+    let bad_request = |e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let tz = stats_service::StatsTimezone::parse(query.timezone.as_deref()).map_err(bad_request)?;
+    let start_date = stats_service::parse_stats_date(query.start_date.as_deref()).map_err(bad_request)?;
+    let end_date = stats_service::parse_stats_date(query.end_date.as_deref()).map_err(bad_request)?;
 
-    q.fetch_all(pool)
-        .await
-        .map(Json)
-        .map_err(db_error)
+    stats_service::query_daily_stats(
+        &state.log_db,
+        start_date,
+        end_date,
+        query.cli_type.as_deref(),
+        tz,
+    )
+    .await
+    .map(Json)
+    .map_err(db_error)
 }
 
 pub async fn get_system_status_handler(
@@ -1274,7 +2091,7 @@ pub async fn get_system_status_handler(
     Ok(Json(SystemStatus {
         status: "running".to_string(),
         port: 7788,
-        uptime: 0,
+        uptime: crate::config::uptime_secs(),
         version: env!("CARGO_PKG_VERSION").to_string(),
     }))
 }
@@ -1291,7 +2108,7 @@ pub async fn get_all_settings(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<AllSettingsResponse>, (StatusCode, Json<ErrorResponse>)> {
     // Get gateway settings
-    let gateway_settings = sqlx::query_as::<_, GatewaySettings>("SELECT debug_log FROM gateway_settings WHERE id = 1")
+    let gateway_settings = sqlx::query_as::<_, GatewaySettings>("SELECT *, admin_token IS NOT NULL AS admin_api_enabled FROM gateway_settings WHERE id = 1")
         .fetch_one(&state.db)
         .await
         .map_err(db_error)?;
@@ -1318,6 +2135,26 @@ pub async fn get_all_settings(
     Ok(Json(AllSettingsResponse {
         gateway: GatewaySettingsResponse {
             debug_log: gateway_settings.debug_log != 0,
+            max_timeout_override_ms: gateway_settings.max_timeout_override_ms,
+            sse_keepalive_ms: gateway_settings.sse_keepalive_ms,
+            body_capture_policy: gateway_settings.body_capture_policy,
+            routing_strategy: gateway_settings.routing_strategy,
+            shadow_provider_id: gateway_settings.shadow_provider_id,
+            shadow_sample_rate: gateway_settings.shadow_sample_rate,
+            log_sample_rate: gateway_settings.log_sample_rate,
+            log_body_format: gateway_settings.log_body_format,
+            log_body_max_depth: gateway_settings.log_body_max_depth,
+            log_body_max_array_items: gateway_settings.log_body_max_array_items,
+            log_body_max_string_chars: gateway_settings.log_body_max_string_chars,
+            validate_on_enable: gateway_settings.validate_on_enable != 0,
+            max_concurrency: gateway_settings.max_concurrency,
+            shutdown_grace_secs: gateway_settings.shutdown_grace_secs,
+            allow_force_provider: gateway_settings.allow_force_provider != 0,
+            admin_api_enabled: gateway_settings.admin_api_enabled != 0,
+            upstream_compression: gateway_settings.upstream_compression,
+            retry_after_max_secs: gateway_settings.retry_after_max_secs,
+            key_expiry_warn_days: gateway_settings.key_expiry_warn_days,
+            auto_pause_expired_keys: gateway_settings.auto_pause_expired_keys != 0,
         },
         timeouts: timeout_settings,
         cli_settings,
@@ -1325,82 +2162,26 @@ pub async fn get_all_settings(
 }
 
 // Get provider stats
-#[derive(Debug, Serialize)]
-pub struct ProviderStatsResponse {
-    pub provider_name: String,
-    pub cli_type: String,
-    pub total_requests: i64,
-    pub total_success: i64,
-    pub total_failure: i64,
-    pub success_rate: f64,
-    pub total_tokens: i64,
-}
-
 pub async fn get_provider_stats(
     State(state): State<Arc<AppState>>,
     Query(query): Query<StatsQuery>,
 ) -> Result<Json<Vec<ProviderStatsResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    let pool = &state.log_db;
-
-    let mut sql = r#"
-        SELECT
-            provider_name,
-            cli_type,
-            COUNT(*) as total_requests,
-            SUM(CASE WHEN status_code >= 200 AND status_code < 300 THEN 1 ELSE 0 END) as total_success,
-            SUM(CASE WHEN status_code IS NULL OR status_code < 200 OR status_code >= 300 THEN 1 ELSE 0 END) as total_failure,
-            SUM(input_tokens + output_tokens) as total_tokens
-        FROM request_logs
-        WHERE 1=1
-    "#.to_string();
-
-    if query.start_date.is_some() {
-        sql.push_str(" AND DATE(created_at, 'unixepoch') >= ?");
-    }
-    if query.end_date.is_some() {
-        sql.push_str(" AND DATE(created_at, 'unixepoch') <= ?");
-    }
-    if query.cli_type.is_some() {
-        sql.push_str(" AND cli_type = ?");
-    }
-
-    sql.push_str(" GROUP BY provider_name, cli_type ORDER BY total_requests DESC");
-
-    let mut q = sqlx::query_as::<_, (String, String, i64, i64, i64, i64)>(&sql);
-    if let Some(ref sd) = query.start_date {
-        q = q.bind(sd);
-    }
-    if let Some(ref ed) = query.end_date {
-        q = q.bind(ed);
-    }
-    if let Some(ref ct) = query.cli_type {
-        q = q.bind(ct);
-    }
-
-    let results = q.fetch_all(pool).await.map_err(db_error)?;
-
-    let stats = results
-        .into_iter()
-        .map(|(provider_name, cli_type, total_requests, total_success, total_failure, total_tokens)| {
-            let success_rate = if total_requests > 0 {
-                (total_success as f64 / total_requests as f64) * 100.0
-            } else {
-                0.0
-            };
+    let bad_request = |e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e }));
+    let tz = stats_service::StatsTimezone::parse(query.timezone.as_deref()).map_err(bad_request)?;
+    let start_date = stats_service::parse_stats_date(query.start_date.as_deref()).map_err(bad_request)?;
+    let end_date = stats_service::parse_stats_date(query.end_date.as_deref()).map_err(bad_request)?;
 
-            ProviderStatsResponse {
-                provider_name,
-                cli_type,
-                total_requests,
-                total_success,
-                total_failure,
-                success_rate,
-                total_tokens,
-            }
-        })
-        .collect();
-
-    Ok(Json(stats))
+    stats_service::query_provider_stats(
+        &state.log_db,
+        start_date,
+        end_date,
+        query.cli_type.as_deref(),
+        query.provider_name.as_deref(),
+        tz,
+    )
+    .await
+    .map(Json)
+    .map_err(db_error)
 }
 
 // MCP, Prompts, Sessions, Backup - placeholder implementations
@@ -1561,3 +2342,314 @@ pub async fn import_from_webdav_handler(
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
     Ok(Json(serde_json::json!({ "success": true, "message": "Not implemented" })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(stream: ByteStream) -> Vec<Bytes> {
+        stream.map(|item| item.unwrap()).collect().await
+    }
+
+    #[tokio::test]
+    async fn heartbeats_stop_once_first_event_passes() {
+        let pending = tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+        });
+        let stream = heartbeat_then(pending, Duration::from_millis(20), |result| {
+            assert!(result.is_ok());
+            async_stream::stream! {
+                yield Ok::<Bytes, std::io::Error>(Bytes::from_static(b"data: first\n\n"));
+                // Longer than the heartbeat interval: no ping may be slipped in here
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                yield Ok(Bytes::from_static(b"data: second\n\n"));
+            }
+        });
+
+        let items = collect(stream).await;
+        let first_event = items.iter().position(|b| b.starts_with(b"data:")).unwrap();
+        assert!(first_event >= 2, "expected heartbeats while waiting, got {:?}", items);
+        assert!(items[..first_event].iter().all(|b| b.as_ref() == SSE_PING));
+        assert_eq!(
+            &items[first_event..],
+            &[Bytes::from_static(b"data: first\n\n"), Bytes::from_static(b"data: second\n\n")]
+        );
+    }
+
+    #[tokio::test]
+    async fn heartbeat_sent_immediately_when_upstream_is_slow() {
+        let pending = tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+        });
+        let mut stream = heartbeat_then(pending, Duration::from_secs(60), |_| {
+            futures_util::stream::iter([Ok(Bytes::from_static(b"data: done\n\n"))])
+        });
+
+        assert_eq!(stream.next().await.unwrap().unwrap().as_ref(), SSE_PING);
+        assert_eq!(stream.next().await.unwrap().unwrap().as_ref(), b"data: done\n\n");
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dropping_the_stream_aborts_the_pending_request() {
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel::<()>();
+        let (alive_tx, alive_rx) = tokio::sync::oneshot::channel::<()>();
+        let pending = tokio::spawn(async move {
+            // Held until the task ends; an aborted task drops it without sending
+            let _alive = alive_tx;
+            let _ = started_tx.send(());
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+        let mut stream = heartbeat_then(pending, Duration::from_secs(60), |_| {
+            futures_util::stream::iter([Ok(Bytes::from_static(b"data: never\n\n"))])
+        });
+
+        assert_eq!(stream.next().await.unwrap().unwrap().as_ref(), SSE_PING);
+        started_rx.await.unwrap();
+        drop(stream);
+
+        let aborted = tokio::time::timeout(Duration::from_secs(1), alive_rx).await;
+        assert!(matches!(aborted, Ok(Err(_))), "pending request kept running after the client left");
+    }
+
+    #[test]
+    fn cancelled_stream_is_logged_as_a_failure() {
+        assert_eq!(stream_log_status(StatusCode::OK, false), 200);
+        assert_eq!(stream_log_status(StatusCode::OK, true), CLIENT_CLOSED_REQUEST);
+        assert!(!(200..300).contains(&stream_log_status(StatusCode::OK, true)));
+    }
+
+    /// An upstream that streams SSE chunks until its connection is closed; the receiver fires
+    /// once a write fails, i.e. once the gateway has dropped the upstream request
+    async fn endless_upstream() -> (reqwest::Response, tokio::sync::oneshot::Receiver<()>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed_tx, closed_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 4096];
+            let _ = socket.read(&mut request).await;
+            let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n";
+            if socket.write_all(head.as_bytes()).await.is_ok() {
+                let chunk = b"data: {}\n\n";
+                loop {
+                    let framed = [format!("{:x}\r\n", chunk.len()).as_bytes(), chunk, b"\r\n"].concat();
+                    if socket.write_all(&framed).await.is_err() || socket.flush().await.is_err() {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            }
+            let _ = closed_tx.send(());
+        });
+
+        let response = reqwest::Client::new().get(format!("http://{}/v1/messages", addr)).send().await.unwrap();
+        (response, closed_rx)
+    }
+
+    #[tokio::test]
+    async fn client_disconnect_mid_stream_cancels_upstream_and_is_logged() {
+        let (response, upstream_closed) = endless_upstream().await;
+        let (mut stream, relayed) = relay_upstream(CliType::ClaudeCode, response, Duration::from_secs(5));
+
+        // Some chunks reach the client before it goes away
+        for _ in 0..2 {
+            assert_eq!(stream.next().await.unwrap().unwrap().as_ref(), b"data: {}\n\n");
+        }
+        drop(stream);
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), upstream_closed).await;
+        assert!(matches!(closed, Ok(Ok(()))), "upstream kept streaming after the client left");
+
+        let (chunks, completed) = relayed.finish().await;
+        assert!(!completed);
+        assert!(chunks.len() >= 2);
+
+        let mut log_info = RequestLogInfo::default();
+        mark_stream_cancelled(&mut log_info, false);
+        assert_eq!(log_info.error_kind.as_deref(), Some(ERROR_KIND_CLIENT_CANCELLED));
+        assert_eq!(stream_log_status(StatusCode::OK, !completed), CLIENT_CLOSED_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn cancel_request_mid_stream_is_logged_as_request_cancelled() {
+        let (response, upstream_closed) = endless_upstream().await;
+        let (upstream, relayed) = relay_upstream(CliType::ClaudeCode, response, Duration::from_secs(5));
+
+        let registry = Arc::new(crate::services::active_requests::ActiveRequests::default());
+        let active = registry.register(ActiveRequestInfo {
+            request_id: "req-1".to_string(),
+            cli_type: "claude_code".to_string(),
+            provider_name: "p".to_string(),
+            model_id: None,
+            client_method: "POST".to_string(),
+            client_path: "/v1/messages".to_string(),
+            streaming: true,
+            started_at: 0,
+            elapsed_ms: 0,
+            cancelling: false,
+        });
+        let cancel_requested = active.cancel_signal();
+        let permit = Arc::new(crate::services::concurrency::ConcurrencyLimiter::default())
+            .try_acquire(1, 0, 0)
+            .unwrap();
+        let mut stream = cancellable_body(Body::from_stream(upstream), active, permit);
+
+        assert_eq!(stream.next().await.unwrap().unwrap().as_ref(), b"data: {}\n\n");
+        assert!(registry.cancel("req-1"));
+        let mut rest = Vec::new();
+        while let Some(item) = stream.next().await {
+            rest.push(item.unwrap());
+        }
+        assert!(rest.last().unwrap().starts_with(b"event: error\n"));
+        drop(stream);
+        assert!(registry.list().is_empty());
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), upstream_closed).await;
+        assert!(matches!(closed, Ok(Ok(()))), "upstream kept streaming after cancel_request");
+
+        let (_, completed) = relayed.finish().await;
+        assert!(!completed);
+        // Still readable after the guard (and with it the registry's sender) is gone
+        assert!(*cancel_requested.borrow());
+        let mut log_info = RequestLogInfo::default();
+        mark_stream_cancelled(&mut log_info, *cancel_requested.borrow());
+        assert_eq!(log_info.error_kind.as_deref(), Some(ERROR_KIND_REQUEST_CANCELLED));
+    }
+
+    const CLAUDE_MESSAGE: &[u8] = br#"{"id":"msg_1","type":"message","role":"assistant","model":"m","content":[{"type":"text","text":"Hi"}],"stop_reason":"end_turn","usage":{"input_tokens":7,"output_tokens":3}}"#;
+
+    fn encode(encoding: &str, body: &[u8]) -> Vec<u8> {
+        use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        match encoding {
+            "gzip" => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            }
+            "deflate" => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            }
+            "raw-deflate" => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            }
+            other => panic!("unknown encoding {}", other),
+        }
+    }
+
+    #[test]
+    fn compressed_bodies_are_decoded_for_logging_and_usage() {
+        for (encoding, header) in [("gzip", "gzip"), ("gzip", " X-GZIP "), ("deflate", "deflate"), ("raw-deflate", "deflate")] {
+            let decoded = maybe_decompress(&encode(encoding, CLAUDE_MESSAGE), Some(header));
+            assert_eq!(decoded, CLAUDE_MESSAGE, "{}", encoding);
+
+            let mut usage = TokenUsage::default();
+            parse_token_usage(&decoded, CliType::ClaudeCode, &mut usage);
+            assert_eq!((usage.input_tokens, usage.output_tokens), (7, 3), "{}", encoding);
+        }
+
+        // A body cut off by the capture cap still yields what decoded before the cut
+        let gzipped = encode("gzip", &CLAUDE_MESSAGE.repeat(50));
+        let partial = maybe_decompress(&gzipped[..gzipped.len() - 8], Some("gzip"));
+        assert!(!partial.is_empty() && CLAUDE_MESSAGE.repeat(50).starts_with(&partial));
+    }
+
+    #[test]
+    fn other_encodings_pass_through_unchanged() {
+        let gzipped = encode("gzip", CLAUDE_MESSAGE);
+        assert_eq!(maybe_decompress(&gzipped, None), gzipped);
+        assert_eq!(maybe_decompress(&gzipped, Some("br")), gzipped);
+        assert_eq!(maybe_decompress(CLAUDE_MESSAGE, Some("identity")), CLAUDE_MESSAGE);
+        // Not actually gzip: left as-is rather than dropped
+        assert_eq!(maybe_decompress(CLAUDE_MESSAGE, Some("gzip")), CLAUDE_MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn upstream_compression_setting_controls_accept_encoding() {
+        let db = crate::db::test_pool(false).await;
+        let provider = crate::db::test_provider(&db).await;
+        let provider = ProviderWithMaps { provider, model_maps: vec![] };
+
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("accept-encoding", "gzip, deflate".parse().unwrap());
+        let accept_encoding = |compression| {
+            let request = build_upstream_request(
+                &reqwest::Client::new(),
+                &provider,
+                CliType::ClaudeCode,
+                &axum::http::Method::POST,
+                &headers,
+                "/v1/messages",
+                CLAUDE_MESSAGE,
+                compression,
+            )
+            .builder
+            .build()
+            .unwrap();
+            request.headers().get("accept-encoding").map(|v| v.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(accept_encoding(UpstreamCompression::Passthrough).as_deref(), Some("gzip, deflate"));
+        assert_eq!(accept_encoding(UpstreamCompression::Identity).as_deref(), Some("identity"));
+    }
+
+    #[tokio::test]
+    async fn replay_decodes_gzip_and_passes_unknown_bodies_through() {
+        let gzipped_response = |body: &[u8]| {
+            Response::builder()
+                .header("content-encoding", "gzip")
+                .body(Body::from(encode("gzip", body)))
+                .unwrap()
+        };
+
+        let replayed = replay_as_sse(gzipped_response(CLAUDE_MESSAGE), CliType::ClaudeCode).await;
+        assert!(replayed.headers().get("content-encoding").is_none());
+        assert_eq!(replayed.headers()["content-type"], "text/event-stream");
+        let sse = axum::body::to_bytes(replayed.into_body(), usize::MAX).await.unwrap();
+        assert!(sse.starts_with(b"event: message_start\n"));
+
+        // Not a message the converter knows: the client gets the upstream bytes untouched
+        let unknown = br#"{"unexpected":true}"#;
+        let replayed = replay_as_sse(gzipped_response(unknown), CliType::ClaudeCode).await;
+        assert_eq!(replayed.headers()["content-encoding"], "gzip");
+        let body = axum::body::to_bytes(replayed.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, encode("gzip", unknown));
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_clamped_to_the_configured_maximum() {
+        let db = crate::db::test_pool(false).await;
+        let set_max = |secs: i64| {
+            let db = db.clone();
+            async move {
+                let input = GatewaySettingsUpdate { retry_after_max_secs: Some(secs), ..Default::default() };
+                crate::services::settings::update_gateway(&db, &input).await
+            }
+        };
+        let headers = |value: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::RETRY_AFTER, value.parse().unwrap());
+            headers
+        };
+
+        set_max(300).await.unwrap();
+        assert_eq!(rate_limit_retry_after(&db, &headers("60")).await, Some(60));
+        assert_eq!(rate_limit_retry_after(&db, &headers("7200")).await, Some(300));
+        assert_eq!(rate_limit_retry_after(&db, &headers("later")).await, None);
+        assert_eq!(rate_limit_retry_after(&db, &reqwest::header::HeaderMap::new()).await, None);
+
+        // 0 turns Retry-After handling off; the cap itself is bounded
+        set_max(0).await.unwrap();
+        assert_eq!(rate_limit_retry_after(&db, &headers("60")).await, None);
+        assert!(set_max(provider_service::MAX_RETRY_AFTER_SECS + 1).await.is_err());
+    }
+}
@@ -1,17 +1,31 @@
 pub mod handlers;
 
 use axum::{
-    routing::get,
+    http::StatusCode,
+    middleware,
+    routing::{get, put},
     Router,
 };
 use sqlx::SqlitePool;
 use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 
+use crate::services::active_requests::ActiveRequests;
+use crate::services::concurrency::ConcurrencyLimiter;
+use crate::services::proxy::UpstreamClients;
+use crate::services::routing::ProviderScores;
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: SqlitePool,
     pub log_db: SqlitePool,
+    pub scores: Arc<ProviderScores>,
+    pub upstream_clients: Arc<UpstreamClients>,
+    pub active_requests: Arc<ActiveRequests>,
+    pub concurrency: Arc<ConcurrencyLimiter>,
+    pub config: crate::config::Config,
+    /// Lets admin API handlers emit the same events as the Tauri commands
+    pub app: tauri::AppHandle,
 }
 
 pub fn create_router(state: AppState) -> Router {
@@ -20,13 +34,86 @@ pub fn create_router(state: AppState) -> Router {
         .allow_methods(Any)
         .allow_headers(Any);
 
-    // Desktop-only mode: No /api routes needed
-    // Frontend uses Tauri IPC instead of HTTP
-    // Only CLI proxy is required
+    let state = Arc::new(state);
+
+    // The desktop frontend uses Tauri IPC; /admin exposes a subset of the same operations
+    // for headless use, behind the admin token
+    let admin = Router::new()
+        .route("/providers", get(handlers::list_providers).post(handlers::create_provider_handler))
+        .route("/providers/:id", get(handlers::get_provider_handler).put(handlers::update_provider_handler))
+        .route("/cli/:cli_type", put(handlers::update_cli_settings_handler))
+        .route("/stats/daily", get(handlers::get_daily_stats))
+        .route("/stats/providers", get(handlers::get_provider_stats));
+    let admin = admin_router(admin, state.db.clone());
+
     Router::new()
         .route("/health", get(|| async { "ok" }))
+        .nest("/admin", admin)
         // Catch-all proxy route for CLI tools (Claude Code, Codex, Gemini)
         .fallback(handlers::proxy_handler_catchall)
         .layer(cors)
-        .with_state(Arc::new(state))
+        .with_state(state)
+}
+
+/// Put the admin routes behind the admin token. Unknown admin paths get a 404 so they never
+/// fall through to the proxy.
+fn admin_router<S: Clone + Send + Sync + 'static>(routes: Router<S>, db: SqlitePool) -> Router<S> {
+    routes
+        .route_layer(middleware::from_fn_with_state(db, handlers::require_admin_token))
+        .fallback(|| async { StatusCode::NOT_FOUND })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same layout as create_router, with stand-ins for the admin handlers and the proxy
+    async fn serve(db: SqlitePool) -> String {
+        let admin = admin_router(Router::new().route("/providers", get(|| async { "providers" })), db);
+        let app = Router::new()
+            .nest("/admin", admin)
+            .fallback(|| async { "proxied" });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    async fn get_status(url: &str, token: Option<&str>) -> u16 {
+        let mut request = reqwest::Client::new().get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.unwrap().status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn admin_routes_require_the_configured_token() {
+        let db = crate::db::test_pool(false).await;
+        let base = serve(db.clone()).await;
+        let providers = format!("{}/admin/providers", base);
+
+        // No token configured: the admin API is off
+        assert_eq!(get_status(&providers, None).await, 403);
+        assert_eq!(get_status(&providers, Some("anything")).await, 403);
+
+        sqlx::query("UPDATE gateway_settings SET admin_token = 'secret' WHERE id = 1")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(get_status(&providers, None).await, 401);
+        assert_eq!(get_status(&providers, Some("wrong")).await, 401);
+        assert_eq!(get_status(&providers, Some("secret")).await, 200);
+    }
+
+    #[tokio::test]
+    async fn unknown_admin_paths_do_not_reach_the_proxy() {
+        let db = crate::db::test_pool(false).await;
+        let base = serve(db).await;
+
+        assert_eq!(get_status(&format!("{}/admin/nope", base), Some("secret")).await, 404);
+        assert_eq!(get_status(&format!("{}/admin/providers/1/extra", base), None).await, 404);
+        let proxied = reqwest::get(format!("{}/v1/messages", base)).await.unwrap();
+        assert_eq!(proxied.text().await.unwrap(), "proxied");
+    }
 }
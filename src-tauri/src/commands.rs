@@ -1,68 +1,112 @@
 use crate::config::get_data_dir;
+use crate::error::{AppError, AppResult};
 use crate::db::models::{
-    Provider, ProviderCreate, ProviderResponse, ProviderUpdate,
-    GatewaySettings, TimeoutSettings, TimeoutSettingsUpdate,
-    CliSettingsRow, CliSettingsResponse, CliSettingsUpdate,
-    RequestLogItem, RequestLogDetail, PaginatedLogs,
-    SystemLogItem, SystemLogListResponse,
-    DailyStats, ProviderStatsRow, ProviderStatsResponse,
-    McpConfig, McpCliFlag, McpResponse, McpCreate, McpUpdate,
+    Provider, ProviderCreate, ProviderResponse, ProviderUpdate, ModelMapInput, TestCompletionResult, ProviderTestResult,
+    GatewaySettings, GatewaySettingsUpdate, TimeoutSettings, TimeoutSettingsUpdate, AllSettings, ProviderUseragent,
+    UseragentRule, UseragentImportResult,
+    CliSettingsRow, CliSettingsResponse, CliSettingsUpdate, ConfigValidationError, CliEnabledChanged, ConfigFileInfo,
+    RequestLogItem, RequestLogDetail, PaginatedLogs, ProviderRequestLogs, ProviderLogSummary,
+    SystemLogItem, SystemLogListResponse, AppLogEntry, LogSettings, LogSettingsUpdate,
+    DailyStats, ProviderStatsResponse,
+    McpConfig, McpCliFlag, McpResponse, McpCreate, McpUpdate, McpConflict, McpConflictItem,
     PromptPreset, PromptCliFlag, PromptResponse, PromptCreate, PromptUpdate,
     SkillRepo, SkillRepoCreate,
-    SkillConfig, SkillCliFlag, DiscoverableSkill, InstalledSkillResponse,
-    WebdavSettings, WebdavSettingsUpdate, WebdavBackup,
-    ProjectInfo, SessionInfo, PaginatedProjects, PaginatedSessions, SessionMessage,
-    SystemStatus,
+    SkillConfig, SkillCliFlag, DiscoverableSkill, InstalledSkillResponse, RepoSkillsResult,
+    OrphanedSkillRow, UnregisteredSkillDir, SkillReconcileReport, SkillRepairActions, SkillRepairResult,
+    WebdavSettings, WebdavSettingsUpdate, WebdavBackup, WebhookSettings, WebhookSettingsUpdate, GithubSettings,
+    ProjectInfo, SessionInfo, PaginatedProjects, PaginatedSessions, SessionMessage, SessionMessages,
+    SystemStatus, ActiveRequestInfo, ConcurrencyStatus, ProviderConcurrency, CliHealth, EffectiveConfig, DatabaseCompactResult, StorageUsage, StorageCategory, SchemaReport, DashboardSummary, DashboardTopProvider, DiagnosisReport,
 };
+use crate::services::active_requests::ActiveRequests;
+use crate::services::concurrency::ConcurrencyLimiter;
+use crate::services::proxy::UpstreamClients;
+use crate::services::routing::ProviderScores;
+use crate::services::skill_installs::{SkillInstallGuard, SkillInstalls, INSTALL_CANCELLED};
 use crate::LogDb;
 use sqlx::SqlitePool;
 use tauri::State;
 use std::io::Read;
+use std::sync::Arc;
 
+// Provider commands return `AppResult` (errors serialized as `{ code, message }`); the rest
+// still return plain string errors until they are migrated.
 type Result<T> = std::result::Result<T, String>;
 
+// Provider and settings commands are thin wrappers over `*_impl` functions that take plain
+// pools instead of Tauri `State`, so the HTTP admin API (and tests) can run the same logic
+// without a Tauri runtime.
+
 #[tauri::command]
 pub async fn get_providers(
     db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    scores: State<'_, Arc<ProviderScores>>,
+    cli_type: Option<String>,
+    with_stats: Option<bool>,
+    search: Option<String>,
+) -> AppResult<Vec<ProviderResponse>> {
+    get_providers_impl(db.inner(), &log_db.0, &scores, cli_type, with_stats.unwrap_or(false), search).await
+}
+
+/// Provider list shared by `get_providers` and the HTTP admin API
+pub(crate) async fn get_providers_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    scores: &ProviderScores,
     cli_type: Option<String>,
-) -> Result<Vec<ProviderResponse>> {
+    with_stats: bool,
+    search: Option<String>,
+) -> AppResult<Vec<ProviderResponse>> {
     let providers = if let Some(ct) = cli_type {
         sqlx::query_as::<_, Provider>(
-            "SELECT * FROM providers WHERE cli_type = ? ORDER BY sort_order, id",
+            "SELECT * FROM providers WHERE cli_type = ? OR id IN (SELECT provider_id FROM provider_cli_types WHERE cli_type = ?) ORDER BY sort_order, id",
         )
         .bind(&ct)
-        .fetch_all(db.inner())
+        .bind(&ct)
+        .fetch_all(db)
         .await
     } else {
         sqlx::query_as::<_, Provider>("SELECT * FROM providers ORDER BY sort_order, id")
-            .fetch_all(db.inner())
+            .fetch_all(db)
             .await
     };
 
-    let providers = providers.map_err(|e| e.to_string())?;
+    let mut providers = providers?;
+    // Case-insensitive match on name or notes
+    if let Some(search) = search.map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty()) {
+        providers.retain(|p| {
+            p.name.to_lowercase().contains(&search)
+                || p.notes.as_deref().is_some_and(|n| n.to_lowercase().contains(&search))
+        });
+    }
     let mut results = Vec::new();
 
+    // Activity quickstats live in the log DB, keyed by provider name
+    let activity: std::collections::HashMap<String, (Option<i64>, i64)> = if with_stats {
+        let since = chrono::Utc::now().timestamp() - 24 * 3600;
+        let rows: Vec<(String, Option<i64>, i64)> = sqlx::query_as(
+            "SELECT provider_name, MAX(created_at), CAST(ROUND(COALESCE(SUM(CASE WHEN created_at >= ? THEN sample_weight ELSE 0 END), 0)) AS INTEGER) FROM request_logs WHERE provider_name != '' AND is_shadow = 0 GROUP BY provider_name",
+        )
+        .bind(since)
+        .fetch_all(log_db)
+        .await?;
+        rows.into_iter().map(|(name, last, count)| (name, (last, count))).collect()
+    } else {
+        std::collections::HashMap::new()
+    };
+
     for provider in providers {
         let mut response = ProviderResponse::from(provider.clone());
+        response.score = scores.get(provider.id);
+        response.cli_types = load_provider_cli_types(db, &provider).await?;
 
-        // Load model maps
-        let maps: Vec<(i64, String, String, i64)> = sqlx::query_as(
-            "SELECT id, source_model, target_model, enabled FROM provider_model_map WHERE provider_id = ? ORDER BY id",
-        )
-        .bind(provider.id)
-        .fetch_all(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+        response.model_maps = load_model_maps(db, provider.id).await?;
 
-        response.model_maps = maps
-            .into_iter()
-            .map(|(id, source_model, target_model, enabled)| crate::db::models::ModelMapResponse {
-                id,
-                source_model,
-                target_model,
-                enabled: enabled != 0,
-            })
-            .collect();
+        if with_stats {
+            let (last_used_ts, requests_24h) = activity.get(&provider.name).copied().unwrap_or((None, 0));
+            response.last_used_ts = last_used_ts;
+            response.requests_24h = Some(requests_24h);
+        }
 
         results.push(response);
     }
@@ -71,47 +115,181 @@ pub async fn get_providers(
 }
 
 #[tauri::command]
-pub async fn get_provider(db: State<'_, SqlitePool>, id: i64) -> Result<ProviderResponse> {
+pub async fn get_provider(
+    db: State<'_, SqlitePool>,
+    scores: State<'_, Arc<ProviderScores>>,
+    id: i64,
+) -> AppResult<ProviderResponse> {
+    get_provider_impl(db.inner(), &scores, id).await
+}
+
+pub(crate) async fn get_provider_impl(db: &SqlitePool, scores: &ProviderScores, id: i64) -> AppResult<ProviderResponse> {
     let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
         .bind(id)
-        .fetch_optional(db.inner())
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or_else(|| "Provider not found".to_string())?;
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::not_found("Provider"))?;
 
+    let cli_types = load_provider_cli_types(db, &provider).await?;
     let mut response = ProviderResponse::from(provider);
+    response.score = scores.get(id);
+    response.cli_types = cli_types;
+
+    response.model_maps = load_model_maps(db, id).await?;
+
+    Ok(response)
+}
 
-    // Load model maps
-    let maps: Vec<(i64, String, String, i64)> = sqlx::query_as(
-        "SELECT id, source_model, target_model, enabled FROM provider_model_map WHERE provider_id = ? ORDER BY id",
+// Model maps of a provider
+async fn load_model_maps(db: &SqlitePool, provider_id: i64) -> AppResult<Vec<crate::db::models::ModelMapResponse>> {
+    let maps: Vec<crate::db::models::ProviderModelMap> = sqlx::query_as(
+        "SELECT * FROM provider_model_map WHERE provider_id = ? ORDER BY id",
     )
-    .bind(id)
-    .fetch_all(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
+    .bind(provider_id)
+    .fetch_all(db)
+    .await?;
 
-    response.model_maps = maps
+    Ok(maps
         .into_iter()
-        .map(|(id, source_model, target_model, enabled)| crate::db::models::ModelMapResponse {
-            id,
-            source_model,
-            target_model,
-            enabled: enabled != 0,
+        .map(|m| crate::db::models::ModelMapResponse {
+            id: m.id,
+            source_model: m.source_model,
+            target_model: m.target_model,
+            enabled: m.enabled != 0,
+            cli_type: m.cli_type,
+            large_input_tokens: m.large_input_tokens,
+            large_target_model: m.large_target_model,
         })
-        .collect();
+        .collect())
+}
 
-    Ok(response)
+// 0 means unlimited
+fn validate_max_concurrency(max_concurrency: Option<i64>) -> AppResult<()> {
+    if max_concurrency.is_some_and(|v| v < 0) {
+        return Err(AppError::Validation("max_concurrency must be >= 0 (0 = unlimited)".to_string()));
+    }
+    Ok(())
 }
 
-#[tauri::command]
-pub async fn create_provider(
-    db: State<'_, SqlitePool>,
-    log_db: State<'_, LogDb>,
+// Size rule of a model map: both the threshold and the large-input target, or neither
+fn model_map_size_rule(map: &ModelMapInput) -> AppResult<(Option<i64>, Option<&str>)> {
+    let large_target = map.large_target_model.as_deref().map(str::trim).filter(|s| !s.is_empty());
+    match (map.large_input_tokens, large_target) {
+        (None, None) => Ok((None, None)),
+        (Some(tokens), Some(target)) if tokens > 0 => Ok((Some(tokens), Some(target))),
+        (Some(tokens), Some(_)) => Err(AppError::Validation(format!(
+            "large_input_tokens must be positive, got {} for {}",
+            tokens, map.source_model
+        ))),
+        _ => Err(AppError::Validation(format!(
+            "Model map {} needs both large_input_tokens and large_target_model for a size rule",
+            map.source_model
+        ))),
+    }
+}
+
+// Check model map scopes and size rules before anything is written; an empty cli_type is treated as "all types"
+fn validate_model_maps(model_maps: &[ModelMapInput]) -> AppResult<()> {
+    let mut seen = std::collections::HashSet::new();
+    for map in model_maps {
+        let scope = map.cli_type.as_deref().map(str::trim).filter(|s| !s.is_empty());
+        if let Some(cli_type) = scope {
+            cli_type.parse::<crate::services::proxy::CliType>().map_err(AppError::Validation)?;
+        }
+        model_map_size_rule(map)?;
+        if !seen.insert((map.source_model.as_str(), scope)) {
+            return Err(AppError::Validation(format!(
+                "Duplicate model map for {} ({})",
+                map.source_model,
+                scope.unwrap_or("all CLI types")
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn insert_model_maps(db: &mut sqlx::SqliteConnection, provider_id: i64, model_maps: &[ModelMapInput]) -> AppResult<()> {
+    for map in model_maps {
+        let scope = map.cli_type.as_deref().map(str::trim).filter(|s| !s.is_empty());
+        let (large_input_tokens, large_target) = model_map_size_rule(map)?;
+        sqlx::query(
+            "INSERT INTO provider_model_map (provider_id, source_model, target_model, enabled, cli_type, large_input_tokens, large_target_model) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(provider_id)
+        .bind(&map.source_model)
+        .bind(&map.target_model)
+        .bind(map.enabled as i64)
+        .bind(scope)
+        .bind(large_input_tokens)
+        .bind(large_target)
+        .execute(&mut *db)
+        .await?;
+    }
+    Ok(())
+}
+
+// Reject a base_url that points at the gateway itself, which would make every request loop
+fn ensure_not_gateway_url(config: &crate::config::Config, base_url: &str) -> AppResult<()> {
+    if crate::services::proxy::is_gateway_address(base_url, &config.server.host, config.server.port) {
+        return Err(AppError::Validation(format!(
+            "base_url {} points at this gateway ({}:{}), which would create a proxy loop",
+            base_url.trim(),
+            config.server.host,
+            config.server.port
+        )));
+    }
+    Ok(())
+}
+
+// Supported CLI types of a provider (primary cli_type first)
+async fn load_provider_cli_types(db: &SqlitePool, provider: &Provider) -> AppResult<Vec<String>> {
+    let extra: Vec<String> = sqlx::query_scalar(
+        "SELECT cli_type FROM provider_cli_types WHERE provider_id = ? AND cli_type != ? ORDER BY cli_type",
+    )
+    .bind(provider.id)
+    .bind(&provider.cli_type)
+    .fetch_all(db)
+    .await?;
+
+    let mut cli_types = vec![provider.cli_type.clone()];
+    cli_types.extend(extra);
+    Ok(cli_types)
+}
+
+// Replace a provider's supported CLI types; the primary cli_type is always kept
+async fn save_provider_cli_types(db: &mut sqlx::SqliteConnection, id: i64, primary: &str, cli_types: &[String]) -> AppResult<()> {
+    for cli_type in cli_types {
+        cli_type.parse::<crate::services::proxy::CliType>().map_err(AppError::Validation)?;
+    }
+
+    sqlx::query("DELETE FROM provider_cli_types WHERE provider_id = ?")
+        .bind(id)
+        .execute(&mut *db)
+        .await?;
+
+    for cli_type in std::iter::once(primary).chain(cli_types.iter().map(String::as_str)) {
+        sqlx::query("INSERT OR IGNORE INTO provider_cli_types (provider_id, cli_type) VALUES (?, ?)")
+            .bind(id)
+            .bind(cli_type)
+            .execute(&mut *db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Validate a new provider and insert it with its CLI types and model maps; returns its id.
+/// sort_order continues after the current maximum, so consecutive inserts stay in order.
+async fn insert_provider(
+    conn: &mut sqlx::SqliteConnection,
+    config: &crate::config::Config,
     input: ProviderCreate,
-) -> Result<ProviderResponse> {
+) -> AppResult<i64> {
+    ensure_not_gateway_url(config, &input.base_url)?;
+    validate_model_maps(input.model_maps.as_deref().unwrap_or_default())?;
+    validate_max_concurrency(input.max_concurrency)?;
+
     let now = chrono::Utc::now().timestamp();
     let cli_type = input.cli_type.unwrap_or_else(|| "claude_code".to_string());
-    let provider_name = input.name.clone();
 
     // Normalize custom_useragent: treat empty string as None
     let custom_ua = input.custom_useragent
@@ -120,10 +298,65 @@ pub async fn create_provider(
         .filter(|s| !s.is_empty())
         .map(|s| s.to_string());
 
+    // Custom auth overrides: empty strings mean the CLI default
+    crate::services::proxy::validate_auth_settings(input.auth_header_name.as_deref(), input.auth_scheme.as_deref())
+        .map_err(AppError::Validation)?;
+    let auth_header_name = input.auth_header_name
+        .as_deref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+    let auth_scheme = input.auth_scheme
+        .as_deref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+
+    crate::services::proxy::validate_upstream_proxy(input.upstream_proxy.as_deref()).map_err(AppError::Validation)?;
+    crate::services::proxy::validate_custom_ca(input.custom_ca_pem.as_deref()).map_err(AppError::Validation)?;
+    let upstream_proxy = input.upstream_proxy
+        .as_deref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+
+    let strip_headers = crate::services::proxy::normalize_strip_headers(input.strip_headers.as_deref().unwrap_or_default()).map_err(AppError::Validation)?;
+    let header_overrides = match input.header_overrides {
+        Some(ref overrides) => crate::services::proxy::normalize_header_overrides(overrides).map_err(AppError::Validation)?,
+        None => None,
+    };
+    let inject_system_prompt = input.inject_system_prompt
+        .as_deref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+    let notes = input.notes
+        .as_deref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+    let custom_ca_pem = input.custom_ca_pem
+        .as_deref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+    if input.key_expires_at.is_some_and(|at| at < 0) {
+        return Err(AppError::Validation("key_expires_at must not be negative".to_string()));
+    }
+    let key_expires_at = input.key_expires_at.filter(|&at| at > 0);
+    let request_transform = match input.request_transform {
+        Some(ref patch) => crate::services::proxy::normalize_json_patch(patch).map_err(|e| AppError::Validation(format!("request_transform: {}", e)))?,
+        None => None,
+    };
+    let response_transform = match input.response_transform {
+        Some(ref patch) => crate::services::proxy::normalize_json_patch(patch).map_err(|e| AppError::Validation(format!("response_transform: {}", e)))?,
+        None => None,
+    };
+
     let result = sqlx::query(
         r#"
-        INSERT INTO providers (cli_type, name, base_url, api_key, enabled, failure_threshold, blacklist_minutes, consecutive_failures, sort_order, custom_useragent, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?, 0, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM providers), ?, ?, ?)
+        INSERT INTO providers (cli_type, name, base_url, api_key, enabled, failure_threshold, blacklist_minutes, consecutive_failures, sort_order, custom_useragent, auth_header_name, auth_scheme, upstream_proxy, strip_headers, header_overrides, inject_system_prompt, force_non_stream, max_concurrency, notes, accept_invalid_certs, custom_ca_pem, priority, request_transform, response_transform, key_expires_at, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, 0, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM providers), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, COALESCE(?, (SELECT COALESCE(MAX(priority), 0) + 1 FROM providers)), ?, ?, ?, ?, ?)
         "#,
     )
     .bind(&cli_type)
@@ -134,59 +367,236 @@ pub async fn create_provider(
     .bind(input.failure_threshold.unwrap_or(3))
     .bind(input.blacklist_minutes.unwrap_or(10))
     .bind(&custom_ua)
+    .bind(&auth_header_name)
+    .bind(&auth_scheme)
+    .bind(&upstream_proxy)
+    .bind(&strip_headers)
+    .bind(&header_overrides)
+    .bind(&inject_system_prompt)
+    .bind(input.force_non_stream.unwrap_or(false) as i64)
+    .bind(input.max_concurrency.unwrap_or(0))
+    .bind(&notes)
+    .bind(input.accept_invalid_certs.unwrap_or(false) as i64)
+    .bind(&custom_ca_pem)
+    .bind(input.priority)
+    .bind(&request_transform)
+    .bind(&response_transform)
+    .bind(key_expires_at)
     .bind(now)
     .bind(now)
-    .execute(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
+    .execute(&mut *conn)
+    .await?;
 
     let id = result.last_insert_rowid();
 
+    save_provider_cli_types(conn, id, &cli_type, input.cli_types.as_deref().unwrap_or_default()).await?;
+
     // Insert model maps if provided
     if let Some(model_maps) = input.model_maps {
-        for map in model_maps {
-            sqlx::query(
-                "INSERT INTO provider_model_map (provider_id, source_model, target_model, enabled) VALUES (?, ?, ?, ?)",
-            )
-            .bind(id)
-            .bind(&map.source_model)
-            .bind(&map.target_model)
-            .bind(map.enabled as i64)
-            .execute(db.inner())
-            .await
-            .map_err(|e| e.to_string())?;
-        }
+        insert_model_maps(conn, id, &model_maps).await?;
     }
 
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn create_provider(
+    db: State<'_, SqlitePool>,
+    scores: State<'_, Arc<ProviderScores>>,
+    log_db: State<'_, LogDb>,
+    config: State<'_, crate::config::Config>,
+    input: ProviderCreate,
+) -> AppResult<ProviderResponse> {
+    create_provider_impl(db.inner(), &log_db.0, &scores, &config, input).await
+}
+
+/// Create a provider; shared by `create_provider` and the HTTP admin API
+pub(crate) async fn create_provider_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    scores: &ProviderScores,
+    config: &crate::config::Config,
+    input: ProviderCreate,
+) -> AppResult<ProviderResponse> {
+    let provider_name = input.name.clone();
+    let enabled = input.enabled.unwrap_or(true);
+
+    let mut tx = db.begin().await?;
+    let id = insert_provider(&mut tx, config, input).await?;
+    tx.commit().await?;
+
     // Log system event
     let _ = crate::services::stats::record_system_log(
-        &log_db.0,
+        log_db,
         "provider_created",
         &format!("服务商 {} 已创建", provider_name),
     ).await;
 
-    get_provider(db, id).await
+    let connection_warning = if enabled { check_enabled_provider(db, id).await } else { None };
+    let mut response = get_provider_impl(db, scores, id).await?;
+    response.connection_warning = connection_warning;
+    Ok(response)
+}
+
+/// With gateway_settings.validate_on_enable, probe a provider that was just enabled and
+/// return a warning when the check fails. Never fails the save itself.
+async fn check_enabled_provider(db: &SqlitePool, id: i64) -> Option<String> {
+    let validate: i64 = sqlx::query_scalar("SELECT validate_on_enable FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(0);
+    if validate == 0 {
+        return None;
+    }
+
+    let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()?;
+    let client = match provider_test_client(&Default::default()) {
+        Ok(client) => client,
+        Err(e) => return Some(format!("Connection check could not run: {}", e)),
+    };
+    let result = probe_provider(&client, &provider).await;
+    if result.success {
+        return None;
+    }
+    let error = result.error.unwrap_or_else(|| "unknown error".to_string());
+    tracing::warn!(provider = %provider.name, error = %error, "Provider failed the connection check on enable");
+    Some(format!("Connection check failed: {}", error))
+}
+
+/// Create many providers at once (paste-many flows). All-or-nothing: the first invalid
+/// provider rolls back the whole batch and the error names it.
+#[tauri::command]
+pub async fn bulk_create_providers(
+    db: State<'_, SqlitePool>,
+    scores: State<'_, Arc<ProviderScores>>,
+    log_db: State<'_, LogDb>,
+    config: State<'_, crate::config::Config>,
+    input: Vec<ProviderCreate>,
+) -> AppResult<Vec<ProviderResponse>> {
+    bulk_create_providers_impl(db.inner(), &log_db.0, &scores, &config, input).await
+}
+
+pub(crate) async fn bulk_create_providers_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    scores: &ProviderScores,
+    config: &crate::config::Config,
+    input: Vec<ProviderCreate>,
+) -> AppResult<Vec<ProviderResponse>> {
+    if input.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let names: Vec<String> = input.iter().map(|p| p.name.clone()).collect();
+    let mut tx = db.begin().await?;
+    let mut ids = Vec::with_capacity(input.len());
+    for (i, provider) in input.into_iter().enumerate() {
+        let id = insert_provider(&mut tx, config, provider)
+            .await
+            .map_err(|e| e.with_context(format!("Provider #{} ({})", i + 1, names[i])))?;
+        ids.push(id);
+    }
+    tx.commit().await?;
+
+    let _ = crate::services::stats::record_system_log(
+        log_db,
+        "provider_created",
+        &format!("批量创建 {} 个服务商: {}", names.len(), names.join(", ")),
+    ).await;
+
+    let mut providers = Vec::with_capacity(ids.len());
+    for id in ids {
+        providers.push(get_provider_impl(db, scores, id).await?);
+    }
+    Ok(providers)
 }
 
 #[tauri::command]
 pub async fn update_provider(
     db: State<'_, SqlitePool>,
+    scores: State<'_, Arc<ProviderScores>>,
     log_db: State<'_, LogDb>,
+    config: State<'_, crate::config::Config>,
+    id: i64,
+    input: ProviderUpdate,
+) -> AppResult<ProviderResponse> {
+    update_provider_impl(db.inner(), &log_db.0, &scores, &config, id, input).await
+}
+
+/// Apply a partial provider update; shared by `update_provider` and the HTTP admin API
+pub(crate) async fn update_provider_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    scores: &ProviderScores,
+    config: &crate::config::Config,
     id: i64,
     input: ProviderUpdate,
-) -> Result<ProviderResponse> {
+) -> AppResult<ProviderResponse> {
+    if let Some(ref base_url) = input.base_url {
+        ensure_not_gateway_url(config, base_url)?;
+    }
+    crate::services::proxy::validate_auth_settings(input.auth_header_name.as_deref(), input.auth_scheme.as_deref())
+        .map_err(AppError::Validation)?;
+    crate::services::proxy::validate_custom_ca(input.custom_ca_pem.as_deref()).map_err(AppError::Validation)?;
+    validate_model_maps(input.model_maps.as_deref().unwrap_or_default())?;
+    validate_max_concurrency(input.max_concurrency)?;
+
     let now = chrono::Utc::now().timestamp();
 
-    // Get provider name for logging
-    let provider_name: Option<(String,)> = sqlx::query_as(
-        "SELECT name FROM providers WHERE id = ?",
+    // Get provider name for logging, and whether this update enables it
+    let current: Option<(String, i64)> = sqlx::query_as(
+        "SELECT name, enabled FROM providers WHERE id = ?",
     )
     .bind(id)
-    .fetch_optional(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
+    .fetch_optional(db)
+    .await?;
 
-    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
+    let being_enabled = input.enabled == Some(true) && current.as_ref().is_some_and(|(_, enabled)| *enabled == 0);
+    let provider_name = current.map(|(n, _)| n).unwrap_or_else(|| format!("Provider#{}", id));
+
+    // The masked proxy URL sent back unchanged means "keep the stored one"
+    let mut upstream_proxy = input.upstream_proxy.as_deref().map(|s| s.trim().to_string());
+    if let Some(ref proxy) = upstream_proxy {
+        let current: Option<String> = sqlx::query_scalar("SELECT upstream_proxy FROM providers WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db)
+            .await?
+            .flatten();
+        if current.is_some_and(|c| crate::services::proxy::mask_proxy_url(&c) == *proxy) {
+            upstream_proxy = None;
+        } else {
+            crate::services::proxy::validate_upstream_proxy(Some(proxy)).map_err(AppError::Validation)?;
+        }
+    }
+
+    // Header rules: an empty list/map clears the stored rules
+    let strip_headers = match input.strip_headers {
+        Some(ref names) => Some(crate::services::proxy::normalize_strip_headers(names).map_err(AppError::Validation)?),
+        None => None,
+    };
+    let header_overrides = match input.header_overrides {
+        Some(ref overrides) => Some(crate::services::proxy::normalize_header_overrides(overrides).map_err(AppError::Validation)?),
+        None => None,
+    };
+    if input.key_expires_at.is_some_and(|at| at < 0) {
+        return Err(AppError::Validation("key_expires_at must not be negative".to_string()));
+    }
+    // Body transforms: an empty operation array clears the stored transform
+    let request_transform = match input.request_transform {
+        Some(ref patch) => Some(crate::services::proxy::normalize_json_patch(patch).map_err(|e| AppError::Validation(format!("request_transform: {}", e)))?),
+        None => None,
+    };
+    let response_transform = match input.response_transform {
+        Some(ref patch) => Some(crate::services::proxy::normalize_json_patch(patch).map_err(|e| AppError::Validation(format!("response_transform: {}", e)))?),
+        None => None,
+    };
 
     // Check if model maps will be updated (before moving)
     let has_model_maps_update = input.model_maps.is_some();
@@ -223,6 +633,66 @@ pub async fn update_provider(
         updates.push("custom_useragent = ?".to_string());
         has_updates = true;
     }
+    if input.auth_header_name.is_some() {
+        updates.push("auth_header_name = ?".to_string());
+        has_updates = true;
+    }
+    if input.auth_scheme.is_some() {
+        updates.push("auth_scheme = ?".to_string());
+        has_updates = true;
+    }
+    if upstream_proxy.is_some() {
+        updates.push("upstream_proxy = ?".to_string());
+        has_updates = true;
+    }
+    if strip_headers.is_some() {
+        updates.push("strip_headers = ?".to_string());
+        has_updates = true;
+    }
+    if header_overrides.is_some() {
+        updates.push("header_overrides = ?".to_string());
+        has_updates = true;
+    }
+    if input.inject_system_prompt.is_some() {
+        updates.push("inject_system_prompt = ?".to_string());
+        has_updates = true;
+    }
+    if input.force_non_stream.is_some() {
+        updates.push("force_non_stream = ?".to_string());
+        has_updates = true;
+    }
+    if input.max_concurrency.is_some() {
+        updates.push("max_concurrency = ?".to_string());
+        has_updates = true;
+    }
+    if input.notes.is_some() {
+        updates.push("notes = ?".to_string());
+        has_updates = true;
+    }
+    if input.accept_invalid_certs.is_some() {
+        updates.push("accept_invalid_certs = ?".to_string());
+        has_updates = true;
+    }
+    if input.custom_ca_pem.is_some() {
+        updates.push("custom_ca_pem = ?".to_string());
+        has_updates = true;
+    }
+    if input.priority.is_some() {
+        updates.push("priority = ?".to_string());
+        has_updates = true;
+    }
+    if request_transform.is_some() {
+        updates.push("request_transform = ?".to_string());
+        has_updates = true;
+    }
+    if response_transform.is_some() {
+        updates.push("response_transform = ?".to_string());
+        has_updates = true;
+    }
+    if input.key_expires_at.is_some() {
+        updates.push("key_expires_at = NULLIF(?, 0)".to_string());
+        has_updates = true;
+    }
 
     if has_updates {
         let query = format!("UPDATE providers SET {} WHERE id = ?", updates.join(", "));
@@ -255,271 +725,1592 @@ pub async fn update_provider(
                 q = q.bind(ua);
             }
         }
+        // Auth overrides: empty string resets to the CLI default
+        for value in [&input.auth_header_name, &input.auth_scheme].into_iter().flatten() {
+            let value = value.trim();
+            q = q.bind((!value.is_empty()).then(|| value.to_string()));
+        }
+        // Empty proxy switches back to a direct connection
+        if let Some(ref proxy) = upstream_proxy {
+            q = q.bind((!proxy.is_empty()).then(|| proxy.clone()));
+        }
+        for rules in [&strip_headers, &header_overrides].into_iter().flatten() {
+            q = q.bind(rules.clone());
+        }
+        // Empty prompt stops the injection
+        if let Some(ref prompt) = input.inject_system_prompt {
+            let prompt = prompt.trim();
+            q = q.bind((!prompt.is_empty()).then(|| prompt.to_string()));
+        }
+        if let Some(force_non_stream) = input.force_non_stream {
+            q = q.bind(force_non_stream as i64);
+        }
+        if let Some(max_concurrency) = input.max_concurrency {
+            q = q.bind(max_concurrency);
+        }
+        // Empty notes clear them
+        if let Some(ref notes) = input.notes {
+            let notes = notes.trim();
+            q = q.bind((!notes.is_empty()).then(|| notes.to_string()));
+        }
+        if let Some(accept_invalid_certs) = input.accept_invalid_certs {
+            q = q.bind(accept_invalid_certs as i64);
+        }
+        // Empty CA goes back to the system roots only
+        if let Some(ref pem) = input.custom_ca_pem {
+            let pem = pem.trim();
+            q = q.bind((!pem.is_empty()).then(|| pem.to_string()));
+        }
+        if let Some(priority) = input.priority {
+            q = q.bind(priority);
+        }
+        for transform in [&request_transform, &response_transform].into_iter().flatten() {
+            q = q.bind(transform.clone());
+        }
+        if let Some(expires_at) = input.key_expires_at {
+            q = q.bind(expires_at);
+        }
 
         q.bind(id)
-            .execute(db.inner())
-            .await
-            .map_err(|e| e.to_string())?;
+            .execute(db)
+            .await?;
+    }
+
+    // Update supported CLI types if provided
+    let has_cli_types_update = input.cli_types.is_some();
+    if let Some(ref cli_types) = input.cli_types {
+        let primary: String = sqlx::query_scalar("SELECT cli_type FROM providers WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db)
+            .await?
+            .ok_or_else(|| AppError::not_found("Provider"))?;
+        let mut conn = db.acquire().await?;
+        save_provider_cli_types(&mut conn, id, &primary, cli_types).await?;
     }
 
     // Update model maps if provided
     if let Some(model_maps) = input.model_maps {
-        // Delete existing maps
+        // Replace the existing maps in one transaction so a failed insert keeps the old set
+        let mut tx = db.begin().await?;
         sqlx::query("DELETE FROM provider_model_map WHERE provider_id = ?")
             .bind(id)
-            .execute(db.inner())
-            .await
-            .map_err(|e| e.to_string())?;
-
-        // Insert new maps
-        for map in model_maps {
-            sqlx::query(
-                "INSERT INTO provider_model_map (provider_id, source_model, target_model, enabled) VALUES (?, ?, ?, ?)",
-            )
-            .bind(id)
-            .bind(&map.source_model)
-            .bind(&map.target_model)
-            .bind(map.enabled as i64)
-            .execute(db.inner())
-            .await
-            .map_err(|e| e.to_string())?;
-        }
+            .execute(&mut *tx)
+            .await?;
+        insert_model_maps(&mut tx, id, &model_maps).await?;
+        tx.commit().await?;
     }
 
     // Log system event (only if there were actual updates)
-    if has_updates || has_model_maps_update {
+    if has_updates || has_model_maps_update || has_cli_types_update {
         let _ = crate::services::stats::record_system_log(
-            &log_db.0,
+            log_db,
             "provider_updated",
             &format!("服务商 {} 已更新", provider_name),
         ).await;
     }
 
-    get_provider(db, id).await
+    let connection_warning = if being_enabled { check_enabled_provider(db, id).await } else { None };
+    let mut response = get_provider_impl(db, scores, id).await?;
+    response.connection_warning = connection_warning;
+    Ok(response)
+}
+
+#[tauri::command]
+pub async fn delete_provider(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    clients: State<'_, Arc<UpstreamClients>>,
+    id: i64,
+) -> AppResult<()> {
+    delete_provider_impl(db.inner(), &log_db.0, &clients, id).await
+}
+
+pub(crate) async fn delete_provider_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    clients: &UpstreamClients,
+    id: i64,
+) -> AppResult<()> {
+    // Get provider name before deletion
+    let provider_name: Option<(String,)> = sqlx::query_as(
+        "SELECT name FROM providers WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await?;
+
+    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
+
+    // Delete associated model maps first (cascade delete)
+    sqlx::query("DELETE FROM provider_model_map WHERE provider_id = ?")
+        .bind(id)
+        .execute(db)
+        .await?;
+    sqlx::query("DELETE FROM provider_cli_types WHERE provider_id = ?")
+        .bind(id)
+        .execute(db)
+        .await?;
+
+    // Then delete the provider
+    sqlx::query("DELETE FROM providers WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await?;
+    clients.evict(id);
+
+    // Log system event
+    let _ = crate::services::stats::record_system_log(
+        log_db,
+        "provider_deleted",
+        &format!("服务商 {} 已删除", provider_name),
+    ).await;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn reorder_providers(
+    db: State<'_, SqlitePool>,
+    ids: Vec<i64>,
+    cli_type: Option<String>,
+) -> AppResult<()> {
+    crate::services::provider::reorder(db.inner(), &ids, cli_type.as_deref()).await
+}
+
+/// Set the failover priority order (first id is tried first); the display order is unchanged
+#[tauri::command]
+pub async fn reorder_provider_priority(
+    db: State<'_, SqlitePool>,
+    ids: Vec<i64>,
+    cli_type: Option<String>,
+) -> AppResult<()> {
+    crate::services::provider::reorder_priority(db.inner(), &ids, cli_type.as_deref()).await
+}
+
+// Pause/resume routing to a provider without changing its enabled state
+async fn set_provider_paused(db: &SqlitePool, log_db: &SqlitePool, id: i64, paused: bool) -> AppResult<()> {
+    let provider_name: Option<(String,)> = sqlx::query_as(
+        "SELECT name FROM providers WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await?;
+
+    let provider_name = provider_name
+        .map(|(n,)| n)
+        .ok_or_else(|| AppError::not_found("Provider"))?;
+
+    sqlx::query("UPDATE providers SET paused = ?, updated_at = ? WHERE id = ?")
+        .bind(paused as i64)
+        .bind(chrono::Utc::now().timestamp())
+        .bind(id)
+        .execute(db)
+        .await?;
+
+    let (event_type, message) = if paused {
+        ("provider_paused", format!("服务商 {} 已暂停路由", provider_name))
+    } else {
+        ("provider_resumed", format!("服务商 {} 已恢复路由", provider_name))
+    };
+    let _ = crate::services::stats::record_system_log(log_db, event_type, &message).await;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn pause_provider(db: State<'_, SqlitePool>, log_db: State<'_, LogDb>, id: i64) -> AppResult<()> {
+    set_provider_paused(db.inner(), &log_db.0, id, true).await
+}
+
+#[tauri::command]
+pub async fn resume_provider(db: State<'_, SqlitePool>, log_db: State<'_, LogDb>, id: i64) -> AppResult<()> {
+    set_provider_paused(db.inner(), &log_db.0, id, false).await
+}
+
+#[tauri::command]
+pub async fn reset_provider_failures(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    id: i64,
+) -> AppResult<()> {
+    reset_provider_failures_impl(db.inner(), &log_db.0, id).await
+}
+
+pub(crate) async fn reset_provider_failures_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    id: i64,
+) -> AppResult<()> {
+    // Get provider name for logging
+    let provider_name: Option<(String,)> = sqlx::query_as(
+        "SELECT name FROM providers WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await?;
+
+    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
+
+    sqlx::query("UPDATE providers SET consecutive_failures = 0, blacklisted_until = NULL WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await?;
+
+    // Log system event
+    let _ = crate::services::stats::record_system_log(
+        log_db,
+        "provider_reset",
+        &format!("服务商 {} 状态已手动重置", provider_name),
+    ).await;
+
+    Ok(())
+}
+
+/// Test a provider and clear its failure count and blacklist only if the test passes,
+/// so a still-broken provider is not put straight back into rotation.
+#[tauri::command]
+pub async fn recover_provider(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    id: i64,
+) -> AppResult<ProviderTestResult> {
+    recover_provider_impl(db.inner(), &log_db.0, id).await
+}
+
+pub(crate) async fn recover_provider_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    id: i64,
+) -> AppResult<ProviderTestResult> {
+    let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::not_found("Provider"))?;
+
+    let client = provider_test_client(&Default::default()).map_err(AppError::Internal)?;
+    let mut result = probe_provider(&client, &provider).await;
+
+    let message = if result.success {
+        crate::services::provider::reset_failures(db, id)
+            .await?;
+        result.unblacklisted = provider.blacklist_remaining_secs(chrono::Utc::now().timestamp()).is_some();
+        format!("服务商 {} 测试通过，已清除失败计数和黑名单", provider.name)
+    } else {
+        format!(
+            "服务商 {} 测试未通过，保持当前状态: {}",
+            provider.name,
+            result.error.as_deref().unwrap_or("unknown error")
+        )
+    };
+    let event_type = if result.success { "provider_recovered" } else { "provider_recover_failed" };
+    let _ = crate::services::stats::record_system_log(log_db, event_type, &message).await;
+
+    Ok(result)
+}
+
+/// Clear failure counters and blacklists for every provider at once.
+/// Returns the number of providers that were reset.
+#[tauri::command]
+pub async fn reset_all_provider_failures(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+) -> AppResult<u64> {
+    reset_all_provider_failures_impl(db.inner(), &log_db.0).await
+}
+
+pub(crate) async fn reset_all_provider_failures_impl(db: &SqlitePool, log_db: &SqlitePool) -> AppResult<u64> {
+    let now = chrono::Utc::now().timestamp();
+    let result = sqlx::query(
+        "UPDATE providers SET consecutive_failures = 0, blacklisted_until = NULL, updated_at = ? WHERE consecutive_failures > 0 OR blacklisted_until IS NOT NULL",
+    )
+    .bind(now)
+    .execute(db)
+    .await?;
+
+    let count = result.rows_affected();
+
+    // Log system event
+    let _ = crate::services::stats::record_system_log(
+        log_db,
+        "provider_reset_all",
+        &format!("已手动重置全部服务商状态，共 {} 个", count),
+    ).await;
+
+    Ok(count)
+}
+
+/// Export per-provider User-Agent overrides as JSON, keyed by CLI type and provider name
+#[tauri::command]
+pub async fn export_useragent_maps(db: State<'_, SqlitePool>) -> Result<String> {
+    let rules = sqlx::query_as::<_, UseragentRule>(
+        "SELECT cli_type, name AS provider_name, custom_useragent FROM providers WHERE custom_useragent IS NOT NULL AND custom_useragent != '' ORDER BY cli_type, sort_order",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    serde_json::to_string_pretty(&rules).map_err(|e| e.to_string())
+}
+
+/// Import User-Agent overrides.
+/// `mode` is "merge" (default: only listed providers change) or "replace" (overrides not listed are cleared).
+/// Rules are matched to existing providers by CLI type and name; unmatched rules are reported as skipped.
+#[tauri::command]
+pub async fn import_useragent_maps(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    json: String,
+    mode: Option<String>,
+) -> Result<UseragentImportResult> {
+    let replace = match mode.as_deref().unwrap_or("merge") {
+        "merge" => false,
+        "replace" => true,
+        other => return Err(format!("Invalid import mode: {}", other)),
+    };
+
+    let rules: Vec<UseragentRule> = serde_json::from_str(&json)
+        .map_err(|e| format!("JSON 格式错误: {}", e))?;
+
+    // Validate every rule before touching the database
+    for (i, rule) in rules.iter().enumerate() {
+        if rule.provider_name.trim().is_empty() {
+            return Err(format!("Rule #{}: provider_name is required", i + 1));
+        }
+        rule.cli_type.parse::<crate::services::proxy::CliType>()
+            .map_err(|e| format!("Rule #{}: {}", i + 1, e))?;
+        let ua = rule.custom_useragent.trim();
+        if ua.is_empty() {
+            return Err(format!("Rule #{}: custom_useragent is required", i + 1));
+        }
+        reqwest::header::HeaderValue::from_str(ua)
+            .map_err(|_| format!("Rule #{}: custom_useragent is not a valid header value", i + 1))?;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut tx = db.inner().begin().await.map_err(|e| e.to_string())?;
+
+    if replace {
+        sqlx::query("UPDATE providers SET custom_useragent = NULL, updated_at = ? WHERE custom_useragent IS NOT NULL")
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut updated = 0;
+    let mut skipped = Vec::new();
+    for rule in &rules {
+        let result = sqlx::query("UPDATE providers SET custom_useragent = ?, updated_at = ? WHERE cli_type = ? AND name = ?")
+            .bind(rule.custom_useragent.trim())
+            .bind(now)
+            .bind(&rule.cli_type)
+            .bind(&rule.provider_name)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        if result.rows_affected() > 0 {
+            updated += result.rows_affected() as i64;
+        } else {
+            skipped.push(format!("{}/{}", rule.cli_type, rule.provider_name));
+        }
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "useragent_imported",
+        &format!("导入 User-Agent 规则: 更新 {} 个服务商，跳过 {} 条", updated, skipped.len()),
+    ).await;
+
+    Ok(UseragentImportResult { updated, skipped })
+}
+
+/// (input, rewritten UA, provider id whose override applied)
+type UseragentPreview = (String, Option<String>, Option<i64>);
+
+/// Run sample User-Agent strings through a provider's override exactly as the proxy would.
+/// Returns (input, rewritten UA, id of the provider whose override applied) per input;
+/// the last two are None when the client's User-Agent would be forwarded unchanged.
+#[tauri::command]
+pub async fn preview_useragent_rewrites(
+    db: State<'_, SqlitePool>,
+    provider_id: i64,
+    inputs: Vec<String>,
+) -> Result<Vec<UseragentPreview>> {
+    use crate::services::proxy::{apply_useragent_override, useragent_override_value};
+    use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+
+    let custom_ua: Option<String> = sqlx::query_scalar("SELECT custom_useragent FROM providers WHERE id = ?")
+        .bind(provider_id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Provider not found".to_string())?;
+    let applies = useragent_override_value(custom_ua.as_deref()).is_some();
+
+    Ok(inputs
+        .into_iter()
+        .map(|input| {
+            if !applies {
+                return (input, None, None);
+            }
+            let mut headers = HeaderMap::new();
+            if let Ok(value) = HeaderValue::from_str(&input) {
+                headers.insert(USER_AGENT, value);
+            }
+            apply_useragent_override(&mut headers, custom_ua.as_deref());
+            let rewritten = headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_string);
+            (input, rewritten, Some(provider_id))
+        })
+        .collect())
+}
+
+/// Send a short real prompt through the provider's native protocol (non-streaming)
+/// to verify base URL, auth and model end to end.
+#[tauri::command]
+pub async fn test_provider_completion(
+    db: State<'_, SqlitePool>,
+    id: i64,
+    model: String,
+    prompt: Option<String>,
+) -> Result<TestCompletionResult> {
+    use crate::services::proxy::{apply_provider_auth, apply_useragent_override, parse_token_usage, CliType, TokenUsage};
+
+    let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Provider not found".to_string())?;
+
+    let model = model.trim().to_string();
+    if model.is_empty() {
+        return Err("Model is required".to_string());
+    }
+    let prompt = prompt
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "Reply with the single word: pong".to_string());
+
+    let cli_type: CliType = provider.cli_type.parse()?;
+    let base_url = provider.base_url.trim_end_matches('/');
+
+    // Build a minimal request in the provider's protocol
+    let (url, body) = match cli_type {
+        CliType::ClaudeCode => (
+            format!("{}/v1/messages", base_url),
+            serde_json::json!({
+                "model": model,
+                "max_tokens": 64,
+                "messages": [{ "role": "user", "content": prompt }],
+            }),
+        ),
+        CliType::Codex => (
+            format!("{}/responses", base_url),
+            serde_json::json!({
+                "model": model,
+                "input": prompt,
+                "max_output_tokens": 64,
+            }),
+        ),
+        CliType::Gemini => (
+            format!("{}/v1beta/models/{}:generateContent", base_url, model),
+            serde_json::json!({
+                "contents": [{ "role": "user", "parts": [{ "text": prompt }] }],
+                "generationConfig": { "maxOutputTokens": 64 },
+            }),
+        ),
+    };
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    let url = apply_provider_auth(
+        &mut headers,
+        &url,
+        &provider.api_key,
+        cli_type,
+        provider.auth_header_name.as_deref(),
+        provider.auth_scheme.as_deref(),
+    )?;
+    apply_useragent_override(&mut headers, provider.custom_useragent.as_deref());
+    headers.insert(reqwest::header::CONTENT_TYPE, "application/json".parse().unwrap());
+    if cli_type == CliType::ClaudeCode {
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+    }
+
+    let timeout_secs = get_timeout_settings_impl(db.inner()).await
+        .map(|t| t.non_stream_timeout)
+        .unwrap_or(120);
+    let client = crate::services::proxy::ClientOptions::for_provider(&provider)
+        .apply(reqwest::Client::builder())?
+        .timeout(std::time::Duration::from_secs(timeout_secs.max(1) as u64))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let start = std::time::Instant::now();
+    let response = match client.post(&url).headers(headers).json(&body).send().await {
+        Ok(resp) => resp,
+        Err(e) => {
+            return Ok(TestCompletionResult {
+                success: false,
+                status_code: None,
+                text: None,
+                input_tokens: None,
+                output_tokens: None,
+                latency_ms: start.elapsed().as_millis() as i64,
+                // Without the URL: query-parameter auth puts the API key in it
+                error: Some(if e.is_timeout() { "Request timeout".to_string() } else { e.without_url().to_string() }),
+            });
+        }
+    };
+
+    let status = response.status();
+    let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+    let latency_ms = start.elapsed().as_millis() as i64;
+
+    let json = serde_json::from_slice::<serde_json::Value>(&bytes).ok();
+    let text = json.as_ref().and_then(|j| extract_completion_text(j, cli_type));
+
+    let mut usage = TokenUsage::default();
+    parse_token_usage(&bytes, cli_type, &mut usage);
+    let has_usage = usage.input_tokens > 0 || usage.output_tokens > 0;
+
+    let error = if status.is_success() {
+        None
+    } else {
+        let raw = String::from_utf8_lossy(&bytes);
+        Some(format!("HTTP {}: {}", status.as_u16(), raw.chars().take(500).collect::<String>()))
+    };
+
+    Ok(TestCompletionResult {
+        success: status.is_success(),
+        status_code: Some(status.as_u16()),
+        text: text.map(|t| t.chars().take(500).collect()),
+        input_tokens: has_usage.then_some(usage.input_tokens),
+        output_tokens: has_usage.then_some(usage.output_tokens),
+        latency_ms,
+        error,
+    })
+}
+
+/// Timeout for a single connectivity probe
+const PROVIDER_TEST_TIMEOUT_SECS: u64 = 15;
+/// Providers probed at the same time by test_all_providers
+const MAX_CONCURRENT_PROVIDER_TESTS: usize = 4;
+
+// Probe a provider with an authenticated model-list request. Cheap and token-free,
+// yet still exercises base URL, TLS and credentials. `client` is used for direct
+// providers; ones with an upstream proxy get their own client.
+async fn probe_provider(client: &reqwest::Client, provider: &Provider) -> ProviderTestResult {
+    use crate::services::proxy::CliType;
+
+    let mut result = ProviderTestResult {
+        provider_id: provider.id,
+        provider_name: provider.name.clone(),
+        cli_type: provider.cli_type.clone(),
+        reachable: false,
+        success: false,
+        status_code: None,
+        latency_ms: 0,
+        error: None,
+        unblacklisted: false,
+    };
+
+    // Providers with a proxy or custom TLS settings need their own client
+    let custom_client;
+    let options = crate::services::proxy::ClientOptions::for_provider(provider);
+    let client = if options.is_default() {
+        client
+    } else {
+        match provider_test_client(&options) {
+            Ok(c) => {
+                custom_client = c;
+                &custom_client
+            }
+            Err(e) => {
+                result.error = Some(e);
+                return result;
+            }
+        }
+    };
+
+    let cli_type: CliType = match provider.cli_type.parse() {
+        Ok(ct) => ct,
+        Err(e) => {
+            result.error = Some(e);
+            return result;
+        }
+    };
+    let (url, headers) = match provider_models_request(provider, cli_type, "") {
+        Ok(request) => request,
+        Err(e) => {
+            result.error = Some(e);
+            return result;
+        }
+    };
+
+    let start = std::time::Instant::now();
+    let response = client.get(&url).headers(headers).send().await;
+    result.latency_ms = start.elapsed().as_millis() as i64;
+
+    match response {
+        Ok(resp) => {
+            let status = resp.status();
+            result.reachable = true;
+            result.success = status.is_success();
+            result.status_code = Some(status.as_u16());
+            if !status.is_success() {
+                let body = resp.text().await.unwrap_or_default();
+                result.error = Some(format!("HTTP {}: {}", status.as_u16(), body.chars().take(500).collect::<String>()));
+            }
+        }
+        Err(e) => {
+            // Without the URL: query-parameter auth puts the API key in it
+            result.error = Some(if e.is_timeout() { "Request timeout".to_string() } else { e.without_url().to_string() });
+        }
+    }
+    result
+}
+
+/// URL and headers for an authenticated GET of the provider's model-list endpoint.
+/// `query` (e.g. "?limit=1000") is appended before auth, which may add its own parameter.
+fn provider_models_request(
+    provider: &Provider,
+    cli_type: crate::services::proxy::CliType,
+    query: &str,
+) -> Result<(String, reqwest::header::HeaderMap)> {
+    use crate::services::proxy::{apply_header_rules, apply_provider_auth, apply_useragent_override, CliType};
+
+    let base_url = provider.base_url.trim_end_matches('/');
+    let url = match cli_type {
+        CliType::ClaudeCode => format!("{}/v1/models{}", base_url, query),
+        CliType::Codex => format!("{}/models{}", base_url, query),
+        CliType::Gemini => format!("{}/v1beta/models{}", base_url, query),
+    };
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    let url = apply_provider_auth(
+        &mut headers,
+        &url,
+        &provider.api_key,
+        cli_type,
+        provider.auth_header_name.as_deref(),
+        provider.auth_scheme.as_deref(),
+    )?;
+    apply_useragent_override(&mut headers, provider.custom_useragent.as_deref());
+    if cli_type == CliType::ClaudeCode {
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+    }
+    apply_header_rules(&mut headers, provider.strip_headers.as_deref(), provider.header_overrides.as_deref());
+    Ok((url, headers))
+}
+
+fn provider_test_client(options: &crate::services::proxy::ClientOptions) -> Result<reqwest::Client> {
+    options
+        .apply(reqwest::Client::builder())?
+        .timeout(std::time::Duration::from_secs(PROVIDER_TEST_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Check that a provider is reachable and accepts its credentials.
+#[tauri::command]
+pub async fn test_provider_connection(
+    db: State<'_, SqlitePool>,
+    id: i64,
+) -> Result<ProviderTestResult> {
+    let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Provider not found".to_string())?;
+
+    let client = provider_test_client(&Default::default())?;
+    Ok(probe_provider(&client, &provider).await)
+}
+
+/// How long a fetched upstream model list is reused
+const MODEL_LIST_CACHE_SECS: u64 = 300;
+
+struct CachedModelList {
+    // Editing the provider (base URL, key, ...) bumps updated_at and invalidates the entry
+    updated_at: i64,
+    fetched_at: std::time::Instant,
+    models: Vec<String>,
+}
+
+static MODEL_LIST_CACHE: std::sync::OnceLock<std::sync::Mutex<std::collections::HashMap<i64, CachedModelList>>> =
+    std::sync::OnceLock::new();
+
+/// Model ids offered by a provider's upstream, for picking model-map targets.
+/// Results are cached per provider for a few minutes.
+#[tauri::command]
+pub async fn list_provider_models(
+    db: State<'_, SqlitePool>,
+    id: i64,
+) -> Result<Vec<String>> {
+    use crate::services::proxy::CliType;
+
+    let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Provider not found".to_string())?;
+
+    let cache = MODEL_LIST_CACHE.get_or_init(Default::default);
+    if let Some(cached) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(&id) {
+        if cached.updated_at == provider.updated_at
+            && cached.fetched_at.elapsed() < std::time::Duration::from_secs(MODEL_LIST_CACHE_SECS)
+        {
+            return Ok(cached.models.clone());
+        }
+    }
+
+    let cli_type: CliType = provider.cli_type.parse()?;
+    // Ask for the largest page so one request covers typical catalogs
+    let query = match cli_type {
+        CliType::ClaudeCode => "?limit=1000",
+        CliType::Codex => "",
+        CliType::Gemini => "?pageSize=1000",
+    };
+    let (url, headers) = provider_models_request(&provider, cli_type, query)?;
+
+    let timeout_secs = get_timeout_settings_impl(db.inner()).await
+        .map(|t| t.non_stream_timeout)
+        .unwrap_or(120);
+    let client = crate::services::proxy::ClientOptions::for_provider(&provider)
+        .apply(reqwest::Client::builder())?
+        .timeout(std::time::Duration::from_secs(timeout_secs.max(1) as u64))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    // Errors are reported without the URL: query-parameter auth puts the API key in it
+    let response = client.get(&url).headers(headers).send().await.map_err(|e| {
+        if e.is_timeout() { "Request timeout".to_string() } else { e.without_url().to_string() }
+    })?;
+    let status = response.status();
+    let bytes = response.bytes().await.map_err(|e| e.without_url().to_string())?;
+    if !status.is_success() {
+        let raw = String::from_utf8_lossy(&bytes);
+        return Err(format!("HTTP {}: {}", status.as_u16(), raw.chars().take(500).collect::<String>()));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&bytes)
+        .map_err(|e| format!("Unexpected model list response: {}", e))?;
+    let mut models = parse_model_ids(&json, cli_type);
+    models.sort();
+    models.dedup();
+
+    cache.lock().unwrap_or_else(|e| e.into_inner()).insert(
+        id,
+        CachedModelList {
+            updated_at: provider.updated_at,
+            fetched_at: std::time::Instant::now(),
+            models: models.clone(),
+        },
+    );
+    Ok(models)
+}
+
+// Anthropic and OpenAI list `data[].id`; Gemini lists `models[].name` as "models/<id>"
+fn parse_model_ids(json: &serde_json::Value, cli_type: crate::services::proxy::CliType) -> Vec<String> {
+    use crate::services::proxy::CliType;
+
+    let (list, field) = match cli_type {
+        CliType::Gemini => (&json["models"], "name"),
+        _ => (&json["data"], "id"),
+    };
+    list.as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|m| m[field].as_str())
+                .map(|id| id.trim_start_matches("models/").to_string())
+                .filter(|id| !id.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Probe every enabled provider concurrently. With `clear_blacklist`, providers that
+/// pass get their failure count and blacklist reset.
+#[tauri::command]
+pub async fn test_all_providers(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    clear_blacklist: Option<bool>,
+) -> Result<Vec<ProviderTestResult>> {
+    use futures_util::StreamExt;
+
+    let providers = sqlx::query_as::<_, Provider>(
+        "SELECT * FROM providers WHERE enabled = 1 ORDER BY cli_type, sort_order, id",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let client = provider_test_client(&Default::default())?;
+    let mut results: Vec<ProviderTestResult> = futures_util::stream::iter(providers.clone())
+        .map(|provider| {
+            let client = client.clone();
+            async move { probe_provider(&client, &provider).await }
+        })
+        .buffered(MAX_CONCURRENT_PROVIDER_TESTS)
+        .collect()
+        .await;
+
+    if clear_blacklist.unwrap_or(false) {
+        let now = chrono::Utc::now().timestamp();
+        for (result, provider) in results.iter_mut().zip(&providers) {
+            let blacklisted = provider.blacklist_remaining_secs(now).is_some();
+            if !result.success || !blacklisted {
+                continue;
+            }
+            sqlx::query("UPDATE providers SET consecutive_failures = 0, blacklisted_until = NULL WHERE id = ?")
+                .bind(provider.id)
+                .execute(db.inner())
+                .await
+                .map_err(|e| e.to_string())?;
+            result.unblacklisted = true;
+        }
+    }
+
+    let passed = results.iter().filter(|r| r.success).count();
+    let failed: Vec<&str> = results.iter().filter(|r| !r.success).map(|r| r.provider_name.as_str()).collect();
+    let unblacklisted = results.iter().filter(|r| r.unblacklisted).count();
+    let mut message = format!("批量测试服务商: {} 个通过，{} 个失败", passed, failed.len());
+    if !failed.is_empty() {
+        message.push_str(&format!("（{}）", failed.join(", ")));
+    }
+    if unblacklisted > 0 {
+        message.push_str(&format!("，已解除 {} 个服务商的黑名单", unblacklisted));
+    }
+    let _ = crate::services::stats::record_system_log(&log_db.0, "providers_tested", &message).await;
+
+    Ok(results)
+}
+
+// Pull the first text fragment out of a non-streaming completion response
+fn extract_completion_text(json: &serde_json::Value, cli_type: crate::services::proxy::CliType) -> Option<String> {
+    use crate::services::proxy::CliType;
+
+    match cli_type {
+        // {"content": [{"type": "text", "text": "..."}]}
+        CliType::ClaudeCode => json.get("content")?.as_array()?
+            .iter()
+            .find_map(|c| c.get("text").and_then(|t| t.as_str()))
+            .map(|s| s.to_string()),
+        // {"output": [{"content": [{"type": "output_text", "text": "..."}]}]}
+        CliType::Codex => json.get("output")?.as_array()?
+            .iter()
+            .filter_map(|o| o.get("content").and_then(|c| c.as_array()))
+            .flatten()
+            .find_map(|c| c.get("text").and_then(|t| t.as_str()))
+            .map(|s| s.to_string()),
+        // {"candidates": [{"content": {"parts": [{"text": "..."}]}}]}
+        CliType::Gemini => json.get("candidates")?.get(0)?
+            .get("content")?.get("parts")?.as_array()?
+            .iter()
+            .find_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .map(|s| s.to_string()),
+    }
+}
+
+// Settings commands
+#[tauri::command]
+pub async fn get_gateway_settings(db: State<'_, SqlitePool>) -> Result<GatewaySettings> {
+    get_gateway_settings_impl(db.inner()).await
+}
+
+pub(crate) async fn get_gateway_settings_impl(db: &SqlitePool) -> Result<GatewaySettings> {
+    // 只返回是否设置了管理令牌，不返回令牌本身
+    sqlx::query_as::<_, GatewaySettings>("SELECT *, admin_token IS NOT NULL AS admin_api_enabled FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Update only the gateway settings present in `input`
+#[tauri::command]
+pub async fn update_gateway_settings(
+    db: State<'_, SqlitePool>,
+    input: GatewaySettingsUpdate,
+) -> Result<()> {
+    crate::services::settings::update_gateway(db.inner(), &input).await?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_timeout_settings(db: State<'_, SqlitePool>) -> Result<TimeoutSettings> {
+    get_timeout_settings_impl(db.inner()).await
+}
+
+pub(crate) async fn get_timeout_settings_impl(db: &SqlitePool) -> Result<TimeoutSettings> {
+    sqlx::query_as::<_, TimeoutSettings>(
+        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout FROM timeout_settings WHERE id = 1",
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn update_timeout_settings(
+    db: State<'_, SqlitePool>,
+    input: TimeoutSettingsUpdate,
+) -> Result<()> {
+    update_timeout_settings_impl(db.inner(), input).await
+}
+
+pub(crate) async fn update_timeout_settings_impl(
+    db: &SqlitePool,
+    input: TimeoutSettingsUpdate,
+) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let current = get_timeout_settings_impl(db).await?;
+
+    sqlx::query(
+        "UPDATE timeout_settings SET stream_first_byte_timeout = ?, stream_idle_timeout = ?, non_stream_timeout = ?, updated_at = ? WHERE id = 1",
+    )
+    .bind(input.stream_first_byte_timeout.unwrap_or(current.stream_first_byte_timeout))
+    .bind(input.stream_idle_timeout.unwrap_or(current.stream_idle_timeout))
+    .bind(input.non_stream_timeout.unwrap_or(current.non_stream_timeout))
+    .bind(now)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_cli_settings(db: State<'_, SqlitePool>, cli_type: String) -> Result<CliSettingsResponse> {
+    get_cli_settings_impl(db.inner(), cli_type).await
+}
+
+pub(crate) async fn get_cli_settings_impl(db: &SqlitePool, cli_type: String) -> Result<CliSettingsResponse> {
+    let row = sqlx::query_as::<_, CliSettingsRow>(
+        "SELECT cli_type, default_json_config, updated_at FROM cli_settings WHERE cli_type = ?",
+    )
+    .bind(&cli_type)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(row) = row {
+        // Check if CLI is enabled by reading config file
+        let enabled = check_cli_enabled(&cli_type);
+        Ok(CliSettingsResponse {
+            cli_type: row.cli_type,
+            enabled,
+            default_json_config: row.default_json_config.unwrap_or_default(),
+        })
+    } else {
+        Ok(CliSettingsResponse {
+            cli_type,
+            enabled: false,
+            default_json_config: String::new(),
+        })
+    }
+}
+
+/// Check default_json_config syntax (JSON for claude_code/gemini, TOML for codex)
+fn validate_cli_config(cli_type: &str, config: &str) -> std::result::Result<(), ConfigValidationError> {
+    match cli_type {
+        "claude_code" | "gemini" => {
+            serde_json::from_str::<serde_json::Value>(config).map_err(|e| ConfigValidationError {
+                message: format!("JSON 格式错误: {}", e),
+                line: Some(e.line()),
+                column: Some(e.column()),
+            })?;
+        }
+        "codex" => {
+            config.parse::<toml_edit::DocumentMut>().map_err(|e| {
+                let (line, column) = match e.span() {
+                    Some(span) => {
+                        let (line, column) = line_column_at(config, span.start);
+                        (Some(line), Some(column))
+                    }
+                    None => (None, None),
+                };
+                ConfigValidationError {
+                    message: format!("TOML 格式错误: {}", e.message()),
+                    line,
+                    column,
+                }
+            })?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// 1-based line and column (in characters) of a byte offset
+fn line_column_at(text: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+#[tauri::command]
+pub async fn update_cli_settings(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    cli_type: String,
+    input: CliSettingsUpdate,
+) -> std::result::Result<(), ConfigValidationError> {
+    if let Some(changed) = update_cli_settings_impl(db.inner(), &log_db.0, cli_type, input).await? {
+        use tauri::Emitter;
+        let _ = app.emit("cli-enabled-changed", changed);
+    }
+    Ok(())
+}
+
+/// Save a CLI's default config and/or toggle it; shared by `update_cli_settings` and the HTTP admin API.
+/// Returns the `cli-enabled-changed` event for the caller to emit when the CLI was toggled.
+pub(crate) async fn update_cli_settings_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    cli_type: String,
+    input: CliSettingsUpdate,
+) -> std::result::Result<Option<CliEnabledChanged>, ConfigValidationError> {
+    let now = chrono::Utc::now().timestamp();
+
+    // Validate and update database
+    if let Some(ref config) = input.default_json_config {
+        let config_trimmed = config.trim();
+
+        // Validate format if config is not empty; positions refer to the untrimmed editor text
+        if !config_trimmed.is_empty() {
+            validate_cli_config(&cli_type, config)?;
+        }
+
+        sqlx::query(
+            "UPDATE cli_settings SET default_json_config = ?, updated_at = ? WHERE cli_type = ?",
+        )
+        .bind(config_trimmed)
+        .bind(now)
+        .bind(&cli_type)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+
+    // Update CLI config file if enabled flag is provided
+    let Some(enabled) = input.enabled else {
+        return Ok(None);
+    };
+
+    // Get default_json_config from database
+    let row = sqlx::query_as::<_, CliSettingsRow>(
+        "SELECT cli_type, default_json_config, updated_at FROM cli_settings WHERE cli_type = ?",
+    )
+    .bind(&cli_type)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let default_config = row.and_then(|r| r.default_json_config).unwrap_or_default();
+    sync_cli_config(&cli_type, enabled, &default_config).await?;
+
+    // Audit trail + notify other windows / tray
+    let files: Vec<String> = get_cli_toggle_files(&cli_type)
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect();
+    let _ = crate::services::stats::record_system_log(
+        log_db,
+        if enabled { "cli_enabled" } else { "cli_disabled" },
+        &format!(
+            "CLI {} 已{}，涉及文件: {}",
+            cli_type,
+            if enabled { "启用" } else { "停用" },
+            files.join(", ")
+        ),
+    ).await;
+
+    Ok(Some(CliEnabledChanged { cli_type, enabled, files }))
+}
+
+// Config files written/restored when toggling a CLI
+fn get_cli_toggle_files(cli_type: &str) -> Vec<std::path::PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return vec![];
+    };
+    match cli_type {
+        "claude_code" => vec![home.join(".claude").join("settings.json")],
+        "codex" => vec![home.join(".codex").join("auth.json"), home.join(".codex").join("config.toml")],
+        "gemini" => vec![home.join(".gemini").join("settings.json"), home.join(".gemini").join(".env")],
+        _ => vec![],
+    }
+}
+
+/// Every file the gateway reads or writes for a CLI, with its on-disk status.
+/// A file shared by several features (e.g. Gemini's settings.json) is listed once.
+#[tauri::command]
+pub async fn get_cli_config_files(cli_type: String) -> Result<Vec<ConfigFileInfo>> {
+    cli_type.parse::<crate::services::proxy::CliType>()?;
+
+    let mut files: Vec<(std::path::PathBuf, &str)> = get_cli_toggle_files(&cli_type)
+        .into_iter()
+        .map(|path| (path, "gateway"))
+        .collect();
+    files.extend(get_mcp_config_path(&cli_type).map(|path| (path, "mcp")));
+    files.extend(get_prompt_file_path(&cli_type).map(|path| (path, "prompt")));
+
+    let mut result: Vec<ConfigFileInfo> = Vec::new();
+    for (path, usage) in files {
+        let path_str = path.to_string_lossy().to_string();
+        if let Some(existing) = result.iter_mut().find(|f| f.path == path_str) {
+            existing.usages.push(usage.to_string());
+            continue;
+        }
+
+        let metadata = std::fs::metadata(&path).ok();
+        result.push(ConfigFileInfo {
+            name: path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+            path: path_str,
+            usages: vec![usage.to_string()],
+            exists: metadata.is_some(),
+            has_backup: get_backup_path(&path).exists(),
+            size: metadata.as_ref().map(|m| m.len()),
+            modified_at: metadata
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64),
+        });
+    }
+    Ok(result)
+}
+
+// Placeholder returned in place of stored secrets
+const REDACTED_SECRET: &str = "******";
+
+/// Fetch every settings group in one call so the settings UI can hydrate with a single round-trip.
+/// The WebDAV password is redacted; use `get_webdav_settings` when the real value is needed.
+#[tauri::command]
+pub async fn get_all_settings(db: State<'_, SqlitePool>) -> Result<AllSettings> {
+    get_all_settings_impl(db.inner()).await
+}
+
+pub(crate) async fn get_all_settings_impl(db: &SqlitePool) -> Result<AllSettings> {
+    let gateway = get_gateway_settings_impl(db).await?;
+    let timeouts = get_timeout_settings_impl(db).await?;
+
+    let mut webdav = get_webdav_settings_impl(db).await?;
+    if !webdav.password.is_empty() {
+        webdav.password = REDACTED_SECRET.to_string();
+    }
+
+    let useragent_overrides = sqlx::query_as::<_, ProviderUseragent>(
+        "SELECT id AS provider_id, name AS provider_name, cli_type, custom_useragent FROM providers WHERE custom_useragent IS NOT NULL AND custom_useragent != '' ORDER BY cli_type, sort_order",
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut cli_settings = Vec::new();
+    for cli_type in ["claude_code", "codex", "gemini"] {
+        cli_settings.push(get_cli_settings_impl(db, cli_type.to_string()).await?);
+    }
+
+    Ok(AllSettings {
+        gateway,
+        timeouts,
+        webdav,
+        useragent_overrides,
+        cli_settings,
+    })
+}
+
+// Webhook commands
+#[tauri::command]
+pub async fn get_webhook_settings(db: State<'_, SqlitePool>) -> Result<WebhookSettings> {
+    get_webhook_settings_impl(db.inner()).await
+}
+
+pub(crate) async fn get_webhook_settings_impl(db: &SqlitePool) -> Result<WebhookSettings> {
+    let settings = sqlx::query_as::<_, WebhookSettings>(
+        "SELECT COALESCE(url, '') AS url, template, enabled FROM webhook_settings WHERE id = 1",
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(settings.unwrap_or(WebhookSettings {
+        url: String::new(),
+        template: None,
+        enabled: false,
+    }))
 }
 
 #[tauri::command]
-pub async fn delete_provider(
+pub async fn update_webhook_settings(
     db: State<'_, SqlitePool>,
-    log_db: State<'_, LogDb>,
-    id: i64,
-) -> Result<()> {
-    // Get provider name before deletion
-    let provider_name: Option<(String,)> = sqlx::query_as(
-        "SELECT name FROM providers WHERE id = ?",
+    input: WebhookSettingsUpdate,
+) -> Result<WebhookSettings> {
+    update_webhook_settings_impl(db.inner(), input).await
+}
+
+pub(crate) async fn update_webhook_settings_impl(
+    db: &SqlitePool,
+    input: WebhookSettingsUpdate,
+) -> Result<WebhookSettings> {
+    let current = get_webhook_settings_impl(db).await?;
+
+    let url = input.url.map(|u| u.trim().to_string()).unwrap_or(current.url);
+    if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err("Webhook URL must start with http:// or https://".to_string());
+    }
+    let template = match input.template {
+        Some(t) if t.trim().is_empty() => None,
+        Some(t) => Some(t),
+        None => current.template,
+    };
+    let enabled = input.enabled.unwrap_or(current.enabled);
+
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query(
+        "INSERT INTO webhook_settings (id, url, template, enabled, updated_at) VALUES (1, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET url = excluded.url, template = excluded.template, enabled = excluded.enabled, updated_at = excluded.updated_at",
     )
-    .bind(id)
-    .fetch_optional(db.inner())
+    .bind(&url)
+    .bind(&template)
+    .bind(enabled as i64)
+    .bind(now)
+    .execute(db)
     .await
     .map_err(|e| e.to_string())?;
 
-    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
+    Ok(WebhookSettings { url, template, enabled })
+}
 
-    // Delete associated model maps first (cascade delete)
-    sqlx::query("DELETE FROM provider_model_map WHERE provider_id = ?")
-        .bind(id)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+#[tauri::command]
+pub async fn delete_webhook_settings(db: State<'_, SqlitePool>) -> Result<()> {
+    delete_webhook_settings_impl(db.inner()).await
+}
 
-    // Then delete the provider
-    sqlx::query("DELETE FROM providers WHERE id = ?")
-        .bind(id)
-        .execute(db.inner())
+pub(crate) async fn delete_webhook_settings_impl(db: &SqlitePool) -> Result<()> {
+    sqlx::query("DELETE FROM webhook_settings WHERE id = 1")
+        .execute(db)
         .await
         .map_err(|e| e.to_string())?;
-
-    // Log system event
-    let _ = crate::services::stats::record_system_log(
-        &log_db.0,
-        "provider_deleted",
-        &format!("服务商 {} 已删除", provider_name),
-    ).await;
-
     Ok(())
 }
 
+/// Send a sample payload to the configured webhook and report the outcome
 #[tauri::command]
-pub async fn reorder_providers(db: State<'_, SqlitePool>, ids: Vec<i64>) -> Result<()> {
-    for (idx, id) in ids.iter().enumerate() {
-        sqlx::query("UPDATE providers SET sort_order = ? WHERE id = ?")
-            .bind(idx as i64)
-            .bind(id)
-            .execute(db.inner())
-            .await
-            .map_err(|e| e.to_string())?;
+pub async fn test_webhook(db: State<'_, SqlitePool>) -> Result<()> {
+    test_webhook_impl(db.inner()).await
+}
+
+pub(crate) async fn test_webhook_impl(db: &SqlitePool) -> Result<()> {
+    let settings = get_webhook_settings_impl(db).await?;
+    if settings.url.is_empty() {
+        return Err("Webhook URL is not configured".to_string());
     }
-    Ok(())
+
+    let payload = crate::services::webhook::WebhookPayload::new(
+        "test",
+        Some("example-provider"),
+        "This is a test notification from CCG Gateway",
+    );
+    crate::services::webhook::send(&settings.url, settings.template.as_deref(), &payload).await
 }
 
+// GitHub commands
 #[tauri::command]
-pub async fn reset_provider_failures(
-    db: State<'_, SqlitePool>,
-    log_db: State<'_, LogDb>,
-    id: i64,
-) -> Result<()> {
-    // Get provider name for logging
-    let provider_name: Option<(String,)> = sqlx::query_as(
-        "SELECT name FROM providers WHERE id = ?",
+pub async fn get_github_settings(db: State<'_, SqlitePool>) -> Result<GithubSettings> {
+    get_github_settings_impl(db.inner()).await
+}
+
+pub(crate) async fn get_github_settings_impl(db: &SqlitePool) -> Result<GithubSettings> {
+    let has_token = crate::services::github::load_token(db).await.is_some();
+    Ok(GithubSettings { has_token })
+}
+
+/// Set the GitHub token used for update checks and skill downloads; empty or None clears it
+#[tauri::command]
+pub async fn update_github_token(db: State<'_, SqlitePool>, token: Option<String>) -> Result<GithubSettings> {
+    update_github_token_impl(db.inner(), token).await
+}
+
+pub(crate) async fn update_github_token_impl(
+    db: &SqlitePool,
+    token: Option<String>,
+) -> Result<GithubSettings> {
+    let token = token.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query(
+        "INSERT INTO github_settings (id, token, updated_at) VALUES (1, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET token = excluded.token, updated_at = excluded.updated_at",
     )
-    .bind(id)
-    .fetch_optional(db.inner())
+    .bind(&token)
+    .bind(now)
+    .execute(db)
     .await
     .map_err(|e| e.to_string())?;
 
-    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
+    Ok(GithubSettings { has_token: token.is_some() })
+}
 
-    sqlx::query("UPDATE providers SET consecutive_failures = 0, blacklisted_until = NULL WHERE id = ?")
-        .bind(id)
-        .execute(db.inner())
+// File logging commands
+#[tauri::command]
+pub async fn get_file_logging() -> Result<bool> {
+    Ok(crate::config::is_file_log_enabled())
+}
+
+/// Resolved configuration as applied at runtime (env overrides included), for diagnostics
+#[tauri::command]
+pub async fn get_effective_config(config: State<'_, crate::config::Config>) -> Result<EffectiveConfig> {
+    Ok(EffectiveConfig {
+        host: config.server.host.clone(),
+        port: config.server.port,
+        data_dir: get_data_dir().display().to_string(),
+        log_dir: crate::config::get_log_dir().display().to_string(),
+        db_path: config.database.path.display().to_string(),
+        log_db_path: config.database.log_path.display().to_string(),
+        file_logging: crate::config::is_file_log_enabled(),
+        log_level: crate::config::get_log_level(),
+    })
+}
+
+/// Serializes compact_databases runs
+static COMPACT_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// Database file size including its WAL file
+fn database_file_size(path: &std::path::Path) -> u64 {
+    let wal = std::path::PathBuf::from(format!("{}-wal", path.display()));
+    [path, wal.as_path()]
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+async fn compact_database(name: &str, pool: &SqlitePool, path: &std::path::Path) -> Result<DatabaseCompactResult> {
+    let size_before = database_file_size(path);
+    crate::db::retry_on_locked(|| sqlx::query("VACUUM").execute(pool))
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| format!("Failed to compact {} database: {}", name, e))?;
+    // The log DB runs in WAL mode: fold the WAL back so the freed space shows up on disk
+    let _ = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(pool).await;
+    let size_after = database_file_size(path);
+
+    Ok(DatabaseCompactResult {
+        database: name.to_string(),
+        path: path.display().to_string(),
+        size_before,
+        size_after,
+        reclaimed: size_before.saturating_sub(size_after),
+    })
+}
 
-    // Log system event
+/// VACUUM the main and log databases and report their sizes before and after
+#[tauri::command]
+pub async fn compact_databases(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    config: State<'_, crate::config::Config>,
+) -> Result<Vec<DatabaseCompactResult>> {
+    let _guard = COMPACT_LOCK
+        .try_lock()
+        .map_err(|_| "Database compaction is already running".to_string())?;
+
+    let results = vec![
+        compact_database("main", db.inner(), &config.database.path).await?,
+        compact_database("log", &log_db.0, &config.database.log_path).await?,
+    ];
+
+    let reclaimed: u64 = results.iter().map(|r| r.reclaimed).sum();
+    tracing::info!(reclaimed, "Compacted databases");
     let _ = crate::services::stats::record_system_log(
         &log_db.0,
-        "provider_reset",
-        &format!("服务商 {} 状态已手动重置", provider_name),
+        "databases_compacted",
+        &format!("数据库已压缩，共释放 {:.1} MB", reclaimed as f64 / 1024.0 / 1024.0),
     ).await;
 
-    Ok(())
+    Ok(results)
 }
 
-// Settings commands
-#[tauri::command]
-pub async fn get_gateway_settings(db: State<'_, SqlitePool>) -> Result<GatewaySettings> {
-    sqlx::query_as::<_, GatewaySettings>("SELECT debug_log FROM gateway_settings WHERE id = 1")
-        .fetch_one(db.inner())
-        .await
-        .map_err(|e| e.to_string())
+// Database size and file count, including its -wal/-shm files
+fn database_usage(path: &std::path::Path) -> (u64, u64) {
+    ["", "-wal", "-shm"]
+        .iter()
+        .filter_map(|suffix| std::fs::metadata(format!("{}{}", path.display(), suffix)).ok())
+        .fold((0, 0), |(bytes, items), m| (bytes + m.len(), items + 1))
 }
 
-#[tauri::command]
-pub async fn update_gateway_settings(db: State<'_, SqlitePool>, debug_log: bool) -> Result<()> {
-    let now = chrono::Utc::now().timestamp();
-    sqlx::query("UPDATE gateway_settings SET debug_log = ?, updated_at = ? WHERE id = 1")
-        .bind(debug_log as i64)
-        .bind(now)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
-    Ok(())
+// Total size of a directory tree and its number of top-level entries; (0, 0) if missing
+fn directory_usage(path: &std::path::Path) -> (u64, u64) {
+    let items = std::fs::read_dir(path).map(|entries| entries.count() as u64).unwrap_or(0);
+    let bytes = walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum();
+    (bytes, items)
 }
 
+/// Disk usage of the data directory by category: databases, installed skills,
+/// the skill repo ZIP cache and log files
 #[tauri::command]
-pub async fn get_timeout_settings(db: State<'_, SqlitePool>) -> Result<TimeoutSettings> {
-    sqlx::query_as::<_, TimeoutSettings>(
-        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout FROM timeout_settings WHERE id = 1",
-    )
-    .fetch_one(db.inner())
+pub async fn get_storage_usage(config: State<'_, crate::config::Config>) -> Result<StorageUsage> {
+    let data_dir = get_data_dir();
+    let db_path = config.database.path.clone();
+    let log_db_path = config.database.log_path.clone();
+
+    tokio::task::spawn_blocking(move || {
+        // (name, path, is_database)
+        let entries = [
+            ("main_db", db_path, true),
+            ("log_db", log_db_path, true),
+            ("skills", data_dir.join("skills"), false),
+            ("skill_repo_cache", data_dir.join("skill_repo"), false),
+            ("logs", crate::config::get_log_dir(), false),
+        ];
+        let categories: Vec<StorageCategory> = entries
+            .into_iter()
+            .map(|(name, path, is_database)| {
+                let (bytes, items) = if is_database { database_usage(&path) } else { directory_usage(&path) };
+                StorageCategory {
+                    name: name.to_string(),
+                    path: path.display().to_string(),
+                    bytes,
+                    items,
+                }
+            })
+            .collect();
+
+        StorageUsage {
+            data_dir: data_dir.display().to_string(),
+            total_bytes: categories.iter().map(|c| c.bytes).sum(),
+            categories,
+        }
+    })
     .await
     .map_err(|e| e.to_string())
 }
 
+/// Compare the main and log databases against the schema this build expects
 #[tauri::command]
-pub async fn update_timeout_settings(
-    db: State<'_, SqlitePool>,
-    input: TimeoutSettingsUpdate,
-) -> Result<()> {
-    let now = chrono::Utc::now().timestamp();
-    let current = get_timeout_settings(db.clone()).await?;
-
-    sqlx::query(
-        "UPDATE timeout_settings SET stream_first_byte_timeout = ?, stream_idle_timeout = ?, non_stream_timeout = ?, updated_at = ? WHERE id = 1",
-    )
-    .bind(input.stream_first_byte_timeout.unwrap_or(current.stream_first_byte_timeout))
-    .bind(input.stream_idle_timeout.unwrap_or(current.stream_idle_timeout))
-    .bind(input.non_stream_timeout.unwrap_or(current.non_stream_timeout))
-    .bind(now)
-    .execute(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
-    Ok(())
+pub async fn verify_schema(db: State<'_, SqlitePool>, log_db: State<'_, LogDb>) -> Result<Vec<SchemaReport>> {
+    Ok(vec![
+        crate::db::verify_schema(db.inner(), false).await.map_err(|e| e.to_string())?,
+        crate::db::verify_schema(&log_db.0, true).await.map_err(|e| e.to_string())?,
+    ])
 }
 
-#[tauri::command]
-pub async fn get_cli_settings(db: State<'_, SqlitePool>, cli_type: String) -> Result<CliSettingsResponse> {
-    let row = sqlx::query_as::<_, CliSettingsRow>(
-        "SELECT cli_type, default_json_config, updated_at FROM cli_settings WHERE cli_type = ?",
-    )
-    .bind(&cli_type)
-    .fetch_optional(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
+/// Serializes migrate_schema runs
+static MIGRATE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
 
-    if let Some(row) = row {
-        // Check if CLI is enabled by reading config file
-        let enabled = check_cli_enabled(&cli_type);
-        Ok(CliSettingsResponse {
-            cli_type: row.cli_type,
-            enabled,
-            default_json_config: row.default_json_config.unwrap_or_default(),
-        })
-    } else {
-        Ok(CliSettingsResponse {
-            cli_type,
-            enabled: false,
-            default_json_config: String::new(),
-        })
+/// Bring both databases to the expected schema with the same migration init_db runs,
+/// regardless of the recorded version. Returns the reports after migrating.
+#[tauri::command]
+pub async fn migrate_schema(db: State<'_, SqlitePool>, log_db: State<'_, LogDb>) -> Result<Vec<SchemaReport>> {
+    let _guard = MIGRATE_LOCK
+        .try_lock()
+        .map_err(|_| "Schema migration is already running".to_string())?;
+
+    let mut changes = 0;
+    for (pool, is_log_db) in [(db.inner(), false), (&log_db.0, true)] {
+        // A database written by a newer build would lose its newer columns
+        let report = crate::db::verify_schema(pool, is_log_db).await.map_err(|e| e.to_string())?;
+        if report.version > report.expected_version {
+            return Err(format!(
+                "The {} database is at schema version {}, newer than this build ({}); refusing to migrate",
+                report.database, report.version, report.expected_version
+            ));
+        }
+        changes += crate::db::migrate_schema(pool, is_log_db)
+            .await
+            .map_err(|e| format!("Failed to migrate {} database: {}", report.database, e))?;
     }
+
+    tracing::info!(changes, "Schema migration finished");
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "schema_migrated",
+        &format!("数据库结构已修复，应用 {} 个变更", changes),
+    ).await;
+
+    Ok(vec![
+        crate::db::verify_schema(db.inner(), false).await.map_err(|e| e.to_string())?,
+        crate::db::verify_schema(&log_db.0, true).await.map_err(|e| e.to_string())?,
+    ])
 }
 
+/// Enable or disable writing logs to files; takes effect immediately and persists across restarts
 #[tauri::command]
-pub async fn update_cli_settings(
-    db: State<'_, SqlitePool>,
-    cli_type: String,
-    input: CliSettingsUpdate,
-) -> Result<()> {
-    let now = chrono::Utc::now().timestamp();
+pub async fn set_file_logging(log_db: State<'_, crate::LogDb>, enabled: bool) -> Result<()> {
+    crate::config::set_file_log_enabled(enabled)?;
+    tracing::info!(enabled, "File logging setting changed");
 
-    // Validate and update database
-    if let Some(ref config) = input.default_json_config {
-        let config_trimmed = config.trim();
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "file_logging_changed",
+        &format!("文件日志已{}", if enabled { "开启" } else { "关闭" }),
+    ).await;
 
-        // Validate format if config is not empty
-        if !config_trimmed.is_empty() {
-            match cli_type.as_str() {
-                "claude_code" | "gemini" => {
-                    // Validate JSON format
-                    serde_json::from_str::<serde_json::Value>(config_trimmed)
-                        .map_err(|e| format!("JSON 格式错误: {}", e))?;
-                }
-                "codex" => {
-                    // Validate TOML format
-                    config_trimmed.parse::<toml_edit::DocumentMut>()
-                        .map_err(|e| format!("TOML 格式错误: {}", e))?;
-                }
-                _ => {}
-            }
-        }
+    Ok(())
+}
 
-        sqlx::query(
-            "UPDATE cli_settings SET default_json_config = ?, updated_at = ? WHERE cli_type = ?",
-        )
-        .bind(config_trimmed)
-        .bind(now)
-        .bind(&cli_type)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
-    }
+/// Lines returned by get_crash_log when no limit is given
+const CRASH_LOG_DEFAULT_LINES: usize = 200;
+/// Only the end of crash.log is read; each panic appends a full backtrace
+const CRASH_LOG_MAX_TAIL_BYTES: u64 = 1024 * 1024;
 
-    // Update CLI config file if enabled flag is provided
-    if let Some(enabled) = input.enabled {
-        // Get default_json_config from database
-        let row = sqlx::query_as::<_, CliSettingsRow>(
-            "SELECT cli_type, default_json_config, updated_at FROM cli_settings WHERE cli_type = ?",
-        )
-        .bind(&cli_type)
-        .fetch_optional(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+fn crash_log_path() -> std::path::PathBuf {
+    crate::config::get_log_dir().join("crash.log")
+}
+
+/// Last lines of crash.log written by the panic hook; empty when there is none
+#[tauri::command]
+pub async fn get_crash_log(limit_lines: Option<usize>) -> Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let limit = limit_lines.unwrap_or(CRASH_LOG_DEFAULT_LINES).max(1);
+    let mut file = match std::fs::File::open(crash_log_path()) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    let start = len.saturating_sub(CRASH_LOG_MAX_TAIL_BYTES);
+    file.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).map_err(|e| e.to_string())?;
 
-        let default_config = row.and_then(|r| r.default_json_config).unwrap_or_default();
-        sync_cli_config(&cli_type, enabled, &default_config, db).await?;
+    let text = String::from_utf8_lossy(&buf);
+    let mut lines: Vec<&str> = text.lines().collect();
+    // A tail read usually starts mid-line
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
     }
+    let skip = lines.len().saturating_sub(limit);
+    Ok(lines[skip..].join("\n"))
+}
 
-    Ok(())
+/// Delete crash.log (e.g. once the user has read it)
+#[tauri::command]
+pub async fn clear_crash_log() -> Result<()> {
+    match std::fs::remove_file(crash_log_path()) {
+        Ok(()) => {
+            tracing::info!("Crash log cleared");
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 // Normalize text for comparison: trim, normalize whitespace, remove extra blank lines
@@ -600,20 +2391,13 @@ fn mcp_enabled_in_file(cli_type: &str, mcp_name: &str) -> bool {
     }
 }
 
-// Check if prompt content matches the file content
-fn prompt_enabled_in_file(cli_type: &str, prompt_content: &str) -> bool {
-    let home = match dirs::home_dir() {
-        Some(h) => h,
+// Check if prompt content matches the file content (global or project-local file)
+fn prompt_enabled_in_file(cli_type: &str, prompt_content: &str, project_path: Option<&str>) -> bool {
+    let prompt_path = match get_project_prompt_file_path(cli_type, project_path) {
+        Some(path) => path,
         None => return false,
     };
 
-    let prompt_path = match cli_type {
-        "claude_code" => home.join(".claude").join("CLAUDE.md"),
-        "codex" => home.join(".codex").join("AGENTS.md"),
-        "gemini" => home.join(".gemini").join("GEMINI.md"),
-        _ => return false,
-    };
-
     if !prompt_path.exists() {
         return false;
     }
@@ -738,11 +2522,11 @@ fn get_mcp_config_path(cli_type: &str) -> Option<std::path::PathBuf> {
     }
 }
 
-async fn sync_cli_config(cli_type: &str, enabled: bool, default_config: &str, db: State<'_, SqlitePool>) -> Result<()> {
+async fn sync_cli_config(cli_type: &str, enabled: bool, default_config: &str) -> Result<()> {
     match cli_type {
-        "claude_code" => sync_claude_code_config(enabled, default_config, db).await,
-        "codex" => sync_codex_config(enabled, default_config, db).await,
-        "gemini" => sync_gemini_config(enabled, default_config, db).await,
+        "claude_code" => sync_claude_code_config(enabled, default_config).await,
+        "codex" => sync_codex_config(enabled, default_config).await,
+        "gemini" => sync_gemini_config(enabled, default_config).await,
         _ => Err("Invalid CLI type".to_string()),
     }
 }
@@ -805,7 +2589,7 @@ fn deep_merge(base: &mut serde_json::Value, override_val: &serde_json::Value) {
 }
 
 // Sync Claude Code configuration (settings.json)
-async fn sync_claude_code_config(enabled: bool, default_config: &str, _db: State<'_, SqlitePool>) -> Result<()> {
+async fn sync_claude_code_config(enabled: bool, default_config: &str) -> Result<()> {
     let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
     let config_path = home.join(".claude").join("settings.json");
 
@@ -868,7 +2652,7 @@ async fn sync_claude_code_config(enabled: bool, default_config: &str, _db: State
 }
 
 // Sync Codex configuration (auth.json + config.toml)
-async fn sync_codex_config(enabled: bool, default_config: &str, _db: State<'_, SqlitePool>) -> Result<()> {
+async fn sync_codex_config(enabled: bool, default_config: &str) -> Result<()> {
     let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
     let codex_dir = home.join(".codex");
     let auth_path = codex_dir.join("auth.json");
@@ -965,7 +2749,7 @@ async fn sync_codex_config(enabled: bool, default_config: &str, _db: State<'_, S
 }
 
 // Sync Gemini configuration (settings.json + .env)
-async fn sync_gemini_config(enabled: bool, default_config: &str, _db: State<'_, SqlitePool>) -> Result<()> {
+async fn sync_gemini_config(enabled: bool, default_config: &str) -> Result<()> {
     let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
     let gemini_dir = home.join(".gemini");
     let config_path = gemini_dir.join("settings.json");
@@ -1048,60 +2832,125 @@ async fn sync_gemini_config(enabled: bool, default_config: &str, _db: State<'_,
     Ok(())
 }
 
-// Log commands
+// Log commands
+#[tauri::command]
+pub async fn get_request_logs(
+    log_db: State<'_, crate::LogDb>,
+    page: Option<i64>,
+    page_size: Option<i64>,
+    cli_type: Option<String>,
+    client_ip: Option<String>,
+) -> Result<PaginatedLogs> {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+    let offset = (page - 1) * page_size;
+    let pool = &log_db.0;
+
+    let client_ip = client_ip.map(|ip| ip.trim().to_string()).filter(|ip| !ip.is_empty());
+    let mut conditions = Vec::new();
+    if cli_type.is_some() {
+        conditions.push("cli_type = ?");
+    }
+    if client_ip.is_some() {
+        conditions.push("client_ip = ?");
+    }
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    };
+
+    let items_sql = format!(
+        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, is_shadow, client_ip FROM request_logs{} ORDER BY id DESC LIMIT ? OFFSET ?",
+        where_clause
+    );
+    let mut items_query = sqlx::query_as::<_, RequestLogItem>(&items_sql);
+    let count_sql = format!("SELECT COUNT(*) FROM request_logs{}", where_clause);
+    let mut count_query = sqlx::query_scalar::<_, i64>(&count_sql);
+    for value in [&cli_type, &client_ip].into_iter().flatten() {
+        items_query = items_query.bind(value);
+        count_query = count_query.bind(value);
+    }
+
+    let items = items_query
+        .bind(page_size)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let total = count_query.fetch_one(pool).await.map_err(|e| e.to_string())?;
+
+    Ok(PaginatedLogs {
+        items,
+        total,
+        page,
+        page_size,
+    })
+}
+
+/// Request logs for a single provider, plus a summary (success rate, average latency)
+/// over the last `window_hours` (default 24).
 #[tauri::command]
-pub async fn get_request_logs(
+pub async fn get_provider_request_logs(
     log_db: State<'_, crate::LogDb>,
+    provider_name: String,
     page: Option<i64>,
     page_size: Option<i64>,
-    cli_type: Option<String>,
-) -> Result<PaginatedLogs> {
+    window_hours: Option<i64>,
+) -> Result<ProviderRequestLogs> {
     let page = page.unwrap_or(1).max(1);
     let page_size = page_size.unwrap_or(20).clamp(1, 100);
     let offset = (page - 1) * page_size;
     let pool = &log_db.0;
 
-    let (items, total) = if let Some(ct) = cli_type {
-        let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs WHERE cli_type = ? ORDER BY id DESC LIMIT ? OFFSET ?",
-        )
-        .bind(&ct)
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| e.to_string())?;
-
-        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs WHERE cli_type = ?")
-            .bind(&ct)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| e.to_string())?;
+    let items = sqlx::query_as::<_, RequestLogItem>(
+        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, is_shadow, client_ip FROM request_logs WHERE provider_name = ? ORDER BY id DESC LIMIT ? OFFSET ?",
+    )
+    .bind(&provider_name)
+    .bind(page_size)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
 
-        (items, total.0)
-    } else {
-        let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs ORDER BY id DESC LIMIT ? OFFSET ?",
-        )
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(pool)
+    let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs WHERE provider_name = ?")
+        .bind(&provider_name)
+        .fetch_one(pool)
         .await
         .map_err(|e| e.to_string())?;
 
-        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs")
-            .fetch_one(pool)
-            .await
-            .map_err(|e| e.to_string())?;
+    let window_hours = window_hours.unwrap_or(24).max(1);
+    let since = chrono::Utc::now().timestamp() - window_hours * 3600;
+    let (request_count, success_count, avg_elapsed_ms): (i64, i64, Option<f64>) = sqlx::query_as(
+        // Weighted by sample_weight so log sampling (which only drops successes) doesn't skew the rate
+        "SELECT CAST(ROUND(COALESCE(SUM(sample_weight), 0)) AS INTEGER), CAST(ROUND(COALESCE(SUM(CASE WHEN status_code BETWEEN 200 AND 299 THEN sample_weight ELSE 0 END), 0)) AS INTEGER), SUM(elapsed_ms * sample_weight) / SUM(sample_weight) FROM request_logs WHERE provider_name = ? AND created_at >= ? AND is_shadow = 0",
+    )
+    .bind(&provider_name)
+    .bind(since)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.to_string())?;
 
-        (items, total.0)
+    let success_rate = if request_count > 0 {
+        (success_count as f64 / request_count as f64) * 100.0
+    } else {
+        0.0
     };
 
-    Ok(PaginatedLogs {
-        items,
-        total,
-        page,
-        page_size,
+    Ok(ProviderRequestLogs {
+        logs: PaginatedLogs {
+            items,
+            total: total.0,
+            page,
+            page_size,
+        },
+        summary: ProviderLogSummary {
+            window_hours,
+            request_count,
+            success_count,
+            success_rate,
+            avg_elapsed_ms: avg_elapsed_ms.unwrap_or(0.0),
+        },
     })
 }
 
@@ -1114,13 +2963,43 @@ pub async fn clear_request_logs(log_db: State<'_, crate::LogDb>) -> Result<()> {
     Ok(())
 }
 
+#[tauri::command]
+pub async fn delete_request_log(log_db: State<'_, crate::LogDb>, id: i64) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM request_logs WHERE id = ?")
+        .bind(id)
+        .execute(&log_db.0)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
+
+#[tauri::command]
+pub async fn delete_request_logs(log_db: State<'_, crate::LogDb>, ids: Vec<i64>) -> Result<u64> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let sql = format!("DELETE FROM request_logs WHERE id IN ({})", placeholders);
+    let mut query = sqlx::query(&sql);
+    for id in &ids {
+        query = query.bind(id);
+    }
+
+    let result = query
+        .execute(&log_db.0)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
+
 #[tauri::command]
 pub async fn get_request_log_detail(
     log_db: State<'_, crate::LogDb>,
     id: i64,
 ) -> Result<RequestLogDetail> {
     sqlx::query_as::<_, RequestLogDetail>(
-        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, error_message FROM request_logs WHERE id = ?",
+        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, error_message, effective_timeout_ms, error_kind, is_shadow, selection_reason, client_ip FROM request_logs WHERE id = ?",
     )
     .bind(id)
     .fetch_optional(&log_db.0)
@@ -1130,7 +3009,9 @@ pub async fn get_request_log_detail(
 }
 
 // System logs commands
+// `start_time`/`end_time` are unix seconds (inclusive)
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn get_system_logs(
     log_db: State<'_, crate::LogDb>,
     page: Option<i64>,
@@ -1138,6 +3019,8 @@ pub async fn get_system_logs(
     level: Option<String>,
     event_type: Option<String>,
     provider_name: Option<String>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
 ) -> Result<SystemLogListResponse> {
     let page = page.unwrap_or(1).max(1);
     let page_size = page_size.unwrap_or(20).clamp(1, 100);
@@ -1159,11 +3042,17 @@ pub async fn get_system_logs(
         sql.push_str(" AND provider_name = ?");
         count_sql.push_str(" AND provider_name = ?");
     }
+    if start_time.is_some() {
+        sql.push_str(" AND created_at >= ?");
+        count_sql.push_str(" AND created_at >= ?");
+    }
+    if end_time.is_some() {
+        sql.push_str(" AND created_at <= ?");
+        count_sql.push_str(" AND created_at <= ?");
+    }
 
     sql.push_str(" ORDER BY id DESC LIMIT ? OFFSET ?");
-    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql)
-        .bind(page_size)
-        .bind(offset);
+    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql);
 
     if let Some(ref lvl) = level {
         q = q.bind(lvl);
@@ -1174,8 +3063,16 @@ pub async fn get_system_logs(
     if let Some(ref pn) = provider_name {
         q = q.bind(pn);
     }
+    if let Some(st) = start_time {
+        q = q.bind(st);
+    }
+    if let Some(et) = end_time {
+        q = q.bind(et);
+    }
 
-    let items = q.fetch_all(&log_db.0)
+    let items = q.bind(page_size)
+        .bind(offset)
+        .fetch_all(&log_db.0)
         .await
         .map_err(|e| e.to_string())?;
 
@@ -1190,6 +3087,12 @@ pub async fn get_system_logs(
     if let Some(ref pn) = provider_name {
         count_q = count_q.bind(pn);
     }
+    if let Some(st) = start_time {
+        count_q = count_q.bind(st);
+    }
+    if let Some(et) = end_time {
+        count_q = count_q.bind(et);
+    }
     let (total,) = count_q.fetch_one(&log_db.0)
         .await
         .map_err(|e| e.to_string())?;
@@ -1202,6 +3105,139 @@ pub async fn get_system_logs(
     })
 }
 
+/// Export system logs (oldest first) as `json` (default) or `csv`.
+/// Returns the file content; the frontend decides where to save it.
+#[tauri::command]
+pub async fn export_system_logs(
+    log_db: State<'_, crate::LogDb>,
+    format: Option<String>,
+    event_type: Option<String>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+) -> Result<String> {
+    let format = format.as_deref().map(str::trim).unwrap_or("json").to_ascii_lowercase();
+    if format != "json" && format != "csv" {
+        return Err(format!("Unsupported export format: {} (use json or csv)", format));
+    }
+
+    let mut sql = "SELECT * FROM system_logs WHERE 1=1".to_string();
+    if event_type.is_some() {
+        sql.push_str(" AND event_type = ?");
+    }
+    if start_time.is_some() {
+        sql.push_str(" AND created_at >= ?");
+    }
+    if end_time.is_some() {
+        sql.push_str(" AND created_at <= ?");
+    }
+    sql.push_str(" ORDER BY id");
+
+    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql);
+    if let Some(ref et) = event_type {
+        q = q.bind(et);
+    }
+    if let Some(st) = start_time {
+        q = q.bind(st);
+    }
+    if let Some(et) = end_time {
+        q = q.bind(et);
+    }
+    let items = q.fetch_all(&log_db.0).await.map_err(|e| e.to_string())?;
+
+    if format == "json" {
+        return serde_json::to_string_pretty(&items).map_err(|e| e.to_string());
+    }
+
+    // RFC 4180: quote every field that may contain separators, quotes or newlines
+    let quote = |field: &str| format!("\"{}\"", field.replace('"', "\"\""));
+    let mut csv = String::from("id,created_at,time,event_type,message\n");
+    for item in &items {
+        let time = chrono::DateTime::from_timestamp(item.created_at, 0)
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            item.id,
+            item.created_at,
+            time,
+            quote(&item.event_type),
+            quote(&item.message)
+        ));
+    }
+    Ok(csv)
+}
+
+/// Retention for request and system logs, enforced hourly in the background
+#[tauri::command]
+pub async fn get_log_settings(db: State<'_, SqlitePool>) -> Result<LogSettings> {
+    get_log_settings_impl(db.inner()).await
+}
+
+pub(crate) async fn get_log_settings_impl(db: &SqlitePool) -> Result<LogSettings> {
+    crate::services::log_retention::load_settings(db)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn update_log_settings(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    input: LogSettingsUpdate,
+) -> Result<LogSettings> {
+    update_log_settings_impl(db.inner(), &log_db.0, input).await
+}
+
+pub(crate) async fn update_log_settings_impl(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    input: LogSettingsUpdate,
+) -> Result<LogSettings> {
+    if input.request_log_retention_days.is_some_and(|d| d < 0)
+        || input.system_log_retention_days.is_some_and(|d| d < 0)
+    {
+        return Err("Retention days must not be negative".to_string());
+    }
+
+    let current = crate::services::log_retention::load_settings(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    let settings = LogSettings {
+        request_log_retention_days: input.request_log_retention_days.unwrap_or(current.request_log_retention_days),
+        system_log_retention_days: input.system_log_retention_days.unwrap_or(current.system_log_retention_days),
+    };
+
+    sqlx::query(
+        "INSERT INTO log_settings (id, request_log_retention_days, system_log_retention_days, updated_at) VALUES (1, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET request_log_retention_days = excluded.request_log_retention_days, system_log_retention_days = excluded.system_log_retention_days, updated_at = excluded.updated_at",
+    )
+    .bind(settings.request_log_retention_days)
+    .bind(settings.system_log_retention_days)
+    .bind(chrono::Utc::now().timestamp())
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    // Apply the new retention right away instead of waiting for the next hourly run
+    crate::services::log_retention::prune(db, log_db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(settings)
+}
+
+/// Recent in-memory tracing output (most recent first).
+/// `level` filters to that severity and above, e.g. "warn" returns warnings and errors.
+#[tauri::command]
+pub async fn get_app_logs(level: Option<String>, limit: Option<usize>) -> Result<Vec<AppLogEntry>> {
+    let min_level = match level.as_deref().map(str::trim).filter(|l| !l.is_empty()) {
+        Some(l) => Some(l.parse::<tracing::Level>().map_err(|_| format!("Invalid log level: {}", l))?),
+        None => None,
+    };
+    let limit = limit.unwrap_or(200).clamp(1, 1000);
+    Ok(crate::services::app_log::recent_logs(min_level, limit))
+}
+
 #[tauri::command]
 pub async fn clear_system_logs(log_db: State<'_, crate::LogDb>) -> Result<()> {
     sqlx::query("DELETE FROM system_logs")
@@ -1217,8 +3253,222 @@ pub async fn get_system_status() -> Result<SystemStatus> {
     Ok(SystemStatus {
         status: "running".to_string(),
         port: 7788,
-        uptime: 0,
+        uptime: crate::config::uptime_secs(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    })
+}
+
+/// Proxied requests currently in flight, oldest first
+#[tauri::command]
+pub async fn list_active_requests(
+    active_requests: State<'_, Arc<ActiveRequests>>,
+) -> Result<Vec<ActiveRequestInfo>> {
+    Ok(active_requests.list())
+}
+
+/// In-flight upstream requests against the global limit and each provider's limit.
+/// Lists providers with a limit set or requests in flight.
+#[tauri::command]
+pub async fn get_concurrency_status(
+    db: State<'_, SqlitePool>,
+    limiter: State<'_, Arc<ConcurrencyLimiter>>,
+) -> Result<ConcurrencyStatus> {
+    let max_concurrency: i64 = sqlx::query_scalar("SELECT max_concurrency FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or(0);
+    let rows: Vec<(i64, String, String, i64)> = sqlx::query_as(
+        "SELECT id, name, cli_type, max_concurrency FROM providers ORDER BY sort_order, id",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let (in_flight, per_provider) = limiter.in_flight();
+    let providers = rows
+        .into_iter()
+        .map(|(provider_id, provider_name, cli_type, max_concurrency)| ProviderConcurrency {
+            in_flight: per_provider.get(&provider_id).copied().unwrap_or(0) as i64,
+            provider_id,
+            provider_name,
+            cli_type,
+            max_concurrency,
+        })
+        .filter(|p| p.in_flight > 0 || p.max_concurrency > 0)
+        .collect();
+
+    Ok(ConcurrencyStatus {
+        in_flight: in_flight as i64,
+        max_concurrency,
+        providers,
+    })
+}
+
+/// Abort the upstream call of an in-flight request
+#[tauri::command]
+pub async fn cancel_request(
+    active_requests: State<'_, Arc<ActiveRequests>>,
+    log_db: State<'_, LogDb>,
+    request_id: String,
+) -> Result<()> {
+    if !active_requests.cancel(&request_id) {
+        return Err(format!("Request not found or already finished: {}", request_id));
+    }
+
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "request_cancelled",
+        &format!("请求 {} 已被手动取消", request_id),
+    )
+    .await;
+    Ok(())
+}
+
+/// Provider health per CLI type. "Available" uses the same rules as routing, so a CLI with
+/// none available is exactly one whose requests fail with no_provider_available.
+#[tauri::command]
+pub async fn get_cli_health(db: State<'_, SqlitePool>) -> Result<Vec<CliHealth>> {
+    let now = chrono::Utc::now().timestamp();
+
+    let mut health = Vec::new();
+    for cli_type in ["claude_code", "codex", "gemini"] {
+        let (total, enabled, paused, blacklisted, available) = sqlx::query_as::<_, (i64, i64, i64, i64, i64)>(
+            r#"
+            SELECT
+                COUNT(*),
+                COALESCE(SUM(enabled = 1), 0),
+                COALESCE(SUM(enabled = 1 AND paused = 1), 0),
+                COALESCE(SUM(blacklisted_until > ?), 0),
+                COALESCE(SUM(enabled = 1 AND paused = 0 AND (blacklisted_until IS NULL OR blacklisted_until <= ?)), 0)
+            FROM providers
+            WHERE cli_type = ? OR id IN (SELECT provider_id FROM provider_cli_types WHERE cli_type = ?)
+            "#,
+        )
+        .bind(now)
+        .bind(now)
+        .bind(cli_type)
+        .bind(cli_type)
+        .fetch_one(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+
+        health.push(CliHealth {
+            cli_type: cli_type.to_string(),
+            total_providers: total,
+            enabled_providers: enabled,
+            paused_providers: paused,
+            blacklisted_providers: blacklisted,
+            available_providers: available,
+            serveable: available > 0,
+        });
+    }
+    Ok(health)
+}
+
+/// Dry-run the provider selection for a request: which provider and model it would use and
+/// why the other providers would be skipped. Nothing is sent upstream. `pin`/`force` stand in
+/// for the `x-ccg-provider`/`x-ccg-force` headers and `body` for the request body (size rules).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn explain_routing(
+    db: State<'_, SqlitePool>,
+    scores: State<'_, Arc<ProviderScores>>,
+    limiter: State<'_, Arc<crate::services::concurrency::ConcurrencyLimiter>>,
+    cli_type: String,
+    model: Option<String>,
+    path: Option<String>,
+    method: Option<String>,
+    body: Option<String>,
+    pin: Option<String>,
+    force: Option<bool>,
+) -> Result<crate::db::models::RoutingExplanation> {
+    let cli_type: crate::services::proxy::CliType = cli_type.parse()?;
+    let (strategy, allow_force_provider): (String, i64) =
+        sqlx::query_as("SELECT routing_strategy, allow_force_provider FROM gateway_settings WHERE id = 1")
+            .fetch_optional(db.inner())
+            .await
+            .map_err(|e| e.to_string())?
+            .unwrap_or_default();
+
+    let query = crate::services::routing::RoutingQuery {
+        method: method.as_deref().map(str::trim).filter(|m| !m.is_empty()).unwrap_or("POST"),
+        path: path.as_deref(),
+        model: model.as_deref(),
+        body: body.as_deref().map(str::as_bytes),
+        pin: pin.as_deref().map(str::trim).filter(|p| !p.is_empty()),
+        // Forcing is gated the same way as for real requests
+        force: force.unwrap_or(false) && allow_force_provider != 0,
+    };
+    crate::services::routing::explain_routing(
+        db.inner(),
+        cli_type,
+        query,
+        strategy.parse().unwrap_or_default(),
+        &scores,
+        &limiter,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Everything the home screen needs in one call; "today" starts at local midnight
+#[tauri::command]
+pub async fn get_dashboard_summary(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, crate::LogDb>,
+) -> Result<DashboardSummary> {
+    let now = chrono::Utc::now().timestamp();
+    let tz = crate::services::stats::StatsTimezone::Local;
+    let today_start = tz.day_start(tz.date_of(now));
+
+    // Totals come from usage_buckets: request_logs may be sampled (log_sample_rate)
+    let (requests_today, success_today, tokens_today) = sqlx::query_as::<_, (i64, Option<i64>, Option<i64>)>(
+        r#"
+        SELECT
+            COALESCE(SUM(request_count), 0),
+            SUM(success_count),
+            SUM(input_tokens + output_tokens)
+        FROM usage_buckets
+        WHERE bucket_start >= ?
+        "#,
+    )
+    .bind(today_start)
+    .fetch_one(&log_db.0)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let top_providers = sqlx::query_as::<_, DashboardTopProvider>(
+        "SELECT provider_name, SUM(request_count) AS requests FROM usage_buckets WHERE bucket_start >= ? GROUP BY provider_name ORDER BY requests DESC LIMIT 3",
+    )
+    .bind(today_start)
+    .fetch_all(&log_db.0)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let providers = sqlx::query_as::<_, (i64, Option<i64>)>("SELECT enabled, blacklisted_until FROM providers")
+        .fetch_all(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+    let is_blacklisted = |until: Option<i64>| until.map(|t| t > now).unwrap_or(false);
+    let blacklisted_providers = providers.iter().filter(|(_, until)| is_blacklisted(*until)).count() as i64;
+    // "Active" means enabled, as in get_cli_health's enabled_providers: a paused provider stays
+    // active and only stops being routed to; blacklisting is reported in its own count
+    let active_providers = providers.iter().filter(|(enabled, _)| *enabled != 0).count() as i64;
+
+    Ok(DashboardSummary {
+        requests_today,
+        success_rate_today: if requests_today > 0 {
+            (success_today.unwrap_or(0) as f64 / requests_today as f64) * 100.0
+        } else {
+            0.0
+        },
+        tokens_today: tokens_today.unwrap_or(0),
+        active_providers,
+        blacklisted_providers,
+        top_providers,
         version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime: crate::config::uptime_secs(),
     })
 }
 
@@ -1282,9 +3532,28 @@ pub async fn get_mcp(db: State<'_, SqlitePool>, id: i64) -> Result<McpResponse>
     })
 }
 
+// Names are unique in mcp_configs: two rows would fight over the same mcpServers key
+fn mcp_write_error(e: sqlx::Error, name: &str) -> String {
+    match e.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => {
+            format!("An MCP named '{}' already exists, choose a different name", name)
+        }
+        _ => e.to_string(),
+    }
+}
+
+fn validate_mcp_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("MCP name cannot be empty".to_string());
+    }
+    Ok(name.to_string())
+}
+
 #[tauri::command]
-pub async fn create_mcp(db: State<'_, SqlitePool>, input: McpCreate) -> Result<McpResponse> {
+pub async fn create_mcp(db: State<'_, SqlitePool>, mut input: McpCreate) -> Result<McpResponse> {
     let now = chrono::Utc::now().timestamp();
+    input.name = validate_mcp_name(&input.name)?;
 
     let result = sqlx::query(
         "INSERT INTO mcp_configs (name, config_json, updated_at) VALUES (?, ?, ?)",
@@ -1294,7 +3563,7 @@ pub async fn create_mcp(db: State<'_, SqlitePool>, input: McpCreate) -> Result<M
     .bind(now)
     .execute(db.inner())
     .await
-    .map_err(|e| e.to_string())?;
+    .map_err(|e| mcp_write_error(e, &input.name))?;
 
     let id = result.last_insert_rowid();
 
@@ -1319,7 +3588,10 @@ pub async fn update_mcp(db: State<'_, SqlitePool>, id: i64, input: McpUpdate) ->
             .map_err(|e| e.to_string())?
             .ok_or_else(|| "MCP not found".to_string())?;
 
-        let new_name = input.name.unwrap_or(current.name.clone());
+        let new_name = match input.name {
+            Some(name) => validate_mcp_name(&name)?,
+            None => current.name.clone(),
+        };
         let new_config = input.config_json.unwrap_or(current.config_json.clone());
 
         sqlx::query(
@@ -1331,7 +3603,7 @@ pub async fn update_mcp(db: State<'_, SqlitePool>, id: i64, input: McpUpdate) ->
         .bind(id)
         .execute(db.inner())
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| mcp_write_error(e, &new_name))?;
 
         (new_name, new_config)
     } else {
@@ -1353,6 +3625,34 @@ pub async fn update_mcp(db: State<'_, SqlitePool>, id: i64, input: McpUpdate) ->
     get_mcp(db, id).await
 }
 
+/// MCPs whose names collide: exact duplicates left over from databases without the unique
+/// constraint, or names differing only by case/surrounding whitespace
+#[tauri::command]
+pub async fn find_mcp_conflicts(db: State<'_, SqlitePool>) -> Result<Vec<McpConflict>> {
+    let rows = sqlx::query_as::<_, McpConflictItem>(
+        r#"
+        SELECT id, name, updated_at FROM mcp_configs
+        WHERE lower(trim(name)) IN (
+            SELECT lower(trim(name)) FROM mcp_configs GROUP BY lower(trim(name)) HAVING COUNT(*) > 1
+        )
+        ORDER BY lower(trim(name)), id
+        "#,
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut conflicts: Vec<McpConflict> = Vec::new();
+    for row in rows {
+        let key = row.name.trim().to_ascii_lowercase();
+        match conflicts.last_mut() {
+            Some(conflict) if conflict.name == key => conflict.mcps.push(row),
+            _ => conflicts.push(McpConflict { name: key, mcps: vec![row] }),
+        }
+    }
+    Ok(conflicts)
+}
+
 #[tauri::command]
 pub async fn delete_mcp(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
     // Get MCP name before deletion
@@ -1387,6 +3687,10 @@ async fn sync_single_mcp_to_cli(
 ) -> Result<()> {
     let cli_types = vec!["claude_code", "codex", "gemini"];
 
+    // ${VAR} placeholders are resolved here so secrets stay out of the stored config
+    let expanded_config = expand_mcp_config_env(mcp_name, mcp_config_json);
+    let mcp_config_json = expanded_config.as_str();
+
     for cli_type in cli_types {
         // Check if this MCP is enabled for this CLI
         let is_enabled = cli_flags.iter()
@@ -1400,7 +3704,7 @@ async fn sync_single_mcp_to_cli(
                 continue;
             }
 
-            // For ClaudeCode and Gemini (JSON format)
+            // For ClaudeCode and Gemini (JSON format; Gemini gets its own server shape)
             // Read existing config or create new one
             let mut config = if path.exists() {
                 let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
@@ -1412,7 +3716,10 @@ async fn sync_single_mcp_to_cli(
             // Update MCP section
             if is_enabled {
                 // Add or update this MCP
-                if let Ok(mcp_json) = serde_json::from_str::<serde_json::Value>(mcp_config_json) {
+                if let Ok(mut mcp_json) = serde_json::from_str::<serde_json::Value>(mcp_config_json) {
+                    if cli_type == "gemini" {
+                        mcp_json = gemini_mcp_server(&mcp_json);
+                    }
                     if let Some(obj) = config.as_object_mut() {
                         if !obj.contains_key("mcpServers") {
                             obj.insert("mcpServers".to_string(), serde_json::json!({}));
@@ -1431,16 +3738,113 @@ async fn sync_single_mcp_to_cli(
                 }
             }
 
-            // Write config file
-            if let Some(parent) = path.parent() {
-                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            // Write config file
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            let config_str = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+            std::fs::write(&path, config_str).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+// Expand ${VAR} placeholders from the process environment in command, args, env, url and
+// header values. Unresolved placeholders without a default are left as-is (with a warning)
+// rather than blanked.
+fn expand_mcp_config_env(mcp_name: &str, mcp_config_json: &str) -> String {
+    let Ok(mut config) = serde_json::from_str::<serde_json::Value>(mcp_config_json) else {
+        return mcp_config_json.to_string();
+    };
+    let Some(obj) = config.as_object_mut() else {
+        return mcp_config_json.to_string();
+    };
+
+    let mut unresolved = Vec::new();
+    for key in ["command", "url"] {
+        if let Some(serde_json::Value::String(value)) = obj.get_mut(key) {
+            *value = expand_env_placeholders(value, &mut unresolved);
+        }
+    }
+    if let Some(args) = obj.get_mut("args").and_then(|v| v.as_array_mut()) {
+        for arg in args.iter_mut() {
+            if let serde_json::Value::String(value) = arg {
+                *value = expand_env_placeholders(value, &mut unresolved);
+            }
+        }
+    }
+    for key in ["env", "headers"] {
+        if let Some(map) = obj.get_mut(key).and_then(|v| v.as_object_mut()) {
+            for value in map.values_mut() {
+                if let serde_json::Value::String(value) = value {
+                    *value = expand_env_placeholders(value, &mut unresolved);
+                }
+            }
+        }
+    }
+
+    if !unresolved.is_empty() {
+        unresolved.sort();
+        unresolved.dedup();
+        tracing::warn!(
+            mcp = %mcp_name,
+            "MCP config references unset environment variables, left unexpanded: {}",
+            unresolved.join(", ")
+        );
+    }
+    config.to_string()
+}
+
+// Replace ${NAME} with the environment variable NAME, and ${NAME:-default} with it or with
+// `default` when it is unset or empty. `$${` is an escaped, literal `${`.
+// Unknown names without a default are kept verbatim.
+fn expand_env_placeholders(value: &str, unresolved: &mut Vec<String>) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            // Escaped: drop one "$" and keep the rest as text
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let placeholder = after.find('}').and_then(|end| {
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+            let valid = !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            valid.then_some((end, name, default))
+        });
+        match placeholder {
+            Some((end, name, default)) => {
+                // With a default, an empty variable counts as unset (like the shell's :-)
+                let env_value = std::env::var(name).ok().filter(|v| default.is_none() || !v.is_empty());
+                match (env_value, default) {
+                    (Some(env_value), _) => result.push_str(&env_value),
+                    (None, Some(default)) => result.push_str(default),
+                    (None, None) => {
+                        unresolved.push(name.to_string());
+                        result.push_str(&rest[start..start + 2 + end + 1]);
+                    }
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                // Not a placeholder: keep "${" and continue after it
+                result.push_str("${");
+                rest = after;
             }
-            let config_str = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
-            std::fs::write(&path, config_str).map_err(|e| e.to_string())?;
         }
     }
-
-    Ok(())
+    result.push_str(rest);
+    result
 }
 
 // Helper function to sync a single MCP to Codex config.toml
@@ -1540,6 +3944,51 @@ fn sync_single_codex_mcp(
     Ok(())
 }
 
+// Translate a stored (Claude-style) MCP config to Gemini's settings.json server shape.
+// Gemini has no `type`: stdio servers use command/args/env/cwd, streamable HTTP servers use
+// `httpUrl` and SSE servers use `url`. Gemini-only options (timeout, trust, tool filters)
+// are passed through when present.
+fn gemini_mcp_server(mcp_config: &serde_json::Value) -> serde_json::Value {
+    let mut server = serde_json::Map::new();
+    let Some(config) = mcp_config.as_object() else {
+        return serde_json::Value::Object(server);
+    };
+
+    let url = config.get("url").and_then(|v| v.as_str());
+    let http_url = config.get("httpUrl").and_then(|v| v.as_str());
+    match (config.get("type").and_then(|v| v.as_str()), url, http_url) {
+        (_, _, Some(http_url)) => {
+            server.insert("httpUrl".to_string(), http_url.into());
+        }
+        (Some("sse"), Some(url), _) => {
+            server.insert("url".to_string(), url.into());
+        }
+        // Claude's `http` transport is streamable HTTP
+        (_, Some(url), _) => {
+            server.insert("httpUrl".to_string(), url.into());
+        }
+        _ => {
+            for key in ["command", "args", "env", "cwd"] {
+                if let Some(value) = config.get(key) {
+                    server.insert(key.to_string(), value.clone());
+                }
+            }
+        }
+    }
+    if server.contains_key("url") || server.contains_key("httpUrl") {
+        if let Some(headers) = config.get("headers").filter(|v| v.is_object()) {
+            server.insert("headers".to_string(), headers.clone());
+        }
+    }
+
+    for key in ["timeout", "trust", "includeTools", "excludeTools", "description"] {
+        if let Some(value) = config.get(key) {
+            server.insert(key.to_string(), value.clone());
+        }
+    }
+    serde_json::Value::Object(server)
+}
+
 // Delete a single MCP from all CLI configs
 fn delete_mcp_from_cli(mcp_name: &str) -> Result<()> {
     let cli_types = vec!["claude_code", "codex", "gemini"];
@@ -1580,6 +4029,25 @@ fn delete_mcp_from_cli(mcp_name: &str) -> Result<()> {
 }
 
 // Prompt commands
+fn prompt_response(prompt: PromptPreset) -> PromptResponse {
+    // Read real status from prompt files
+    let cli_flags = ["claude_code", "codex", "gemini"]
+        .iter()
+        .map(|cli_type| PromptCliFlag {
+            cli_type: cli_type.to_string(),
+            enabled: prompt_enabled_in_file(cli_type, &prompt.content, prompt.project_path.as_deref()),
+        })
+        .collect();
+
+    PromptResponse {
+        id: prompt.id,
+        name: prompt.name,
+        content: prompt.content,
+        project_path: prompt.project_path,
+        cli_flags,
+    }
+}
+
 #[tauri::command]
 pub async fn get_prompts(db: State<'_, SqlitePool>) -> Result<Vec<PromptResponse>> {
     let prompts = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets ORDER BY id")
@@ -1587,28 +4055,7 @@ pub async fn get_prompts(db: State<'_, SqlitePool>) -> Result<Vec<PromptResponse
         .await
         .map_err(|e| e.to_string())?;
 
-    let cli_types = vec!["claude_code", "codex", "gemini"];
-
-    let mut results = Vec::new();
-    for prompt in prompts {
-        // Read real status from prompt files
-        let mut cli_flags = Vec::new();
-        for cli_type in &cli_types {
-            let enabled = prompt_enabled_in_file(cli_type, &prompt.content);
-            cli_flags.push(PromptCliFlag {
-                cli_type: cli_type.to_string(),
-                enabled,
-            });
-        }
-
-        results.push(PromptResponse {
-            id: prompt.id,
-            name: prompt.name,
-            content: prompt.content,
-            cli_flags,
-        });
-    }
-    Ok(results)
+    Ok(prompts.into_iter().map(prompt_response).collect())
 }
 
 #[tauri::command]
@@ -1620,34 +4067,94 @@ pub async fn get_prompt(db: State<'_, SqlitePool>, id: i64) -> Result<PromptResp
         .map_err(|e| e.to_string())?
         .ok_or_else(|| "Prompt not found".to_string())?;
 
-    // Read real status from prompt files
-    let cli_types = vec!["claude_code", "codex", "gemini"];
-    let mut cli_flags = Vec::new();
-    for cli_type in &cli_types {
-        let enabled = prompt_enabled_in_file(cli_type, &prompt.content);
-        cli_flags.push(PromptCliFlag {
-            cli_type: cli_type.to_string(),
-            enabled,
-        });
+    Ok(prompt_response(prompt))
+}
+
+/// Trim the project path; empty means global. A project must be an existing absolute directory.
+fn normalize_prompt_project_path(project_path: Option<&str>) -> Result<Option<String>> {
+    let Some(path) = project_path.map(str::trim).filter(|p| !p.is_empty()) else {
+        return Ok(None);
+    };
+    let dir = std::path::Path::new(path);
+    if !dir.is_absolute() {
+        return Err(format!("Project path must be absolute: {}", path));
     }
+    if !dir.is_dir() {
+        return Err(format!("Project directory not found: {}", path));
+    }
+    // Drop trailing separators so the same project always compares equal
+    let trimmed = path.trim_end_matches(['/', '\\']);
+    Ok(Some(if trimmed.is_empty() { path } else { trimmed }.to_string()))
+}
 
-    Ok(PromptResponse {
-        id: prompt.id,
-        name: prompt.name,
-        content: prompt.content,
-        cli_flags,
-    })
+/// Project-scoped prompts can only be enabled for CLIs with a project-local prompt file
+fn check_prompt_scope(project_path: Option<&str>, cli_flags: &[PromptCliFlag]) -> Result<()> {
+    if project_path.is_none() {
+        return Ok(());
+    }
+    if let Some(flag) = cli_flags
+        .iter()
+        .find(|f| f.enabled && get_project_prompt_file_path(&f.cli_type, project_path).is_none())
+    {
+        return Err(format!("Project-scoped prompts are not supported for {}", flag.cli_type));
+    }
+    Ok(())
+}
+
+/// Project prompt files may hold the project's own instructions. Enabling a prompt there only
+/// replaces a file that is empty or already holds a prompt the gateway wrote.
+async fn check_project_prompt_file(
+    db: &SqlitePool,
+    project_path: Option<&str>,
+    cli_flags: &[PromptCliFlag],
+    content: &str,
+) -> Result<()> {
+    if project_path.is_none() {
+        return Ok(());
+    }
+    let mut presets: Option<Vec<String>> = None;
+    for flag in cli_flags.iter().filter(|f| f.enabled) {
+        let Some(path) = get_project_prompt_file_path(&flag.cli_type, project_path) else {
+            continue;
+        };
+        let Ok(existing) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let existing = normalize_text(&existing);
+        if existing.is_empty() || existing == normalize_text(content) {
+            continue;
+        }
+        if presets.is_none() {
+            let contents: Vec<String> = sqlx::query_scalar("SELECT content FROM prompt_presets")
+                .fetch_all(db)
+                .await
+                .map_err(|e| e.to_string())?;
+            presets = Some(contents.iter().map(|c| normalize_text(c)).collect());
+        }
+        if !presets.iter().flatten().any(|p| *p == existing) {
+            return Err(format!(
+                "{} already has instructions that were not written by CCG Gateway; move or clear them first",
+                path.display()
+            ));
+        }
+    }
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn create_prompt(db: State<'_, SqlitePool>, input: PromptCreate) -> Result<PromptResponse> {
     let now = chrono::Utc::now().timestamp();
+    let project_path = normalize_prompt_project_path(input.project_path.as_deref())?;
+    let cli_flags = input.cli_flags.unwrap_or_default();
+    check_prompt_scope(project_path.as_deref(), &cli_flags)?;
+    check_project_prompt_file(db.inner(), project_path.as_deref(), &cli_flags, &input.content).await?;
 
     let result = sqlx::query(
-        "INSERT INTO prompt_presets (name, content, updated_at) VALUES (?, ?, ?)",
+        "INSERT INTO prompt_presets (name, content, project_path, updated_at) VALUES (?, ?, ?, ?)",
     )
     .bind(&input.name)
     .bind(&input.content)
+    .bind(&project_path)
     .bind(now)
     .execute(db.inner())
     .await
@@ -1656,9 +4163,8 @@ pub async fn create_prompt(db: State<'_, SqlitePool>, input: PromptCreate) -> Re
     let id = result.last_insert_rowid();
 
     // Sync to CLI files if cli_flags provided
-    let cli_flags = input.cli_flags.unwrap_or_default();
     if !cli_flags.is_empty() {
-        sync_single_prompt_to_cli(&input.content, &cli_flags).await?;
+        sync_single_prompt_to_cli(&input.content, &cli_flags, project_path.as_deref()).await?;
     }
 
     get_prompt(db, id).await
@@ -1668,22 +4174,47 @@ pub async fn create_prompt(db: State<'_, SqlitePool>, input: PromptCreate) -> Re
 pub async fn update_prompt(db: State<'_, SqlitePool>, id: i64, input: PromptUpdate) -> Result<PromptResponse> {
     let now = chrono::Utc::now().timestamp();
 
-    let content = if input.name.is_some() || input.content.is_some() {
-        let current = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
-            .bind(id)
-            .fetch_optional(db.inner())
-            .await
-            .map_err(|e| e.to_string())?
-            .ok_or_else(|| "Prompt not found".to_string())?;
+    let current = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Prompt not found".to_string())?;
+
+    let project_path = match input.project_path.as_deref() {
+        Some(path) => normalize_prompt_project_path(Some(path))?,
+        None => current.project_path.clone(),
+    };
+    let scope_changed = project_path != current.project_path;
+
+    // Moving the prompt keeps it enabled where the new scope allows
+    let cli_flags = match input.cli_flags {
+        Some(flags) => Some(flags),
+        None if scope_changed => Some(
+            prompt_response(current.clone())
+                .cli_flags
+                .into_iter()
+                .filter(|f| get_project_prompt_file_path(&f.cli_type, project_path.as_deref()).is_some())
+                .collect(),
+        ),
+        None => None,
+    };
+    if let Some(ref flags) = cli_flags {
+        check_prompt_scope(project_path.as_deref(), flags)?;
+        let new_content = input.content.as_deref().unwrap_or(&current.content);
+        check_project_prompt_file(db.inner(), project_path.as_deref(), flags, new_content).await?;
+    }
 
+    let content = if input.name.is_some() || input.content.is_some() || scope_changed {
         let new_name = input.name.unwrap_or(current.name.clone());
         let new_content = input.content.unwrap_or(current.content.clone());
 
         sqlx::query(
-            "UPDATE prompt_presets SET name = ?, content = ?, updated_at = ? WHERE id = ?",
+            "UPDATE prompt_presets SET name = ?, content = ?, project_path = ?, updated_at = ? WHERE id = ?",
         )
         .bind(&new_name)
         .bind(&new_content)
+        .bind(&project_path)
         .bind(now)
         .bind(id)
         .execute(db.inner())
@@ -1692,19 +4223,17 @@ pub async fn update_prompt(db: State<'_, SqlitePool>, id: i64, input: PromptUpda
 
         new_content
     } else {
-        // Get current values if not updating
-        let current = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
-            .bind(id)
-            .fetch_optional(db.inner())
-            .await
-            .map_err(|e| e.to_string())?
-            .ok_or_else(|| "Prompt not found".to_string())?;
-        current.content
+        current.content.clone()
     };
 
+    // Clear the prompt from the files of its old scope
+    if scope_changed {
+        sync_single_prompt_to_cli(&current.content, &[], current.project_path.as_deref()).await?;
+    }
+
     // Sync to CLI files if cli_flags provided
-    if let Some(cli_flags) = input.cli_flags {
-        sync_single_prompt_to_cli(&content, &cli_flags).await?;
+    if let Some(cli_flags) = cli_flags {
+        sync_single_prompt_to_cli(&content, &cli_flags, project_path.as_deref()).await?;
     }
 
     get_prompt(db, id).await
@@ -1728,6 +4257,7 @@ pub async fn delete_prompt(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
 async fn sync_single_prompt_to_cli(
     prompt_content: &str,
     cli_flags: &[PromptCliFlag],
+    project_path: Option<&str>,
 ) -> Result<()> {
     let cli_types = vec!["claude_code", "codex", "gemini"];
 
@@ -1737,8 +4267,15 @@ async fn sync_single_prompt_to_cli(
             .any(|f| f.cli_type == cli_type && f.enabled);
 
         // Get the prompt file path for this CLI
-        let prompt_path = get_prompt_file_path(cli_type);
+        let prompt_path = get_project_prompt_file_path(cli_type, project_path);
         if let Some(path) = prompt_path {
+            // Project-local config dirs are created on demand
+            if project_path.is_some() && is_enabled {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+            }
+
             // Check if CLI directory exists (skip if CLI not installed)
             if let Some(parent) = path.parent() {
                 if !parent.exists() {
@@ -1786,42 +4323,66 @@ fn get_prompt_file_path(cli_type: &str) -> Option<std::path::PathBuf> {
     }
 }
 
+// Prompt file for a scope: the global file, or the project-local one (only Claude Code has
+// a project-local convention so far: <project>/.claude/CLAUDE.md)
+fn get_project_prompt_file_path(cli_type: &str, project_path: Option<&str>) -> Option<std::path::PathBuf> {
+    let Some(project) = project_path else {
+        return get_prompt_file_path(cli_type);
+    };
+    match cli_type {
+        "claude_code" => Some(std::path::Path::new(project).join(".claude").join("CLAUDE.md")),
+        _ => None,
+    }
+}
+
 // Stats commands
+// `timezone` is `local` (default), `UTC`, an IANA name like `Asia/Shanghai` or a fixed offset
+// like `+08:00`; day boundaries for both daily and provider stats are computed in that timezone
+// and `end_date` is inclusive.
 #[tauri::command]
 pub async fn get_daily_stats(
     log_db: State<'_, crate::LogDb>,
     start_date: Option<String>,
     end_date: Option<String>,
     cli_type: Option<String>,
+    timezone: Option<String>,
 ) -> Result<Vec<DailyStats>> {
-    let pool = &log_db.0;
-
-    let mut query = "SELECT * FROM usage_daily WHERE 1=1".to_string();
-    if start_date.is_some() {
-        query.push_str(" AND usage_date >= ?");
-    }
-    if end_date.is_some() {
-        query.push_str(" AND usage_date <= ?");
-    }
-    if cli_type.is_some() {
-        query.push_str(" AND cli_type = ?");
-    }
-    query.push_str(" ORDER BY usage_date DESC");
-
-    let mut q = sqlx::query_as::<_, DailyStats>(&query);
-    if let Some(ref sd) = start_date {
-        q = q.bind(sd);
-    }
-    if let Some(ref ed) = end_date {
-        q = q.bind(ed);
-    }
-    if let Some(ref ct) = cli_type {
-        q = q.bind(ct);
-    }
+    let tz = crate::services::stats::StatsTimezone::parse(timezone.as_deref())?;
+    let start_date = crate::services::stats::parse_stats_date(start_date.as_deref())?;
+    let end_date = crate::services::stats::parse_stats_date(end_date.as_deref())?;
+    crate::services::stats::query_daily_stats(
+        &log_db.0,
+        start_date,
+        end_date,
+        cli_type.as_deref(),
+        tz,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
 
-    q.fetch_all(pool).await.map_err(|e| e.to_string())
+/// Per-bucket avg/p95 latency for one provider. `bucket` is "minute" or "hour"; the range
+/// defaults to the last 24 hours.
+#[tauri::command]
+pub async fn get_provider_latency_series(
+    log_db: State<'_, crate::LogDb>,
+    provider_name: String,
+    bucket: String,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> Result<Vec<crate::db::models::LatencyBucket>> {
+    let bucket_secs = match bucket.as_str() {
+        "minute" => 60,
+        "hour" => 3600,
+        _ => return Err(format!("Invalid bucket: {} (expected minute or hour)", bucket)),
+    };
+    let end = end.unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let start = start.unwrap_or(end - 86400);
+    crate::services::stats::query_latency_series(&log_db.0, &provider_name, bucket_secs, start, end).await
 }
 
+// Per-model breakdown needs request_logs. Sampling keeps every failure but only some
+// successes; rows are weighted to compensate, so figures are estimates while it is active
 #[tauri::command]
 pub async fn get_provider_stats(
     log_db: State<'_, crate::LogDb>,
@@ -1829,68 +4390,21 @@ pub async fn get_provider_stats(
     end_date: Option<String>,
     cli_type: Option<String>,
     provider_name: Option<String>,
+    timezone: Option<String>,
 ) -> Result<Vec<ProviderStatsResponse>> {
-    let pool = &log_db.0;
-
-    let mut query = r#"
-        SELECT
-            cli_type,
-            provider_name,
-            model_id,
-            COUNT(*) as total_requests,
-            SUM(CASE WHEN status_code >= 200 AND status_code < 300 THEN 1 ELSE 0 END) as total_success,
-            SUM(input_tokens + output_tokens) as total_tokens,
-            SUM(elapsed_ms) as total_elapsed_ms
-        FROM request_logs
-        WHERE 1=1
-    "#.to_string();
-
-    if start_date.is_some() {
-        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') >= ?");
-    }
-    if end_date.is_some() {
-        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') <= ?");
-    }
-    if cli_type.is_some() {
-        query.push_str(" AND cli_type = ?");
-    }
-    if provider_name.is_some() {
-        query.push_str(" AND provider_name = ?");
-    }
-    query.push_str(" GROUP BY cli_type, provider_name, model_id ORDER BY total_requests DESC");
-
-    let mut q = sqlx::query_as::<_, ProviderStatsRow>(&query);
-    if let Some(ref sd) = start_date {
-        q = q.bind(sd);
-    }
-    if let Some(ref ed) = end_date {
-        q = q.bind(ed);
-    }
-    if let Some(ref ct) = cli_type {
-        q = q.bind(ct);
-    }
-    if let Some(ref pn) = provider_name {
-        q = q.bind(pn);
-    }
-
-    let rows = q.fetch_all(pool).await.map_err(|e| e.to_string())?;
-
-    let results = rows.into_iter().map(|row| ProviderStatsResponse {
-        cli_type: row.cli_type,
-        provider_name: row.provider_name,
-        model_id: row.model_id,
-        total_requests: row.total_requests,
-        total_success: row.total_success,
-        total_tokens: row.total_tokens,
-        total_elapsed_ms: row.total_elapsed_ms,
-        success_rate: if row.total_requests > 0 {
-            (row.total_success as f64 / row.total_requests as f64) * 100.0
-        } else {
-            0.0
-        },
-    }).collect();
-
-    Ok(results)
+    let tz = crate::services::stats::StatsTimezone::parse(timezone.as_deref())?;
+    let start_date = crate::services::stats::parse_stats_date(start_date.as_deref())?;
+    let end_date = crate::services::stats::parse_stats_date(end_date.as_deref())?;
+    crate::services::stats::query_provider_stats(
+        &log_db.0,
+        start_date,
+        end_date,
+        cli_type.as_deref(),
+        provider_name.as_deref(),
+        tz,
+    )
+    .await
+    .map_err(|e| e.to_string())
 }
 
 // Session helpers
@@ -1904,13 +4418,15 @@ fn get_cli_base_dir(cli_type: &str) -> std::path::PathBuf {
 }
 
 /// Parse Claude Code session file to extract info (first_message, git_branch, summary)
-/// Returns (first_message, git_branch, summary)
-fn parse_claude_session_info(file_path: &std::path::Path) -> (String, String, String) {
+/// Returns (first_message, git_branch, summary, parse_failed)
+fn parse_claude_session_info(file_path: &std::path::Path) -> (String, String, String, bool) {
     use std::io::{BufRead, BufReader};
     
     let mut first_message = String::new();
     let mut git_branch = String::new();
     let mut summary = String::new();
+    let mut parsed_lines = 0;
+    let mut bad_lines = 0;
     
     // Check file size to avoid reading very large files entirely
     let file_size = file_path.metadata().map(|m| m.len()).unwrap_or(0);
@@ -1918,7 +4434,7 @@ fn parse_claude_session_info(file_path: &std::path::Path) -> (String, String, St
     
     let file = match std::fs::File::open(file_path) {
         Ok(f) => f,
-        Err(_) => return (first_message, git_branch, summary),
+        Err(_) => return (first_message, git_branch, summary, false),
     };
     
     let reader = BufReader::new(file);
@@ -1933,7 +4449,10 @@ fn parse_claude_session_info(file_path: &std::path::Path) -> (String, String, St
         
         let line = match line {
             Ok(l) => l,
-            Err(_) => continue,
+            Err(_) => {
+                bad_lines += 1;
+                continue;
+            }
         };
         
         let line = line.trim();
@@ -1943,8 +4462,12 @@ fn parse_claude_session_info(file_path: &std::path::Path) -> (String, String, St
         
         let data: serde_json::Value = match serde_json::from_str(line) {
             Ok(d) => d,
-            Err(_) => continue,
+            Err(_) => {
+                bad_lines += 1;
+                continue;
+            }
         };
+        parsed_lines += 1;
         
         // Extract summary
         if data.get("type").and_then(|t| t.as_str()) == Some("summary") {
@@ -1999,7 +4522,9 @@ fn parse_claude_session_info(file_path: &std::path::Path) -> (String, String, St
         }
     }
     
-    (first_message, git_branch, summary)
+    // Only lines that were actually read count; a file whose every line is garbage can't be shown
+    let parse_failed = parsed_lines == 0 && bad_lines > 0;
+    (first_message, git_branch, summary, parse_failed)
 }
 
 /// Decode Claude Code project name to (display_name, full_path)
@@ -2249,6 +4774,84 @@ fn build_gemini_path_mapping(target_hashes: &std::collections::HashSet<String>)
     results
 }
 
+/// Persisted Gemini project hash -> path mappings, so the directory scan only runs for
+/// hashes that have not been resolved yet
+fn gemini_path_cache_file() -> std::path::PathBuf {
+    get_data_dir().join("gemini_path_cache.json")
+}
+
+fn load_gemini_path_cache() -> std::collections::HashMap<String, String> {
+    std::fs::read_to_string(gemini_path_cache_file())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_gemini_path_cache(cache: &std::collections::HashMap<String, String>) {
+    let result = serde_json::to_string_pretty(cache)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(gemini_path_cache_file(), s).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        tracing::warn!("Failed to save Gemini path cache: {}", e);
+    }
+}
+
+/// Resolve project hashes via the persisted cache, scanning only for unknown hashes.
+/// Cached entries whose directory no longer exists are dropped and rescanned.
+fn resolve_gemini_paths(target_hashes: &std::collections::HashSet<String>) -> std::collections::HashMap<String, String> {
+    let mut cache = load_gemini_path_cache();
+    let cached = cache.len();
+    cache.retain(|_, path| std::path::Path::new(path).is_dir());
+    let mut changed = cache.len() != cached;
+
+    let missing: std::collections::HashSet<String> = target_hashes
+        .iter()
+        .filter(|h| !cache.contains_key(*h))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        let found = build_gemini_path_mapping(&missing);
+        changed |= !found.is_empty();
+        cache.extend(found);
+    }
+    if changed {
+        save_gemini_path_cache(&cache);
+    }
+
+    cache.retain(|hash, _| target_hashes.contains(hash));
+    cache
+}
+
+// Project hash directories (64-char hex) under Gemini's tmp folder that contain chats
+fn gemini_project_hashes(tmp_dir: &std::path::Path) -> std::collections::HashSet<String> {
+    let Ok(entries) = std::fs::read_dir(tmp_dir) else {
+        return Default::default();
+    };
+    entries
+        .flatten()
+        .filter(|e| e.path().join("chats").is_dir())
+        .filter_map(|e| e.file_name().to_str().map(|s| s.to_string()))
+        .filter(|name| name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit()))
+        .collect()
+}
+
+/// Clear the persisted Gemini hash -> path cache and rescan for every known project hash
+/// (same search roots and depth limits as the project listing). Returns how many resolved.
+#[tauri::command]
+pub async fn rebuild_gemini_path_cache() -> Result<usize> {
+    tokio::task::spawn_blocking(|| {
+        let tmp_dir = get_cli_base_dir("gemini").join("tmp");
+        let hashes = gemini_project_hashes(&tmp_dir);
+        let _ = std::fs::remove_file(gemini_path_cache_file());
+        let mapping = build_gemini_path_mapping(&hashes);
+        save_gemini_path_cache(&mapping);
+        tracing::info!("Rebuilt Gemini path cache: {}/{} projects resolved", mapping.len(), hashes.len());
+        mapping.len()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
 // Handle Gemini projects (from hash directories with chats subfolder)
 fn get_gemini_projects(tmp_dir: std::path::PathBuf, page: i64, page_size: i64) -> Result<PaginatedProjects> {
     use std::collections::HashSet;
@@ -2302,8 +4905,8 @@ fn get_gemini_projects(tmp_dir: std::path::PathBuf, page: i64, page_size: i64) -
     let start = ((page - 1) * page_size) as usize;
     let page_dirs: Vec<_> = project_dirs.into_iter().skip(start).take(page_size as usize).collect();
     
-    // Build path mapping using rainbow table method
-    let path_mapping = build_gemini_path_mapping(&all_hashes);
+    // Build path mapping using rainbow table method (cached across listings)
+    let path_mapping = resolve_gemini_paths(&all_hashes);
     
     let mut projects = Vec::new();
     for (path, _) in page_dirs {
@@ -2449,17 +5052,29 @@ fn get_codex_sessions(project_name: &str, page: i64, page_size: i64) -> Result<P
         
         // Try to extract first message
         let mut first_message = String::new();
+        let mut parsed_lines = 0;
+        let mut bad_lines = 0;
         if let Ok(file) = std::fs::File::open(&path) {
             let reader = BufReader::new(file);
-            for line in reader.lines().flatten() {
-                if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
-                    if data.get("type").and_then(|t| t.as_str()) == Some("event_msg") {
-                        if let Some(payload) = data.get("payload") {
-                            if payload.get("type").and_then(|t| t.as_str()) == Some("user_message") {
-                                if let Some(msg) = payload.get("message").and_then(|m| m.as_str()) {
-                                    first_message = msg.chars().take(200).collect();
-                                    break;
-                                }
+            for line in reader.lines() {
+                let Ok(line) = line else {
+                    bad_lines += 1;
+                    continue;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) else {
+                    bad_lines += 1;
+                    continue;
+                };
+                parsed_lines += 1;
+                if data.get("type").and_then(|t| t.as_str()) == Some("event_msg") {
+                    if let Some(payload) = data.get("payload") {
+                        if payload.get("type").and_then(|t| t.as_str()) == Some("user_message") {
+                            if let Some(msg) = payload.get("message").and_then(|m| m.as_str()) {
+                                first_message = msg.chars().take(200).collect();
+                                break;
                             }
                         }
                     }
@@ -2474,6 +5089,7 @@ fn get_codex_sessions(project_name: &str, page: i64, page_size: i64) -> Result<P
             first_message,
             git_branch: String::new(),
             summary: String::new(),
+            parse_failed: parsed_lines == 0 && bad_lines > 0,
         });
     }
     
@@ -2550,8 +5166,11 @@ fn get_gemini_sessions(project_name: &str, page: i64, page_size: i64) -> Result<
         
         // Try to extract first message
         let mut first_message = String::new();
+        let mut parse_failed = false;
         if let Ok(content) = std::fs::read_to_string(&path) {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
+            let parsed = serde_json::from_str::<serde_json::Value>(&content);
+            parse_failed = parsed.is_err() && !content.trim().is_empty();
+            if let Ok(json) = parsed {
                 if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
                     for msg in messages {
                         if msg.get("type").and_then(|t| t.as_str()) == Some("user") {
@@ -2572,6 +5191,7 @@ fn get_gemini_sessions(project_name: &str, page: i64, page_size: i64) -> Result<
             first_message,
             git_branch: String::new(),
             summary: String::new(),
+            parse_failed,
         });
     }
     
@@ -2584,7 +5204,7 @@ fn get_gemini_sessions(project_name: &str, page: i64, page_size: i64) -> Result<
 }
 
 // Parse Codex messages from JSONL file
-fn get_codex_messages(session_id: &str) -> Result<Vec<SessionMessage>> {
+fn get_codex_messages(session_id: &str) -> Result<SessionMessages> {
     use std::io::{BufRead, BufReader};
     use walkdir::WalkDir;
     
@@ -2617,218 +5237,237 @@ fn get_codex_messages(session_id: &str) -> Result<Vec<SessionMessage>> {
     let reader = BufReader::new(file);
     
     let mut messages = Vec::new();
+    let mut parse_warnings = 0;
     
-    for line in reader.lines().flatten() {
-        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
-            let msg_type = data.get("type").and_then(|t| t.as_str());
-            
-            // Only process response_item for structured messages
-            if msg_type == Some("response_item") {
-                if let Some(payload) = data.get("payload") {
-                    let item_type = payload.get("type").and_then(|t| t.as_str());
-                    let role = payload.get("role").and_then(|r| r.as_str());
-                    let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
-                    
-                    // User messages
-                    if role == Some("user") && item_type == Some("message") {
-                        if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
-                            let text_parts: Vec<String> = content_list.iter()
-                                .filter_map(|item| {
-                                    if item.get("type").and_then(|t| t.as_str()) == Some("input_text") {
-                                        item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect();
-                            if !text_parts.is_empty() {
-                                messages.push(SessionMessage {
-                                    role: "user".to_string(),
-                                    content: text_parts.join("\n\n"),
-                                    timestamp,
-                                });
-                            }
+    for line in reader.lines() {
+        // Partially-written or corrupt lines are skipped but counted
+        let Ok(line) = line else {
+            parse_warnings += 1;
+            continue;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) else {
+            parse_warnings += 1;
+            continue;
+        };
+        let msg_type = data.get("type").and_then(|t| t.as_str());
+        
+        // Only process response_item for structured messages
+        if msg_type == Some("response_item") {
+            if let Some(payload) = data.get("payload") {
+                let item_type = payload.get("type").and_then(|t| t.as_str());
+                let role = payload.get("role").and_then(|r| r.as_str());
+                let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
+                
+                // User messages
+                if role == Some("user") && item_type == Some("message") {
+                    if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
+                        let text_parts: Vec<String> = content_list.iter()
+                            .filter_map(|item| {
+                                if item.get("type").and_then(|t| t.as_str()) == Some("input_text") {
+                                    item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect();
+                        if !text_parts.is_empty() {
+                            messages.push(SessionMessage {
+                                role: "user".to_string(),
+                                content: text_parts.join("\n\n"),
+                                timestamp,
+                            });
                         }
                     }
-                    // Assistant messages
-                    else if role == Some("assistant") && item_type == Some("message") {
-                        if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
-                            let text_parts: Vec<String> = content_list.iter()
-                                .filter_map(|item| {
-                                    let item_type = item.get("type").and_then(|t| t.as_str());
-                                    if item_type == Some("output_text") || item_type == Some("text") {
-                                        item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect();
-                            if !text_parts.is_empty() {
-                                messages.push(SessionMessage {
-                                    role: "assistant".to_string(),
-                                    content: text_parts.join("\n\n"),
-                                    timestamp,
-                                });
-                            }
+                }
+                // Assistant messages
+                else if role == Some("assistant") && item_type == Some("message") {
+                    if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
+                        let text_parts: Vec<String> = content_list.iter()
+                            .filter_map(|item| {
+                                let item_type = item.get("type").and_then(|t| t.as_str());
+                                if item_type == Some("output_text") || item_type == Some("text") {
+                                    item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect();
+                        if !text_parts.is_empty() {
+                            messages.push(SessionMessage {
+                                role: "assistant".to_string(),
+                                content: text_parts.join("\n\n"),
+                                timestamp,
+                            });
                         }
                     }
-                    // Reasoning summary
-                    else if item_type == Some("reasoning") {
-                        let summary = payload.get("summary").and_then(|s| s.as_array());
-                        if let Some(summary_arr) = summary {
-                            let text_parts: Vec<String> = summary_arr.iter()
-                                .filter_map(|item| {
-                                    if item.get("type").and_then(|t| t.as_str()) == Some("summary_text") {
-                                        item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect();
-                            if !text_parts.is_empty() {
-                                messages.push(SessionMessage {
-                                    role: "assistant".to_string(),
-                                    content: format!("**[推理]**\n{}", text_parts.join("\n")),
-                                    timestamp,
-                                });
-                            }
+                }
+                // Reasoning summary
+                else if item_type == Some("reasoning") {
+                    let summary = payload.get("summary").and_then(|s| s.as_array());
+                    if let Some(summary_arr) = summary {
+                        let text_parts: Vec<String> = summary_arr.iter()
+                            .filter_map(|item| {
+                                if item.get("type").and_then(|t| t.as_str()) == Some("summary_text") {
+                                    item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect();
+                        if !text_parts.is_empty() {
+                            messages.push(SessionMessage {
+                                role: "assistant".to_string(),
+                                content: format!("**[推理]**\n{}", text_parts.join("\n")),
+                                timestamp,
+                            });
                         }
                     }
-                    // Function call (tool use)
-                    else if item_type == Some("function_call") {
-                        let name = payload.get("name")
-                            .and_then(|n| n.as_str())
-                            .unwrap_or("unknown");
-                        let arguments = payload.get("arguments")
-                            .and_then(|a| a.as_str())
-                            .unwrap_or("{}");
-                        let args_str = match serde_json::from_str::<serde_json::Value>(arguments) {
-                            Ok(args_obj) => serde_json::to_string_pretty(&args_obj).unwrap_or_else(|_| arguments.to_string()),
-                            Err(_) => arguments.to_string(),
-                        };
+                }
+                // Function call (tool use)
+                else if item_type == Some("function_call") {
+                    let name = payload.get("name")
+                        .and_then(|n| n.as_str())
+                        .unwrap_or("unknown");
+                    let arguments = payload.get("arguments")
+                        .and_then(|a| a.as_str())
+                        .unwrap_or("{}");
+                    let args_str = match serde_json::from_str::<serde_json::Value>(arguments) {
+                        Ok(args_obj) => serde_json::to_string_pretty(&args_obj).unwrap_or_else(|_| arguments.to_string()),
+                        Err(_) => arguments.to_string(),
+                    };
+                    messages.push(SessionMessage {
+                        role: "assistant".to_string(),
+                        content: format!("**[调用工具: {}]**\n```json\n{}\n```", name, args_str),
+                        timestamp,
+                    });
+                }
+                // Function call output (tool result)
+                else if item_type == Some("function_call_output") {
+                    let output = payload.get("output")
+                        .and_then(|o| o.as_str())
+                        .unwrap_or("");
+                    if !output.is_empty() {
                         messages.push(SessionMessage {
-                            role: "assistant".to_string(),
-                            content: format!("**[调用工具: {}]**\n```json\n{}\n```", name, args_str),
+                            role: "user".to_string(),
+                            content: format!("**[工具结果]**\n```\n{}\n```", output),
                             timestamp,
                         });
                     }
-                    // Function call output (tool result)
-                    else if item_type == Some("function_call_output") {
-                        let output = payload.get("output")
-                            .and_then(|o| o.as_str())
-                            .unwrap_or("");
-                        if !output.is_empty() {
-                            messages.push(SessionMessage {
-                                role: "user".to_string(),
-                                content: format!("**[工具结果]**\n```\n{}\n```", output),
-                                timestamp,
-                            });
-                        }
-                    }
                 }
             }
         }
     }
     
-    Ok(messages)
+    Ok(SessionMessages { messages, parse_warnings })
 }
 
 // Parse Claude Code messages from JSONL content
-fn parse_claude_jsonl(content: &str) -> Result<Vec<SessionMessage>> {
+fn parse_claude_jsonl(content: &str) -> Result<SessionMessages> {
     use std::io::{BufRead, BufReader};
     
     let mut messages = Vec::new();
+    let mut parse_warnings = 0;
     let reader = BufReader::new(content.as_bytes());
     
-    for line in reader.lines().flatten() {
+    for line in reader.lines() {
+        // Partially-written or corrupt lines are skipped but counted
+        let Ok(line) = line else {
+            parse_warnings += 1;
+            continue;
+        };
         if line.trim().is_empty() {
             continue;
         }
         
-        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
-            let msg_type = data.get("type").and_then(|t| t.as_str());
+        let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) else {
+            parse_warnings += 1;
+            continue;
+        };
+        let msg_type = data.get("type").and_then(|t| t.as_str());
+        
+        if msg_type == Some("user") || msg_type == Some("assistant") {
+            let role = msg_type.unwrap();
+            let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
             
-            if msg_type == Some("user") || msg_type == Some("assistant") {
-                let role = msg_type.unwrap();
-                let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
+            if let Some(message) = data.get("message") {
+                let content_val = message.get("content");
                 
-                if let Some(message) = data.get("message") {
-                    let content_val = message.get("content");
-                    
-                    let content = if let Some(arr) = content_val.and_then(|c| c.as_array()) {
-                        let mut text_parts = Vec::new();
-                        for item in arr {
-                            if let Some(item_type) = item.get("type").and_then(|t| t.as_str()) {
-                                match item_type {
-                                    "text" => {
-                                        if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
-                                            text_parts.push(text.to_string());
-                                        }
-                                    }
-                                    "tool_use" if role == "assistant" => {
-                                        // Tool call from assistant
-                                        let tool_name = item.get("name")
-                                            .and_then(|n| n.as_str())
-                                            .unwrap_or("unknown");
-                                        let tool_input = item.get("input");
-                                        let input_str = if let Some(input) = tool_input {
-                                            serde_json::to_string_pretty(input).unwrap_or_else(|_| "{}".to_string())
-                                        } else {
-                                            "{}".to_string()
-                                        };
-                                        text_parts.push(format!("**[调用工具: {}]**\n```json\n{}\n```", tool_name, input_str));
+                let content = if let Some(arr) = content_val.and_then(|c| c.as_array()) {
+                    let mut text_parts = Vec::new();
+                    for item in arr {
+                        if let Some(item_type) = item.get("type").and_then(|t| t.as_str()) {
+                            match item_type {
+                                "text" => {
+                                    if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
+                                        text_parts.push(text.to_string());
                                     }
-                                    "tool_result" if role == "user" => {
-                                        // Tool result from user
-                                        let result_content = item.get("content");
-                                        let result_str = if let Some(content) = result_content {
-                                            if let Some(s) = content.as_str() {
-                                                s.to_string()
-                                            } else {
-                                                serde_json::to_string_pretty(content).unwrap_or_else(|_| "".to_string())
-                                            }
+                                }
+                                "tool_use" if role == "assistant" => {
+                                    // Tool call from assistant
+                                    let tool_name = item.get("name")
+                                        .and_then(|n| n.as_str())
+                                        .unwrap_or("unknown");
+                                    let tool_input = item.get("input");
+                                    let input_str = if let Some(input) = tool_input {
+                                        serde_json::to_string_pretty(input).unwrap_or_else(|_| "{}".to_string())
+                                    } else {
+                                        "{}".to_string()
+                                    };
+                                    text_parts.push(format!("**[调用工具: {}]**\n```json\n{}\n```", tool_name, input_str));
+                                }
+                                "tool_result" if role == "user" => {
+                                    // Tool result from user
+                                    let result_content = item.get("content");
+                                    let result_str = if let Some(content) = result_content {
+                                        if let Some(s) = content.as_str() {
+                                            s.to_string()
                                         } else {
-                                            String::new()
-                                        };
-                                        if !result_str.is_empty() {
-                                            text_parts.push(format!("**[工具结果]**\n```\n{}\n```", result_str));
+                                            serde_json::to_string_pretty(content).unwrap_or_else(|_| "".to_string())
                                         }
+                                    } else {
+                                        String::new()
+                                    };
+                                    if !result_str.is_empty() {
+                                        text_parts.push(format!("**[工具结果]**\n```\n{}\n```", result_str));
                                     }
-                                    "thinking" if role == "assistant" => {
-                                        // Thinking from assistant
-                                        if let Some(thinking) = item.get("thinking").and_then(|t| t.as_str()) {
-                                            if !thinking.is_empty() {
-                                                text_parts.push(format!("**[思考]**\n{}", thinking));
-                                            }
+                                }
+                                "thinking" if role == "assistant" => {
+                                    // Thinking from assistant
+                                    if let Some(thinking) = item.get("thinking").and_then(|t| t.as_str()) {
+                                        if !thinking.is_empty() {
+                                            text_parts.push(format!("**[思考]**\n{}", thinking));
                                         }
                                     }
-                                    "image" => {
-                                        text_parts.push("[图片]".to_string());
-                                    }
-                                    _ => {}
                                 }
+                                "image" => {
+                                    text_parts.push("[图片]".to_string());
+                                }
+                                _ => {}
                             }
                         }
-                        text_parts.join("\n\n")
-                    } else if let Some(text) = content_val.and_then(|c| c.as_str()) {
-                        text.to_string()
-                    } else {
-                        continue;
-                    };
-                    
-                    if !content.is_empty() && content != "Warmup" {
-                        messages.push(SessionMessage {
-                            role: role.to_string(),
-                            content,
-                            timestamp,
-                        });
                     }
+                    text_parts.join("\n\n")
+                } else if let Some(text) = content_val.and_then(|c| c.as_str()) {
+                    text.to_string()
+                } else {
+                    continue;
+                };
+                
+                if !content.is_empty() && content != "Warmup" {
+                    messages.push(SessionMessage {
+                        role: role.to_string(),
+                        content,
+                        timestamp,
+                    });
                 }
             }
         }
     }
     
-    Ok(messages)
+    Ok(SessionMessages { messages, parse_warnings })
 }
 
 // Session commands
@@ -3008,7 +5647,7 @@ pub async fn get_project_sessions(
                     }
 
                     // Try to read first message from JSONL (Claude Code uses JSONL format)
-                    let (first_message, git_branch, _) = parse_claude_session_info(&path);
+                    let (first_message, git_branch, _, parse_failed) = parse_claude_session_info(&path);
 
                     sessions.push(SessionInfo {
                         session_id,
@@ -3017,6 +5656,7 @@ pub async fn get_project_sessions(
                         first_message,
                         git_branch,
                         summary: String::new(),
+                        parse_failed,
                     });
                 }
             }
@@ -3043,7 +5683,7 @@ pub async fn get_session_messages(
     cli_type: String,
     project_name: String,
     session_id: String,
-) -> Result<Vec<SessionMessage>> {
+) -> Result<SessionMessages> {
     // Special handling for Codex JSONL format
     if cli_type == "codex" {
         return get_codex_messages(&session_id);
@@ -3174,7 +5814,8 @@ pub async fn get_session_messages(
         }
     }
 
-    Ok(messages)
+    // Gemini sessions are a single JSON document, so a corrupt file fails outright above
+    Ok(SessionMessages { messages, parse_warnings: 0 })
 }
 
 #[tauri::command]
@@ -3232,13 +5873,19 @@ pub async fn delete_session(
 pub async fn delete_project(
     cli_type: String,
     project_name: String,
-) -> Result<()> {
+) -> Result<u64> {
+    if project_name.trim().is_empty() {
+        return Err("Project name is required".to_string());
+    }
+
     let base_dir = get_cli_base_dir(&cli_type);
     
     if cli_type == "codex" {
         // For Codex, delete all session files matching the project cwd
+        // (project_name is the cwd recorded in the session, never joined onto a path)
         use walkdir::WalkDir;
         let sessions_dir = base_dir.join("sessions");
+        let mut removed = 0u64;
         if sessions_dir.exists() {
             // Use WalkDir to recursively search all subdirectories
             for entry in WalkDir::new(&sessions_dir)
@@ -3254,47 +5901,383 @@ pub async fn delete_project(
                     if filename.starts_with("rollout-") && filename.ends_with(".jsonl") {
                         if let Some(cwd) = extract_codex_cwd(path) {
                             if cwd == project_name {
-                                let _ = std::fs::remove_file(path);
+                                std::fs::remove_file(path)
+                                    .map_err(|e| format!("Failed to delete session {}: {}", path.display(), e))?;
+                                removed += 1;
                             }
                         }
                     }
                 }
             }
         }
-        return Ok(());
+        if removed == 0 {
+            return Err(format!("Project not found: {}", project_name));
+        }
+        return Ok(removed);
     }
     
     // For Claude Code and Gemini, delete the project directory
-    let project_dir = match cli_type.as_str() {
-        "gemini" => base_dir.join("tmp").join(&project_name),
-        _ => base_dir.join("projects").join(&project_name),
-    };
+    let project_dir = resolve_project_dir(&base_dir, &cli_type, &project_name)?;
+
+    let removed = walkdir::WalkDir::new(&project_dir)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .count() as u64;
 
     std::fs::remove_dir_all(&project_dir)
         .map_err(|e| format!("Failed to delete project: {}", e))?;
 
-    Ok(())
+    Ok(removed)
+}
+
+/// Original bytes of a session file, for moving a session to another machine in the CLI's
+/// own format (JSONL for Claude Code / Codex, JSON for Gemini)
+#[tauri::command]
+pub async fn export_session_raw(
+    cli_type: String,
+    project_name: String,
+    session_id: String,
+) -> Result<Vec<u8>> {
+    let path = find_session_file(&cli_type, &project_name, &session_id)?;
+    std::fs::read(&path).map_err(|e| format!("Failed to read session file '{}': {}", path.display(), e))
+}
+
+/// Absolute path of a session file, resolved the same way the session views do
+/// (recursive search for Codex, the project hash folder for Gemini)
+#[tauri::command]
+pub async fn get_session_file_path(
+    cli_type: String,
+    project_name: String,
+    session_id: String,
+) -> Result<String> {
+    let path = find_session_file(&cli_type, &project_name, &session_id)?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Reveal a session file in the system file manager, for inspecting the raw file when
+/// the parsed view looks wrong. Returns the resolved path.
+#[tauri::command]
+pub async fn open_session_file(
+    app: tauri::AppHandle,
+    cli_type: String,
+    project_name: String,
+    session_id: String,
+) -> Result<String> {
+    use tauri_plugin_opener::OpenerExt;
+
+    let path = find_session_file(&cli_type, &project_name, &session_id)?;
+    app.opener()
+        .reveal_item_in_dir(&path)
+        .map_err(|e| format!("Failed to open file manager for '{}': {}", path.display(), e))?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Write a session exported by `export_session_raw` into the CLI's session folder and
+/// return its session id. The content is validated and written unmodified; the file name
+/// comes from the session itself. Codex files go into the sessions/YYYY/MM/DD folder of
+/// their start time and keep their recorded cwd, so `project_name` is not used for Codex.
+#[tauri::command]
+pub async fn import_session_raw(
+    cli_type: String,
+    project_name: String,
+    bytes: Vec<u8>,
+    overwrite: Option<bool>,
+) -> Result<String> {
+    let content = std::str::from_utf8(&bytes).map_err(|_| "Session file is not valid UTF-8".to_string())?;
+    let base_dir = get_cli_base_dir(&cli_type);
+
+    let (session_id, path) = match cli_type.as_str() {
+        "claude_code" => {
+            let lines = parse_jsonl_lines(content)?;
+            let session_id = lines
+                .iter()
+                .find_map(|l| l.get("sessionId").and_then(|s| s.as_str()))
+                .ok_or_else(|| "No sessionId found in the Claude Code session".to_string())?
+                .to_string();
+            let project_dir = base_dir.join("projects").join(checked_path_segment(&project_name, "project name")?);
+            let file_name = format!("{}.jsonl", checked_path_segment(&session_id, "session id")?);
+            (session_id, project_dir.join(file_name))
+        }
+        "codex" => {
+            let lines = parse_jsonl_lines(content)?;
+            let payload = lines
+                .iter()
+                .find(|l| l.get("type").and_then(|t| t.as_str()) == Some("session_meta"))
+                .and_then(|l| l.get("payload"))
+                .ok_or_else(|| "No session_meta found in the Codex session".to_string())?;
+            let id = payload.get("id").and_then(|s| s.as_str())
+                .ok_or_else(|| "Codex session_meta has no id".to_string())?;
+            let started = payload.get("timestamp").and_then(|s| s.as_str())
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                .ok_or_else(|| "Codex session_meta has no valid timestamp".to_string())?
+                .with_timezone(&chrono::Local);
+            // Codex names rollouts after the local start time: rollout-YYYY-MM-DDTHH-MM-SS-<id>.jsonl
+            let session_id = format!("rollout-{}-{}", started.format("%Y-%m-%dT%H-%M-%S"), checked_path_segment(id, "session id")?);
+            let dir = base_dir.join("sessions").join(started.format("%Y/%m/%d").to_string());
+            let path = dir.join(format!("{}.jsonl", session_id));
+            (session_id, path)
+        }
+        "gemini" => {
+            let json: serde_json::Value = serde_json::from_str(content)
+                .map_err(|e| format!("Invalid Gemini session JSON: {}", e))?;
+            let session_id = json.get("sessionId").and_then(|s| s.as_str())
+                .ok_or_else(|| "No sessionId found in the Gemini session".to_string())?
+                .to_string();
+            let chats_dir = base_dir.join("tmp").join(checked_path_segment(&project_name, "project name")?).join("chats");
+            let file_name = format!("{}.json", checked_path_segment(&session_id, "session id")?);
+            (session_id, chats_dir.join(file_name))
+        }
+        _ => return Err(format!("Unsupported CLI type: {}", cli_type)),
+    };
+
+    if path.exists() && !overwrite.unwrap_or(false) {
+        return Err(format!("Session already exists: {}", path.display()));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create session directory: {}", e))?;
+    }
+    std::fs::write(&path, &bytes).map_err(|e| format!("Failed to write session file '{}': {}", path.display(), e))?;
+    tracing::info!(cli_type = %cli_type, session_id = %session_id, "Imported session to {}", path.display());
+    Ok(session_id)
+}
+
+// Every non-empty line must be a JSON value
+fn parse_jsonl_lines(content: &str) -> Result<Vec<serde_json::Value>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| format!("Invalid JSONL at line {}: {}", i + 1, e))
+        })
+        .collect()
+}
+
+// A single file/directory name that cannot escape its parent folder
+fn checked_path_segment<'a>(value: &'a str, what: &str) -> Result<&'a str> {
+    if value.is_empty() || value == "." || value == ".." || value.contains(['/', '\\', '\0']) {
+        return Err(format!("Invalid {}: {}", what, value));
+    }
+    Ok(value)
+}
+
+// Locate an existing session file; Codex sessions are found by file stem and recorded cwd
+fn find_session_file(cli_type: &str, project_name: &str, session_id: &str) -> Result<std::path::PathBuf> {
+    checked_path_segment(session_id, "session id")?;
+    let base_dir = get_cli_base_dir(cli_type);
+
+    if cli_type == "codex" {
+        return walkdir::WalkDir::new(base_dir.join("sessions"))
+            .follow_links(false)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .map(|e| e.into_path())
+            .find(|path| {
+                path.is_file()
+                    && path.file_stem().and_then(|s| s.to_str()) == Some(session_id)
+                    && extract_codex_cwd(path).as_deref() == Some(project_name)
+            })
+            .ok_or_else(|| "Session file not found".to_string());
+    }
+
+    let project_dir = resolve_project_dir(&base_dir, cli_type, project_name)?;
+    let path = match cli_type {
+        "gemini" => project_dir.join("chats").join(format!("{}.json", session_id)),
+        _ => project_dir.join(format!("{}.jsonl", session_id)),
+    };
+    if !path.is_file() {
+        return Err(format!("Session file not found: {}", path.display()));
+    }
+    Ok(path)
+}
+
+/// Resolve a Claude Code / Gemini project directory, rejecting names that would escape
+/// the CLI's projects folder (separators, `.`/`..`) and directories that don't exist
+fn resolve_project_dir(base_dir: &std::path::Path, cli_type: &str, project_name: &str) -> Result<std::path::PathBuf> {
+    checked_path_segment(project_name, "project name")?;
+
+    let parent = match cli_type {
+        "gemini" => base_dir.join("tmp"),
+        _ => base_dir.join("projects"),
+    };
+    let project_dir = parent.join(project_name);
+    if !project_dir.is_dir() {
+        return Err(format!("Project not found: {}", project_name));
+    }
+
+    // Symlinks or odd path components must still resolve inside the projects folder
+    let canonical_parent = parent.canonicalize()
+        .map_err(|e| format!("Failed to resolve projects directory: {}", e))?;
+    let canonical_dir = project_dir.canonicalize()
+        .map_err(|e| format!("Failed to resolve project directory: {}", e))?;
+    if canonical_dir == canonical_parent || !canonical_dir.starts_with(&canonical_parent) {
+        return Err(format!("Project directory is outside {}: {}", canonical_parent.display(), project_name));
+    }
+
+    Ok(project_dir)
 }
 
-/// 退出应用程序（导入后需要手动重启）
-async fn exit_application() -> Result<()> {
-    tokio::spawn(async {
+/// 退出应用程序（导入后需要手动重启），按 shutdown_grace_secs 等待进行中的请求
+async fn exit_application(app: tauri::AppHandle) -> Result<()> {
+    tokio::spawn(async move {
         // 延迟 3 秒，等待响应返回前端并给用户时间看提示
         tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
-        std::process::exit(0);
+        crate::services::shutdown::exit_gracefully(&app).await;
     });
 
     Ok(())
 }
 
+/// 在不重启的情况下导入数据库：附加导入文件，在同一事务中用其数据替换当前各表内容。
+/// 只复制两边都存在的表和列，旧版本备份缺少的列使用默认值；连接池保持不变，无需重新打开。
+async fn restore_database_in_place(app: &tauri::AppHandle, db: &SqlitePool, data: &[u8]) -> Result<()> {
+    if !data.starts_with(b"SQLite format 3\0") {
+        return Err("Invalid backup file: not a SQLite database".to_string());
+    }
+
+    let temp_path = get_data_dir().join("ccg_gateway.import.db");
+    write_database_file(app, &temp_path, data)?;
+    let result = import_attached_database(db, &temp_path).await;
+    let _ = std::fs::remove_file(&temp_path);
+    result
+}
+
+/// 附加并复制导入文件的数据，然后按启动时的流程执行迁移：
+/// 版本回退到备份的版本，使备份之后引入的数据迁移（新列回填等）对导入的数据重新执行
+async fn import_attached_database(db: &SqlitePool, path: &std::path::Path) -> Result<()> {
+    let mut conn = db.acquire().await.map_err(|e| e.to_string())?;
+    sqlx::query("ATTACH DATABASE ? AS imported")
+        .bind(path.to_string_lossy().to_string())
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| format!("Failed to open backup: {}", e))?;
+
+    let result = copy_attached_tables(&mut conn).await;
+
+    let _ = sqlx::query("DETACH DATABASE imported").execute(&mut *conn).await;
+    drop(conn);
+    result?;
+
+    crate::db::migrate_schema(db, false)
+        .await
+        .map_err(|e| format!("Failed to migrate imported database: {}", e))?;
+    Ok(())
+}
+
+// 将 imported 库中的数据复制到 main 库（单事务，失败则全部回滚）
+async fn copy_attached_tables(conn: &mut sqlx::SqliteConnection) -> Result<()> {
+    use sqlx::Connection;
+
+    let tables: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM imported.sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name NOT IN ('_schema_version', '_schema_migrations')",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    // 没有版本表的备份来自版本表引入之前，视为版本 0
+    let has_version_table: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM imported.sqlite_master WHERE type = 'table' AND name = '_schema_version')",
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    let imported_version: i64 = if has_version_table {
+        sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM imported._schema_version")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?
+    } else {
+        0
+    };
+
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+
+    let mut copies = Vec::new();
+    for table in tables {
+        let main_columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?, 'main')")
+            .bind(&table)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        if main_columns.is_empty() {
+            continue;
+        }
+        let imported_columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?, 'imported')")
+            .bind(&table)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let columns: Vec<String> = main_columns
+            .into_iter()
+            .filter(|c| imported_columns.contains(c))
+            .map(|c| format!("\"{}\"", c.replace('"', "\"\"")))
+            .collect();
+        let table = format!("\"{}\"", table.replace('"', "\"\""));
+
+        sqlx::query(&format!("DELETE FROM main.{}", table))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        copies.push((table, columns.join(", ")));
+    }
+
+    for (table, columns) in copies {
+        if columns.is_empty() {
+            continue;
+        }
+        sqlx::query(&format!(
+            "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM imported.{table}"
+        ))
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to import table {}: {}", table, e))?;
+    }
+
+    // 回退到备份的版本，随后 migrate_schema 只会执行备份之后引入的数据迁移；来自更新版本的备份不回退
+    let current_version: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM main._schema_version")
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    if imported_version >= current_version {
+        return tx.commit().await.map_err(|e| e.to_string());
+    }
+    sqlx::query("DELETE FROM main._schema_version WHERE version > ?")
+        .bind(imported_version)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("INSERT OR REPLACE INTO main._schema_version (version, applied_at) VALUES (?, ?)")
+        .bind(imported_version)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM main._schema_migrations WHERE version > ?")
+        .bind(imported_version)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
 // Backup commands
 #[tauri::command]
 pub async fn get_webdav_settings(db: State<'_, SqlitePool>) -> Result<WebdavSettings> {
+    get_webdav_settings_impl(db.inner()).await
+}
+
+pub(crate) async fn get_webdav_settings_impl(db: &SqlitePool) -> Result<WebdavSettings> {
     // Try to get existing settings
     let settings = sqlx::query_as::<_, WebdavSettings>(
-        "SELECT url, username, password FROM webdav_settings WHERE id = 1"
+        "SELECT url, username, password, COALESCE(NULLIF(path, ''), ?) AS remote_folder FROM webdav_settings WHERE id = 1"
     )
-    .fetch_optional(db.inner())
+    .bind(DEFAULT_WEBDAV_FOLDER)
+    .fetch_optional(db)
     .await
     .map_err(|e| e.to_string())?;
 
@@ -3307,7 +6290,7 @@ pub async fn get_webdav_settings(db: State<'_, SqlitePool>) -> Result<WebdavSett
                 "INSERT INTO webdav_settings (id, url, username, password, updated_at) VALUES (1, '', '', '', ?)"
             )
             .bind(now)
-            .execute(db.inner())
+            .execute(db)
             .await
             .map_err(|e| e.to_string())?;
 
@@ -3315,6 +6298,7 @@ pub async fn get_webdav_settings(db: State<'_, SqlitePool>) -> Result<WebdavSett
                 url: String::new(),
                 username: String::new(),
                 password: String::new(),
+                remote_folder: DEFAULT_WEBDAV_FOLDER.to_string(),
             })
         }
     }
@@ -3326,20 +6310,74 @@ pub async fn update_webdav_settings(
     input: WebdavSettingsUpdate,
 ) -> Result<WebdavSettings> {
     let now = chrono::Utc::now().timestamp();
-    let current = get_webdav_settings(db.clone()).await?;
+    let current = get_webdav_settings_impl(db.inner()).await?;
+
+    // Store the URL in canonical form (validated, no trailing slash)
+    let url = match input.url {
+        Some(url) if url.trim().is_empty() => String::new(),
+        Some(url) => normalize_webdav_url(&url)?,
+        None => current.url,
+    };
+
+    let remote_folder = match input.remote_folder {
+        Some(folder) => normalize_webdav_folder(&folder)?,
+        None => current.remote_folder,
+    };
 
     sqlx::query(
-        "UPDATE webdav_settings SET url = ?, username = ?, password = ?, updated_at = ? WHERE id = 1"
+        "UPDATE webdav_settings SET url = ?, username = ?, password = ?, path = ?, updated_at = ? WHERE id = 1"
     )
-    .bind(input.url.unwrap_or(current.url))
+    .bind(url)
     .bind(input.username.unwrap_or(current.username))
     .bind(input.password.unwrap_or(current.password))
+    .bind(remote_folder)
     .bind(now)
     .execute(db.inner())
     .await
     .map_err(|e| e.to_string())?;
 
-    get_webdav_settings(db).await
+    get_webdav_settings_impl(db.inner()).await
+}
+
+// Remote subfolder used for backups when none is configured
+const DEFAULT_WEBDAV_FOLDER: &str = "ccg-gateway-backup";
+
+// Validate the backup folder name: relative, no empty/"."/".." segments; empty means the default
+fn normalize_webdav_folder(folder: &str) -> Result<String> {
+    let folder = folder.trim().trim_end_matches('/');
+    if folder.is_empty() {
+        return Ok(DEFAULT_WEBDAV_FOLDER.to_string());
+    }
+    if folder.starts_with('/') || folder.contains('\\') {
+        return Err(format!("Invalid WebDAV folder '{}': must be a relative path without backslashes", folder));
+    }
+    if folder.split('/').any(|seg| seg.is_empty() || seg == "." || seg == "..") {
+        return Err(format!("Invalid WebDAV folder '{}': path traversal or empty segments are not allowed", folder));
+    }
+    Ok(folder.to_string())
+}
+
+// Full URL of the remote backup folder
+fn webdav_remote_dir(settings: &WebdavSettings) -> String {
+    format!("{}/{}", settings.url.trim_end_matches('/'), settings.remote_folder)
+}
+
+// Validate a WebDAV URL and return it in canonical form (http/https, host required, no trailing slash)
+fn normalize_webdav_url(url: &str) -> Result<String> {
+    let url = url.trim();
+    if !url.contains("://") {
+        return Err(format!("Invalid WebDAV URL '{}': missing scheme (expected http:// or https://)", url));
+    }
+
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid WebDAV URL '{}': {}", url, e))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(format!("Invalid WebDAV URL '{}': scheme must be http or https", url));
+    }
+    if parsed.host_str().map(|h| h.is_empty()).unwrap_or(true) {
+        return Err(format!("Invalid WebDAV URL '{}': missing host", url));
+    }
+
+    Ok(parsed.as_str().trim_end_matches('/').to_string())
 }
 
 #[tauri::command]
@@ -3350,6 +6388,7 @@ pub async fn test_webdav_connection(
 ) -> Result<bool> {
     use reqwest::Client;
 
+    let url = normalize_webdav_url(&url)?;
     let client = Client::new();
     let response = client
         .request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), &url)
@@ -3359,58 +6398,267 @@ pub async fn test_webdav_connection(
         .await
         .map_err(|e| format!("Connection failed: {}", e))?;
 
-    Ok(response.status().is_success() || response.status().as_u16() == 207)
+    Ok(response.status().is_success() || response.status().as_u16() == 207)
+}
+
+// 备份进度事件名
+const BACKUP_PROGRESS_EVENT: &str = "backup://progress";
+// 本地读写数据库文件、上传时每块的大小
+const BACKUP_CHUNK_SIZE: usize = 256 * 1024;
+// 两次进度事件之间至少间隔的字节数，避免逐块发送
+const BACKUP_PROGRESS_STEP: u64 = 1024 * 1024;
+
+fn emit_backup_progress(app: &tauri::AppHandle, operation: &str, file: &str, bytes: u64, total: Option<u64>, done: bool) {
+    use tauri::Emitter;
+
+    let _ = app.emit(BACKUP_PROGRESS_EVENT, crate::db::models::BackupProgress {
+        operation: operation.to_string(),
+        file: file.to_string(),
+        bytes,
+        total,
+        done,
+    });
+}
+
+/// Throttled `backup://progress` reporting for one file being read, written or transferred
+struct BackupProgressReporter {
+    app: tauri::AppHandle,
+    operation: &'static str,
+    file: String,
+    total: Option<u64>,
+    bytes: u64,
+    last_emitted: u64,
+}
+
+impl BackupProgressReporter {
+    fn start(app: &tauri::AppHandle, operation: &'static str, file: &str, total: Option<u64>) -> Self {
+        emit_backup_progress(app, operation, file, 0, total, false);
+        Self {
+            app: app.clone(),
+            operation,
+            file: file.to_string(),
+            total,
+            bytes: 0,
+            last_emitted: 0,
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.bytes += len as u64;
+        if self.bytes - self.last_emitted >= BACKUP_PROGRESS_STEP {
+            emit_backup_progress(&self.app, self.operation, &self.file, self.bytes, self.total, false);
+            self.last_emitted = self.bytes;
+        }
+    }
+
+    fn finish(&self) {
+        emit_backup_progress(&self.app, self.operation, &self.file, self.bytes, self.total, true);
+    }
+}
+
+fn file_label(path: &std::path::Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+// 分块读取数据库文件，发送 read 进度
+fn read_database_file(app: &tauri::AppHandle, path: &std::path::Path) -> Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to read database: {}", e))?;
+    let total = file.metadata().map(|m| m.len()).ok();
+    let mut progress = BackupProgressReporter::start(app, "read", &file_label(path), total);
+
+    let mut content = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut buf = vec![0u8; BACKUP_CHUNK_SIZE];
+    loop {
+        let len = file.read(&mut buf)
+            .map_err(|e| format!("Failed to read database: {}", e))?;
+        if len == 0 {
+            break;
+        }
+        content.extend_from_slice(&buf[..len]);
+        progress.advance(len);
+    }
+    progress.finish();
+    Ok(content)
+}
+
+// 分块写入数据库文件，发送 write 进度
+fn write_database_file(app: &tauri::AppHandle, path: &std::path::Path, data: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::File::create(path)
+        .map_err(|e| format!("Failed to write database: {}", e))?;
+    let mut progress = BackupProgressReporter::start(app, "write", &file_label(path), Some(data.len() as u64));
+    for chunk in data.chunks(BACKUP_CHUNK_SIZE) {
+        file.write_all(chunk)
+            .map_err(|e| format!("Failed to write database: {}", e))?;
+        progress.advance(chunk.len());
+    }
+    file.sync_all()
+        .map_err(|e| format!("Failed to write database: {}", e))?;
+    progress.finish();
+    Ok(())
 }
 
+/// Read the database file for download. Emits `backup://progress` while reading.
 #[tauri::command]
-pub async fn export_to_local() -> Result<Vec<u8>> {
+pub async fn export_to_local(app: tauri::AppHandle) -> Result<Vec<u8>> {
     // Get the database path from config
     let db_path = get_data_dir().join("ccg_gateway.db");
 
-    // Read the database file
-    let content = std::fs::read(&db_path)
-        .map_err(|e| format!("Failed to read database: {}", e))?;
-
-    Ok(content)
+    read_database_file(&app, &db_path)
 }
 
+/// `restart` 默认为 true：写入数据库文件后退出应用；为 false 时就地导入数据并继续运行
+/// 写入过程中发送 backup://progress 进度事件
 #[tauri::command]
-pub async fn import_from_local(data: Vec<u8>) -> Result<()> {
+pub async fn import_from_local(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    data: Vec<u8>,
+    restart: Option<bool>,
+) -> Result<()> {
+    // The restored data no longer matches the last incremental backup
+    crate::services::incremental_backup::clear_state();
+
+    if !restart.unwrap_or(true) {
+        return restore_database_in_place(&app, db.inner(), &data).await;
+    }
+
     let db_path = get_data_dir().join("ccg_gateway.db");
 
     // Write the database file
-    std::fs::write(&db_path, &data)
-        .map_err(|e| format!("Failed to write database: {}", e))?;
+    write_database_file(&app, &db_path, &data)?;
 
     // 退出应用，用户需手动重启
-    exit_application().await?;
+    exit_application(app).await?;
 
     Ok(())
 }
 
+/// Export the selected config sections (all when empty) as a passphrase-encrypted bundle.
+/// The bundle carries API keys, so it is only ever written encrypted; the admin token, GitHub
+/// token and WebDAV password are left out unless `include_secrets` is set.
+#[tauri::command]
+pub async fn export_config_encrypted(
+    db: State<'_, SqlitePool>,
+    passphrase: String,
+    sections: Option<Vec<String>>,
+    include_secrets: Option<bool>,
+) -> Result<Vec<u8>> {
+    use crate::services::{config_bundle, encryption};
+
+    let bundle = config_bundle::export(
+        db.inner(),
+        sections.as_deref().unwrap_or_default(),
+        include_secrets.unwrap_or(false),
+    )
+    .await?;
+    let json = serde_json::to_vec(&bundle).map_err(|e| e.to_string())?;
+    encryption::encrypt(&json, &passphrase)
+}
+
+/// Import an encrypted config bundle. `mode` is `replace` (clear the bundle's sections first)
+/// or `merge` (overwrite matching rows, keep the rest; providers match by name and CLI type).
+/// Returns the number of tables written.
+#[tauri::command]
+pub async fn import_config_encrypted(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    data: Vec<u8>,
+    passphrase: String,
+    mode: Option<String>,
+) -> Result<usize> {
+    use crate::services::config_bundle::{self, ImportMode};
+    use crate::services::encryption;
+
+    let mode: ImportMode = mode.as_deref().unwrap_or("merge").parse()?;
+    // Decrypt and validate fully before touching the database
+    let json = encryption::decrypt(&data, &passphrase)?;
+    let bundle = config_bundle::parse(&json)?;
+    let applied = config_bundle::import(db.inner(), &bundle, mode).await?;
+    // The imported data no longer matches the last incremental backup
+    crate::services::incremental_backup::clear_state();
+
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "config_imported",
+        &format!("已导入加密配置包（{}），共 {} 个表", bundle.sections.join(", "), applied),
+    ).await;
+
+    Ok(applied)
+}
+
+/// Upload a backup to WebDAV. With `incremental`, only config tables that changed since the
+/// last backup are uploaded as `ccg_gateway_inc_{ts}.json`; a full baseline is uploaded instead
+/// when there is none yet or the current one already has too many increments.
+/// Returns the uploaded filename, or an empty string when nothing changed.
+/// Emits `backup://progress` while reading the database and uploading.
 #[tauri::command]
-pub async fn export_to_webdav(db: State<'_, SqlitePool>) -> Result<String> {
+pub async fn export_to_webdav(app: tauri::AppHandle, db: State<'_, SqlitePool>, incremental: Option<bool>) -> Result<String> {
+    use crate::services::incremental_backup::{self as inc, BackupState};
     use reqwest::Client;
 
-    let settings = get_webdav_settings(db.clone()).await?;
+    let settings = get_webdav_settings_impl(db.inner()).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
 
+    let client = Client::new();
+    let timestamp = chrono::Local::now().format(inc::BACKUP_TIMESTAMP_FORMAT);
+    let snapshot = inc::snapshot(db.inner()).await?;
+    // Increment chains are tracked per remote folder
+    let target = webdav_remote_dir(&settings);
+
+    if incremental.unwrap_or(false) {
+        if let Some(state) = inc::load_state(&target).filter(|s| !s.baseline.is_empty() && s.sequence < inc::MAX_INCREMENTS_PER_BASELINE) {
+            let hashes = snapshot.hashes.clone();
+            let Some(increment) = inc::build_increment(&state, snapshot) else {
+                return Ok(String::new());
+            };
+            let filename = format!("{}{}.json", inc::INCREMENT_PREFIX, timestamp);
+            let content = serde_json::to_vec(&increment).map_err(|e| e.to_string())?;
+            upload_webdav_file(&app, &client, &settings, &filename, content).await?;
+
+            inc::save_state(&target, &BackupState {
+                baseline: state.baseline,
+                sequence: increment.sequence,
+                watermark: increment.watermark,
+                table_hashes: hashes,
+            })?;
+            return Ok(filename);
+        }
+    }
+
     // Read database file
     let db_path = get_data_dir().join("ccg_gateway.db");
-    let content = std::fs::read(&db_path)
-        .map_err(|e| format!("Failed to read database: {}", e))?;
+    let content = read_database_file(&app, &db_path)?;
 
     // Generate filename
-    let filename = format!(
-        "ccg_gateway_{}.db",
-        chrono::Local::now().format("%Y%m%d_%H%M%S")
-    );
+    let filename = format!("ccg_gateway_{}.db", timestamp);
+    upload_webdav_file(&app, &client, &settings, &filename, content).await?;
+
+    // Every full backup starts a new increment chain
+    inc::save_state(&target, &BackupState {
+        baseline: filename.clone(),
+        sequence: 0,
+        watermark: snapshot.watermark,
+        table_hashes: snapshot.hashes,
+    })?;
+
+    Ok(filename)
+}
 
+// 分块流式上传，发送 upload 进度（按已交给连接的字节数计，服务端确认后发送完成事件）
+async fn upload_webdav_file(
+    app: &tauri::AppHandle,
+    client: &reqwest::Client,
+    settings: &WebdavSettings,
+    filename: &str,
+    content: Vec<u8>,
+) -> Result<()> {
     // Ensure remote directory exists
-    let client = Client::new();
-    let remote_dir = format!("{}/ccg-gateway-backup", settings.url.trim_end_matches('/'));
+    let remote_dir = webdav_remote_dir(settings);
 
     // Try to create directory (ignore error if exists)
     let _ = client
@@ -3421,10 +6669,21 @@ pub async fn export_to_webdav(db: State<'_, SqlitePool>) -> Result<String> {
 
     // Upload file
     let remote_file = format!("{}/{}", remote_dir, filename);
+    let total = content.len() as u64;
+    let mut progress = BackupProgressReporter::start(app, "upload", filename, Some(total));
+    let chunks: Vec<Vec<u8>> = content.chunks(BACKUP_CHUNK_SIZE).map(<[u8]>::to_vec).collect();
+    drop(content);
+    let body = futures_util::stream::iter(chunks.into_iter().map(move |chunk| {
+        progress.advance(chunk.len());
+        Ok::<_, std::io::Error>(chunk)
+    }));
+
     let response = client
         .put(&remote_file)
         .basic_auth(&settings.username, Some(&settings.password))
-        .body(content)
+        // Streamed bodies are sent chunked otherwise, which some WebDAV servers reject
+        .header(reqwest::header::CONTENT_LENGTH, total)
+        .body(reqwest::Body::wrap_stream(body))
         .send()
         .await
         .map_err(|e| format!("Upload failed: {}", e))?;
@@ -3433,20 +6692,56 @@ pub async fn export_to_webdav(db: State<'_, SqlitePool>) -> Result<String> {
         return Err(format!("Upload failed with status: {}", response.status()));
     }
 
-    Ok(filename)
+    emit_backup_progress(app, "upload", filename, total, Some(total), true);
+    Ok(())
+}
+
+// 流式下载，发送 download 进度
+async fn download_webdav_file(
+    app: &tauri::AppHandle,
+    client: &reqwest::Client,
+    settings: &WebdavSettings,
+    filename: &str,
+) -> Result<Vec<u8>> {
+    use futures_util::StreamExt;
+
+    let remote_file = format!("{}/{}", webdav_remote_dir(settings), filename);
+
+    let response = client
+        .get(&remote_file)
+        .basic_auth(&settings.username, Some(&settings.password))
+        .send()
+        .await
+        .map_err(|e| format!("Download failed: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Download failed with status: {}", response.status()));
+    }
+
+    let total = response.content_length();
+    let mut progress = BackupProgressReporter::start(app, "download", filename, total);
+    let mut content = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("Download interrupted: {}", e))?;
+        content.extend_from_slice(&chunk);
+        progress.advance(chunk.len());
+    }
+    progress.finish();
+    Ok(content)
 }
 
 #[tauri::command]
 pub async fn list_webdav_backups(db: State<'_, SqlitePool>) -> Result<Vec<WebdavBackup>> {
     use reqwest::Client;
 
-    let settings = get_webdav_settings(db).await?;
+    let settings = get_webdav_settings_impl(db.inner()).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
 
     let client = Client::new();
-    let remote_dir = format!("{}/ccg-gateway-backup", settings.url.trim_end_matches('/'));
+    let remote_dir = webdav_remote_dir(&settings);
 
     let response = client
         .request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), &remote_dir)
@@ -3514,16 +6809,18 @@ pub async fn list_webdav_backups(db: State<'_, SqlitePool>) -> Result<Vec<Webdav
                 if name.ends_with(":response") || name == "response" {
                     in_response = false;
                     
-                    // Check if this is a .db file we care about
-                    if current_href.contains("ccg_gateway_") && current_href.ends_with(".db") {
+                    // Check if this is a .db file (or incremental .json) we care about
+                    if current_href.contains("ccg_gateway_") && (current_href.ends_with(".db") || current_href.ends_with(".json")) {
                         // Extract filename from href
                         if let Some(start) = current_href.rfind('/') {
                             let filename = current_href[start + 1..].to_string();
-                            if filename.starts_with("ccg_gateway_") {
+                            let incremental = crate::services::incremental_backup::is_increment_file(&filename);
+                            if filename.starts_with("ccg_gateway_") && (incremental || filename.ends_with(".db")) {
                                 backups.push(WebdavBackup {
                                     filename,
                                     size: current_size,
                                     modified: current_modified.clone(),
+                                    incremental,
                                 });
                             }
                         }
@@ -3543,46 +6840,95 @@ pub async fn list_webdav_backups(db: State<'_, SqlitePool>) -> Result<Vec<Webdav
     Ok(backups)
 }
 
+/// Restore a WebDAV backup. Picking an incremental backup restores its full baseline
+/// and then applies every increment of that chain up to and including the chosen one; the
+/// chain is rebuilt in a scratch database, so the current data is only replaced once all of it
+/// applied. `restart` works as in `import_from_local`.
+/// Emits `backup://progress` for each downloaded file and the database write.
 #[tauri::command]
 pub async fn import_from_webdav(
+    app: tauri::AppHandle,
     db: State<'_, SqlitePool>,
     filename: String,
+    restart: Option<bool>,
 ) -> Result<()> {
+    use crate::services::incremental_backup as inc;
     use reqwest::Client;
 
-    let settings = get_webdav_settings(db).await?;
+    let settings = get_webdav_settings_impl(db.inner()).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
 
     let client = Client::new();
-    let remote_file = format!(
-        "{}/ccg-gateway-backup/{}",
-        settings.url.trim_end_matches('/'),
-        filename
-    );
 
-    let response = client
-        .get(&remote_file)
-        .basic_auth(&settings.username, Some(&settings.password))
-        .send()
-        .await
-        .map_err(|e| format!("Download failed: {}", e))?;
+    if inc::is_increment_file(&filename) {
+        let target = inc::parse_increment(&download_webdav_file(&app, &client, &settings, &filename).await?)?;
+        let baseline_ts = inc::backup_timestamp(&target.baseline)
+            .ok_or_else(|| format!("Invalid baseline in incremental backup: {}", target.baseline))?
+            .to_string();
+        let target_ts = inc::backup_timestamp(&filename).unwrap_or_default().to_string();
 
-    if !response.status().is_success() {
-        return Err(format!("Download failed with status: {}", response.status()));
+        // Increments of this chain were written after the baseline and no later than the target
+        let mut chain = Vec::new();
+        for backup in list_webdav_backups(db.clone()).await? {
+            if !backup.incremental || backup.filename == filename {
+                continue;
+            }
+            let Some(ts) = inc::backup_timestamp(&backup.filename) else {
+                continue;
+            };
+            if ts > baseline_ts.as_str() && ts <= target_ts.as_str() {
+                let increment = inc::parse_increment(&download_webdav_file(&app, &client, &settings, &backup.filename).await?)?;
+                if increment.sequence < target.sequence {
+                    chain.push(increment);
+                }
+            }
+        }
+        let baseline = target.baseline.clone();
+        chain.push(target);
+        let chain = inc::order_chain(&baseline, chain)?;
+
+        let content = download_webdav_file(&app, &client, &settings, &baseline).await?;
+        if !content.starts_with(b"SQLite format 3\0") {
+            return Err("Invalid backup file: not a SQLite database".to_string());
+        }
+
+        // Rebuild baseline + increments in a scratch file so a failure leaves the current data untouched
+        let temp_path = get_data_dir().join("ccg_gateway.import.db");
+        write_database_file(&app, &temp_path, &content)?;
+        let result = match inc::restore_chain(&temp_path, &chain).await {
+            Ok(()) if restart.unwrap_or(true) => std::fs::read(&temp_path)
+                .map_err(|e| format!("Failed to read restored database: {}", e))
+                .and_then(|restored| write_database_file(&app, &get_data_dir().join("ccg_gateway.db"), &restored)),
+            Ok(()) => import_attached_database(db.inner(), &temp_path).await,
+            Err(e) => Err(e),
+        };
+        let _ = std::fs::remove_file(&temp_path);
+        result?;
+        inc::clear_state();
+
+        if restart.unwrap_or(true) {
+            // 退出应用，用户需手动重启
+            exit_application(app).await?;
+        }
+        return Ok(());
     }
 
-    let content = response.bytes().await.map_err(|e| e.to_string())?;
+    let content = download_webdav_file(&app, &client, &settings, &filename).await?;
+    inc::clear_state();
+
+    if !restart.unwrap_or(true) {
+        return restore_database_in_place(&app, db.inner(), &content).await;
+    }
 
     // Write to database file
     let db_path = get_data_dir().join("ccg_gateway.db");
 
-    std::fs::write(&db_path, &content)
-        .map_err(|e| format!("Failed to write database: {}", e))?;
+    write_database_file(&app, &db_path, &content)?;
 
     // 退出应用，用户需手动重启
-    exit_application().await?;
+    exit_application(app).await?;
 
     Ok(())
 }
@@ -3594,17 +6940,13 @@ pub async fn delete_webdav_backup(
 ) -> Result<()> {
     use reqwest::Client;
 
-    let settings = get_webdav_settings(db).await?;
+    let settings = get_webdav_settings_impl(db.inner()).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
 
     let client = Client::new();
-    let remote_file = format!(
-        "{}/ccg-gateway-backup/{}",
-        settings.url.trim_end_matches('/'),
-        filename
-    );
+    let remote_file = format!("{}/{}", webdav_remote_dir(&settings), filename);
 
     let response = client
         .delete(&remote_file)
@@ -3620,6 +6962,78 @@ pub async fn delete_webdav_backup(
     Ok(())
 }
 
+// Parse WebDAV getlastmodified (HTTP-date, RFC 2822 style) into a unix timestamp
+fn parse_webdav_modified(modified: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc2822(modified.trim())
+        .ok()
+        .map(|dt| dt.timestamp())
+}
+
+/// Backups to delete so that only the newest `keep` full backups remain, plus the increments
+/// that can no longer be restored. Files whose name doesn't carry a valid backup timestamp
+/// were not written by the gateway (or were renamed by hand) and are never selected.
+fn stale_backups(backups: Vec<WebdavBackup>, keep: usize) -> Vec<String> {
+    use crate::services::incremental_backup::backup_timestamp;
+
+    let (increments, mut backups): (Vec<_>, Vec<_>) = backups
+        .into_iter()
+        .filter(|b| backup_timestamp(&b.filename).is_some())
+        .partition(|b| b.incremental);
+
+    // Sort by real modified time descending, fall back to filename when the date is unparsable
+    backups.sort_by(|a, b| {
+        let a_ts = parse_webdav_modified(&a.modified);
+        let b_ts = parse_webdav_modified(&b.modified);
+        b_ts.cmp(&a_ts).then_with(|| b.filename.cmp(&a.filename))
+    });
+
+    // `keep` counts full backups; increments older than the oldest kept baseline can no longer be restored
+    let oldest_kept = backups
+        .iter()
+        .take(keep)
+        .filter_map(|b| backup_timestamp(&b.filename))
+        .min()
+        .map(|ts| ts.to_string());
+    let mut stale: Vec<String> = backups
+        .iter()
+        .skip(keep)
+        .map(|b| b.filename.clone())
+        .collect();
+    stale.extend(
+        increments
+            .into_iter()
+            .filter(|b| match (&oldest_kept, backup_timestamp(&b.filename)) {
+                (Some(oldest), Some(ts)) => ts < oldest.as_str(),
+                // No full backup kept: nothing left to restore the increments onto
+                _ => true,
+            })
+            .map(|b| b.filename),
+    );
+    stale
+}
+
+/// Delete all but the newest `keep` backups on the remote.
+/// Returns the filenames that were deleted (or would be deleted when `dry_run` is set).
+#[tauri::command]
+pub async fn prune_webdav_backups(
+    db: State<'_, SqlitePool>,
+    keep: usize,
+    dry_run: Option<bool>,
+) -> Result<Vec<String>> {
+    let stale = stale_backups(list_webdav_backups(db.clone()).await?, keep);
+
+    if dry_run.unwrap_or(false) {
+        return Ok(stale);
+    }
+
+    for filename in &stale {
+        delete_webdav_backup(db.clone(), filename.clone()).await?;
+        tracing::info!("Pruned WebDAV backup: {}", filename);
+    }
+
+    Ok(stale)
+}
+
 // ==================== Skill 相关命令 ====================
 
 // 获取 SSOT 目录 (ccg-gateway 数据目录下的 skills/)
@@ -3636,14 +7050,51 @@ fn get_skill_cache_dir() -> std::path::PathBuf {
     dir
 }
 
-// 获取缓存的仓库 ZIP 文件路径
-fn get_cached_repo_zip(owner: &str, name: &str, branch: &str) -> std::path::PathBuf {
-    get_skill_cache_dir().join(format!("{}_{}__{}.zip", owner, name, branch))
+// 将相对路径拼接到 base 下：只允许普通路径段，拼接结果必须仍在 base 内
+fn join_within(base: &std::path::Path, relative: &str) -> Result<std::path::PathBuf> {
+    let normal = std::path::Path::new(relative)
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)));
+    let path = base.join(relative);
+    if relative.is_empty() || !normal || path == base || !path.starts_with(base) {
+        return Err(format!("Path escapes {}: {}", base.display(), relative));
+    }
+    Ok(path)
+}
+
+// 校验 skill 来源：仓库 owner/name/branch 必须是单个路径段；directory 是仓库内的相对路径，
+// 允许嵌套（如 skills/pdf），但每一段都必须是安全的路径段
+fn validate_skill_source(skill: &DiscoverableSkill) -> Result<()> {
+    checked_path_segment(&skill.repo_owner, "repo owner")?;
+    checked_path_segment(&skill.repo_name, "repo name")?;
+    if !skill.repo_branch.is_empty() {
+        checked_path_segment(&skill.repo_branch, "repo branch")?;
+    }
+    if skill.directory.is_empty() {
+        return Err("Invalid skill directory: empty".to_string());
+    }
+    for segment in skill.directory.split('/') {
+        checked_path_segment(segment, "skill directory")
+            .map_err(|_| format!("Invalid skill directory: {}", skill.directory))?;
+    }
+    Ok(())
+}
+
+// 获取缓存的仓库 ZIP 文件路径（owner/name/branch 不能让路径离开缓存目录）
+fn get_cached_repo_zip(owner: &str, name: &str, branch: &str) -> Result<std::path::PathBuf> {
+    repo_zip_path(owner, name, branch, "zip")
+}
+
+fn repo_zip_path(owner: &str, name: &str, branch: &str, extension: &str) -> Result<std::path::PathBuf> {
+    checked_path_segment(owner, "repo owner")?;
+    checked_path_segment(name, "repo name")?;
+    checked_path_segment(branch, "repo branch")?;
+    join_within(&get_skill_cache_dir(), &format!("{}_{}__{}.{}", owner, name, branch, extension))
 }
 
 // 读取缓存的 ZIP 文件（如果存在）
 fn read_cached_zip(owner: &str, name: &str, branch: &str) -> Option<Vec<u8>> {
-    let path = get_cached_repo_zip(owner, name, branch);
+    let path = get_cached_repo_zip(owner, name, branch).ok()?;
     if path.exists() {
         std::fs::read(&path).ok()
     } else {
@@ -3651,12 +7102,9 @@ fn read_cached_zip(owner: &str, name: &str, branch: &str) -> Option<Vec<u8>> {
     }
 }
 
-// 保存 ZIP 到缓存
-fn save_zip_to_cache(owner: &str, name: &str, branch: &str, bytes: &[u8]) -> Result<()> {
-    let path = get_cached_repo_zip(owner, name, branch);
-    std::fs::write(&path, bytes).map_err(|e| e.to_string())?;
-    tracing::info!("Saved repo ZIP to cache: {}", path.display());
-    Ok(())
+// 获取下载中的仓库 ZIP 临时文件路径（用于断点续传）
+fn get_partial_repo_zip(owner: &str, name: &str, branch: &str) -> Result<std::path::PathBuf> {
+    repo_zip_path(owner, name, branch, "zip.part")
 }
 
 // 删除缓存的仓库 ZIP
@@ -3667,7 +7115,9 @@ fn delete_cached_repo_zip(owner: &str, name: &str) {
         let prefix = format!("{}_{}_", owner, name);
         for entry in entries.flatten() {
             if let Some(filename) = entry.file_name().to_str() {
-                if filename.starts_with(&prefix) && filename.ends_with(".zip") {
+                if filename.starts_with(&prefix)
+                    && (filename.ends_with(".zip") || filename.ends_with(".zip.part"))
+                {
                     let _ = std::fs::remove_file(entry.path());
                     tracing::info!("Deleted cached ZIP: {}", filename);
                 }
@@ -3696,25 +7146,11 @@ fn skill_enabled_in_cli(cli_type: &str, directory: &str) -> bool {
     cli_dir.join(directory).join("SKILL.md").exists()
 }
 
-// 解析 SKILL.md frontmatter
-fn parse_skill_metadata(content: &str) -> (Option<String>, Option<String>) {
-    let content = content.trim_start_matches('\u{feff}');
-    let parts: Vec<&str> = content.splitn(3, "---").collect();
-    if parts.len() < 3 {
-        return (None, None);
-    }
-    let front_matter = parts[1].trim();
-    let mut name = None;
-    let mut description = None;
-    for line in front_matter.lines() {
-        let line = line.trim();
-        if let Some(val) = line.strip_prefix("name:") {
-            name = Some(val.trim().to_string());
-        } else if let Some(val) = line.strip_prefix("description:") {
-            description = Some(val.trim().to_string());
-        }
-    }
-    (name, description)
+// 读取已安装 skill 的 SKILL.md 元数据（文件不存在时返回空元数据）
+fn read_installed_skill_metadata(directory: &str) -> crate::services::frontmatter::SkillMetadata {
+    std::fs::read_to_string(get_ssot_dir().join(directory).join("SKILL.md"))
+        .map(|content| crate::services::frontmatter::parse_skill_metadata(&content))
+        .unwrap_or_default()
 }
 
 // 递归复制目录
@@ -3829,7 +7265,7 @@ pub async fn add_skill_repo(db: State<'_, SqlitePool>, input: SkillRepoCreate) -
     let user_branch = input.branch.unwrap_or_else(|| "main".to_string());
     
     // 检测实际分支
-    let client = reqwest::Client::new();
+    let client = crate::services::github::client(db.inner()).await?;
     let actual_branch = detect_repo_branch(&client, &owner, &name, &user_branch).await?;
     
     // 如果用户指定的分支不存在，返回错误提示
@@ -3851,6 +7287,8 @@ pub async fn add_skill_repo(db: State<'_, SqlitePool>, input: SkillRepoCreate) -
         owner,
         name,
         branch: actual_branch,
+        skill_count: None,
+        last_refreshed_ts: None,
     })
 }
 
@@ -3874,7 +7312,13 @@ async fn detect_repo_branch(
             Ok(response) if response.status().is_success() => {
                 return Ok(br.to_string());
             }
-            _ => continue,
+            // 被限流时后续分支也无法检测，直接返回明确的提示
+            Ok(response) => {
+                if let Some(message) = crate::services::github::rate_limit_error(&response) {
+                    return Err(message);
+                }
+            }
+            Err(_) => continue,
         }
     }
     Err(format!("无法访问仓库 {}/{}，请检查仓库地址是否正确", owner, name))
@@ -3908,7 +7352,7 @@ pub async fn update_skill_repo(
     let user_branch = if new_branch.is_empty() { "main".to_string() } else { new_branch };
     
     // 检测实际分支
-    let client = reqwest::Client::new();
+    let client = crate::services::github::client(db.inner()).await?;
     let actual_branch = detect_repo_branch(&client, &new_owner, &new_name, &user_branch).await?;
     
     // 如果用户指定的分支不存在，返回错误提示
@@ -3952,72 +7396,409 @@ pub async fn update_skill_repo(
         owner: new_owner,
         name: new_name,
         branch: actual_branch,
+        skill_count: None,
+        last_refreshed_ts: None,
     })
 }
 
 // ==================== Skill 发现命令 ====================
 
-#[tauri::command]
-pub async fn discover_repo_skills(owner: String, name: String, branch: String) -> Result<Vec<DiscoverableSkill>> {
-    let branch_to_use = if branch.is_empty() { "main" } else { &branch };
-    
-    // 优先使用缓存
-    if let Some(bytes) = read_cached_zip(&owner, &name, branch_to_use) {
+// 同时下载/扫描的仓库数量上限
+const MAX_CONCURRENT_REPO_DOWNLOADS: usize = 4;
+
+// 加载仓库 skills（优先使用缓存，force_refresh 时删除缓存后重新下载）
+async fn load_repo_skills(
+    client: &reqwest::Client,
+    owner: &str,
+    name: &str,
+    branch: &str,
+    force_refresh: bool,
+    app: Option<&tauri::AppHandle>,
+) -> Result<Vec<DiscoverableSkill>> {
+    let branch_to_use = if branch.is_empty() { "main" } else { branch };
+
+    if force_refresh {
+        // 删除旧缓存（包括未完成的下载）
+        delete_cached_repo_zip(owner, name);
+    } else if let Some(bytes) = read_cached_zip(owner, name, branch_to_use) {
         tracing::info!("Using cached ZIP for {}/{}", owner, name);
-        let mut skills = scan_zip_for_skills(&bytes, &owner, &name, branch_to_use)?;
+        let mut skills = scan_zip_for_skills(&bytes, owner, name, branch_to_use)?;
         skills.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
         return Ok(skills);
     }
-    
-    // 没有缓存则下载
-    let client = reqwest::Client::new();
-    let bytes = download_repo_zip(&client, &owner, &name, branch_to_use).await?;
-    
-    // 保存到缓存
-    let _ = save_zip_to_cache(&owner, &name, branch_to_use, &bytes);
-    
-    let mut skills = scan_zip_for_skills(&bytes, &owner, &name, branch_to_use)?;
+
+    // 没有缓存则下载（下载完成后自动写入缓存）
+    let bytes = download_repo_zip(client, owner, name, branch_to_use, app, None).await?;
+
+    let mut skills = scan_zip_for_skills(&bytes, owner, name, branch_to_use)?;
     skills.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
     Ok(skills)
 }
 
+// 记录仓库扫描结果（skill 数量与扫描时间）
+async fn record_repo_scan(db: &SqlitePool, owner: &str, name: &str, skill_count: usize) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query("UPDATE skill_repos SET skill_count = ?, last_refreshed_ts = ? WHERE owner = ? AND name = ?")
+        .bind(skill_count as i64)
+        .bind(now)
+        .bind(owner)
+        .bind(name)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn discover_repo_skills(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    owner: String,
+    name: String,
+    branch: String,
+) -> Result<Vec<DiscoverableSkill>> {
+    let client = crate::services::github::client(db.inner()).await?;
+    let skills = load_repo_skills(&client, &owner, &name, &branch, false, Some(&app)).await?;
+    record_repo_scan(db.inner(), &owner, &name, skills.len()).await?;
+    Ok(skills)
+}
+
 // 强制刷新仓库 skills（删除缓存后重新下载）
 #[tauri::command]
-pub async fn refresh_repo_skills(owner: String, name: String, branch: String) -> Result<Vec<DiscoverableSkill>> {
-    let branch_to_use = if branch.is_empty() { "main" } else { &branch };
-    
-    // 删除旧缓存
-    delete_cached_repo_zip(&owner, &name);
-    
-    // 重新下载
-    let client = reqwest::Client::new();
-    let bytes = download_repo_zip(&client, &owner, &name, branch_to_use).await?;
-    
-    // 保存到缓存
-    let _ = save_zip_to_cache(&owner, &name, branch_to_use, &bytes);
-    
-    let mut skills = scan_zip_for_skills(&bytes, &owner, &name, branch_to_use)?;
-    skills.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+pub async fn refresh_repo_skills(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    owner: String,
+    name: String,
+    branch: String,
+) -> Result<Vec<DiscoverableSkill>> {
+    let client = crate::services::github::client(db.inner()).await?;
+    let skills = load_repo_skills(&client, &owner, &name, &branch, true, Some(&app)).await?;
+    record_repo_scan(db.inner(), &owner, &name, skills.len()).await?;
     Ok(skills)
 }
 
-// 下载仓库 ZIP
-async fn download_repo_zip(
-    client: &reqwest::Client,
-    owner: &str,
-    name: &str,
-    branch: &str,
-) -> Result<Vec<u8>> {
-    let url = format!("https://github.com/{}/{}/archive/refs/heads/{}.zip", owner, name, branch);
-    let response = client.get(&url).send().await.map_err(|e| e.to_string())?;
-    
-    if !response.status().is_success() {
-        return Err(format!("下载失败: HTTP {}", response.status()));
+// 并发发现所有已配置仓库的 skills，单个仓库失败不影响其他仓库
+#[tauri::command]
+pub async fn discover_all_repo_skills(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    refresh: Option<bool>,
+) -> Result<Vec<RepoSkillsResult>> {
+    use futures_util::StreamExt;
+
+    let pool = db.inner().clone();
+    let repos = get_skill_repos(db).await?;
+    let force_refresh = refresh.unwrap_or(false);
+    let client = crate::services::github::client(&pool).await?;
+
+    let mut results: Vec<RepoSkillsResult> = futures_util::stream::iter(repos)
+        .map(|repo| {
+            let client = client.clone();
+            let pool = pool.clone();
+            let app = app.clone();
+            async move {
+                match load_repo_skills(&client, &repo.owner, &repo.name, &repo.branch, force_refresh, Some(&app)).await {
+                    Ok(skills) => {
+                        if let Err(e) = record_repo_scan(&pool, &repo.owner, &repo.name, skills.len()).await {
+                            tracing::warn!("Failed to record scan for {}/{}: {}", repo.owner, repo.name, e);
+                        }
+                        RepoSkillsResult {
+                            owner: repo.owner,
+                            name: repo.name,
+                            branch: repo.branch,
+                            skills,
+                            error: None,
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to load skills from {}/{}: {}", repo.owner, repo.name, e);
+                        RepoSkillsResult {
+                            owner: repo.owner,
+                            name: repo.name,
+                            branch: repo.branch,
+                            skills: vec![],
+                            error: Some(e),
+                        }
+                    }
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_REPO_DOWNLOADS)
+        .collect()
+        .await;
+
+    results.sort_by(|a, b| (&a.owner, &a.name).cmp(&(&b.owner, &b.name)));
+    Ok(results)
+}
+
+// 导入离线 Skill 目录（DiscoverableSkill 数组 JSON），用于无法访问 GitHub 的环境。
+// 同 key 的条目会被覆盖；replace 为 true 时先清空已有目录。返回导入条数
+#[tauri::command]
+pub async fn import_skill_catalog(
+    db: State<'_, SqlitePool>,
+    json: String,
+    replace: Option<bool>,
+) -> Result<usize> {
+    let skills: Vec<DiscoverableSkill> =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid skill catalog JSON: {}", e))?;
+    for (i, skill) in skills.iter().enumerate() {
+        if skill.name.trim().is_empty()
+            || skill.directory.trim().is_empty()
+            || skill.repo_owner.trim().is_empty()
+            || skill.repo_name.trim().is_empty()
+        {
+            return Err(format!(
+                "Catalog entry #{} is missing name, directory, repo_owner or repo_name",
+                i + 1
+            ));
+        }
+        // 目录来自外部文件，安装时会拼接成缓存和 SSOT 路径
+        validate_skill_source(skill).map_err(|e| format!("Catalog entry #{}: {}", i + 1, e))?;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut tx = db.inner().begin().await.map_err(|e| e.to_string())?;
+    if replace.unwrap_or(false) {
+        sqlx::query("DELETE FROM skill_catalog")
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    for skill in &skills {
+        // key 缺失时按 discover_repo_skills 的格式生成
+        let key = if skill.key.trim().is_empty() {
+            format!("{}/{}:{}", skill.repo_owner, skill.repo_name, skill.directory)
+        } else {
+            skill.key.clone()
+        };
+        let branch = if skill.repo_branch.trim().is_empty() { "main" } else { skill.repo_branch.as_str() };
+        let tags = (!skill.tags.is_empty())
+            .then(|| serde_json::to_string(&skill.tags))
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO skill_catalog (key, name, description, directory, readme_url, repo_owner, repo_name, repo_branch, version, author, license, tags, imported_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&key)
+        .bind(&skill.name)
+        .bind(&skill.description)
+        .bind(&skill.directory)
+        .bind(&skill.readme_url)
+        .bind(&skill.repo_owner)
+        .bind(&skill.repo_name)
+        .bind(branch)
+        .bind(&skill.version)
+        .bind(&skill.author)
+        .bind(&skill.license)
+        .bind(&tags)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    tracing::info!("Imported {} skills into the offline catalog", skills.len());
+    Ok(skills.len())
+}
+
+// 获取离线 Skill 目录（不访问网络）
+#[tauri::command]
+pub async fn get_skill_catalog(db: State<'_, SqlitePool>) -> Result<Vec<DiscoverableSkill>> {
+    let entries = sqlx::query_as::<_, crate::db::models::SkillCatalogEntry>(
+        "SELECT * FROM skill_catalog ORDER BY repo_owner, repo_name, name",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(entries.into_iter().map(DiscoverableSkill::from).collect())
+}
+
+// 下载进度事件名
+const SKILL_DOWNLOAD_PROGRESS_EVENT: &str = "skill-download://progress";
+// 两次进度事件之间至少间隔的字节数，避免逐块发送
+const SKILL_DOWNLOAD_PROGRESS_STEP: u64 = 256 * 1024;
+
+// 正在写入的 .part 文件各自一把锁：同一仓库的并发下载排队进行，不会交错写同一个文件
+static REPO_ZIP_DOWNLOADS: std::sync::OnceLock<std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, Arc<tokio::sync::Mutex<()>>>>> =
+    std::sync::OnceLock::new();
+
+fn repo_zip_download_lock(part_path: &std::path::Path) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = REPO_ZIP_DOWNLOADS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    locks.entry(part_path.to_path_buf()).or_default().clone()
+}
+
+// 解析 206 响应的 Content-Range（bytes <start>-<end>/<total|*>），返回起始偏移和总大小
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+    if end < start {
+        return None;
+    }
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start, total))
+}
+
+// 下载仓库 ZIP（流式写入 .part 文件，支持断点续传，完成后重命名为缓存文件）
+// 传入 app 时发送 skill-download://progress 进度事件；传入 cancel 时可被取消，保留 .part 供下次续传
+async fn download_repo_zip(
+    client: &reqwest::Client,
+    owner: &str,
+    name: &str,
+    branch: &str,
+    app: Option<&tauri::AppHandle>,
+    cancel: Option<&SkillInstallGuard>,
+) -> Result<Vec<u8>> {
+    use futures_util::StreamExt;
+    use std::io::Write;
+    use tauri::Emitter;
+
+    let emit_progress = |bytes: u64, total: Option<u64>, resumed_from: u64, done: bool| {
+        if let Some(app) = app {
+            let _ = app.emit(SKILL_DOWNLOAD_PROGRESS_EVENT, crate::db::models::SkillDownloadProgress {
+                owner: owner.to_string(),
+                name: name.to_string(),
+                bytes,
+                total,
+                resumed_from,
+                done,
+            });
+        }
+    };
+
+    let url = format!("https://github.com/{}/{}/archive/refs/heads/{}.zip", owner, name, branch);
+    let part_path = get_partial_repo_zip(owner, name, branch)?;
+    let cache_path = get_cached_repo_zip(owner, name, branch)?;
+
+    // 同一仓库同时只有一个下载在写 .part；排在后面的直接使用前一个下载好的缓存
+    let lock = repo_zip_download_lock(&part_path);
+    let _download = lock.lock().await;
+    if let Some(bytes) = read_cached_zip(owner, name, branch) {
+        tracing::info!("Using ZIP for {}/{} downloaded by a concurrent request", owner, name);
+        return Ok(bytes);
+    }
+
+    // 最多尝试两次：续传结果无效时删除 .part 从头下载
+    for attempt in 0..2 {
+        let resume_from = std::fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+
+        if resume_from > 0 {
+            tracing::info!("Resuming download of {}/{} from {} bytes", owner, name, resume_from);
+        }
+
+        // 5xx 自动退避重试，限流时返回明确提示
+        let response = crate::services::github::send(|| {
+            let request = client.get(&url);
+            if resume_from > 0 {
+                request.header(reqwest::header::RANGE, format!("bytes={}-", resume_from))
+            } else {
+                request
+            }
+        })
+        .await?;
+        let status = response.status();
+
+        // 服务端不接受该范围（.part 已损坏或已过期），从头开始
+        if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE && attempt == 0 {
+            let _ = std::fs::remove_file(&part_path);
+            continue;
+        }
+
+        if !status.is_success() {
+            return Err(format!("下载失败: HTTP {}", status));
+        }
+
+        // 206 表示续传，其余成功状态（服务端忽略 Range）需要覆盖重写
+        let resumed = resume_from > 0 && status == reqwest::StatusCode::PARTIAL_CONTENT;
+        let content_range = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_range);
+        // 返回的范围必须正好接在 .part 之后，否则拼接出的文件是错的，删除后从头下载
+        if resumed && content_range.map(|(start, _)| start) != Some(resume_from) {
+            tracing::warn!(
+                "Range response for {}/{} does not start at {} ({:?}), restarting download",
+                owner, name, resume_from, content_range
+            );
+            let _ = std::fs::remove_file(&part_path);
+            if attempt == 0 {
+                continue;
+            }
+            return Err(format!("下载失败: {}/{} 续传范围不匹配", owner, name));
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&part_path)
+            .map_err(|e| e.to_string())?;
+
+        // 续传时 Content-Length 只是剩余部分，总大小优先取 Content-Range
+        let offset = if resumed { resume_from } else { 0 };
+        let total = content_range
+            .and_then(|(_, total)| total)
+            .filter(|_| resumed)
+            .or_else(|| response.content_length().map(|len| len + offset));
+        let mut downloaded = offset;
+        let mut last_emitted = offset;
+        emit_progress(downloaded, total, offset, false);
+
+        let mut stream = response.bytes_stream();
+        loop {
+            let next = match cancel {
+                Some(cancel) => tokio::select! {
+                    chunk = stream.next() => chunk,
+                    _ = cancel.cancelled() => return Err(INSTALL_CANCELLED.to_string()),
+                },
+                None => stream.next().await,
+            };
+            let Some(chunk) = next else {
+                break;
+            };
+            let chunk = chunk.map_err(|e| format!("下载中断: {}", e))?;
+            file.write_all(&chunk).map_err(|e| e.to_string())?;
+            downloaded += chunk.len() as u64;
+            if downloaded - last_emitted >= SKILL_DOWNLOAD_PROGRESS_STEP {
+                emit_progress(downloaded, total, offset, false);
+                last_emitted = downloaded;
+            }
+        }
+        file.flush().map_err(|e| e.to_string())?;
+        drop(file);
+        emit_progress(downloaded, total, offset, true);
+
+        let bytes = std::fs::read(&part_path).map_err(|e| e.to_string())?;
+
+        // 校验 ZIP 完整性，续传拼接出的无效文件需要重新下载
+        if zip::ZipArchive::new(std::io::Cursor::new(&bytes)).is_err() {
+            let _ = std::fs::remove_file(&part_path);
+            if resumed && attempt == 0 {
+                tracing::warn!("Resumed ZIP for {}/{} is invalid, restarting download", owner, name);
+                continue;
+            }
+            return Err("下载的 ZIP 文件无效".to_string());
+        }
+
+        std::fs::rename(&part_path, &cache_path).map_err(|e| e.to_string())?;
+        tracing::info!("Saved repo ZIP to cache: {}", cache_path.display());
+        return Ok(bytes);
     }
-    
-    response.bytes().await
-        .map(|b| b.to_vec())
-        .map_err(|e| e.to_string())
+
+    Err(format!("下载失败: {}/{}", owner, name))
 }
 
 // 扫描 ZIP 中的 skills
@@ -4084,7 +7865,7 @@ fn scan_zip_for_skills(
             }
         }
 
-        let (name, description) = parse_skill_metadata(&content);
+        let metadata = crate::services::frontmatter::parse_skill_metadata(&content);
         let directory_name = std::path::Path::new(&dir)
             .file_name()
             .map(|s| s.to_string_lossy().to_string())
@@ -4092,13 +7873,17 @@ fn scan_zip_for_skills(
 
         skills.push(DiscoverableSkill {
             key: format!("{}/{}:{}", owner, repo_name, dir),
-            name: name.unwrap_or_else(|| directory_name.clone()),
-            description: description.unwrap_or_default(),
+            name: metadata.name.unwrap_or_else(|| directory_name.clone()),
+            description: metadata.description.unwrap_or_default(),
             directory: dir.clone(),
             readme_url: Some(format!("https://github.com/{}/{}/tree/{}/{}", owner, repo_name, branch, dir)),
             repo_owner: owner.to_string(),
             repo_name: repo_name.to_string(),
             repo_branch: branch.to_string(),
+            version: metadata.version,
+            author: metadata.author,
+            license: metadata.license,
+            tags: metadata.tags,
         });
     }
 
@@ -4107,13 +7892,43 @@ fn scan_zip_for_skills(
 
 // ==================== Skill 安装/卸载命令 ====================
 
+// 安装进度事件名
+const SKILL_INSTALL_PROGRESS_EVENT: &str = "skill-install://progress";
+
+fn emit_skill_install_progress(app: &tauri::AppHandle, key: &str, phase: &str, current: u64, total: Option<u64>) {
+    use tauri::Emitter;
+
+    let _ = app.emit(SKILL_INSTALL_PROGRESS_EVENT, crate::db::models::SkillInstallProgress {
+        key: key.to_string(),
+        phase: phase.to_string(),
+        current,
+        total,
+    });
+}
+
+// 安装 skill：发送 skill-install://progress 进度事件（下载阶段的字节进度见 skill-download://progress），
+// 可通过 cancel_skill_install 按 skill key 取消。先解压到临时目录，完成后才替换 SSOT 目录并写入数据库，
+// 取消或失败时删除临时目录，已安装的旧版本保持不变
 #[tauri::command]
-pub async fn install_skill(db: State<'_, SqlitePool>, skill: DiscoverableSkill, reinstall: Option<bool>) -> Result<InstalledSkillResponse> {
+pub async fn install_skill(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    skill_installs: State<'_, Arc<SkillInstalls>>,
+    skill: DiscoverableSkill,
+    reinstall: Option<bool>,
+    install_as: Option<String>,
+    zip_path: Option<String>,
+) -> Result<InstalledSkillResponse> {
+    validate_skill_source(&skill)?;
     let ssot_dir = get_ssot_dir();
-    let directory_name = std::path::Path::new(&skill.directory)
-        .file_name()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| skill.directory.clone());
+    // 指定 install_as 时使用别名目录，用于解决不同仓库同名 skill 的冲突
+    let directory_name = match install_as.map(|s| s.trim().to_string()).filter(|s| !s.is_empty()) {
+        Some(alias) => checked_path_segment(&alias, "install directory name")?.to_string(),
+        None => std::path::Path::new(&skill.directory)
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| skill.directory.clone()),
+    };
 
     let is_reinstall = reinstall.unwrap_or(false);
 
@@ -4124,34 +7939,53 @@ pub async fn install_skill(db: State<'_, SqlitePool>, skill: DiscoverableSkill,
         .await
         .map_err(|e| e.to_string())?;
 
-    if existing.is_some() && !is_reinstall {
-        return Err(format!("Skill '{}' is already installed", directory_name));
+    if let Some(ref old) = existing {
+        if !is_reinstall {
+            let same_repo = old.repo_owner.as_deref() == Some(skill.repo_owner.as_str())
+                && old.repo_name.as_deref() == Some(skill.repo_name.as_str());
+            if same_repo {
+                return Err(format!("Skill '{}' is already installed", directory_name));
+            }
+            // 不同仓库的同名目录，需要显式选择覆盖（reinstall）或改名安装（install_as）
+            let conflicting_repo = match (&old.repo_owner, &old.repo_name) {
+                (Some(owner), Some(name)) => format!("{}/{}", owner, name),
+                _ => "a local installation".to_string(),
+            };
+            return Err(format!(
+                "Skill directory '{}' is already used by {}; reinstall to overwrite it or use install_as to install under a different name",
+                directory_name, conflicting_repo
+            ));
+        }
     }
 
-    // 如果是重装，先删除旧的 SSOT 目录
-    if is_reinstall {
-        let old_skill_path = ssot_dir.join(&directory_name);
-        if old_skill_path.exists() {
-            let _ = std::fs::remove_dir_all(&old_skill_path);
-        }
+    let install = skill_installs.register(&skill.key)?;
+
+    // 先解压到临时目录（以 . 开头，不会与 skill 目录重名）
+    let staging_name = format!(".{}.installing", directory_name);
+    let staging_dir = join_within(&ssot_dir, &staging_name)?;
+    if staging_dir.exists() {
+        let _ = std::fs::remove_dir_all(&staging_dir);
     }
 
-    // 优先使用缓存的 ZIP
-    let branch_to_use = if skill.repo_branch.is_empty() { "main" } else { &skill.repo_branch };
-    let bytes = if let Some(cached) = read_cached_zip(&skill.repo_owner, &skill.repo_name, branch_to_use) {
-        tracing::info!("Using cached ZIP for install: {}/{}", skill.repo_owner, skill.repo_name);
-        cached
-    } else {
-        // 没有缓存则下载
-        let client = reqwest::Client::new();
-        let downloaded = download_repo_zip(&client, &skill.repo_owner, &skill.repo_name, branch_to_use).await?;
-        // 保存到缓存
-        let _ = save_zip_to_cache(&skill.repo_owner, &skill.repo_name, branch_to_use, &downloaded);
-        downloaded
-    };
+    if let Err(e) = stage_skill_install(&app, db.inner(), &install, &skill, zip_path.as_deref(), &ssot_dir, &staging_name).await {
+        let _ = std::fs::remove_dir_all(&staging_dir);
+        if install.is_cancelled() {
+            tracing::info!("Cancelled install of skill: {}", skill.key);
+            emit_skill_install_progress(&app, &skill.key, "cancelled", 0, None);
+            return Err(INSTALL_CANCELLED.to_string());
+        }
+        return Err(e);
+    }
 
-    // 提取 skill 到 SSOT
-    extract_skill_from_zip(&bytes, &skill.directory, &ssot_dir, &directory_name)?;
+    // 替换 SSOT 目录（重装时覆盖旧版本）；此后不再响应取消
+    let skill_path = join_within(&ssot_dir, &directory_name)?;
+    if skill_path.exists() {
+        std::fs::remove_dir_all(&skill_path).map_err(|e| e.to_string())?;
+    }
+    std::fs::rename(&staging_dir, &skill_path).map_err(|e| {
+        let _ = std::fs::remove_dir_all(&staging_dir);
+        format!("Failed to move skill into place: {}", e)
+    })?;
 
     // 保存到数据库（如果是重装则更新）
     let now = chrono::Utc::now().timestamp();
@@ -4199,6 +8033,9 @@ pub async fn install_skill(db: State<'_, SqlitePool>, skill: DiscoverableSkill,
         SkillCliFlag { cli_type: "gemini".to_string(), enabled: false },
     ];
 
+    emit_skill_install_progress(&app, &skill.key, "done", 0, None);
+
+    let metadata = read_installed_skill_metadata(&directory_name);
     Ok(InstalledSkillResponse {
         id,
         name: skill.name,
@@ -4211,15 +8048,65 @@ pub async fn install_skill(db: State<'_, SqlitePool>, skill: DiscoverableSkill,
         installed_at: now,
         cli_flags,
         exists_on_disk: true, // 刚安装完肯定存在
+        version: metadata.version,
+        author: metadata.author,
+        license: metadata.license,
+        tags: metadata.tags,
     })
 }
 
-// 从 ZIP 中提取 skill 到 SSOT
+// 获取仓库 ZIP（本地 ZIP、缓存或下载）并把 skill 解压到 SSOT 下的 staging_name 目录
+async fn stage_skill_install(
+    app: &tauri::AppHandle,
+    db: &SqlitePool,
+    install: &SkillInstallGuard,
+    skill: &DiscoverableSkill,
+    zip_path: Option<&str>,
+    ssot_dir: &std::path::Path,
+    staging_name: &str,
+) -> Result<()> {
+    // 优先使用带外提供的本地 ZIP（GitHub 仓库归档格式），其次是缓存的 ZIP
+    let branch_to_use = if skill.repo_branch.is_empty() { "main" } else { &skill.repo_branch };
+    let local_zip = zip_path.map(str::trim).filter(|p| !p.is_empty());
+    let bytes = if let Some(path) = local_zip {
+        let bytes = std::fs::read(path).map_err(|e| format!("Failed to read ZIP '{}': {}", path, e))?;
+        zip::ZipArchive::new(std::io::Cursor::new(&bytes)).map_err(|e| format!("Invalid ZIP '{}': {}", path, e))?;
+        // 写入缓存，同仓库的其他 skill 及离线发现可直接复用
+        let cache_path = get_cached_repo_zip(&skill.repo_owner, &skill.repo_name, branch_to_use)?;
+        if let Err(e) = std::fs::write(&cache_path, &bytes) {
+            tracing::warn!("Failed to cache local ZIP {}: {}", cache_path.display(), e);
+        }
+        tracing::info!("Using local ZIP for install: {}", path);
+        bytes
+    } else if let Some(cached) = read_cached_zip(&skill.repo_owner, &skill.repo_name, branch_to_use) {
+        tracing::info!("Using cached ZIP for install: {}/{}", skill.repo_owner, skill.repo_name);
+        cached
+    } else {
+        // 没有缓存则下载（下载完成后自动写入缓存）
+        let client = crate::services::github::client(db).await?;
+        emit_skill_install_progress(app, &skill.key, "download", 0, None);
+        download_repo_zip(&client, &skill.repo_owner, &skill.repo_name, branch_to_use, Some(app), Some(install)).await?
+    };
+
+    // 提取 skill 到临时目录
+    extract_skill_from_zip(&bytes, &skill.directory, ssot_dir, staging_name, Some(install), &mut |current, total| {
+        emit_skill_install_progress(app, &skill.key, "extract", current, Some(total));
+    })?;
+
+    if install.is_cancelled() {
+        return Err(INSTALL_CANCELLED.to_string());
+    }
+    Ok(())
+}
+
+// 从 ZIP 中提取 skill 到 SSOT，每解压一个文件回调一次 (已解压数, 总数)；cancel 被触发时中止
 fn extract_skill_from_zip(
     bytes: &[u8],
     skill_dir: &str,
     ssot_dir: &std::path::Path,
     directory_name: &str,
+    cancel: Option<&SkillInstallGuard>,
+    on_progress: &mut dyn FnMut(u64, u64),
 ) -> Result<()> {
     let cursor = std::io::Cursor::new(bytes);
     let mut archive = zip::ZipArchive::new(cursor).map_err(|e| e.to_string())?;
@@ -4233,21 +8120,32 @@ fn extract_skill_from_zip(
     };
 
     let skill_prefix = format!("{}/{}/", root_name, skill_dir);
-    let dest_dir = ssot_dir.join(directory_name);
+    let dest_dir = join_within(ssot_dir, directory_name)?;
 
     // 创建目标目录
     std::fs::create_dir_all(&dest_dir).map_err(|e| e.to_string())?;
 
+    let total = archive.file_names().filter(|name| name.starts_with(&skill_prefix)).count() as u64;
+    let mut extracted = 0;
+    on_progress(extracted, total);
+
     for i in 0..archive.len() {
+        if cancel.is_some_and(|c| c.is_cancelled()) {
+            return Err(INSTALL_CANCELLED.to_string());
+        }
+
         let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
         let file_path = file.name().to_string();
 
         if let Some(relative) = file_path.strip_prefix(&skill_prefix) {
+            extracted += 1;
+            on_progress(extracted, total);
             if relative.is_empty() {
                 continue;
             }
 
-            let out_path = dest_dir.join(relative);
+            // 压缩包内的 ../ 等条目不能写到 skill 目录之外
+            let out_path = join_within(&dest_dir, relative.trim_end_matches('/'))?;
 
             if file.is_dir() {
                 std::fs::create_dir_all(&out_path).map_err(|e| e.to_string())?;
@@ -4264,6 +8162,18 @@ fn extract_skill_from_zip(
     Ok(())
 }
 
+/// 取消进行中的 skill 安装；没有进行中的安装时返回错误
+#[tauri::command]
+pub async fn cancel_skill_install(
+    skill_installs: State<'_, Arc<SkillInstalls>>,
+    key: String,
+) -> Result<()> {
+    if !skill_installs.cancel(&key) {
+        return Err(format!("No installation in progress for skill '{}'", key));
+    }
+    Ok(())
+}
+
 #[tauri::command]
 pub async fn uninstall_skill(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
     // 获取 skill 信息
@@ -4324,6 +8234,7 @@ pub async fn get_installed_skills(db: State<'_, SqlitePool>) -> Result<Vec<Insta
 
         // 检查 skill 目录是否存在于 SSOT 目录
         let exists_on_disk = ssot_dir.join(&skill.directory).exists();
+        let metadata = read_installed_skill_metadata(&skill.directory);
 
         results.push(InstalledSkillResponse {
             id: skill.id,
@@ -4337,6 +8248,10 @@ pub async fn get_installed_skills(db: State<'_, SqlitePool>) -> Result<Vec<Insta
             installed_at: skill.installed_at,
             cli_flags,
             exists_on_disk,
+            version: metadata.version,
+            author: metadata.author,
+            license: metadata.license,
+            tags: metadata.tags,
         });
     }
     Ok(results)
@@ -4360,6 +8275,206 @@ pub async fn toggle_skill_cli(db: State<'_, SqlitePool>, id: i64, cli_type: Stri
     Ok(())
 }
 
+// 重命名已安装 skill 的目录：移动 SSOT 目录、更新数据库，并在已启用的 CLI 中用新名称替换旧名称
+#[tauri::command]
+pub async fn rename_skill(db: State<'_, SqlitePool>, id: i64, new_directory: String) -> Result<()> {
+    let new_directory = new_directory.trim().to_string();
+    if new_directory.is_empty() || new_directory == "." || new_directory == ".." || new_directory.contains(['/', '\\']) {
+        return Err(format!("Invalid skill directory name: '{}'", new_directory));
+    }
+
+    let skill = sqlx::query_as::<_, SkillConfig>("SELECT * FROM skill_configs WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Skill not found".to_string())?;
+    let old_directory = skill.directory;
+    if old_directory == new_directory {
+        return Ok(());
+    }
+
+    // 新名称不能与其他已安装 skill 或磁盘上已有的目录冲突
+    let taken: Option<i64> = sqlx::query_scalar("SELECT id FROM skill_configs WHERE directory = ? AND id != ?")
+        .bind(&new_directory)
+        .bind(id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+    if taken.is_some() {
+        return Err(format!("Skill directory '{}' is already used by another installed skill", new_directory));
+    }
+    let ssot_dir = get_ssot_dir();
+    let old_path = ssot_dir.join(&old_directory);
+    let new_path = ssot_dir.join(&new_directory);
+    if new_path.exists() {
+        return Err(format!("Directory already exists: {}", new_path.display()));
+    }
+    let enabled_clis: Vec<&str> = ["claude_code", "codex", "gemini"]
+        .into_iter()
+        .filter(|cli_type| skill_enabled_in_cli(cli_type, &old_directory))
+        .collect();
+    for cli_type in &enabled_clis {
+        if let Some(cli_dir) = get_skill_cli_dir(cli_type) {
+            if cli_dir.join(&new_directory).exists() {
+                return Err(format!("Directory already exists: {}", cli_dir.join(&new_directory).display()));
+            }
+        }
+    }
+
+    // SSOT 目录可能已被手动删除（exists_on_disk=false），此时只更新记录
+    let exists_on_disk = old_path.exists();
+    if exists_on_disk {
+        std::fs::rename(&old_path, &new_path)
+            .map_err(|e| format!("Failed to rename skill directory: {}", e))?;
+    }
+
+    let update = sqlx::query("UPDATE skill_configs SET directory = ? WHERE id = ?")
+        .bind(&new_directory)
+        .bind(id)
+        .execute(db.inner())
+        .await;
+    if let Err(e) = update {
+        // 数据库更新失败时恢复目录，保持磁盘与记录一致
+        if exists_on_disk {
+            let _ = std::fs::rename(&new_path, &old_path);
+        }
+        return Err(e.to_string());
+    }
+
+    // 重新同步到已启用的 CLI；SSOT 缺失时直接重命名 CLI 中的副本，保留启用状态
+    for cli_type in enabled_clis {
+        if exists_on_disk {
+            remove_skill_from_cli(&old_directory, cli_type)?;
+            sync_skill_to_cli(&new_directory, cli_type)?;
+        } else if let Some(cli_dir) = get_skill_cli_dir(cli_type) {
+            std::fs::rename(cli_dir.join(&old_directory), cli_dir.join(&new_directory))
+                .map_err(|e| format!("Failed to rename skill in {}: {}", cli_type, e))?;
+        }
+    }
+
+    tracing::info!("Renamed skill {} -> {}", old_directory, new_directory);
+    Ok(())
+}
+
+// 列出 SSOT 中的 skill 目录（跳过以 . 开头的目录，如安装中的临时目录）
+fn list_ssot_skill_dirs() -> Result<Vec<String>> {
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(get_ssot_dir()).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with('.') && entry.path().is_dir() {
+            dirs.push(name);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+// 检查 skill 记录与 SSOT 目录是否一致：列出目录已不存在的记录，以及没有记录的目录
+#[tauri::command]
+pub async fn reconcile_skills(db: State<'_, SqlitePool>) -> Result<SkillReconcileReport> {
+    let skills = sqlx::query_as::<_, SkillConfig>("SELECT * FROM skill_configs ORDER BY name")
+        .fetch_all(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let ssot_dir = get_ssot_dir();
+    let registered: std::collections::HashSet<&str> = skills.iter().map(|s| s.directory.as_str()).collect();
+
+    let orphaned_rows = skills
+        .iter()
+        .filter(|s| !ssot_dir.join(&s.directory).is_dir())
+        .map(|s| OrphanedSkillRow {
+            id: s.id,
+            name: s.name.clone(),
+            directory: s.directory.clone(),
+        })
+        .collect();
+
+    let orphaned_directories = list_ssot_skill_dirs()?
+        .into_iter()
+        .filter(|dir| !registered.contains(dir.as_str()))
+        .map(|directory| {
+            let skill_md = std::fs::read_to_string(ssot_dir.join(&directory).join("SKILL.md")).ok();
+            let metadata = skill_md
+                .as_deref()
+                .map(crate::services::frontmatter::parse_skill_metadata)
+                .unwrap_or_default();
+            UnregisteredSkillDir {
+                directory,
+                has_skill_md: skill_md.is_some(),
+                name: metadata.name,
+                description: metadata.description,
+            }
+        })
+        .collect();
+
+    Ok(SkillReconcileReport { orphaned_rows, orphaned_directories })
+}
+
+// 按 reconcile_skills 的结果修复：删除目录已不存在的记录（CLI 目录中的副本保持不变），
+// 为 SSOT 中没有记录的目录读取 SKILL.md 重新登记（视为本地安装，不关联仓库）。
+// 执行前重新检查每一项，已不再孤立的项会被拒绝；所有修改在同一事务中提交
+#[tauri::command]
+pub async fn repair_skills(db: State<'_, SqlitePool>, actions: SkillRepairActions) -> Result<SkillRepairResult> {
+    let ssot_dir = get_ssot_dir();
+    let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+
+    for id in &actions.remove_rows {
+        let directory: String = sqlx::query_scalar("SELECT directory FROM skill_configs WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Skill not found: {}", id))?;
+        if ssot_dir.join(&directory).is_dir() {
+            return Err(format!("Skill directory '{}' exists on disk; uninstall the skill instead", directory));
+        }
+        sqlx::query("DELETE FROM skill_configs WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for directory in &actions.register_directories {
+        if directory.starts_with('.') || directory.contains(['/', '\\']) {
+            return Err(format!("Invalid skill directory name: '{}'", directory));
+        }
+        let content = std::fs::read_to_string(ssot_dir.join(directory).join("SKILL.md"))
+            .map_err(|_| format!("No SKILL.md found in skill directory '{}'", directory))?;
+        let taken: Option<i64> = sqlx::query_scalar("SELECT id FROM skill_configs WHERE directory = ?")
+            .bind(directory)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        if taken.is_some() {
+            return Err(format!("Skill directory '{}' is already registered", directory));
+        }
+
+        let metadata = crate::services::frontmatter::parse_skill_metadata(&content);
+        sqlx::query("INSERT INTO skill_configs (name, description, directory, installed_at) VALUES (?, ?, ?, ?)")
+            .bind(metadata.name.unwrap_or_else(|| directory.clone()))
+            .bind(metadata.description.filter(|d| !d.is_empty()))
+            .bind(directory)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    let result = SkillRepairResult {
+        removed: actions.remove_rows.len(),
+        registered: actions.register_directories.len(),
+    };
+    tracing::info!("Repaired skills: removed {} rows, registered {} directories", result.removed, result.registered);
+    Ok(result)
+}
+
 // ==================== 检查更新命令 ====================
 
 const GITHUB_OWNER: &str = "mos1128";
@@ -4375,24 +8490,21 @@ pub struct GitHubRelease {
 }
 
 #[tauri::command]
-pub async fn check_for_updates() -> Result<Option<GitHubRelease>> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build()
-        .map_err(|e| e.to_string())?;
+pub async fn check_for_updates(db: State<'_, SqlitePool>) -> Result<Option<GitHubRelease>> {
+    let client = crate::services::github::client(db.inner()).await?;
 
     let url = format!(
         "https://api.github.com/repos/{}/{}/releases/latest",
         GITHUB_OWNER, GITHUB_REPO
     );
 
-    let response = client
-        .get(&url)
-        .header("Accept", "application/vnd.github.v3+json")
-        .header("User-Agent", "ccg-gateway")
-        .send()
-        .await
-        .map_err(|e| format!("网络请求失败: {}", e))?;
+    let response = crate::services::github::send(|| {
+        client
+            .get(&url)
+            .timeout(std::time::Duration::from_secs(10))
+            .header("Accept", "application/vnd.github.v3+json")
+    })
+    .await?;
 
     if response.status() == 404 {
         return Ok(None);
@@ -4415,3 +8527,568 @@ pub async fn check_for_updates() -> Result<Option<GitHubRelease>> {
         published_at: release["published_at"].as_str().map(|s| s.to_string()),
     }))
 }
+
+/// Compare dotted versions numerically, ignoring a leading 'v' (missing parts count as 0)
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |v: &str| -> Vec<u64> {
+        v.trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|n| n.chars().take_while(|c| c.is_ascii_digit()).collect::<String>().parse().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parse(a), parse(b));
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)))
+        .find(|o| o.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// Explain why check_for_updates fails: network, HTTP status, rate limit or token
+#[tauri::command]
+pub async fn diagnose_update_connectivity(db: State<'_, SqlitePool>) -> Result<DiagnosisReport> {
+    let url = format!(
+        "https://api.github.com/repos/{}/{}/releases/latest",
+        GITHUB_OWNER, GITHUB_REPO
+    );
+    let mut report = DiagnosisReport {
+        url: url.clone(),
+        token_configured: crate::services::github::load_token(db.inner()).await.is_some(),
+        reachable: false,
+        status: None,
+        elapsed_ms: 0,
+        rate_limit: None,
+        rate_limit_remaining: None,
+        rate_limit_reset: None,
+        rate_limited: false,
+        current_version: env!("CARGO_PKG_VERSION").to_string(),
+        latest_tag: None,
+        update_available: None,
+        error: None,
+    };
+
+    let client = match crate::services::github::client(db.inner()).await {
+        Ok(client) => client,
+        Err(e) => {
+            report.error = Some(e);
+            return Ok(report);
+        }
+    };
+
+    // One attempt only: retries would hide what actually happened
+    let start = std::time::Instant::now();
+    let result = client
+        .get(&url)
+        .timeout(std::time::Duration::from_secs(10))
+        .header("Accept", "application/vnd.github.v3+json")
+        .send()
+        .await;
+    report.elapsed_ms = start.elapsed().as_millis() as i64;
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            report.error = Some(format!("网络请求失败: {}", e));
+            return Ok(report);
+        }
+    };
+    report.reachable = true;
+    report.status = Some(response.status().as_u16());
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<i64>().ok())
+    };
+    report.rate_limit = header("x-ratelimit-limit");
+    report.rate_limit_remaining = header("x-ratelimit-remaining");
+    report.rate_limit_reset = header("x-ratelimit-reset");
+    if let Some(message) = crate::services::github::rate_limit_error(&response) {
+        report.rate_limited = true;
+        report.error = Some(message);
+        return Ok(report);
+    }
+
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND {
+        report.error = Some("No release has been published".to_string());
+        return Ok(report);
+    }
+    if !status.is_success() {
+        report.error = Some(format!("GitHub API 错误: {}", status));
+        return Ok(report);
+    }
+
+    match response.json::<serde_json::Value>().await {
+        Ok(release) => {
+            let tag = release["tag_name"].as_str().filter(|t| !t.is_empty()).map(str::to_string);
+            report.update_available = tag
+                .as_deref()
+                .map(|tag| compare_versions(tag, &report.current_version).is_gt());
+            report.latest_tag = tag;
+        }
+        Err(e) => report.error = Some(format!("解析响应失败: {}", e)),
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webdav_url_without_scheme_is_rejected() {
+        let err = normalize_webdav_url("dav.example.com/remote.php/dav").unwrap_err();
+        assert!(err.contains("missing scheme"), "{}", err);
+        assert!(normalize_webdav_url("ftp://dav.example.com").is_err());
+        assert!(normalize_webdav_url("").is_err());
+    }
+
+    #[test]
+    fn env_placeholders_expand_set_unset_default_and_escaped() {
+        std::env::set_var("CCG_TEST_MCP_KEY", "secret");
+        std::env::set_var("CCG_TEST_MCP_EMPTY", "");
+        std::env::remove_var("CCG_TEST_MCP_UNSET");
+        let expand = |value: &str| {
+            let mut unresolved = Vec::new();
+            let expanded = expand_env_placeholders(value, &mut unresolved);
+            (expanded, unresolved)
+        };
+
+        assert_eq!(expand("Bearer ${CCG_TEST_MCP_KEY}"), ("Bearer secret".to_string(), vec![]));
+        assert_eq!(expand("${CCG_TEST_MCP_EMPTY}"), (String::new(), vec![]));
+        assert_eq!(
+            expand("--key=${CCG_TEST_MCP_UNSET}"),
+            ("--key=${CCG_TEST_MCP_UNSET}".to_string(), vec!["CCG_TEST_MCP_UNSET".to_string()])
+        );
+
+        // Defaults apply to unset and empty variables only
+        assert_eq!(expand("${CCG_TEST_MCP_UNSET:-http://localhost}").0, "http://localhost");
+        assert_eq!(expand("${CCG_TEST_MCP_EMPTY:-fallback}").0, "fallback");
+        assert_eq!(expand("${CCG_TEST_MCP_KEY:-fallback}").0, "secret");
+        assert_eq!(expand("${CCG_TEST_MCP_UNSET:-}"), (String::new(), vec![]));
+
+        // $${ is a literal ${; anything that is not a valid name is left alone
+        assert_eq!(expand("$${CCG_TEST_MCP_KEY} ${CCG_TEST_MCP_KEY}"), ("${CCG_TEST_MCP_KEY} secret".to_string(), vec![]));
+        assert_eq!(expand("cost: $5 ${1X} ${} ${OPEN").0, "cost: $5 ${1X} ${} ${OPEN");
+    }
+
+    #[test]
+    fn mcp_config_expands_only_the_documented_fields() {
+        std::env::set_var("CCG_TEST_MCP_TOKEN", "t0k");
+        let config = r#"{"command":"${CCG_TEST_MCP_TOKEN}","args":["--t","${CCG_TEST_MCP_TOKEN}"],"env":{"T":"${CCG_TEST_MCP_TOKEN}"},"headers":{"Authorization":"Bearer ${CCG_TEST_MCP_TOKEN}"},"type":"${CCG_TEST_MCP_TOKEN}"}"#;
+        let expanded: serde_json::Value = serde_json::from_str(&expand_mcp_config_env("test", config)).unwrap();
+
+        assert_eq!(expanded["command"], "t0k");
+        assert_eq!(expanded["args"][1], "t0k");
+        assert_eq!(expanded["env"]["T"], "t0k");
+        assert_eq!(expanded["headers"]["Authorization"], "Bearer t0k");
+        assert_eq!(expanded["type"], "${CCG_TEST_MCP_TOKEN}");
+        // Not JSON: passed through untouched
+        assert_eq!(expand_mcp_config_env("test", "not json ${CCG_TEST_MCP_TOKEN}"), "not json ${CCG_TEST_MCP_TOKEN}");
+    }
+
+    #[test]
+    fn content_range_gives_the_resume_offset() {
+        assert_eq!(parse_content_range("bytes 100-199/200"), Some((100, Some(200))));
+        assert_eq!(parse_content_range("bytes 0-99/*"), Some((0, None)));
+        assert_eq!(parse_content_range("bytes */200"), None);
+        assert_eq!(parse_content_range("bytes 200-100/300"), None);
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+    }
+
+    #[tokio::test]
+    async fn downloads_of_the_same_zip_are_serialized() {
+        let part = std::path::Path::new("/tmp/skill_repo/owner_repo_main.zip.part");
+        let first = repo_zip_download_lock(part);
+        let held = first.lock().await;
+        assert!(repo_zip_download_lock(part).try_lock().is_err());
+        assert!(repo_zip_download_lock(std::path::Path::new("/tmp/skill_repo/owner_other_main.zip.part")).try_lock().is_ok());
+        drop(held);
+        assert!(repo_zip_download_lock(part).try_lock().is_ok());
+    }
+
+    fn backup(filename: &str, modified: &str) -> WebdavBackup {
+        WebdavBackup {
+            filename: filename.to_string(),
+            size: 0,
+            modified: modified.to_string(),
+            incremental: crate::services::incremental_backup::is_increment_file(filename),
+        }
+    }
+
+    #[test]
+    fn prune_keeps_the_newest_backups_and_their_increments() {
+        let backups = vec![
+            backup("ccg_gateway_20240101_000000.db", "Mon, 01 Jan 2024 00:00:00 GMT"),
+            backup("ccg_gateway_inc_20240101_120000.json", "Mon, 01 Jan 2024 12:00:00 GMT"),
+            backup("ccg_gateway_20240102_000000.db", "Tue, 02 Jan 2024 00:00:00 GMT"),
+            backup("ccg_gateway_inc_20240102_120000.json", "Tue, 02 Jan 2024 12:00:00 GMT"),
+            backup("ccg_gateway_20240103_000000.db", "Wed, 03 Jan 2024 00:00:00 GMT"),
+        ];
+
+        let mut stale = stale_backups(backups.clone(), 2);
+        stale.sort();
+        assert_eq!(stale, ["ccg_gateway_20240101_000000.db", "ccg_gateway_inc_20240101_120000.json"]);
+        assert!(stale_backups(backups.clone(), 5).is_empty());
+        assert_eq!(stale_backups(backups, 0).len(), 5);
+    }
+
+    #[test]
+    fn prune_skips_names_without_a_valid_timestamp() {
+        let backups = vec![
+            backup("ccg_gateway_20240101_000000.db", "Mon, 01 Jan 2024 00:00:00 GMT"),
+            backup("ccg_gateway_20240102_000000.db", "Tue, 02 Jan 2024 00:00:00 GMT"),
+            // Renamed or foreign files: older than everything, but never ours to delete
+            backup("ccg_gateway_before-upgrade.db", "Sun, 01 Jan 2023 00:00:00 GMT"),
+            backup("ccg_gateway_inc_manual.json", "Sun, 01 Jan 2023 00:00:00 GMT"),
+            backup("ccg_gateway_99999999_999999.db", "Sun, 01 Jan 2023 00:00:00 GMT"),
+        ];
+
+        assert_eq!(stale_backups(backups.clone(), 1), ["ccg_gateway_20240101_000000.db"]);
+        let mut stale = stale_backups(backups, 0);
+        stale.sort();
+        assert_eq!(stale, ["ccg_gateway_20240101_000000.db", "ccg_gateway_20240102_000000.db"]);
+    }
+
+    #[test]
+    fn webdav_url_trailing_slashes_are_trimmed() {
+        assert_eq!(
+            normalize_webdav_url("  https://dav.example.com/remote.php/dav/  ").unwrap(),
+            "https://dav.example.com/remote.php/dav"
+        );
+        assert_eq!(normalize_webdav_url("http://dav.example.com/").unwrap(), "http://dav.example.com");
+        assert_eq!(normalize_webdav_url("HTTPS://Dav.Example.com:8443//").unwrap(), "https://dav.example.com:8443");
+    }
+
+    fn provider_input(name: &str, base_url: &str) -> ProviderCreate {
+        serde_json::from_value(serde_json::json!({ "name": name, "base_url": base_url, "api_key": "sk-test" })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn provider_impls_create_update_and_delete() {
+        let (db, log_db) = (crate::db::test_pool(false).await, crate::db::test_pool(true).await);
+        let (scores, clients, config) = (ProviderScores::default(), UpstreamClients::default(), crate::config::Config::default());
+
+        let created = create_provider_impl(&db, &log_db, &scores, &config, provider_input("a", "https://a.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(created.cli_type, "claude_code");
+        assert!(created.enabled);
+        let err = create_provider_impl(&db, &log_db, &scores, &config, provider_input("a", "https://b.example.com"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Conflict(_)), "{:?}", err);
+
+        let update: ProviderUpdate = serde_json::from_value(serde_json::json!({ "name": "renamed", "enabled": false })).unwrap();
+        let updated = update_provider_impl(&db, &log_db, &scores, &config, created.id, update).await.unwrap();
+        assert_eq!((updated.name.as_str(), updated.enabled), ("renamed", false));
+        // Pointing a provider at the gateway itself would loop
+        let gateway_url = format!("http://127.0.0.1:{}", config.server.port);
+        let update: ProviderUpdate = serde_json::from_value(serde_json::json!({ "base_url": gateway_url })).unwrap();
+        let err = update_provider_impl(&db, &log_db, &scores, &config, created.id, update).await.unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)), "{:?}", err);
+
+        delete_provider_impl(&db, &log_db, &clients, created.id).await.unwrap();
+        let err = get_provider_impl(&db, &scores, created.id).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)), "{:?}", err);
+        let events: Vec<String> = sqlx::query_scalar("SELECT event_type FROM system_logs ORDER BY id")
+            .fetch_all(&log_db)
+            .await
+            .unwrap();
+        assert_eq!(events, ["provider_created", "provider_updated", "provider_deleted"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_size_rule_keeps_the_existing_model_maps() {
+        let (db, log_db) = (crate::db::test_pool(false).await, crate::db::test_pool(true).await);
+        let (scores, config) = (ProviderScores::default(), crate::config::Config::default());
+        let mut input = provider_input("a", "https://a.example.com");
+        input.model_maps = Some(vec![serde_json::from_value(serde_json::json!({
+            "source_model": "claude-*", "target_model": "small", "enabled": true,
+            "large_input_tokens": 100000, "large_target_model": "large",
+        }))
+        .unwrap()]);
+        let created = create_provider_impl(&db, &log_db, &scores, &config, input).await.unwrap();
+
+        for size_rule in [
+            serde_json::json!({ "large_input_tokens": 0, "large_target_model": "large" }),
+            serde_json::json!({ "large_input_tokens": 1000 }),
+            serde_json::json!({ "large_target_model": "large" }),
+        ] {
+            let mut map = serde_json::json!({ "source_model": "gpt-*", "target_model": "other", "enabled": true });
+            map.as_object_mut().unwrap().extend(size_rule.as_object().unwrap().clone());
+            let update: ProviderUpdate = serde_json::from_value(serde_json::json!({
+                "model_maps": [{ "source_model": "claude-*", "target_model": "replaced", "enabled": true }, map],
+            }))
+            .unwrap();
+            let err = update_provider_impl(&db, &log_db, &scores, &config, created.id, update).await.unwrap_err();
+            assert!(matches!(err, AppError::Validation(_)), "{:?}", err);
+        }
+
+        let maps: Vec<(String, String, Option<i64>)> =
+            sqlx::query_as("SELECT source_model, target_model, large_input_tokens FROM provider_model_map WHERE provider_id = ?")
+                .bind(created.id)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(maps, [("claude-*".to_string(), "small".to_string(), Some(100000))]);
+    }
+
+    #[tokio::test]
+    async fn settings_impls_apply_partial_updates() {
+        let db = crate::db::test_pool(false).await;
+        let before = get_timeout_settings_impl(&db).await.unwrap();
+        let input: TimeoutSettingsUpdate = serde_json::from_value(serde_json::json!({ "non_stream_timeout": 321 })).unwrap();
+        update_timeout_settings_impl(&db, input).await.unwrap();
+        let after = get_timeout_settings_impl(&db).await.unwrap();
+        assert_eq!(after.non_stream_timeout, 321);
+        assert_eq!(after.stream_idle_timeout, before.stream_idle_timeout);
+        assert_eq!(after.stream_first_byte_timeout, before.stream_first_byte_timeout);
+    }
+
+    #[tokio::test]
+    async fn cli_settings_impl_validates_before_saving() {
+        let (db, log_db) = (crate::db::test_pool(false).await, crate::db::test_pool(true).await);
+        let input = |config: &str| CliSettingsUpdate { default_json_config: Some(config.to_string()), enabled: None };
+
+        let err = update_cli_settings_impl(&db, &log_db, "claude_code".to_string(), input("{\n  \"env\": ,\n}"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.line, Some(2));
+        let changed = update_cli_settings_impl(&db, &log_db, "claude_code".to_string(), input("  {\"env\": {}}  "))
+            .await
+            .unwrap();
+        // Not toggled, so there is nothing to emit
+        assert!(changed.is_none());
+        let saved = get_cli_settings_impl(&db, "claude_code".to_string()).await.unwrap();
+        assert_eq!(saved.default_json_config, "{\"env\": {}}");
+    }
+
+    #[tokio::test]
+    async fn query_auth_failures_do_not_leak_the_api_key() {
+        let db = crate::db::test_pool(false).await;
+        crate::db::test_provider(&db).await;
+        // Nothing listens on the discard port, so the request fails with a connection error
+        sqlx::query("UPDATE providers SET base_url = 'http://127.0.0.1:9', api_key = 'sk-query-secret', auth_scheme = 'query:key' WHERE id = 1")
+            .execute(&db)
+            .await
+            .unwrap();
+        let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = 1").fetch_one(&db).await.unwrap();
+        let (url, _) = provider_models_request(&provider, crate::services::proxy::CliType::ClaudeCode, "").unwrap();
+        assert!(url.contains("sk-query-secret"));
+
+        let result = probe_provider(&reqwest::Client::new(), &provider).await;
+        let error = result.error.unwrap();
+        assert!(!result.reachable);
+        assert!(!error.contains("sk-query-secret"), "{}", error);
+
+        let err = reqwest::Client::new().get(&url).send().await.unwrap_err();
+        assert!(!AppError::from(err).to_string().contains("sk-query-secret"));
+    }
+
+    #[tokio::test]
+    async fn gateway_settings_do_not_expose_the_admin_token() {
+        let db = crate::db::test_pool(false).await;
+        let settings = get_gateway_settings_impl(&db).await.unwrap();
+        assert_eq!(settings.admin_api_enabled, 0);
+
+        sqlx::query("UPDATE gateway_settings SET admin_token = 'admin-token-0123456789' WHERE id = 1")
+            .execute(&db)
+            .await
+            .unwrap();
+        let settings = get_gateway_settings_impl(&db).await.unwrap();
+        assert_eq!(settings.admin_api_enabled, 1);
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(!json.contains("admin-token-0123456789"));
+        assert!(!json.contains("admin_token"));
+    }
+
+    #[tokio::test]
+    async fn restoring_an_old_backup_runs_data_migrations() {
+        use sqlx::sqlite::SqliteConnectOptions;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old.db");
+        let old = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&path).create_if_missing(true))
+            .await
+            .unwrap();
+        for sql in [
+            "CREATE TABLE _schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER NOT NULL)",
+            "INSERT INTO _schema_version VALUES (36, 0)",
+            "CREATE TABLE providers (id INTEGER PRIMARY KEY, name TEXT NOT NULL, base_url TEXT NOT NULL, api_key TEXT NOT NULL, sort_order INTEGER NOT NULL DEFAULT 0, created_at INTEGER NOT NULL, updated_at INTEGER NOT NULL)",
+            "INSERT INTO providers VALUES (1, 'a', 'https://a.example.com', 'k', 3, 0, 0), (2, 'b', 'https://b.example.com', 'k', 7, 0, 0)",
+        ] {
+            sqlx::query(sql).execute(&old).await.unwrap();
+        }
+        old.close().await;
+
+        // 内存库附加的文件也会以内存方式打开，这里使用文件库
+        let db = crate::db::init_db(&dir.path().join("ccg_gateway.db")).await.unwrap();
+        let expected_version = crate::db::schema_inspector::SchemaInspector::new(&db).get_version().await.unwrap();
+        import_attached_database(&db, &path).await.unwrap();
+
+        let priorities: Vec<(i64, i64)> = sqlx::query_as("SELECT sort_order, priority FROM providers ORDER BY id")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(priorities, vec![(3, 3), (7, 7)]);
+        let version = crate::db::schema_inspector::SchemaInspector::new(&db).get_version().await.unwrap();
+        assert_eq!(version, expected_version);
+    }
+
+    #[test]
+    fn project_dir_rejects_traversal() {
+        let base = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(base.path().join("projects/my-project")).unwrap();
+        std::fs::create_dir_all(base.path().join("secret")).unwrap();
+
+        assert!(resolve_project_dir(base.path(), "claude_code", "my-project").is_ok());
+        for name in ["", ".", "..", "../secret", "my-project/..", "..\\secret", "a\0b"] {
+            let err = resolve_project_dir(base.path(), "claude_code", name).unwrap_err();
+            assert!(err.starts_with("Invalid project name"), "{:?}: {}", name, err);
+        }
+        assert!(resolve_project_dir(base.path(), "gemini", "my-project").is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.path().join("secret"), base.path().join("projects/link")).unwrap();
+            let err = resolve_project_dir(base.path(), "claude_code", "link").unwrap_err();
+            assert!(err.contains("outside"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn gemini_mcp_stdio_server_keeps_command_fields() {
+        let server = gemini_mcp_server(&serde_json::json!({
+            "type": "stdio",
+            "command": "npx",
+            "args": ["-y", "server"],
+            "env": { "TOKEN": "x" },
+            "cwd": "/tmp",
+            "headers": { "ignored": "yes" },
+            "trust": true,
+        }));
+        assert_eq!(
+            server,
+            serde_json::json!({
+                "command": "npx",
+                "args": ["-y", "server"],
+                "env": { "TOKEN": "x" },
+                "cwd": "/tmp",
+                "trust": true,
+            })
+        );
+    }
+
+    #[test]
+    fn gemini_mcp_http_and_sse_servers_use_their_url_keys() {
+        let http = gemini_mcp_server(&serde_json::json!({
+            "type": "http",
+            "url": "https://mcp.example.com/mcp",
+            "headers": { "Authorization": "Bearer t" },
+            "timeout": 5000,
+        }));
+        assert_eq!(
+            http,
+            serde_json::json!({
+                "httpUrl": "https://mcp.example.com/mcp",
+                "headers": { "Authorization": "Bearer t" },
+                "timeout": 5000,
+            })
+        );
+
+        let sse = gemini_mcp_server(&serde_json::json!({ "type": "sse", "url": "https://mcp.example.com/sse" }));
+        assert_eq!(sse, serde_json::json!({ "url": "https://mcp.example.com/sse" }));
+
+        // An explicit httpUrl wins, and `type` is never written
+        let native = gemini_mcp_server(&serde_json::json!({ "type": "sse", "httpUrl": "https://a/mcp", "url": "https://b/sse" }));
+        assert_eq!(native, serde_json::json!({ "httpUrl": "https://a/mcp" }));
+        assert_eq!(gemini_mcp_server(&serde_json::json!("not an object")), serde_json::json!({}));
+    }
+
+    fn catalog_skill(owner: &str, name: &str, branch: &str, directory: &str) -> DiscoverableSkill {
+        serde_json::from_value(serde_json::json!({
+            "key": "k",
+            "name": "skill",
+            "description": "",
+            "directory": directory,
+            "readme_url": null,
+            "repo_owner": owner,
+            "repo_name": name,
+            "repo_branch": branch,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn skill_sources_must_be_safe_path_segments() {
+        assert!(validate_skill_source(&catalog_skill("owner", "repo", "main", "skills/pdf")).is_ok());
+        assert!(validate_skill_source(&catalog_skill("owner", "repo", "", "pdf")).is_ok());
+        for directory in ["..", ".", "", "../x", "skills/../..", "/etc", "skills//pdf", "a\\b"] {
+            assert!(validate_skill_source(&catalog_skill("owner", "repo", "main", directory)).is_err(), "{:?}", directory);
+        }
+        assert!(validate_skill_source(&catalog_skill("..", "repo", "main", "pdf")).is_err());
+        assert!(validate_skill_source(&catalog_skill("owner", "a/b", "main", "pdf")).is_err());
+        assert!(validate_skill_source(&catalog_skill("owner", "repo", "../../x", "pdf")).is_err());
+    }
+
+    #[test]
+    fn joined_paths_stay_inside_the_base_dir() {
+        let base = std::path::Path::new("/data/skills");
+        assert_eq!(join_within(base, "pdf").unwrap(), base.join("pdf"));
+        assert_eq!(join_within(base, "pdf/scripts/run.sh").unwrap(), base.join("pdf/scripts/run.sh"));
+        for relative in ["", ".", "..", "../other", "pdf/../../x", "/etc/passwd"] {
+            assert!(join_within(base, relative).is_err(), "{:?}", relative);
+        }
+    }
+
+    #[test]
+    fn skill_zip_entries_cannot_escape_the_skill_dir() {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("repo-main/pdf/SKILL.md", options).unwrap();
+        zip.write_all(b"---\nname: pdf\n---\n").unwrap();
+        zip.start_file("repo-main/pdf/../../evil.txt", options).unwrap();
+        zip.write_all(b"x").unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let ssot = tempfile::tempdir().unwrap();
+        let err = extract_skill_from_zip(&bytes, "pdf", ssot.path(), "pdf", None, &mut |_, _| {}).unwrap_err();
+        assert!(err.contains("escapes"), "{}", err);
+        assert!(!ssot.path().parent().unwrap().join("evil.txt").exists());
+        assert!(ssot.path().join("pdf/SKILL.md").exists());
+    }
+
+    #[tokio::test]
+    async fn project_prompt_files_with_unmanaged_content_are_not_overwritten() {
+        let db = crate::db::test_pool(false).await;
+        let project = tempfile::tempdir().unwrap();
+        let project_path = project.path().to_str().unwrap();
+        let file = project.path().join(".claude").join("CLAUDE.md");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        let flags = [PromptCliFlag { cli_type: "claude_code".to_string(), enabled: true }];
+
+        std::fs::write(&file, "# Project rules\nKeep these.\n").unwrap();
+        let err = check_project_prompt_file(&db, Some(project_path), &flags, "new prompt").await.unwrap_err();
+        assert!(err.contains("not written by CCG Gateway"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "# Project rules\nKeep these.\n");
+        // Disabling never writes the project file
+        let disabled = [PromptCliFlag { cli_type: "claude_code".to_string(), enabled: false }];
+        check_project_prompt_file(&db, Some(project_path), &disabled, "new prompt").await.unwrap();
+
+        // Content that came from a preset, or no content at all, may be replaced
+        sqlx::query("INSERT INTO prompt_presets (name, content, updated_at) VALUES ('old', '# Project rules\n\nKeep these.', 1)")
+            .execute(&db)
+            .await
+            .unwrap();
+        check_project_prompt_file(&db, Some(project_path), &flags, "new prompt").await.unwrap();
+        std::fs::write(&file, "\n").unwrap();
+        sqlx::query("DELETE FROM prompt_presets").execute(&db).await.unwrap();
+        check_project_prompt_file(&db, Some(project_path), &flags, "new prompt").await.unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -62,13 +64,82 @@ pub fn get_log_dir() -> PathBuf {
     get_data_dir().join("logs")
 }
 
-/// Check if file logging is enabled via environment variable
-pub fn is_file_log_enabled() -> bool {
+/// Time the application started, for uptime reporting
+static STARTED_AT: OnceLock<std::time::Instant> = OnceLock::new();
+
+/// Record the application start time (first call wins)
+pub fn mark_started() {
+    STARTED_AT.get_or_init(std::time::Instant::now);
+}
+
+/// Seconds since `mark_started` was called
+pub fn uptime_secs() -> i64 {
+    STARTED_AT.get_or_init(std::time::Instant::now).elapsed().as_secs() as i64
+}
+
+/// Log filter directives actually applied to the tracing subscriber
+static LOG_LEVEL: OnceLock<String> = OnceLock::new();
+
+/// Record the log filter the binary installed (call once at startup)
+pub fn set_log_level(directives: String) {
+    let _ = LOG_LEVEL.set(directives);
+}
+
+/// Get the applied log filter, if the binary recorded one
+pub fn get_log_level() -> Option<String> {
+    LOG_LEVEL.get().cloned()
+}
+
+/// Runtime file logging flag, initialized lazily from the persisted setting
+static FILE_LOG_ENABLED: OnceLock<AtomicBool> = OnceLock::new();
+
+/// Callback installed by the binary to swap the file logging layer at runtime
+type FileLogReloader = Box<dyn Fn(bool) -> Result<(), String> + Send + Sync>;
+static FILE_LOG_RELOADER: OnceLock<FileLogReloader> = OnceLock::new();
+
+/// Path of the persisted file logging setting ("1" / "0")
+fn file_log_setting_path() -> PathBuf {
+    get_data_dir().join("file_logging")
+}
+
+/// Load the initial file logging setting.
+/// A value saved via `set_file_log_enabled` wins; otherwise the CCG_LOG_FILE env var is used.
+fn load_file_log_enabled() -> bool {
+    if let Ok(value) = std::fs::read_to_string(file_log_setting_path()) {
+        return value.trim() == "1";
+    }
     std::env::var("CCG_LOG_FILE")
         .map(|v| v == "true" || v == "1")
         .unwrap_or(false)
 }
 
+/// Check if file logging is currently enabled
+pub fn is_file_log_enabled() -> bool {
+    FILE_LOG_ENABLED
+        .get_or_init(|| AtomicBool::new(load_file_log_enabled()))
+        .load(Ordering::Relaxed)
+}
+
+/// Register the callback that enables/disables the file logging layer
+pub fn set_file_log_reloader(reloader: impl Fn(bool) -> Result<(), String> + Send + Sync + 'static) {
+    let _ = FILE_LOG_RELOADER.set(Box::new(reloader));
+}
+
+/// Persist the file logging setting and apply it to the running subscriber
+pub fn set_file_log_enabled(enabled: bool) -> Result<(), String> {
+    std::fs::create_dir_all(get_data_dir()).map_err(|e| e.to_string())?;
+    std::fs::write(file_log_setting_path(), if enabled { "1" } else { "0" }).map_err(|e| e.to_string())?;
+
+    if let Some(reload) = FILE_LOG_RELOADER.get() {
+        reload(enabled)?;
+    }
+
+    FILE_LOG_ENABLED
+        .get_or_init(|| AtomicBool::new(enabled))
+        .store(enabled, Ordering::Relaxed);
+    Ok(())
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -0,0 +1,176 @@
+//! 版本化数据迁移
+//!
+//! 表结构（新增表/列）由 schema diff 自动对齐，这里只处理结构对比无法表达的数据变更，例如新列的回填。
+//! 每个迁移绑定引入它的 schema 版本：只对升级前版本低于该版本的数据库执行，
+//! 执行后记录到 `_schema_migrations`，之后重复运行 `migrate_schema` 不会再次执行。
+
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+
+/// 单个数据迁移
+pub struct DataMigration {
+    /// 引入该迁移的 schema 版本
+    pub version: i64,
+    /// 唯一名称，作为执行记录的主键
+    pub name: &'static str,
+    /// 按顺序在同一事务中执行的语句
+    pub statements: &'static [&'static str],
+}
+
+/// 主数据库的数据迁移（按版本递增排列，只能追加不能修改已发布的条目）
+const MAIN_MIGRATIONS: &[DataMigration] = &[
+    // priority 列是新加的：沿用 sort_order，保持原有故障转移顺序
+    DataMigration {
+        version: 37,
+        name: "backfill_provider_priority",
+        statements: &["UPDATE providers SET priority = sort_order"],
+    },
+];
+
+/// 日志数据库的数据迁移
+const LOG_MIGRATIONS: &[DataMigration] = &[];
+
+fn migrations_for(is_log_db: bool) -> &'static [DataMigration] {
+    if is_log_db {
+        LOG_MIGRATIONS
+    } else {
+        MAIN_MIGRATIONS
+    }
+}
+
+/// 创建迁移记录表
+async fn create_migrations_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS _schema_migrations (
+            name TEXT PRIMARY KEY,
+            version INTEGER NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// 执行升级前版本之后引入、且尚未记录的数据迁移；必须在表结构对齐之后调用
+/// 每个迁移与其执行记录在同一事务中提交，中途失败时下次启动会从失败的迁移继续
+/// 返回本次执行的迁移数量
+pub async fn run(pool: &SqlitePool, from_version: i64, is_log_db: bool) -> Result<usize, sqlx::Error> {
+    create_migrations_table(pool).await?;
+
+    let applied: HashSet<String> = sqlx::query_scalar("SELECT name FROM _schema_migrations")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut count = 0;
+    for migration in migrations_for(is_log_db) {
+        if migration.version <= from_version || applied.contains(migration.name) {
+            continue;
+        }
+
+        tracing::info!("执行数据迁移: {} (版本 {})", migration.name, migration.version);
+        let mut tx = pool.begin().await?;
+        for sql in migration.statements {
+            sqlx::query(sql).execute(&mut *tx).await?;
+        }
+        sqlx::query("INSERT INTO _schema_migrations (name, version, applied_at) VALUES (?, ?, ?)")
+            .bind(migration.name)
+            .bind(migration.version)
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// 全新数据库直接按最新结构创建，不需要数据迁移：把所有迁移记为已执行
+pub async fn mark_all_applied(pool: &SqlitePool, is_log_db: bool) -> Result<(), sqlx::Error> {
+    create_migrations_table(pool).await?;
+
+    let now = chrono::Utc::now().timestamp();
+    for migration in migrations_for(is_log_db) {
+        sqlx::query("INSERT OR IGNORE INTO _schema_migrations (name, version, applied_at) VALUES (?, ?, ?)")
+            .bind(migration.name)
+            .bind(migration.version)
+            .bind(now)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqliteConnectOptions;
+
+    /// 版本 36 的服务商表（尚无 priority 列）；结构迁移会同时占用多个连接，不能用单连接内存库
+    async fn old_schema_pool(dir: &tempfile::TempDir) -> SqlitePool {
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("ccg_gateway.db"))
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        for sql in [
+            "CREATE TABLE _schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER NOT NULL)",
+            "INSERT INTO _schema_version (version, applied_at) VALUES (36, 0)",
+            "CREATE TABLE providers (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                cli_type TEXT NOT NULL DEFAULT 'claude_code',
+                name TEXT NOT NULL,
+                base_url TEXT NOT NULL,
+                api_key TEXT NOT NULL,
+                enabled INTEGER NOT NULL DEFAULT 1,
+                sort_order INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            "INSERT INTO providers (name, base_url, api_key, sort_order, created_at, updated_at) VALUES
+                ('a', 'https://a.example', 'k', 2, 0, 0),
+                ('b', 'https://b.example', 'k', 0, 0, 0),
+                ('c', 'https://c.example', 'k', 1, 0, 0)",
+        ] {
+            sqlx::query(sql).execute(&pool).await.unwrap();
+        }
+        pool
+    }
+
+    async fn priorities(pool: &SqlitePool) -> Vec<(String, i64, i64)> {
+        sqlx::query_as("SELECT name, sort_order, priority FROM providers ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn upgrade_backfills_priority_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = old_schema_pool(&dir).await;
+        crate::db::migrate_schema(&pool, false).await.unwrap();
+
+        assert_eq!(
+            priorities(&pool).await,
+            [("a".to_string(), 2, 2), ("b".to_string(), 0, 0), ("c".to_string(), 1, 1)]
+        );
+        let recorded: Vec<(String, i64)> = sqlx::query_as("SELECT name, version FROM _schema_migrations")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(recorded, [("backfill_provider_priority".to_string(), 37)]);
+
+        // 已记录的迁移不会再次执行，不会覆盖之后调整的优先级
+        sqlx::query("UPDATE providers SET priority = 9 WHERE name = 'a'").execute(&pool).await.unwrap();
+        crate::db::migrate_schema(&pool, false).await.unwrap();
+        assert_eq!(priorities(&pool).await[0], ("a".to_string(), 2, 9));
+    }
+
+    #[tokio::test]
+    async fn fresh_database_skips_data_migrations() {
+        let pool = crate::db::test_pool(false).await;
+        assert_eq!(run(&pool, 0, false).await.unwrap(), 0);
+    }
+}
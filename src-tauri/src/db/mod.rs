@@ -1,15 +1,55 @@
+pub mod data_migrations;
 pub mod models;
 pub mod schema_definition;
 pub mod schema_diff;
 pub mod schema_inspector;
 pub mod schema_migrator;
 
+use models::SchemaReport;
 use schema_definition::DatabaseSchema;
-use schema_diff::SchemaDiff;
+use schema_diff::{SchemaChange, SchemaDiff};
 use schema_inspector::SchemaInspector;
 use schema_migrator::SchemaMigrator;
-use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteSynchronous};
+use std::future::Future;
 use std::path::Path;
+use std::time::Duration;
+
+/// 等待锁释放的时间（busy_timeout）
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 遇到锁冲突时的最大重试次数
+const LOCKED_MAX_RETRIES: u32 = 3;
+
+/// 判断是否为 SQLite 锁冲突错误（SQLITE_BUSY / SQLITE_LOCKED）
+pub fn is_locked_error(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Database(db_err) => {
+            matches!(db_err.code().as_deref(), Some("5") | Some("6") | Some("517"))
+                || db_err.message().contains("database is locked")
+        }
+        _ => false,
+    }
+}
+
+/// 写操作遇到 "database is locked" 时退避重试
+pub async fn retry_on_locked<T, F, Fut>(mut op: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Err(e) if is_locked_error(&e) && attempt < LOCKED_MAX_RETRIES => {
+                attempt += 1;
+                tracing::warn!("Database is locked, retrying ({}/{})", attempt, LOCKED_MAX_RETRIES);
+                tokio::time::sleep(Duration::from_millis(50 * 2u64.pow(attempt))).await;
+            }
+            result => return result,
+        }
+    }
+}
 
 pub async fn init_db(path: &Path) -> Result<SqlitePool, sqlx::Error> {
     // 1. 确保父目录存在
@@ -17,22 +57,29 @@ pub async fn init_db(path: &Path) -> Result<SqlitePool, sqlx::Error> {
         std::fs::create_dir_all(parent).ok();
     }
 
-    // 2. 连接数据库
-    let db_url = format!("sqlite:{}?mode=rwc", path.display());
+    // 2. 判断数据库类型
+    let is_log_db = path.ends_with("ccg_logs.db") || path.ends_with("ccg_logs");
+
+    // 3. 连接数据库
+    // 日志库并发写入多，使用 WAL；主库备份/恢复直接复制文件，保持默认回滚日志避免 -wal 文件不同步
+    let journal_mode = if is_log_db {
+        SqliteJournalMode::Wal
+    } else {
+        SqliteJournalMode::Delete
+    };
+    let connect_options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .journal_mode(journal_mode)
+        .synchronous(SqliteSynchronous::Normal)
+        .busy_timeout(BUSY_TIMEOUT);
     let pool = SqlitePoolOptions::new()
         .max_connections(5)
-        .connect(&db_url)
+        .connect_with(connect_options)
         .await?;
 
-    // 3. 判断数据库类型
-    let is_log_db = path.ends_with("ccg_logs.db") || path.ends_with("ccg_logs");
-
     // 4. 获取期望的 schema
-    let expected_schema = if is_log_db {
-        DatabaseSchema::log_schema()
-    } else {
-        DatabaseSchema::current()
-    };
+    let expected_schema = schema_for(is_log_db);
 
     // 5. 创建检查器
     let inspector = SchemaInspector::new(&pool);
@@ -41,6 +88,7 @@ pub async fn init_db(path: &Path) -> Result<SqlitePool, sqlx::Error> {
     if inspector.is_empty_database().await? {
         tracing::info!("检测到全新数据库，创建表结构...");
         create_fresh_database(&pool, &expected_schema).await?;
+        data_migrations::mark_all_applied(&pool, is_log_db).await?;
 
         // 插入默认数据（仅主数据库）
         if !is_log_db {
@@ -66,31 +114,135 @@ pub async fn init_db(path: &Path) -> Result<SqlitePool, sqlx::Error> {
 
     // 9. 需要迁移
     tracing::info!("检测到数据库版本过旧，开始自动迁移...");
+    migrate_schema(&pool, is_log_db).await?;
 
-    // 10. 读取实际结构
+    tracing::info!("数据库迁移完成");
+    Ok(pool)
+}
+
+/// 将数据库迁移到期望结构：对比差异、应用变更、更新版本并补齐默认数据
+/// init_db 升级时执行同样的逻辑；结构已一致时重复执行不做任何变更
+/// 返回应用的结构变更数量
+pub async fn migrate_schema(pool: &SqlitePool, is_log_db: bool) -> Result<usize, sqlx::Error> {
+    let expected_schema = schema_for(is_log_db);
+    let inspector = SchemaInspector::new(pool);
+
+    // 1. 读取实际结构
     let actual_tables = inspector.get_tables().await?;
 
-    // 11. 对比差异（通过 SQL 比较）
+    // 旧库可能缺少唯一约束，重建表前先处理重名数据，否则复制数据会失败
+    if !is_log_db && actual_tables.contains("mcp_configs") {
+        dedupe_mcp_names(pool).await?;
+    }
+
+    // 数据迁移按升级前的版本决定是否执行
+    let from_version = inspector.get_version().await?;
+
+    // 2. 对比差异（通过 SQL 比较）
     let diff = SchemaDiff::compare_async(&expected_schema, actual_tables, &inspector).await?;
+    let change_count = diff.change_count();
 
-    // 12. 应用变更
+    // 3. 应用变更
     if diff.has_changes() {
-        tracing::info!("检测到 {} 个结构变更，开始迁移...", diff.change_count());
-        let migrator = SchemaMigrator::new(&pool, &expected_schema);
+        tracing::info!("检测到 {} 个结构变更，开始迁移...", change_count);
+        let migrator = SchemaMigrator::new(pool, &expected_schema);
         migrator.apply(diff).await?;
-        tracing::info!("数据库迁移完成");
     }
 
-    // 13. 更新版本
-    update_version(&pool, expected_schema.version).await?;
+    // 4. 执行数据迁移（新列回填等），完成后才更新版本，失败时下次启动重试
+    let data_count = data_migrations::run(pool, from_version, is_log_db).await?;
+    if data_count > 0 {
+        tracing::info!("已执行 {} 个数据迁移", data_count);
+    }
 
-    // 14. 插入默认数据（仅主数据库）
+    // 5. 更新版本
+    update_version(pool, expected_schema.version).await?;
+
+    // 6. 插入默认数据（仅主数据库）
     if !is_log_db {
-        init_default_data(&pool).await?;
+        init_default_data(pool).await?;
     }
 
-    tracing::info!("数据库迁移完成");
-    Ok(pool)
+    Ok(change_count)
+}
+
+/// 对照期望结构检查数据库（只读），列出缺失/多余的表和列以及需要重建的表
+pub async fn verify_schema(pool: &SqlitePool, is_log_db: bool) -> Result<SchemaReport, sqlx::Error> {
+    let expected_schema = schema_for(is_log_db);
+    let inspector = SchemaInspector::new(pool);
+
+    let version = inspector.get_version().await?;
+    let actual_tables = inspector.get_tables().await?;
+
+    let mut missing_tables = Vec::new();
+    let mut missing_columns = Vec::new();
+    let mut unexpected_columns = Vec::new();
+    for (name, table) in &expected_schema.tables {
+        if !actual_tables.contains(name) {
+            missing_tables.push(name.clone());
+            continue;
+        }
+        let actual_columns: Vec<String> = inspector
+            .get_table_columns(name)
+            .await?
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        for column in &table.columns {
+            if !actual_columns.contains(&column.name) {
+                missing_columns.push(format!("{}.{}", name, column.name));
+            }
+        }
+        for column in &actual_columns {
+            if !table.columns.iter().any(|c| &c.name == column) {
+                unexpected_columns.push(format!("{}.{}", name, column));
+            }
+        }
+    }
+    let mut unexpected_tables: Vec<String> = actual_tables
+        .iter()
+        .filter(|t| !expected_schema.tables.contains_key(*t))
+        .cloned()
+        .collect();
+
+    // 列名一致但类型、约束或默认值不同的表同样需要重建
+    let diff = SchemaDiff::compare_async(&expected_schema, actual_tables, &inspector).await?;
+    let mut changed_tables: Vec<String> = diff
+        .changes
+        .into_iter()
+        .filter_map(|change| match change {
+            SchemaChange::RebuildTable { name } => Some(name),
+            _ => None,
+        })
+        .collect();
+
+    for list in [&mut missing_tables, &mut unexpected_tables, &mut missing_columns, &mut unexpected_columns, &mut changed_tables] {
+        list.sort();
+    }
+    let ok = version >= expected_schema.version
+        && missing_tables.is_empty()
+        && unexpected_tables.is_empty()
+        && changed_tables.is_empty();
+
+    Ok(SchemaReport {
+        database: if is_log_db { "log" } else { "main" }.to_string(),
+        version,
+        expected_version: expected_schema.version,
+        ok,
+        missing_tables,
+        unexpected_tables,
+        missing_columns,
+        unexpected_columns,
+        changed_tables,
+    })
+}
+
+fn schema_for(is_log_db: bool) -> DatabaseSchema {
+    if is_log_db {
+        DatabaseSchema::log_schema()
+    } else {
+        DatabaseSchema::current()
+    }
 }
 
 /// 创建全新数据库
@@ -142,6 +294,46 @@ async fn update_version(pool: &SqlitePool, version: i64) -> Result<(), sqlx::Err
     Ok(())
 }
 
+/// MCP 名称去重：保留 id 最小的一条，其余追加后缀（name-2、name-3…）
+async fn dedupe_mcp_names(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let duplicates: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT id, name FROM mcp_configs WHERE name IN (SELECT name FROM mcp_configs GROUP BY name HAVING COUNT(*) > 1) ORDER BY name, id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut previous: Option<&str> = None;
+    for (id, name) in &duplicates {
+        // 每组第一条保持原名
+        if previous != Some(name.as_str()) {
+            previous = Some(name.as_str());
+            continue;
+        }
+
+        let mut suffix = 2;
+        let new_name = loop {
+            let candidate = format!("{}-{}", name, suffix);
+            let taken: Option<(i64,)> = sqlx::query_as("SELECT id FROM mcp_configs WHERE name = ?")
+                .bind(&candidate)
+                .fetch_optional(pool)
+                .await?;
+            if taken.is_none() {
+                break candidate;
+            }
+            suffix += 1;
+        };
+
+        sqlx::query("UPDATE mcp_configs SET name = ? WHERE id = ?")
+            .bind(&new_name)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        tracing::warn!("MCP 名称重复，已将 id={} 的 {} 重命名为 {}", id, name, new_name);
+    }
+
+    Ok(())
+}
+
 /// 插入默认配置数据
 async fn init_default_data(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     // gateway_settings
@@ -158,6 +350,13 @@ async fn init_default_data(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
 
+    // log_settings
+    sqlx::query(
+        "INSERT OR IGNORE INTO log_settings (id, request_log_retention_days, system_log_retention_days, updated_at) VALUES (1, 0, 0, strftime('%s', 'now'))"
+    )
+    .execute(pool)
+    .await?;
+
     // cli_settings
     sqlx::query("INSERT OR IGNORE INTO cli_settings (cli_type, updated_at) VALUES ('claude_code', strftime('%s', 'now'))")
         .execute(pool)
@@ -169,6 +368,11 @@ async fn init_default_data(pool: &SqlitePool) -> Result<(), sqlx::Error> {
         .execute(pool)
         .await?;
 
+    // provider_cli_types：确保每个服务商的主 cli_type 都在其类型集合中（迁移旧的单类型数据）
+    sqlx::query("INSERT OR IGNORE INTO provider_cli_types (provider_id, cli_type) SELECT id, cli_type FROM providers")
+        .execute(pool)
+        .await?;
+
     // skill_repos (默认仓库)
     sqlx::query("INSERT OR IGNORE INTO skill_repos (owner, name, branch) VALUES ('anthropics', 'skills', 'main')")
         .execute(pool)
@@ -179,3 +383,80 @@ async fn init_default_data(pool: &SqlitePool) -> Result<(), sqlx::Error> {
 
     Ok(())
 }
+
+/// 测试用内存数据库：按最新结构创建并写入默认数据
+#[cfg(test)]
+pub(crate) async fn test_pool(is_log_db: bool) -> SqlitePool {
+    // 内存库每个连接各自独立，只能使用单连接
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("open in-memory database");
+    create_fresh_database(&pool, &schema_for(is_log_db)).await.expect("create schema");
+    data_migrations::mark_all_applied(&pool, is_log_db).await.expect("mark migrations");
+    if !is_log_db {
+        init_default_data(&pool).await.expect("insert default data");
+    }
+    pool
+}
+
+/// 测试用服务商：在测试库中插入一个 claude_code 服务商（id = 1）并返回完整的行
+#[cfg(test)]
+pub(crate) async fn test_provider(pool: &SqlitePool) -> models::Provider {
+    sqlx::query(
+        "INSERT INTO providers (id, cli_type, name, base_url, api_key, created_at, updated_at) VALUES (1, 'claude_code', 'p', 'https://api.example.com', 'sk', 0, 0)",
+    )
+    .execute(pool)
+    .await
+    .expect("insert test provider");
+    sqlx::query_as::<_, models::Provider>("SELECT * FROM providers WHERE id = 1")
+        .fetch_one(pool)
+        .await
+        .expect("load test provider")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_log_writes_and_reads_do_not_hit_lock_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_db = init_db(&dir.path().join("ccg_logs.db")).await.unwrap();
+
+        let mut tasks = Vec::new();
+        for writer in 0..8 {
+            let db = log_db.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..25 {
+                    let provider = format!("provider-{}", writer);
+                    crate::services::stats::record_request(&db, &provider, "claude_code", i % 5 != 0, 10, 20).await?;
+                    crate::services::stats::record_request_log(
+                        &db, "claude_code", &provider, None, Some(200), 5, 10, 20, "POST", "/v1/messages", None,
+                    )
+                    .await?;
+                }
+                Ok::<_, sqlx::Error>(())
+            }));
+        }
+        for _ in 0..4 {
+            let db = log_db.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..50 {
+                    let _: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM request_logs").fetch_one(&db).await?;
+                    let _: Option<i64> = sqlx::query_scalar("SELECT SUM(request_count) FROM usage_daily").fetch_one(&db).await?;
+                }
+                Ok(())
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap().expect("no spurious lock errors");
+        }
+
+        let logged: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM request_logs").fetch_one(&log_db).await.unwrap();
+        let counted: i64 = sqlx::query_scalar("SELECT SUM(request_count) FROM usage_daily").fetch_one(&log_db).await.unwrap();
+        assert_eq!(logged, 200);
+        assert_eq!(counted, 200);
+    }
+}
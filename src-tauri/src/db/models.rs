@@ -17,6 +17,22 @@ pub struct Provider {
     pub blacklisted_until: Option<i64>,
     pub sort_order: i64,
     pub custom_useragent: Option<String>,
+    pub auth_header_name: Option<String>,
+    pub auth_scheme: Option<String>,
+    pub paused: i64,
+    pub upstream_proxy: Option<String>, // 上游 HTTP 代理，NULL 表示直连
+    pub strip_headers: Option<String>,    // 转发前移除的请求头，JSON 数组
+    pub header_overrides: Option<String>, // 转发前覆盖的请求头，JSON 对象
+    pub inject_system_prompt: Option<String>, // 转发前前置到系统提示词的内容
+    pub force_non_stream: i64, // 流式请求改为非流式转发，再以 SSE 回放给客户端
+    pub max_concurrency: i64,  // 同时转发的请求上限，0 表示不限制
+    pub notes: Option<String>, // 备注，仅用于整理和搜索
+    pub accept_invalid_certs: i64,     // 不校验上游证书（不安全）
+    pub custom_ca_pem: Option<String>, // 额外信任的根证书（PEM，可含多个）
+    pub priority: i64,                 // 故障转移优先级（越小越先尝试），与展示用的 sort_order 无关
+    pub request_transform: Option<String>,  // 转发前应用到请求体的 JSON Patch (RFC 6902)
+    pub response_transform: Option<String>, // 返回前应用到非流式响应体的 JSON Patch
+    pub key_expires_at: Option<i64>,        // API Key 过期时间（Unix 秒），仅用于提醒
     pub created_at: i64,
     pub updated_at: i64,
 }
@@ -28,6 +44,9 @@ pub struct ProviderModelMap {
     pub source_model: String,
     pub target_model: String,
     pub enabled: i64,
+    pub cli_type: Option<String>, // 仅对该 CLI 类型生效，NULL 表示所有类型
+    pub large_input_tokens: Option<i64>, // 估算输入 token 超过该值时改用 large_target_model
+    pub large_target_model: Option<String>,
 }
 
 // Input DTOs
@@ -36,6 +55,12 @@ pub struct ModelMapInput {
     pub source_model: String,
     pub target_model: String,
     pub enabled: bool,
+    #[serde(default)]
+    pub cli_type: Option<String>,
+    #[serde(default)]
+    pub large_input_tokens: Option<i64>, // 与 large_target_model 同时设置才生效
+    #[serde(default)]
+    pub large_target_model: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +73,22 @@ pub struct ProviderCreate {
     pub failure_threshold: Option<i64>,
     pub blacklist_minutes: Option<i64>,
     pub custom_useragent: Option<String>,
+    pub auth_header_name: Option<String>,
+    pub auth_scheme: Option<String>,
+    pub upstream_proxy: Option<String>, // 空字符串表示直连
+    pub strip_headers: Option<Vec<String>>, // 空列表表示清除
+    pub header_overrides: Option<std::collections::BTreeMap<String, String>>, // 空对象表示清除
+    pub inject_system_prompt: Option<String>, // 空字符串表示清除
+    pub force_non_stream: Option<bool>,
+    pub max_concurrency: Option<i64>, // 0 表示不限制
+    pub notes: Option<String>,        // 空字符串清除备注
+    pub accept_invalid_certs: Option<bool>,
+    pub custom_ca_pem: Option<String>, // 空字符串清除
+    pub priority: Option<i64>,         // 创建时省略则排在最后
+    pub request_transform: Option<serde_json::Value>,  // JSON Patch 操作数组，空数组表示清除
+    pub response_transform: Option<serde_json::Value>, // 同上
+    pub key_expires_at: Option<i64>,   // API Key 过期时间（Unix 秒），更新时 0 表示清除
+    pub cli_types: Option<Vec<String>>, // 支持的 CLI 类型集合（主 cli_type 总是包含在内）
     pub model_maps: Option<Vec<ModelMapInput>>,
 }
 
@@ -60,6 +101,22 @@ pub struct ProviderUpdate {
     pub failure_threshold: Option<i64>,
     pub blacklist_minutes: Option<i64>,
     pub custom_useragent: Option<String>,
+    pub auth_header_name: Option<String>,
+    pub auth_scheme: Option<String>,
+    pub upstream_proxy: Option<String>, // 空字符串表示直连
+    pub strip_headers: Option<Vec<String>>, // 空列表表示清除
+    pub header_overrides: Option<std::collections::BTreeMap<String, String>>, // 空对象表示清除
+    pub inject_system_prompt: Option<String>, // 空字符串表示清除
+    pub force_non_stream: Option<bool>,
+    pub max_concurrency: Option<i64>, // 0 表示不限制
+    pub notes: Option<String>,        // 空字符串清除备注
+    pub accept_invalid_certs: Option<bool>,
+    pub custom_ca_pem: Option<String>, // 空字符串清除
+    pub priority: Option<i64>,         // 省略则保持原优先级不变
+    pub request_transform: Option<serde_json::Value>,  // JSON Patch 操作数组，空数组表示清除
+    pub response_transform: Option<serde_json::Value>, // 同上
+    pub key_expires_at: Option<i64>,   // API Key 过期时间（Unix 秒），更新时 0 表示清除
+    pub cli_types: Option<Vec<String>>, // 支持的 CLI 类型集合（主 cli_type 总是包含在内）
     pub model_maps: Option<Vec<ModelMapInput>>,
 }
 
@@ -70,49 +127,160 @@ pub struct ModelMapResponse {
     pub source_model: String,
     pub target_model: String,
     pub enabled: bool,
+    pub cli_type: Option<String>,
+    pub large_input_tokens: Option<i64>,
+    pub large_target_model: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProviderResponse {
     pub id: i64,
     pub cli_type: String,
+    pub cli_types: Vec<String>,
     pub name: String,
     pub base_url: String,
     pub api_key: String,
     pub enabled: bool,
+    pub paused: bool, // 暂停路由，但仍视为已启用（区别于 enabled=false）
     pub failure_threshold: i64,
     pub blacklist_minutes: i64,
     pub consecutive_failures: i64,
     pub blacklisted_until: Option<i64>,
     pub sort_order: i64,
     pub custom_useragent: Option<String>,
-    pub is_blacklisted: bool,
+    pub auth_header_name: Option<String>,
+    pub auth_scheme: Option<String>,
+    pub upstream_proxy: Option<String>, // 凭据已打码
+    pub strip_headers: Vec<String>,
+    pub header_overrides: std::collections::BTreeMap<String, String>,
+    pub inject_system_prompt: Option<String>,
+    pub force_non_stream: bool,
+    pub max_concurrency: i64,
+    pub notes: Option<String>,
+    /// Certificate verification is off for this provider; shown as an insecure warning
+    pub accept_invalid_certs: bool,
+    pub custom_ca_pem: Option<String>,
+    pub priority: i64,
+    pub request_transform: Option<serde_json::Value>,
+    pub response_transform: Option<serde_json::Value>,
+    pub key_expires_at: Option<i64>,
+    pub expires_in_days: Option<i64>,          // 距 Key 过期的天数（向下取整），已过期时为负数
+    pub is_blacklisted: bool,                  // 按当前时间计算，前端/托盘无需自行比较
+    pub blacklist_remaining_secs: Option<i64>, // 拉黑剩余秒数，未拉黑时为 None
     pub model_maps: Vec<ModelMapResponse>,
+    pub score: Option<ProviderScore>,
+    // 启用时连通性检测失败的提示（仅在开启 validate_on_enable 时由 create/update 填充）
+    pub connection_warning: Option<String>,
+    // 以下仅在 get_providers(with_stats = true) 时填充
+    pub last_used_ts: Option<i64>,
+    pub requests_24h: Option<i64>,
+}
+
+// Provider routing score (内存中的滚动成功率/延迟)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ProviderScore {
+    pub success_rate: f64,
+    pub avg_latency_ms: f64,
+    pub samples: u64,
+    pub score: f64,
+}
+
+impl Provider {
+    /// Seconds left on the blacklist at `now`, None when not blacklisted
+    pub fn blacklist_remaining_secs(&self, now: i64) -> Option<i64> {
+        self.blacklisted_until.filter(|&until| until > now).map(|until| until - now)
+    }
 }
 
 impl From<Provider> for ProviderResponse {
     fn from(p: Provider) -> Self {
         let now = chrono::Utc::now().timestamp();
-        let is_blacklisted = p.blacklisted_until.map(|t| t > now).unwrap_or(false);
+        let blacklist_remaining_secs = p.blacklist_remaining_secs(now);
         Self {
             id: p.id,
+            cli_types: vec![p.cli_type.clone()], // Will be populated by the caller
             cli_type: p.cli_type,
             name: p.name,
             base_url: p.base_url,
             api_key: p.api_key,
             enabled: p.enabled != 0,
+            paused: p.paused != 0,
             failure_threshold: p.failure_threshold,
             blacklist_minutes: p.blacklist_minutes,
             consecutive_failures: p.consecutive_failures,
             blacklisted_until: p.blacklisted_until,
             sort_order: p.sort_order,
             custom_useragent: p.custom_useragent,
-            is_blacklisted,
+            auth_header_name: p.auth_header_name,
+            auth_scheme: p.auth_scheme,
+            upstream_proxy: p.upstream_proxy.as_deref().map(crate::services::proxy::mask_proxy_url),
+            strip_headers: crate::services::proxy::parse_strip_headers(p.strip_headers.as_deref()),
+            header_overrides: crate::services::proxy::parse_header_overrides(p.header_overrides.as_deref()),
+            inject_system_prompt: p.inject_system_prompt,
+            force_non_stream: p.force_non_stream != 0,
+            max_concurrency: p.max_concurrency,
+            notes: p.notes,
+            accept_invalid_certs: p.accept_invalid_certs != 0,
+            custom_ca_pem: p.custom_ca_pem,
+            priority: p.priority,
+            request_transform: crate::services::proxy::parse_json_patch(p.request_transform.as_deref()),
+            response_transform: crate::services::proxy::parse_json_patch(p.response_transform.as_deref()),
+            key_expires_at: p.key_expires_at,
+            expires_in_days: p.key_expires_at.map(|at| (at - now).div_euclid(86400)),
+            is_blacklisted: blacklist_remaining_secs.is_some(),
+            blacklist_remaining_secs,
             model_maps: vec![], // Will be populated by the caller
+            score: None,
+            connection_warning: None,
+            last_used_ts: None,
+            requests_24h: None,
         }
     }
 }
 
+// User-Agent override rule (用于导入/导出)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UseragentRule {
+    pub cli_type: String,
+    pub provider_name: String,
+    pub custom_useragent: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UseragentImportResult {
+    pub updated: i64,
+    pub skipped: Vec<String>, // 未匹配到服务商的规则 (cli_type/provider_name)
+}
+
+// Provider completion test result
+#[derive(Debug, Serialize)]
+pub struct TestCompletionResult {
+    pub success: bool,
+    pub status_code: Option<u16>,
+    pub text: Option<String>,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub latency_ms: i64,
+    pub error: Option<String>,
+}
+
+// Provider connectivity test result
+#[derive(Debug, Serialize)]
+pub struct ProviderTestResult {
+    pub provider_id: i64,
+    pub provider_name: String,
+    pub cli_type: String,
+    // Got an HTTP response at all
+    pub reachable: bool,
+    // Got a 2xx response (base URL and credentials accepted)
+    pub success: bool,
+    pub status_code: Option<u16>,
+    pub latency_ms: i64,
+    pub error: Option<String>,
+    // Blacklist was cleared because the provider passed
+    pub unblacklisted: bool,
+}
+
 // ==================== Settings 相关实体 ====================
 
 // Gateway Settings (完整版 - 对应数据库表)
@@ -120,6 +288,26 @@ impl From<Provider> for ProviderResponse {
 pub struct GatewaySettingsRow {
     pub id: i64,
     pub debug_log: i64,
+    pub max_timeout_override_ms: i64,
+    pub sse_keepalive_ms: i64,
+    pub body_capture_policy: String,
+    pub routing_strategy: String,
+    pub shadow_provider_id: Option<i64>,
+    pub shadow_sample_rate: f64,
+    pub log_sample_rate: f64,
+    pub log_body_format: String,
+    pub log_body_max_depth: i64,
+    pub log_body_max_array_items: i64,
+    pub log_body_max_string_chars: i64,
+    pub validate_on_enable: i64,
+    pub max_concurrency: i64,
+    pub shutdown_grace_secs: i64,
+    pub allow_force_provider: i64,
+    pub admin_token: Option<String>,
+    pub upstream_compression: String,
+    pub retry_after_max_secs: i64,
+    pub key_expiry_warn_days: i64,
+    pub auto_pause_expired_keys: i64,
     pub updated_at: i64,
 }
 
@@ -127,6 +315,52 @@ pub struct GatewaySettingsRow {
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct GatewaySettings {
     pub debug_log: i64,
+    pub max_timeout_override_ms: i64,
+    pub sse_keepalive_ms: i64,
+    pub body_capture_policy: String,
+    pub routing_strategy: String,
+    pub shadow_provider_id: Option<i64>, // 影子服务商：复制请求用于对比，不影响客户端响应
+    pub shadow_sample_rate: f64,         // 影子流量采样率 0.0 ~ 1.0
+    pub log_sample_rate: f64,            // 成功请求写入 request_logs 的采样率 0.0 ~ 1.0（错误总是记录）
+    pub log_body_format: String,         // 日志 JSON body 存储格式：raw / compact / pretty
+    pub log_body_max_depth: i64,         // 超过该嵌套深度的对象/数组折叠为标记，0 表示不限制
+    pub log_body_max_array_items: i64,   // 数组最多保留的元素数，0 表示不限制
+    pub log_body_max_string_chars: i64,  // 字符串最多保留的字符数，0 表示不限制
+    pub validate_on_enable: i64,         // 启用服务商时检测连通性，失败仅提示不阻止保存
+    pub max_concurrency: i64,            // 全局同时转发的请求上限，0 表示不限制
+    pub shutdown_grace_secs: i64,        // 退出时等待进行中请求完成的最长秒数
+    pub allow_force_provider: i64,       // 允许 x-ccg-force 绕过暂停/拉黑强制使用指定服务商
+    pub admin_api_enabled: i64,          // 是否已设置 /admin 管理接口令牌（不返回令牌本身），未设置时管理接口关闭
+    pub upstream_compression: String,    // 上游压缩响应处理方式：passthrough / identity
+    pub retry_after_max_secs: i64,       // 429 按 Retry-After 拉黑的最长秒数，0 表示忽略 Retry-After
+    pub key_expiry_warn_days: i64,       // API Key 过期前多少天开始每日提醒，0 表示只在过期后提醒
+    pub auto_pause_expired_keys: i64,    // Key 过期后自动暂停该服务商
+}
+
+// Gateway Settings Update (仅更新提供的字段)
+#[derive(Debug, Default, Deserialize)]
+pub struct GatewaySettingsUpdate {
+    pub debug_log: Option<bool>,
+    pub max_timeout_override_ms: Option<i64>,
+    pub sse_keepalive_ms: Option<i64>,
+    pub body_capture_policy: Option<String>,
+    pub routing_strategy: Option<String>,
+    pub shadow_provider_id: Option<i64>, // 0 表示清除影子服务商
+    pub shadow_sample_rate: Option<f64>,
+    pub log_sample_rate: Option<f64>,
+    pub log_body_format: Option<String>,
+    pub log_body_max_depth: Option<i64>,
+    pub log_body_max_array_items: Option<i64>,
+    pub log_body_max_string_chars: Option<i64>,
+    pub validate_on_enable: Option<bool>,
+    pub max_concurrency: Option<i64>,
+    pub shutdown_grace_secs: Option<i64>,
+    pub allow_force_provider: Option<bool>,
+    pub admin_token: Option<String>,     // 空字符串表示关闭管理接口
+    pub upstream_compression: Option<String>,
+    pub retry_after_max_secs: Option<i64>,
+    pub key_expiry_warn_days: Option<i64>,
+    pub auto_pause_expired_keys: Option<bool>,
 }
 
 // Timeout Settings (完整版 - 对应数据库表)
@@ -175,6 +409,70 @@ pub struct CliSettingsUpdate {
     pub default_json_config: Option<String>,
 }
 
+// 配置格式错误（带出错位置，供编辑器定位；行列从 1 开始）
+#[derive(Debug, Serialize)]
+pub struct ConfigValidationError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl From<String> for ConfigValidationError {
+    fn from(message: String) -> Self {
+        Self { message, line: None, column: None }
+    }
+}
+
+// CLI 启用状态变更事件 (cli-enabled-changed)
+#[derive(Debug, Clone, Serialize)]
+pub struct CliEnabledChanged {
+    pub cli_type: String,
+    pub enabled: bool,
+    pub files: Vec<String>,
+}
+
+// Skill 仓库 ZIP 下载进度事件 (skill-download://progress)
+#[derive(Debug, Clone, Serialize)]
+pub struct SkillDownloadProgress {
+    pub owner: String,
+    pub name: String,
+    pub bytes: u64,         // 已下载字节数（含续传前已有部分）
+    pub total: Option<u64>, // 来自 Content-Length，服务端未提供时为 None
+    pub resumed_from: u64,  // 续传起点，0 表示从头下载
+    pub done: bool,
+}
+
+// Skill 安装进度事件 (skill-install://progress)
+#[derive(Debug, Clone, Serialize)]
+pub struct SkillInstallProgress {
+    pub key: String,        // skill key
+    pub phase: String,      // download / extract / done / cancelled
+    pub current: u64,       // extract 阶段为已解压的条目数
+    pub total: Option<u64>, // extract 阶段为待解压的条目总数
+}
+
+// 数据库备份导入/导出进度事件 (backup://progress)
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupProgress {
+    pub operation: String,  // read（读取本地数据库）/ write（写入数据库）/ upload / download（WebDAV）
+    pub file: String,       // 本地数据库文件名或 WebDAV 备份文件名
+    pub bytes: u64,         // 已处理字节数
+    pub total: Option<u64>, // 总字节数，下载时服务端未提供 Content-Length 为 None
+    pub done: bool,
+}
+
+// CLI 配置文件状态
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigFileInfo {
+    pub name: String,
+    pub path: String,
+    pub usages: Vec<String>,     // 用途: gateway(启用/禁用时写入) / mcp / prompt
+    pub exists: bool,
+    pub has_backup: bool,        // 是否存在 .ccg-backup 备份
+    pub size: Option<u64>,
+    pub modified_at: Option<i64>,
+}
+
 // WebDAV Settings
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct WebdavSettingsRow {
@@ -193,6 +491,7 @@ pub struct WebdavSettings {
     pub url: String,
     pub username: String,
     pub password: String,
+    pub remote_folder: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -200,13 +499,89 @@ pub struct WebdavSettingsUpdate {
     pub url: Option<String>,
     pub username: Option<String>,
     pub password: Option<String>,
+    pub remote_folder: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct WebdavBackup {
     pub filename: String,
     pub size: i64,
     pub modified: String,
+    /// 是否为增量备份（JSON），否则为全量数据库文件
+    pub incremental: bool,
+}
+
+// Webhook Settings (失败通知)
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct WebhookSettings {
+    pub url: String,
+    pub template: Option<String>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookSettingsUpdate {
+    pub url: Option<String>,
+    pub template: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+// Log Settings (保留天数，0 表示永久保留)
+#[derive(Debug, Clone, Default, Serialize, FromRow)]
+pub struct LogSettings {
+    pub request_log_retention_days: i64,
+    pub system_log_retention_days: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogSettingsUpdate {
+    pub request_log_retention_days: Option<i64>,
+    pub system_log_retention_days: Option<i64>,
+}
+
+// GitHub Settings (Token 不回传，只返回是否已配置)
+#[derive(Debug, Serialize)]
+pub struct GithubSettings {
+    pub has_token: bool,
+}
+
+// 更新检查连通性诊断：对 check_for_updates 所用的 release 接口单次请求（不重试）的结果
+#[derive(Debug, Serialize)]
+pub struct DiagnosisReport {
+    pub url: String,
+    pub token_configured: bool,
+    // 请求未拿到 HTTP 响应（DNS、TLS、代理、超时）时为 false
+    pub reachable: bool,
+    pub status: Option<u16>,
+    pub elapsed_ms: i64,
+    pub rate_limit: Option<i64>,
+    pub rate_limit_remaining: Option<i64>,
+    // 限流窗口重置时间（unix 秒）
+    pub rate_limit_reset: Option<i64>,
+    pub rate_limited: bool,
+    pub current_version: String,
+    pub latest_tag: Option<String>,
+    pub update_available: Option<bool>,
+    pub error: Option<String>,
+}
+
+// Provider User-Agent override (用于汇总设置)
+#[derive(Debug, Serialize, FromRow)]
+pub struct ProviderUseragent {
+    pub provider_id: i64,
+    pub provider_name: String,
+    pub cli_type: String,
+    pub custom_useragent: String,
+}
+
+// All Settings (汇总 - 用于设置页初始化)
+#[derive(Debug, Serialize)]
+pub struct AllSettings {
+    pub gateway: GatewaySettings,
+    pub timeouts: TimeoutSettings,
+    pub webdav: WebdavSettings,
+    pub useragent_overrides: Vec<ProviderUseragent>,
+    pub cli_settings: Vec<CliSettingsResponse>,
 }
 
 // ==================== MCP 相关实体 ====================
@@ -250,6 +625,21 @@ pub struct McpUpdate {
     pub cli_flags: Option<Vec<McpCliFlag>>,
 }
 
+// 名称冲突的 MCP（同名或仅大小写/首尾空白不同）
+#[derive(Debug, Serialize)]
+pub struct McpConflict {
+    // 归一化后的名称
+    pub name: String,
+    pub mcps: Vec<McpConflictItem>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct McpConflictItem {
+    pub id: i64,
+    pub name: String,
+    pub updated_at: i64,
+}
+
 // ==================== Prompt 相关实体 ====================
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -257,6 +647,8 @@ pub struct PromptPreset {
     pub id: i64,
     pub name: String,
     pub content: String,
+    // 项目路径：为空时写入全局提示词文件，否则写入该项目的本地文件（目前仅 Claude Code）
+    pub project_path: Option<String>,
     pub updated_at: i64,
 }
 
@@ -271,6 +663,7 @@ pub struct PromptResponse {
     pub id: i64,
     pub name: String,
     pub content: String,
+    pub project_path: Option<String>,
     pub cli_flags: Vec<PromptCliFlag>,
 }
 
@@ -278,6 +671,7 @@ pub struct PromptResponse {
 pub struct PromptCreate {
     pub name: String,
     pub content: String,
+    pub project_path: Option<String>,
     pub enabled: Option<bool>,
     pub cli_flags: Option<Vec<PromptCliFlag>>,
 }
@@ -286,6 +680,8 @@ pub struct PromptCreate {
 pub struct PromptUpdate {
     pub name: Option<String>,
     pub content: Option<String>,
+    // 传空字符串表示改回全局
+    pub project_path: Option<String>,
     pub enabled: Option<bool>,
     pub cli_flags: Option<Vec<PromptCliFlag>>,
 }
@@ -298,6 +694,8 @@ pub struct SkillRepo {
     pub owner: String,
     pub name: String,
     pub branch: String,
+    pub skill_count: Option<i64>,       // 最近一次扫描到的 skill 数量，未扫描过为 null
+    pub last_refreshed_ts: Option<i64>, // 最近一次扫描时间
 }
 
 #[derive(Debug, Deserialize)]
@@ -331,6 +729,62 @@ pub struct DiscoverableSkill {
     pub repo_owner: String,
     pub repo_name: String,
     pub repo_branch: String,
+    // 以下来自 SKILL.md frontmatter，缺失时为空
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// 离线 Skill 目录条目（skill_catalog 表），tags 以 JSON 数组存储
+#[derive(Debug, Clone, FromRow)]
+pub struct SkillCatalogEntry {
+    pub key: String,
+    pub name: String,
+    pub description: String,
+    pub directory: String,
+    pub readme_url: Option<String>,
+    pub repo_owner: String,
+    pub repo_name: String,
+    pub repo_branch: String,
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub tags: Option<String>,
+    pub imported_at: i64,
+}
+
+impl From<SkillCatalogEntry> for DiscoverableSkill {
+    fn from(e: SkillCatalogEntry) -> Self {
+        Self {
+            key: e.key,
+            name: e.name,
+            description: e.description,
+            directory: e.directory,
+            readme_url: e.readme_url,
+            repo_owner: e.repo_owner,
+            repo_name: e.repo_name,
+            repo_branch: e.repo_branch,
+            version: e.version,
+            author: e.author,
+            license: e.license,
+            tags: e.tags.and_then(|t| serde_json::from_str(&t).ok()).unwrap_or_default(),
+        }
+    }
+}
+
+// 仓库 skills 批量发现结果
+#[derive(Debug, Serialize)]
+pub struct RepoSkillsResult {
+    pub owner: String,
+    pub name: String,
+    pub branch: String,
+    pub skills: Vec<DiscoverableSkill>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -353,10 +807,206 @@ pub struct InstalledSkillResponse {
     pub installed_at: i64,
     pub cli_flags: Vec<SkillCliFlag>,
     pub exists_on_disk: bool, // skill 文件是否存在于本地
+    // 以下读取自本地 SKILL.md frontmatter
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub tags: Vec<String>,
+}
+
+// 有记录但 SSOT 目录不存在的已安装 Skill
+#[derive(Debug, Serialize)]
+pub struct OrphanedSkillRow {
+    pub id: i64,
+    pub name: String,
+    pub directory: String,
+}
+
+// SSOT 中没有对应记录的 Skill 目录
+#[derive(Debug, Serialize)]
+pub struct UnregisteredSkillDir {
+    pub directory: String,
+    pub has_skill_md: bool,      // 没有 SKILL.md 的目录不能重新登记
+    pub name: Option<String>,    // 读取自 SKILL.md frontmatter
+    pub description: Option<String>,
+}
+
+// Skill 记录与 SSOT 目录的一致性检查结果
+#[derive(Debug, Serialize)]
+pub struct SkillReconcileReport {
+    pub orphaned_rows: Vec<OrphanedSkillRow>,
+    pub orphaned_directories: Vec<UnregisteredSkillDir>,
+}
+
+// 修复操作：删除目录已不存在的记录，或为 SSOT 中的目录重新登记记录
+#[derive(Debug, Default, Deserialize)]
+pub struct SkillRepairActions {
+    #[serde(default)]
+    pub remove_rows: Vec<i64>,
+    #[serde(default)]
+    pub register_directories: Vec<String>,
+}
+
+// 修复结果
+#[derive(Debug, Serialize)]
+pub struct SkillRepairResult {
+    pub removed: usize,
+    pub registered: usize,
 }
 
 // ==================== Request Logs 相关实体 ====================
 
+/// Which requests get their bodies persisted in request_logs (gateway_settings.body_capture_policy)
+///
+/// Bodies that are not captured are dropped before anything is written, so any later
+/// redaction only ever sees bodies that this policy decided to keep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BodyCapturePolicy {
+    None,
+    ErrorsOnly,
+    #[default]
+    All,
+}
+
+impl BodyCapturePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BodyCapturePolicy::None => "none",
+            BodyCapturePolicy::ErrorsOnly => "errors_only",
+            BodyCapturePolicy::All => "all",
+        }
+    }
+
+    /// Whether bodies should be stored for a request with the given outcome
+    pub fn should_capture(&self, is_error: bool) -> bool {
+        match self {
+            BodyCapturePolicy::None => false,
+            BodyCapturePolicy::ErrorsOnly => is_error,
+            BodyCapturePolicy::All => true,
+        }
+    }
+}
+
+impl std::str::FromStr for BodyCapturePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(BodyCapturePolicy::None),
+            "errors_only" => Ok(BodyCapturePolicy::ErrorsOnly),
+            "all" => Ok(BodyCapturePolicy::All),
+            _ => Err(format!("Invalid body capture policy: {} (expected none, errors_only or all)", s)),
+        }
+    }
+}
+
+/// How compressed upstream responses are handled (gateway_settings.upstream_compression)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpstreamCompression {
+    /// Forward the client's Accept-Encoding. gzip/deflate bodies are decoded for the request
+    /// log and usage parsing, while the client still receives the original bytes.
+    #[default]
+    Passthrough,
+    /// Send `Accept-Encoding: identity` upstream so bodies arrive uncompressed, for encodings
+    /// the gateway cannot decode (e.g. br). Provider header overrides still take precedence.
+    Identity,
+}
+
+impl UpstreamCompression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpstreamCompression::Passthrough => "passthrough",
+            UpstreamCompression::Identity => "identity",
+        }
+    }
+}
+
+impl std::str::FromStr for UpstreamCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "passthrough" => Ok(UpstreamCompression::Passthrough),
+            "identity" => Ok(UpstreamCompression::Identity),
+            _ => Err(format!("Invalid upstream compression: {} (expected passthrough or identity)", s)),
+        }
+    }
+}
+
+/// How JSON bodies are written to request_logs (gateway_settings.log_body_format)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogBodyFormat {
+    /// Stored exactly as sent/received
+    #[default]
+    Raw,
+    Compact,
+    Pretty,
+}
+
+impl LogBodyFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogBodyFormat::Raw => "raw",
+            LogBodyFormat::Compact => "compact",
+            LogBodyFormat::Pretty => "pretty",
+        }
+    }
+}
+
+impl std::str::FromStr for LogBodyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(LogBodyFormat::Raw),
+            "compact" => Ok(LogBodyFormat::Compact),
+            "pretty" => Ok(LogBodyFormat::Pretty),
+            _ => Err(format!("Invalid log body format: {} (expected raw, compact or pretty)", s)),
+        }
+    }
+}
+
+/// Readability settings for JSON bodies stored in request_logs; a limit of 0 is off.
+/// Applied after the capture policy and before the size cap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogBodyShape {
+    pub format: LogBodyFormat,
+    pub max_depth: usize,
+    pub max_array_items: usize,
+    pub max_string_chars: usize,
+}
+
+impl LogBodyShape {
+    pub fn from_settings(format: &str, max_depth: i64, max_array_items: i64, max_string_chars: i64) -> Self {
+        Self {
+            format: format.parse().unwrap_or_default(),
+            max_depth: max_depth.max(0) as usize,
+            max_array_items: max_array_items.max(0) as usize,
+            max_string_chars: max_string_chars.max(0) as usize,
+        }
+    }
+
+    /// Whether bodies are stored untouched
+    pub fn is_raw(&self) -> bool {
+        self.format == LogBodyFormat::Raw
+            && self.max_depth == 0
+            && self.max_array_items == 0
+            && self.max_string_chars == 0
+    }
+}
+
+/// error_kind 取值：客户端在流式响应结束前断开连接
+pub const ERROR_KIND_CLIENT_CANCELLED: &str = "client_cancelled";
+/// error_kind 取值：请求在上游响应前被 cancel_request 取消
+pub const ERROR_KIND_REQUEST_CANCELLED: &str = "request_cancelled";
+/// error_kind 取值：没有可用的服务商（全部禁用或被拉黑）
+pub const ERROR_KIND_NO_PROVIDER_AVAILABLE: &str = "no_provider_available";
+
+/// error_kind 取值：等待并发名额超时
+pub const ERROR_KIND_CONCURRENCY_LIMITED: &str = "concurrency_limited";
+/// error_kind 取值：上游返回 429 并给出 Retry-After，服务商被拉黑到该时间
+pub const ERROR_KIND_RATE_LIMITED: &str = "rate_limited";
+
 /// Request log detail info (用于写入日志)
 #[derive(Default)]
 pub struct RequestLogInfo {
@@ -368,6 +1018,17 @@ pub struct RequestLogInfo {
     pub provider_headers: Option<String>,
     pub provider_body: Option<String>,
     pub error_message: Option<String>,
+    pub effective_timeout_ms: Option<i64>,
+    pub error_kind: Option<String>,
+    pub is_shadow: bool,
+    pub body_capture: BodyCapturePolicy,
+    // 成功请求的日志采样率，None 表示全部记录
+    pub log_sample_rate: Option<f64>,
+    pub body_shape: LogBodyShape,
+    // 选择服务商的原因（如 weighted_draw、adaptive_explore）
+    pub selection_reason: Option<String>,
+    // 发起请求的客户端地址（连接的远端 IP）
+    pub client_ip: Option<String>,
 }
 
 // Request Log Item (列表视图)
@@ -384,6 +1045,8 @@ pub struct RequestLogItem {
     pub output_tokens: i64,
     pub client_method: String,
     pub client_path: String,
+    pub is_shadow: bool,
+    pub client_ip: Option<String>,
 }
 
 // Request Log Detail (详情视图)
@@ -408,6 +1071,11 @@ pub struct RequestLogDetail {
     pub provider_headers: Option<String>,
     pub provider_body: Option<String>,
     pub error_message: Option<String>,
+    pub effective_timeout_ms: Option<i64>,
+    pub error_kind: Option<String>,
+    pub is_shadow: bool,
+    pub selection_reason: Option<String>,
+    pub client_ip: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -418,6 +1086,22 @@ pub struct PaginatedLogs {
     pub page_size: i64,
 }
 
+// Provider 维度的请求日志汇总
+#[derive(Debug, Serialize)]
+pub struct ProviderLogSummary {
+    pub window_hours: i64,
+    pub request_count: i64,
+    pub success_count: i64,
+    pub success_rate: f64,
+    pub avg_elapsed_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderRequestLogs {
+    pub logs: PaginatedLogs,
+    pub summary: ProviderLogSummary,
+}
+
 // ==================== System Logs 相关实体 ====================
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -435,6 +1119,7 @@ pub struct SystemLogItem {
     pub created_at: i64,
     pub event_type: String,
     pub message: String,
+    pub source: Option<String>, // 记录该日志的源码位置 (file:line)
 }
 
 #[derive(Debug, Serialize)]
@@ -445,6 +1130,15 @@ pub struct SystemLogListResponse {
     pub page_size: i64,
 }
 
+// App Log Entry (内存中的 tracing 日志)
+#[derive(Debug, Clone, Serialize)]
+pub struct AppLogEntry {
+    pub timestamp: i64, // 毫秒
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
 // ==================== Usage Stats 相关实体 ====================
 
 // Daily Usage Stats (对应 usage_daily 表)
@@ -487,6 +1181,15 @@ pub struct ProviderStatsResponse {
     pub success_rate: f64,
 }
 
+// Provider 延迟时间序列的一个时间桶（基于 request_logs 中成功的非影子请求）
+#[derive(Debug, Serialize)]
+pub struct LatencyBucket {
+    pub bucket_start: i64, // 桶起始时间（unix 秒，UTC 对齐）
+    pub request_count: i64,
+    pub avg_ms: f64,
+    pub p95_ms: i64,
+}
+
 // ==================== Session 相关实体 (非数据库) ====================
 
 // Project Info (从文件系统读取)
@@ -509,6 +1212,8 @@ pub struct SessionInfo {
     pub first_message: String,
     pub git_branch: String,
     pub summary: String,
+    /// 文件存在但没有任何一行能解析（可能写入中断或已损坏）
+    pub parse_failed: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -535,6 +1240,14 @@ pub struct SessionMessage {
     pub timestamp: Option<i64>,
 }
 
+// Session Messages (会话消息及解析告警)
+#[derive(Debug, Serialize)]
+pub struct SessionMessages {
+    pub messages: Vec<SessionMessage>,
+    /// 解析失败而被跳过的行数
+    pub parse_warnings: i64,
+}
+
 // ==================== System Status (非数据库) ====================
 
 #[derive(Debug, Serialize)]
@@ -544,3 +1257,159 @@ pub struct SystemStatus {
     pub uptime: i64,
     pub version: String,
 }
+
+// In-flight proxied request (进行中的请求，非数据库)
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveRequestInfo {
+    pub request_id: String,
+    pub cli_type: String,
+    pub provider_name: String,
+    pub model_id: Option<String>,
+    pub client_method: String,
+    pub client_path: String,
+    pub streaming: bool,
+    // 开始时间（unix 秒）
+    pub started_at: i64,
+    pub elapsed_ms: i64,
+    // 已请求取消，等待上游中止
+    pub cancelling: bool,
+}
+
+// 每种 CLI 的服务商健康概况（按当前时间判断拉黑）
+#[derive(Debug, Serialize)]
+pub struct CliHealth {
+    pub cli_type: String,
+    pub total_providers: i64,
+    pub enabled_providers: i64,
+    pub paused_providers: i64,
+    pub blacklisted_providers: i64,
+    // 当前可被路由选中的服务商数（启用、未暂停、未拉黑）
+    pub available_providers: i64,
+    pub serveable: bool,
+}
+
+// 路由 dry-run：当前状态下请求会被路由到哪个服务商、映射成哪个模型
+#[derive(Debug, Serialize)]
+pub struct RoutingExplanation {
+    pub cli_type: String,
+    pub method: String,
+    pub routing_strategy: String,
+    pub pinned: bool, // 由 x-ccg-provider 指定了服务商，不参与负载均衡
+    pub source_model: Option<String>,
+    // 最可能被选中的服务商；ordered 策略下是确定的，weighted/adaptive 为随机选择
+    pub selected_provider_id: Option<i64>,
+    pub selected_provider_name: Option<String>,
+    pub target_model: Option<String>,
+    pub matched_model_map: Option<String>, // 命中的映射规则（源模型模式）
+    pub upstream_url: Option<String>,
+    pub candidates: Vec<RoutingCandidate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoutingCandidate {
+    pub provider_id: i64,
+    pub provider_name: String,
+    pub sort_order: i64,
+    pub priority: i64,
+    pub eligible: bool,
+    pub skip_reason: Option<String>, // cli_type_not_served / disabled / paused / blacklisted / concurrency_saturated
+    pub blacklist_remaining_secs: Option<i64>,
+    pub score: Option<f64>,
+    pub selection_probability: f64,
+    pub target_model: Option<String>,
+    pub matched_model_map: Option<String>,
+}
+
+// Dashboard summary (首页概览，一次返回)
+#[derive(Debug, Serialize)]
+pub struct DashboardSummary {
+    pub requests_today: i64,
+    pub success_rate_today: f64,
+    pub tokens_today: i64,
+    // 已启用的服务商数，暂停的也算（与 CliHealth.enabled_providers 口径一致）
+    pub active_providers: i64,
+    pub blacklisted_providers: i64,
+    pub top_providers: Vec<DashboardTopProvider>,
+    pub version: String,
+    pub uptime: i64,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DashboardTopProvider {
+    pub provider_name: String,
+    pub requests: i64,
+}
+
+// 当前并发情况（内存计数）
+#[derive(Debug, Serialize)]
+pub struct ConcurrencyStatus {
+    pub in_flight: i64,
+    pub max_concurrency: i64, // 全局上限，0 表示不限制
+    pub providers: Vec<ProviderConcurrency>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderConcurrency {
+    pub provider_id: i64,
+    pub provider_name: String,
+    pub cli_type: String,
+    pub in_flight: i64,
+    pub max_concurrency: i64,
+}
+
+// 数据库结构检查结果（对照代码中的期望结构）
+#[derive(Debug, Serialize)]
+pub struct SchemaReport {
+    // main / log
+    pub database: String,
+    pub version: i64,
+    pub expected_version: i64,
+    pub ok: bool,
+    pub missing_tables: Vec<String>,
+    pub unexpected_tables: Vec<String>, // 迁移时会被删除
+    pub missing_columns: Vec<String>,    // table.column
+    pub unexpected_columns: Vec<String>, // table.column
+    pub changed_tables: Vec<String>,     // 结构不一致，迁移时会重建
+}
+
+// 数据目录磁盘占用（按类别）
+#[derive(Debug, Serialize)]
+pub struct StorageUsage {
+    pub data_dir: String,
+    pub categories: Vec<StorageCategory>,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageCategory {
+    // main_db / log_db / skills / skill_repo_cache / logs
+    pub name: String,
+    pub path: String,
+    pub bytes: u64,
+    // 数据库：文件数（含 -wal/-shm）；目录：顶层条目数（已安装 skill、缓存 ZIP、日志文件）
+    pub items: u64,
+}
+
+// 数据库压缩结果（VACUUM 前后文件大小，含 -wal 文件）
+#[derive(Debug, Serialize)]
+pub struct DatabaseCompactResult {
+    // main / log
+    pub database: String,
+    pub path: String,
+    pub size_before: u64,
+    pub size_after: u64,
+    pub reclaimed: u64,
+}
+
+// Effective runtime config (实际生效的配置，用于诊断)
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    pub host: String,
+    pub port: u16,
+    pub data_dir: String,
+    pub log_dir: String,
+    pub db_path: String,
+    pub log_db_path: String,
+    pub file_logging: bool,
+    pub log_level: Option<String>,
+}
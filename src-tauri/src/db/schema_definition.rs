@@ -73,7 +73,7 @@ impl DatabaseSchema {
     /// 获取当前主数据库 Schema
     pub fn current() -> Self {
         Self {
-            version: 7,
+            version: 42,
             tables: Self::define_main_tables(),
         }
     }
@@ -81,7 +81,7 @@ impl DatabaseSchema {
     /// 获取日志数据库 Schema
     pub fn log_schema() -> Self {
         Self {
-            version: 5,
+            version: 13,
             tables: Self::define_log_tables(),
         }
     }
@@ -179,6 +179,102 @@ impl DatabaseSchema {
                         nullable: true,
                         default_value: None,
                     },
+                    ColumnDefinition {
+                        name: "auth_header_name".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "auth_scheme".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "paused".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "upstream_proxy".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "strip_headers".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "header_overrides".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "inject_system_prompt".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "force_non_stream".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "max_concurrency".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "notes".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "accept_invalid_certs".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "custom_ca_pem".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "priority".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "request_transform".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "response_transform".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "key_expires_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
                     ColumnDefinition {
                         name: "updated_at".to_string(),
                         data_type: "INTEGER".to_string(),
@@ -227,15 +323,58 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: Some("1".to_string()),
                     },
+                    ColumnDefinition {
+                        name: "cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "large_input_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "large_target_model".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![vec![
                     "provider_id".to_string(),
                     "source_model".to_string(),
+                    "cli_type".to_string(),
                 ]],
             },
         );
 
+        // provider_cli_types 表（服务商支持的 CLI 类型集合，含 providers.cli_type 主类型）
+        tables.insert(
+            "provider_cli_types".to_string(),
+            TableDefinition {
+                name: "provider_cli_types".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "provider_id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["provider_id".to_string(), "cli_type".to_string()],
+                unique_constraints: vec![],
+            },
+        );
+
         // gateway_settings 表
         tables.insert(
             "gateway_settings".to_string(),
@@ -254,6 +393,126 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: Some("0".to_string()),
                     },
+                    ColumnDefinition {
+                        name: "max_timeout_override_ms".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("600000".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "sse_keepalive_ms".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "body_capture_policy".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'all'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "routing_strategy".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'ordered'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "shadow_provider_id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "shadow_sample_rate".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_sample_rate".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_body_format".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'raw'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_body_max_depth".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_body_max_array_items".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_body_max_string_chars".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "validate_on_enable".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "max_concurrency".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "shutdown_grace_secs".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("10".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "allow_force_provider".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "admin_token".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "upstream_compression".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'passthrough'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "retry_after_max_secs".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("3600".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "key_expiry_warn_days".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("7".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "auto_pause_expired_keys".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
                     ColumnDefinition {
                         name: "updated_at".to_string(),
                         data_type: "INTEGER".to_string(),
@@ -398,6 +657,12 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: None,
                     },
+                    ColumnDefinition {
+                        name: "project_path".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
                     ColumnDefinition {
                         name: "updated_at".to_string(),
                         data_type: "INTEGER".to_string(),
@@ -464,6 +729,114 @@ impl DatabaseSchema {
             },
         );
 
+        // webhook_settings 表（失败通知 Webhook）
+        tables.insert(
+            "webhook_settings".to_string(),
+            TableDefinition {
+                name: "webhook_settings".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "url".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "template".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "enabled".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![],
+            },
+        );
+
+        // log_settings 表（请求日志 / 系统日志各自的保留天数，0 表示永久保留）
+        tables.insert(
+            "log_settings".to_string(),
+            TableDefinition {
+                name: "log_settings".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "request_log_retention_days".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "system_log_retention_days".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![],
+            },
+        );
+
+        // github_settings 表（可选 GitHub Token，提高 API 限额）
+        tables.insert(
+            "github_settings".to_string(),
+            TableDefinition {
+                name: "github_settings".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "token".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![],
+            },
+        );
+
         // skill_repos 表（Skill 仓库列表）
         tables.insert(
             "skill_repos".to_string(),
@@ -488,12 +861,114 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: Some("'main'".to_string()),
                     },
+                    ColumnDefinition {
+                        name: "skill_count".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "last_refreshed_ts".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
                 ],
                 primary_key: vec!["owner".to_string(), "name".to_string()],
                 unique_constraints: vec![],
             },
         );
 
+        // skill_catalog 表（离线导入的可发现 Skills，无需访问 GitHub）
+        tables.insert(
+            "skill_catalog".to_string(),
+            TableDefinition {
+                name: "skill_catalog".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "key".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "name".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "description".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("''".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "directory".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "readme_url".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "repo_owner".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "repo_name".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "repo_branch".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'main'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "version".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "author".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "license".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "tags".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "imported_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["key".to_string()],
+                unique_constraints: vec![],
+            },
+        );
+
         // skill_configs 表（已安装的 Skills）
         tables.insert(
             "skill_configs".to_string(),
@@ -687,6 +1162,42 @@ impl DatabaseSchema {
                         nullable: true,
                         default_value: None,
                     },
+                    ColumnDefinition {
+                        name: "effective_timeout_ms".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "error_kind".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "is_shadow".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "selection_reason".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "client_ip".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "sample_weight".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![],
@@ -723,6 +1234,12 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: None,
                     },
+                    ColumnDefinition {
+                        name: "source".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![],
@@ -793,6 +1310,70 @@ impl DatabaseSchema {
             },
         );
 
+        // usage_buckets 表（15 分钟粒度，按 UTC 时间戳对齐，用于按时区聚合）
+        tables.insert(
+            "usage_buckets".to_string(),
+            TableDefinition {
+                name: "usage_buckets".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "bucket_start".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "provider_name".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "request_count".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "success_count".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "failure_count".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "input_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "output_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                ],
+                primary_key: vec![
+                    "bucket_start".to_string(),
+                    "provider_name".to_string(),
+                    "cli_type".to_string(),
+                ],
+                unique_constraints: vec![],
+            },
+        );
+
         tables
     }
 }
@@ -0,0 +1,98 @@
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+/// Error returned by commands that have moved off `Result<T, String>`.
+///
+/// Serialized as `{ "code": "...", "message": "..." }` so the frontend can branch on `code`
+/// (and localize) while `message` keeps the detail. The codes are part of the frontend
+/// contract; add new variants rather than renaming existing ones.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Db(String),
+    #[error("{0}")]
+    Io(String),
+    #[error("{0}")]
+    Upstream(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "not_found",
+            Self::Validation(_) => "validation",
+            Self::Conflict(_) => "conflict",
+            Self::Db(_) => "db",
+            Self::Io(_) => "io",
+            Self::Upstream(_) => "upstream",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    pub fn not_found(what: &str) -> Self {
+        Self::NotFound(format!("{} not found", what))
+    }
+
+    /// Prefix the message (e.g. with the item a batch failed on), keeping the code
+    pub fn with_context(self, context: impl std::fmt::Display) -> Self {
+        let message = format!("{}: {}", context, self);
+        match self {
+            Self::NotFound(_) => Self::NotFound(message),
+            Self::Validation(_) => Self::Validation(message),
+            Self::Conflict(_) => Self::Conflict(message),
+            Self::Db(_) => Self::Db(message),
+            Self::Io(_) => Self::Io(message),
+            Self::Upstream(_) => Self::Upstream(message),
+            Self::Internal(_) => Self::Internal(message),
+        }
+    }
+}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AppError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound("Record not found".to_string()),
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => Self::Conflict(db_err.message().to_string()),
+            e => Self::Db(e.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        // The URL may carry an API key (query-parameter auth)
+        Self::Upstream(e.without_url().to_string())
+    }
+}
+
+/// Bridge for callers still on `Result<T, String>`
+impl From<AppError> for String {
+    fn from(e: AppError) -> Self {
+        e.to_string()
+    }
+}
@@ -2,11 +2,14 @@ pub mod api;
 pub mod commands;
 pub mod config;
 pub mod db;
+pub mod error;
 pub mod services;
 
 use config::Config;
 use db::init_db;
+use services::routing::ProviderScores;
 use sqlx::SqlitePool;
+use std::sync::Arc;
 use tauri::Manager;
 use tauri::menu::{MenuBuilder, MenuItemBuilder};
 use tauri::tray::{TrayIconBuilder, TrayIconEvent};
@@ -23,12 +26,15 @@ impl std::ops::Deref for LogDb {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    config::mark_started();
     let config = Config::load();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_opener::init())
         .setup(move |app| {
             let config = config.clone();
+            app.manage(config.clone());
 
             // Initialize database
             let db_path = config.database.path.clone();
@@ -58,10 +64,41 @@ pub fn run() {
                 app.manage(db.clone());
                 app.manage(LogDb(log_db.clone()));
 
+                // Drop logs past their retention now and periodically
+                services::log_retention::spawn(db.clone(), log_db.clone());
+
+                // Daily reminder for provider keys close to (or past) their expiry
+                services::key_expiry::spawn(db.clone(), log_db.clone());
+
+                // Rolling provider scores shared by the proxy and management commands
+                let scores = Arc::new(ProviderScores::default());
+                app.manage(scores.clone());
+
+                // In-flight proxied requests, listed and cancelled from the UI
+                let active_requests = Arc::new(services::active_requests::ActiveRequests::default());
+                app.manage(active_requests.clone());
+
+                // In-flight skill installs, cancelled from the UI by skill key
+                app.manage(Arc::new(services::skill_installs::SkillInstalls::default()));
+
+                // Global and per-provider in-flight counts for the concurrency limits
+                let concurrency = Arc::new(services::concurrency::ConcurrencyLimiter::default());
+                app.manage(concurrency.clone());
+
+                // Upstream HTTP clients, shared with commands so deleted providers' clients are dropped
+                let upstream_clients = Arc::new(services::proxy::UpstreamClients::default());
+                app.manage(upstream_clients.clone());
+
                 // Start HTTP server for proxy
                 let state = api::AppState {
                     db: db.clone(),
                     log_db: log_db.clone(),
+                    scores,
+                    upstream_clients,
+                    active_requests,
+                    concurrency,
+                    config: config.clone(),
+                    app: app.handle().clone(),
                 };
 
                 let router = api::create_router(state);
@@ -80,7 +117,12 @@ pub fn run() {
                     }
                 };
 
-                if let Err(e) = axum::serve(listener, router).await {
+                // ConnectInfo gives handlers the client's address for request logs
+                let service = router.into_make_service_with_connect_info::<std::net::SocketAddr>();
+                if let Err(e) = axum::serve(listener, service)
+                    .with_graceful_shutdown(services::shutdown::stop_signal())
+                    .await
+                {
                     tracing::error!("Gateway server error: {}", e);
                 }
             });
@@ -116,7 +158,10 @@ pub fn run() {
                         }
                     }
                     "quit" => {
-                        std::process::exit(0);
+                        let app = app.clone();
+                        tauri::async_runtime::spawn(async move {
+                            services::shutdown::exit_gracefully(&app).await;
+                        });
                     }
                     _ => {}
                 })
@@ -162,27 +207,71 @@ pub fn run() {
             commands::get_providers,
             commands::get_provider,
             commands::create_provider,
+            commands::bulk_create_providers,
             commands::update_provider,
             commands::delete_provider,
             commands::reorder_providers,
+            commands::reorder_provider_priority,
             commands::reset_provider_failures,
+            commands::recover_provider,
+            commands::pause_provider,
+            commands::resume_provider,
+            commands::reset_all_provider_failures,
+            commands::export_useragent_maps,
+            commands::import_useragent_maps,
+            commands::preview_useragent_rewrites,
+            commands::test_provider_completion,
+            commands::test_provider_connection,
+            commands::list_provider_models,
+            commands::test_all_providers,
             commands::get_gateway_settings,
             commands::update_gateway_settings,
             commands::get_timeout_settings,
             commands::update_timeout_settings,
             commands::get_cli_settings,
             commands::update_cli_settings,
+            commands::get_cli_config_files,
+            commands::get_all_settings,
+            commands::get_webhook_settings,
+            commands::update_webhook_settings,
+            commands::delete_webhook_settings,
+            commands::test_webhook,
+            commands::get_github_settings,
+            commands::update_github_token,
+            commands::get_file_logging,
+            commands::get_effective_config,
+            commands::compact_databases,
+            commands::get_storage_usage,
+            commands::verify_schema,
+            commands::migrate_schema,
+            commands::set_file_logging,
+            commands::get_crash_log,
+            commands::clear_crash_log,
             commands::get_request_logs,
+            commands::get_provider_request_logs,
             commands::get_request_log_detail,
             commands::clear_request_logs,
+            commands::delete_request_log,
+            commands::delete_request_logs,
             commands::get_system_logs,
             commands::clear_system_logs,
+            commands::export_system_logs,
+            commands::get_log_settings,
+            commands::update_log_settings,
+            commands::get_app_logs,
             commands::get_system_status,
+            commands::list_active_requests,
+            commands::get_concurrency_status,
+            commands::cancel_request,
+            commands::get_dashboard_summary,
+            commands::get_cli_health,
+            commands::explain_routing,
             commands::get_mcps,
             commands::get_mcp,
             commands::create_mcp,
             commands::update_mcp,
             commands::delete_mcp,
+            commands::find_mcp_conflicts,
             commands::get_prompts,
             commands::get_prompt,
             commands::create_prompt,
@@ -194,27 +283,44 @@ pub fn run() {
             commands::update_skill_repo,
             commands::discover_repo_skills,
             commands::refresh_repo_skills,
+            commands::import_skill_catalog,
+            commands::get_skill_catalog,
+            commands::discover_all_repo_skills,
             commands::install_skill,
+            commands::cancel_skill_install,
             commands::uninstall_skill,
+            commands::reconcile_skills,
+            commands::repair_skills,
             commands::get_installed_skills,
             commands::toggle_skill_cli,
+            commands::rename_skill,
             commands::get_daily_stats,
             commands::get_provider_stats,
+            commands::get_provider_latency_series,
             commands::get_session_projects,
             commands::get_project_sessions,
             commands::get_session_messages,
             commands::delete_session,
+            commands::export_session_raw,
+            commands::get_session_file_path,
+            commands::open_session_file,
+            commands::import_session_raw,
             commands::delete_project,
+            commands::rebuild_gemini_path_cache,
             commands::get_webdav_settings,
             commands::update_webdav_settings,
             commands::test_webdav_connection,
             commands::export_to_local,
             commands::import_from_local,
+            commands::export_config_encrypted,
+            commands::import_config_encrypted,
             commands::export_to_webdav,
             commands::list_webdav_backups,
             commands::import_from_webdav,
             commands::delete_webdav_backup,
+            commands::prune_webdav_backups,
             commands::check_for_updates,
+            commands::diagnose_update_connectivity,
         ])
         .run(tauri::generate_context!())
         .unwrap_or_else(|e| {
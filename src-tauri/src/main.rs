@@ -1,6 +1,24 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer, Registry};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Build the daily-rolling file logging layer
+fn file_log_layer() -> BoxedLayer {
+    let log_dir = ccg_gateway_lib::config::get_log_dir();
+
+    // Ensure log directory exists
+    if let Err(e) = std::fs::create_dir_all(&log_dir) {
+        eprintln!("Failed to create log directory: {}", e);
+    }
+
+    let file_appender = tracing_appender::rolling::daily(&log_dir, "ccg-gateway.log");
+    tracing_subscriber::fmt::layer()
+        .with_writer(file_appender)
+        .with_ansi(false)
+        .boxed()
+}
 
 fn main() {
     // Register panic hook early to capture crash info before anything else.
@@ -8,62 +26,62 @@ fn main() {
     // - `windows_subsystem = "windows"` suppresses stderr in release builds
     // - `panic = "abort"` in release profile terminates the process immediately
     // - `.expect()` / `panic!()` write to stderr, not through tracing
-    if ccg_gateway_lib::config::is_file_log_enabled() {
+    // The hook is always installed; writes are gated on the current file logging setting.
+    std::panic::set_hook(Box::new(move |info| {
+        if !ccg_gateway_lib::config::is_file_log_enabled() {
+            return;
+        }
         let log_dir = ccg_gateway_lib::config::get_log_dir();
         let _ = std::fs::create_dir_all(&log_dir);
-        std::panic::set_hook(Box::new(move |info| {
-            let crash_path = log_dir.join("crash.log");
-            let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
-            let message = format!(
-                "[{}] PANIC: {}\nBacktrace:\n{}",
-                timestamp,
-                info,
-                std::backtrace::Backtrace::force_capture(),
-            );
-            // Append to crash log
-            use std::io::Write;
-            if let Ok(mut f) = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&crash_path)
-            {
-                let _ = writeln!(f, "{}", message);
-            }
-        }));
-    }
+        let crash_path = log_dir.join("crash.log");
+        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+        let message = format!(
+            "[{}] PANIC: {}\nBacktrace:\n{}",
+            timestamp,
+            info,
+            std::backtrace::Backtrace::force_capture(),
+        );
+        // Append to crash log
+        use std::io::Write;
+        if let Ok(mut f) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&crash_path)
+        {
+            let _ = writeln!(f, "{}", message);
+        }
+    }));
 
     // Default to info level, can be overridden by CCG_LOG_LEVEL env var
     let filter = EnvFilter::try_from_env("CCG_LOG_LEVEL")
         .unwrap_or_else(|_| EnvFilter::new("info,ccg_gateway=debug,ccg_gateway_lib=debug"));
+    ccg_gateway_lib::config::set_log_level(filter.to_string());
 
     let fmt_layer = tracing_subscriber::fmt::layer();
 
-    // Check if file logging is enabled
-    if ccg_gateway_lib::config::is_file_log_enabled() {
-        let log_dir = ccg_gateway_lib::config::get_log_dir();
-        
-        // Ensure log directory exists
-        if let Err(e) = std::fs::create_dir_all(&log_dir) {
-            eprintln!("Failed to create log directory: {}", e);
-        }
+    // File logging layer is reloadable so it can be toggled at runtime
+    let file_enabled = ccg_gateway_lib::config::is_file_log_enabled();
+    let initial_file_layer: Option<BoxedLayer> = file_enabled.then(file_log_layer);
+    let (file_layer, file_layer_handle) = reload::Layer::new(initial_file_layer);
 
-        let file_appender = tracing_appender::rolling::daily(&log_dir, "ccg-gateway.log");
-        let file_layer = tracing_subscriber::fmt::layer()
-            .with_writer(file_appender)
-            .with_ansi(false);
+    tracing_subscriber::registry()
+        .with(file_layer)
+        .with(filter)
+        .with(fmt_layer)
+        .with(ccg_gateway_lib::services::app_log::AppLogLayer)
+        .init();
 
-        tracing_subscriber::registry()
-            .with(filter)
-            .with(fmt_layer)
-            .with(file_layer)
-            .init();
+    ccg_gateway_lib::config::set_file_log_reloader(move |enabled| {
+        file_layer_handle
+            .reload(enabled.then(file_log_layer))
+            .map_err(|e| e.to_string())
+    });
 
-        eprintln!("File logging enabled, log directory: {}", log_dir.display());
-    } else {
-        tracing_subscriber::registry()
-            .with(filter)
-            .with(fmt_layer)
-            .init();
+    if file_enabled {
+        eprintln!(
+            "File logging enabled, log directory: {}",
+            ccg_gateway_lib::config::get_log_dir().display()
+        );
     }
 
     ccg_gateway_lib::run();
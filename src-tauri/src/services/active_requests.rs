@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tokio::sync::watch;
+
+use crate::db::models::ActiveRequestInfo;
+
+/// Header a client can set to choose the request id; one is generated otherwise
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Response header carrying the request id used by the gateway
+pub const RESPONSE_REQUEST_ID_HEADER: &str = "x-ccg-request-id";
+
+struct ActiveRequest {
+    info: ActiveRequestInfo,
+    started: Instant,
+    cancel: watch::Sender<bool>,
+}
+
+/// Proxied requests currently in flight, keyed by request id.
+/// Entries are removed when the request's guard is dropped.
+#[derive(Default)]
+pub struct ActiveRequests {
+    requests: RwLock<HashMap<String, ActiveRequest>>,
+}
+
+impl ActiveRequests {
+    /// Track a request until the returned guard is dropped.
+    /// A duplicate id gets a suffix so an in-flight entry is never replaced.
+    pub fn register(self: &Arc<Self>, mut info: ActiveRequestInfo) -> ActiveRequestGuard {
+        let (cancel, cancelled) = watch::channel(false);
+        let mut requests = self.requests.write().unwrap_or_else(|e| e.into_inner());
+        if requests.contains_key(&info.request_id) {
+            info.request_id = format!("{}-{}", info.request_id, uuid::Uuid::new_v4().simple());
+        }
+        let request_id = info.request_id.clone();
+        requests.insert(
+            request_id.clone(),
+            ActiveRequest { info, started: Instant::now(), cancel },
+        );
+        ActiveRequestGuard {
+            registry: self.clone(),
+            request_id,
+            cancelled,
+        }
+    }
+
+    /// Signal the request to abort its upstream call; false if no such request is in flight
+    pub fn cancel(&self, request_id: &str) -> bool {
+        let requests = self.requests.read().unwrap_or_else(|e| e.into_inner());
+        match requests.get(request_id) {
+            Some(request) => {
+                request.cancel.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Snapshot of in-flight requests, oldest first
+    pub fn list(&self) -> Vec<ActiveRequestInfo> {
+        let requests = self.requests.read().unwrap_or_else(|e| e.into_inner());
+        let mut list: Vec<ActiveRequestInfo> = requests
+            .values()
+            .map(|r| ActiveRequestInfo {
+                elapsed_ms: r.started.elapsed().as_millis() as i64,
+                cancelling: *r.cancel.borrow(),
+                ..r.info.clone()
+            })
+            .collect();
+        list.sort_by_key(|r| std::cmp::Reverse(r.elapsed_ms));
+        list
+    }
+
+    fn remove(&self, request_id: &str) {
+        self.requests.write().unwrap_or_else(|e| e.into_inner()).remove(request_id);
+    }
+}
+
+/// Keeps a request listed as active and delivers its cancel signal
+pub struct ActiveRequestGuard {
+    registry: Arc<ActiveRequests>,
+    request_id: String,
+    cancelled: watch::Receiver<bool>,
+}
+
+impl ActiveRequestGuard {
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// The cancel flag itself, for code that outlives this guard (e.g. a stream's log task)
+    pub fn cancel_signal(&self) -> watch::Receiver<bool> {
+        self.cancelled.clone()
+    }
+
+    /// Resolves once `cancel_request` is called for this request
+    pub async fn cancelled(&self) {
+        let mut rx = self.cancelled.clone();
+        // The sender lives in the registry until this guard drops, so this only errors after removal
+        if rx.wait_for(|c| *c).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        self.registry.remove(&self.request_id);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::db::models::AppLogEntry;
+
+/// Maximum number of log lines kept in memory
+const APP_LOG_CAPACITY: usize = 1000;
+
+static APP_LOG_BUFFER: OnceLock<Mutex<VecDeque<AppLogEntry>>> = OnceLock::new();
+
+fn buffer() -> &'static Mutex<VecDeque<AppLogEntry>> {
+    APP_LOG_BUFFER.get_or_init(|| Mutex::new(VecDeque::with_capacity(APP_LOG_CAPACITY)))
+}
+
+/// Tracing layer that keeps the most recent log lines in a bounded ring buffer
+pub struct AppLogLayer;
+
+impl<S: Subscriber> Layer<S> for AppLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let entry = AppLogEntry {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            level: event.metadata().level().to_string(),
+            target: event.metadata().target().to_string(),
+            message: visitor.finish(),
+        };
+
+        if let Ok(mut logs) = buffer().lock() {
+            if logs.len() >= APP_LOG_CAPACITY {
+                logs.pop_front();
+            }
+            logs.push_back(entry);
+        }
+    }
+}
+
+/// Collects the `message` field plus any structured fields as `key=value`
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl MessageVisitor {
+    fn finish(self) -> String {
+        if self.fields.is_empty() {
+            self.message
+        } else if self.message.is_empty() {
+            self.fields.trim_start().to_string()
+        } else {
+            format!("{}{}", self.message, self.fields)
+        }
+    }
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Return buffered log lines at or above `min_level`, newest first
+pub fn recent_logs(min_level: Option<Level>, limit: usize) -> Vec<AppLogEntry> {
+    let Ok(logs) = buffer().lock() else {
+        return vec![];
+    };
+
+    logs.iter()
+        .rev()
+        .filter(|entry| match min_level {
+            // Level ordering: ERROR is the "smallest", TRACE the largest
+            Some(min) => entry.level.parse::<Level>().map(|l| l <= min).unwrap_or(true),
+            None => true,
+        })
+        .take(limit)
+        .cloned()
+        .collect()
+}
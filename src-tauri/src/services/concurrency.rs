@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long a request waits for a free slot before the gateway gives up on it
+pub const QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Counts {
+    global: usize,
+    providers: HashMap<i64, usize>,
+}
+
+/// In-flight upstream requests, globally and per provider.
+/// Limits are passed per call (0 = unlimited) so settings changes apply immediately.
+#[derive(Default)]
+pub struct ConcurrencyLimiter {
+    counts: Mutex<Counts>,
+    released: Notify,
+}
+
+fn has_room(count: usize, limit: i64) -> bool {
+    limit <= 0 || (count as i64) < limit
+}
+
+impl ConcurrencyLimiter {
+    /// Whether a provider is at its own limit
+    pub fn is_saturated(&self, provider_id: i64, limit: i64) -> bool {
+        let counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        !has_room(counts.providers.get(&provider_id).copied().unwrap_or(0), limit)
+    }
+
+    /// Take a slot if both the global and the provider limit allow it
+    pub fn try_acquire(self: &Arc<Self>, provider_id: i64, provider_limit: i64, global_limit: i64) -> Option<ConcurrencyPermit> {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        let provider_count = counts.providers.get(&provider_id).copied().unwrap_or(0);
+        if !has_room(counts.global, global_limit) || !has_room(provider_count, provider_limit) {
+            return None;
+        }
+        counts.global += 1;
+        counts.providers.insert(provider_id, provider_count + 1);
+        Some(ConcurrencyPermit {
+            limiter: self.clone(),
+            provider_id,
+        })
+    }
+
+    /// Take a slot, queueing until one is released or `timeout` passes
+    pub async fn acquire(
+        self: &Arc<Self>,
+        provider_id: i64,
+        provider_limit: i64,
+        global_limit: i64,
+        timeout: Duration,
+    ) -> Option<ConcurrencyPermit> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Register for the wakeup before checking so a release in between is not missed
+            let notified = self.released.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(permit) = self.try_acquire(provider_id, provider_limit, global_limit) {
+                return Some(permit);
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    /// Total in-flight requests and the per-provider breakdown
+    pub fn in_flight(&self) -> (usize, HashMap<i64, usize>) {
+        let counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        (counts.global, counts.providers.clone())
+    }
+
+    fn release(&self, provider_id: i64) {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        counts.global = counts.global.saturating_sub(1);
+        if let Some(count) = counts.providers.get_mut(&provider_id) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                counts.providers.remove(&provider_id);
+            }
+        }
+        drop(counts);
+        self.released.notify_waiters();
+    }
+}
+
+/// A held concurrency slot, released on drop
+pub struct ConcurrencyPermit {
+    limiter: Arc<ConcurrencyLimiter>,
+    provider_id: i64,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.provider_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Run `tasks` concurrent requests against one limiter and return the peak in-flight count
+    async fn peak_in_flight(limiter: &Arc<ConcurrencyLimiter>, tasks: usize, provider_limit: i64, global_limit: i64) -> usize {
+        let current = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..tasks)
+            .map(|i| {
+                let (limiter, current, peak) = (limiter.clone(), current.clone(), peak.clone());
+                tokio::spawn(async move {
+                    let permit = limiter
+                        .acquire((i % 2) as i64, provider_limit, global_limit, Duration::from_secs(10))
+                        .await
+                        .expect("queued request should eventually get a slot");
+                    let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    current.fetch_sub(1, Ordering::SeqCst);
+                    drop(permit);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        peak.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn global_limit_is_never_exceeded() {
+        let limiter = Arc::new(ConcurrencyLimiter::default());
+        let peak = peak_in_flight(&limiter, 40, 0, 3).await;
+        assert!(peak <= 3, "peak {} exceeded the global limit", peak);
+        assert_eq!(limiter.in_flight().0, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn provider_limit_is_never_exceeded() {
+        let limiter = Arc::new(ConcurrencyLimiter::default());
+        // Two providers with one slot each
+        let peak = peak_in_flight(&limiter, 40, 1, 0).await;
+        assert!(peak <= 2, "peak {} exceeded the per-provider limits", peak);
+        assert!(limiter.in_flight().1.is_empty());
+    }
+
+    #[tokio::test]
+    async fn queued_request_gets_the_released_slot() {
+        let limiter = Arc::new(ConcurrencyLimiter::default());
+        let held = limiter.try_acquire(1, 1, 0).unwrap();
+        assert!(limiter.try_acquire(1, 1, 0).is_none());
+        assert!(limiter.is_saturated(1, 1));
+        // Other providers are unaffected by provider 1's limit
+        assert!(limiter.try_acquire(2, 1, 0).is_some());
+
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(1, 1, 0, Duration::from_secs(10)).await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(held);
+        assert!(waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn queued_request_times_out() {
+        let limiter = Arc::new(ConcurrencyLimiter::default());
+        let _held = limiter.try_acquire(1, 0, 1).unwrap();
+        assert!(limiter.acquire(2, 0, 1, Duration::from_millis(20)).await.is_none());
+        assert_eq!(limiter.in_flight().0, 1);
+    }
+}
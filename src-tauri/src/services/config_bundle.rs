@@ -0,0 +1,407 @@
+//! 配置包：按分区导出/导入配置表（不含日志与统计），用于在设备或同事之间迁移配置
+//!
+//! 配置包是 JSON，每个表整表保存；加密导出时整体交给 `encryption` 处理。
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::services::incremental_backup::{self, TableSnapshot};
+
+/// 配置包格式标识
+pub const BUNDLE_FORMAT: &str = "ccg_gateway_config";
+/// 配置包格式版本
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// 分区 -> 所包含的表
+const SECTIONS: [(&str, &[&str]); 5] = [
+    ("providers", &["providers", "provider_model_map", "provider_cli_types"]),
+    ("settings", &["gateway_settings", "timeout_settings", "cli_settings", "log_settings", "webhook_settings", "webdav_settings", "github_settings"]),
+    ("mcps", &["mcp_configs"]),
+    ("prompts", &["prompt_presets"]),
+    ("skills", &["skill_repos", "skill_configs"]),
+];
+
+/// 默认不随配置包导出的敏感列（表, 列）；配置包不含时导入保留本地值
+const SECRET_COLUMNS: [(&str, &str); 3] = [
+    ("gateway_settings", "admin_token"),
+    ("github_settings", "token"),
+    ("webdav_settings", "password"),
+];
+
+/// 通过 provider_id 关联服务商的子表
+const PROVIDER_CHILD_TABLES: [&str; 2] = ["provider_model_map", "provider_cli_types"];
+
+/// 配置包内容
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigBundle {
+    pub format: String,
+    pub version: u32,
+    pub app_version: String,
+    pub created_at: i64,
+    pub sections: Vec<String>,
+    pub tables: BTreeMap<String, TableSnapshot>,
+}
+
+/// 导入方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// 先清空所导入分区的表，再写入配置包内容
+    Replace,
+    /// 按主键覆盖同一行，保留本地其余数据；服务商按名称与 CLI 类型匹配
+    Merge,
+}
+
+impl std::str::FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(Self::Replace),
+            "merge" => Ok(Self::Merge),
+            other => Err(format!("Unknown import mode: {} (expected replace or merge)", other)),
+        }
+    }
+}
+
+fn section_tables(section: &str) -> Option<&'static [&'static str]> {
+    SECTIONS.iter().find(|(name, _)| *name == section).map(|(_, tables)| *tables)
+}
+
+/// 校验分区名并去重；为空时返回全部分区
+pub fn normalize_sections(sections: &[String]) -> Result<Vec<String>, String> {
+    if sections.is_empty() {
+        return Ok(SECTIONS.iter().map(|(name, _)| name.to_string()).collect());
+    }
+    let mut result = Vec::new();
+    for section in sections {
+        let section = section.trim();
+        if section_tables(section).is_none() {
+            let known: Vec<&str> = SECTIONS.iter().map(|(name, _)| *name).collect();
+            return Err(format!("Unknown config section: {} (expected one of {})", section, known.join(", ")));
+        }
+        if !result.iter().any(|s| s == section) {
+            result.push(section.to_string());
+        }
+    }
+    Ok(result)
+}
+
+fn secret_columns(table: &str) -> impl Iterator<Item = &'static str> + '_ {
+    SECRET_COLUMNS.iter().filter(move |(t, _)| *t == table).map(|(_, column)| *column)
+}
+
+// 从快照中移除敏感列
+fn strip_secrets(table: &str, snapshot: &mut TableSnapshot) {
+    for column in secret_columns(table) {
+        snapshot.columns.retain(|c| c != column);
+        if let Some(rows) = snapshot.rows.as_array_mut() {
+            for row in rows.iter_mut().filter_map(|r| r.as_object_mut()) {
+                row.remove(column);
+            }
+        }
+    }
+}
+
+// 配置包不含敏感列时，按 id 从本地同一行补回，避免整行覆盖时把本地值清空
+async fn keep_local_secrets(
+    conn: &mut sqlx::SqliteConnection,
+    table: &str,
+    snapshot: &TableSnapshot,
+) -> Result<TableSnapshot, String> {
+    let mut snapshot = snapshot.clone();
+    for column in secret_columns(table) {
+        if snapshot.columns.iter().any(|c| c == column) {
+            continue;
+        }
+        if let Some(rows) = snapshot.rows.as_array_mut() {
+            for row in rows.iter_mut().filter_map(|r| r.as_object_mut()) {
+                let Some(id) = row.get("id").and_then(|v| v.as_i64()) else {
+                    continue;
+                };
+                let local: Option<Option<String>> =
+                    sqlx::query_scalar(&format!("SELECT \"{}\" FROM \"{}\" WHERE id = ?", column, table))
+                        .bind(id)
+                        .fetch_optional(&mut *conn)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                row.insert(column.to_string(), local.flatten().into());
+            }
+        }
+        snapshot.columns.push(column.to_string());
+    }
+    Ok(snapshot)
+}
+
+// 合并导入时服务商按 (name, cli_type) 匹配本地服务商：匹配到的沿用本地 id，其余分配新 id，
+// 避免按主键覆盖本地无关的服务商；返回 配置包 id -> 本地 id
+async fn map_provider_ids(
+    conn: &mut sqlx::SqliteConnection,
+    providers: &TableSnapshot,
+) -> Result<HashMap<i64, i64>, String> {
+    let mut next_id: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) + 1 FROM providers")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut ids = HashMap::new();
+    for row in providers.rows.as_array().into_iter().flatten().filter_map(|r| r.as_object()) {
+        let Some(id) = row.get("id").and_then(|v| v.as_i64()) else {
+            continue;
+        };
+        let name = row.get("name").and_then(|v| v.as_str()).unwrap_or_default();
+        let cli_type = row.get("cli_type").and_then(|v| v.as_str()).unwrap_or("claude_code");
+        let local: Option<i64> = sqlx::query_scalar("SELECT id FROM providers WHERE name = ? AND cli_type = ?")
+            .bind(name)
+            .bind(cli_type)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+        let local = local.unwrap_or_else(|| {
+            next_id += 1;
+            next_id - 1
+        });
+        ids.insert(id, local);
+    }
+    Ok(ids)
+}
+
+// 按 id 映射改写服务商及其子表的行，丢弃找不到所属服务商的子表行；
+// 模型映射去掉自身 id，由本地重新分配
+fn remap_provider_rows(table: &str, snapshot: &TableSnapshot, ids: &HashMap<i64, i64>) -> TableSnapshot {
+    let key = if table == "providers" { "id" } else { "provider_id" };
+    let mut snapshot = snapshot.clone();
+    if let Some(rows) = snapshot.rows.as_array_mut() {
+        rows.retain_mut(|row| {
+            let Some(row) = row.as_object_mut() else {
+                return false;
+            };
+            let Some(&local) = row.get(key).and_then(|v| v.as_i64()).and_then(|id| ids.get(&id)) else {
+                return false;
+            };
+            row.insert(key.to_string(), local.into());
+            if table == "provider_model_map" {
+                row.remove("id");
+            }
+            true
+        });
+    }
+    if table == "provider_model_map" {
+        snapshot.columns.retain(|c| c != "id");
+    }
+    snapshot
+}
+
+/// 读取所选分区的配置表；`include_secrets` 为 false 时不含敏感列
+pub async fn export(db: &SqlitePool, sections: &[String], include_secrets: bool) -> Result<ConfigBundle, String> {
+    let sections = normalize_sections(sections)?;
+    let mut tables = BTreeMap::new();
+    for section in &sections {
+        for table in section_tables(section).unwrap_or_default() {
+            if let Some(mut snapshot) = incremental_backup::read_table(db, table).await? {
+                if !include_secrets {
+                    strip_secrets(table, &mut snapshot);
+                }
+                tables.insert(table.to_string(), snapshot);
+            }
+        }
+    }
+
+    Ok(ConfigBundle {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: chrono::Utc::now().timestamp(),
+        sections,
+        tables,
+    })
+}
+
+/// 解析并校验配置包
+pub fn parse(content: &[u8]) -> Result<ConfigBundle, String> {
+    let bundle: ConfigBundle = serde_json::from_slice(content)
+        .map_err(|e| format!("Invalid config bundle: {}", e))?;
+    if bundle.format != BUNDLE_FORMAT {
+        return Err("Invalid config bundle: unknown format".to_string());
+    }
+    if bundle.version > BUNDLE_VERSION {
+        return Err(format!("Unsupported config bundle version: {}", bundle.version));
+    }
+    Ok(bundle)
+}
+
+/// 在一个事务中导入配置包，只处理配置包声明的分区内的表；返回写入的表数
+pub async fn import(db: &SqlitePool, bundle: &ConfigBundle, mode: ImportMode) -> Result<usize, String> {
+    let sections = normalize_sections(&bundle.sections)?;
+    let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+
+    // 合并导入服务商时，匹配到的本地服务商的子表以配置包为准
+    let provider_ids = match bundle.tables.get("providers") {
+        Some(providers) if mode == ImportMode::Merge && sections.iter().any(|s| s == "providers") => {
+            let ids = map_provider_ids(&mut tx, providers).await?;
+            let local_ids: Vec<i64> = ids.values().copied().collect();
+            for table in PROVIDER_CHILD_TABLES {
+                sqlx::query(&format!("DELETE FROM {} WHERE provider_id IN (SELECT value FROM json_each(?))", table))
+                    .bind(serde_json::to_string(&local_ids).unwrap_or_default())
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Some(ids)
+        }
+        _ => None,
+    };
+
+    let mut applied = 0;
+    for section in &sections {
+        for table in section_tables(section).unwrap_or_default() {
+            let Some(snapshot) = bundle.tables.get(*table) else {
+                continue;
+            };
+            let snapshot = match &provider_ids {
+                Some(ids) if *table == "providers" || PROVIDER_CHILD_TABLES.contains(table) => {
+                    remap_provider_rows(table, snapshot, ids)
+                }
+                _ => snapshot.clone(),
+            };
+            let snapshot = keep_local_secrets(&mut tx, table, &snapshot).await?;
+            incremental_backup::apply_table(&mut tx, table, &snapshot, mode == ImportMode::Replace).await?;
+            applied += 1;
+        }
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_secrets(db: &SqlitePool) {
+        for sql in [
+            "INSERT OR REPLACE INTO github_settings (id, token, updated_at) VALUES (1, 'ghp_secret', 0)",
+            "INSERT OR REPLACE INTO webdav_settings (id, password, updated_at) VALUES (1, 'dav_secret', 0)",
+            "UPDATE gateway_settings SET admin_token = 'admin_secret' WHERE id = 1",
+        ] {
+            sqlx::query(sql).execute(db).await.unwrap();
+        }
+    }
+
+    async fn secrets(db: &SqlitePool) -> Vec<Option<String>> {
+        let mut values = Vec::new();
+        for (table, column) in SECRET_COLUMNS {
+            let value: Option<String> = sqlx::query_scalar(&format!("SELECT {} FROM {} WHERE id = 1", column, table))
+                .fetch_one(db)
+                .await
+                .unwrap();
+            values.push(value);
+        }
+        values
+    }
+
+    #[tokio::test]
+    async fn secrets_are_not_exported_and_survive_import() {
+        let db = crate::db::test_pool(false).await;
+        insert_secrets(&db).await;
+
+        let bundle = export(&db, &["settings".to_string()], false).await.unwrap();
+        let json = serde_json::to_string(&bundle).unwrap();
+        for secret in ["ghp_secret", "dav_secret", "admin_secret"] {
+            assert!(!json.contains(secret), "{} was exported", secret);
+        }
+        for (table, column) in SECRET_COLUMNS {
+            assert!(!bundle.tables[table].columns.iter().any(|c| c == column));
+        }
+
+        for mode in [ImportMode::Merge, ImportMode::Replace] {
+            import(&db, &bundle, mode).await.unwrap();
+            let expected = ["admin_secret", "ghp_secret", "dav_secret"].map(|s| Some(s.to_string()));
+            assert_eq!(secrets(&db).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn secrets_are_exported_only_on_request() {
+        let source = crate::db::test_pool(false).await;
+        insert_secrets(&source).await;
+        let bundle = export(&source, &["settings".to_string()], true).await.unwrap();
+        let json = serde_json::to_string(&bundle).unwrap();
+        for secret in ["ghp_secret", "dav_secret", "admin_secret"] {
+            assert!(json.contains(secret));
+        }
+
+        let target = crate::db::test_pool(false).await;
+        import(&target, &bundle, ImportMode::Merge).await.unwrap();
+        assert_eq!(secrets(&target).await, secrets(&source).await);
+    }
+
+    async fn insert_provider(db: &SqlitePool, id: i64, name: &str, base_url: &str) {
+        sqlx::query(
+            "INSERT INTO providers (id, cli_type, name, base_url, api_key, created_at, updated_at) VALUES (?, 'claude_code', ?, ?, 'k', 0, 0)",
+        )
+        .bind(id)
+        .bind(name)
+        .bind(base_url)
+        .execute(db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO provider_cli_types (provider_id, cli_type) VALUES (?, 'claude_code')")
+            .bind(id)
+            .execute(db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO provider_model_map (provider_id, source_model, target_model) VALUES (?, 'm', ?)")
+            .bind(id)
+            .bind(format!("{}-model", name))
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn merge_matches_providers_by_name_and_cli_type() {
+        let local = crate::db::test_pool(false).await;
+        insert_provider(&local, 1, "shared", "https://old.example").await;
+        insert_provider(&local, 2, "local-only", "https://local.example").await;
+
+        // Same ids as the local providers, but different providers behind them
+        let source = crate::db::test_pool(false).await;
+        insert_provider(&source, 1, "remote-only", "https://remote.example").await;
+        insert_provider(&source, 2, "shared", "https://new.example").await;
+        let bundle = export(&source, &["providers".to_string()], false).await.unwrap();
+
+        import(&local, &bundle, ImportMode::Merge).await.unwrap();
+        import(&local, &bundle, ImportMode::Merge).await.unwrap();
+
+        let providers: Vec<(i64, String, String)> = sqlx::query_as("SELECT id, name, base_url FROM providers ORDER BY id")
+            .fetch_all(&local)
+            .await
+            .unwrap();
+        assert_eq!(
+            providers,
+            [
+                (1, "shared".to_string(), "https://new.example".to_string()),
+                (2, "local-only".to_string(), "https://local.example".to_string()),
+                (3, "remote-only".to_string(), "https://remote.example".to_string()),
+            ]
+        );
+
+        let maps: Vec<(i64, String)> = sqlx::query_as("SELECT provider_id, target_model FROM provider_model_map ORDER BY provider_id")
+            .fetch_all(&local)
+            .await
+            .unwrap();
+        assert_eq!(
+            maps,
+            [
+                (1, "shared-model".to_string()),
+                (2, "local-only-model".to_string()),
+                (3, "remote-only-model".to_string()),
+            ]
+        );
+        let cli_types: Vec<i64> = sqlx::query_scalar("SELECT provider_id FROM provider_cli_types ORDER BY provider_id")
+            .fetch_all(&local)
+            .await
+            .unwrap();
+        assert_eq!(cli_types, [1, 2, 3]);
+    }
+}
@@ -0,0 +1,122 @@
+//! 口令加密：Argon2id 从口令派生密钥，AES-256-GCM 加密
+//!
+//! 文件格式：`MAGIC`(8) + salt(16) + nonce(12) + 密文（含 16 字节认证标签）。
+//! GCM 认证失败即视为口令错误或文件损坏，不会产出错误的明文。
+
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use argon2::password_hash::rand_core::RngCore;
+use argon2::Argon2;
+
+/// 加密文件头（含格式版本）
+const MAGIC: &[u8; 8] = b"CCGENC01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// GCM 认证标签长度，空明文的密文也至少这么长
+const TAG_LEN: usize = 16;
+/// 口令最短长度
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key<Aes256Gcm>, String> {
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// 是否为本模块生成的加密文件
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("Passphrase must be at least {} characters", MIN_PASSPHRASE_LEN));
+    }
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt)?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(&key)
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| "Encryption failed".to_string())?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    if !is_encrypted(data) {
+        return Err("Not an encrypted file".to_string());
+    }
+    let rest = &data[MAGIC.len()..];
+    if rest.len() < SALT_LEN + NONCE_LEN + TAG_LEN {
+        return Err("Encrypted file is truncated".to_string());
+    }
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let key = derive_key(passphrase, salt)?;
+    Aes256Gcm::new(&key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Decryption failed: wrong passphrase or corrupted file".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery";
+
+    #[test]
+    fn round_trip_restores_the_plaintext() {
+        let plaintext = b"SQLite format 3\0 backup contents";
+        let encrypted = encrypt(plaintext, PASSPHRASE).unwrap();
+
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(plaintext));
+        assert!(!encrypted.windows(plaintext.len()).any(|w| w == plaintext));
+        assert_eq!(decrypt(&encrypted, PASSPHRASE).unwrap(), plaintext);
+        // Fresh salt and nonce each time
+        assert_ne!(encrypt(plaintext, PASSPHRASE).unwrap(), encrypted);
+        assert_eq!(decrypt(&encrypt(b"", PASSPHRASE).unwrap(), PASSPHRASE).unwrap(), b"");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_reported_clearly() {
+        let encrypted = encrypt(b"secret", PASSPHRASE).unwrap();
+        let err = decrypt(&encrypted, "incorrect horse").unwrap_err();
+        assert!(err.contains("wrong passphrase"), "{}", err);
+    }
+
+    #[test]
+    fn truncated_or_tampered_files_are_rejected() {
+        let encrypted = encrypt(b"secret", PASSPHRASE).unwrap();
+
+        for len in [MAGIC.len(), MAGIC.len() + SALT_LEN, MAGIC.len() + SALT_LEN + NONCE_LEN + TAG_LEN - 1] {
+            let err = decrypt(&encrypted[..len], PASSPHRASE).unwrap_err();
+            assert!(err.contains("truncated"), "{}: {}", len, err);
+        }
+        // Long enough to parse, but the ciphertext was cut: authentication fails
+        assert!(decrypt(&encrypted[..encrypted.len() - 1], PASSPHRASE).is_err());
+
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&tampered, PASSPHRASE).is_err());
+        assert_eq!(decrypt(b"plain", PASSPHRASE).unwrap_err(), "Not an encrypted file");
+    }
+
+    #[test]
+    fn short_passphrases_are_refused() {
+        let short = "x".repeat(MIN_PASSPHRASE_LEN - 1);
+        let err = encrypt(b"secret", &short).unwrap_err();
+        assert!(err.contains(&MIN_PASSPHRASE_LEN.to_string()), "{}", err);
+        // Counted in characters, not bytes
+        assert!(encrypt(b"secret", "密码密码密码密").is_err());
+        assert!(encrypt(b"secret", &"x".repeat(MIN_PASSPHRASE_LEN)).is_ok());
+    }
+}
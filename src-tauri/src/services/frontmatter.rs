@@ -0,0 +1,200 @@
+//! SKILL.md frontmatter 解析
+//!
+//! frontmatter 块交给 serde_yaml 反序列化；各字段先按 YAML 值读取再宽松转换，
+//! 这样 `version: 1.0` 这类数字、`tags` 写成列表或逗号分隔字符串都能识别。
+
+use serde::Deserialize;
+use serde_yaml::Value;
+
+// Skill 元数据（字段缺失时为 None / 空）
+#[derive(Debug, Clone, Default)]
+pub struct SkillMetadata {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub tags: Vec<String>,
+}
+
+// 元数据字段；类型不固定，统一按 YAML 值接收
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Fields {
+    name: Value,
+    description: Value,
+    version: Value,
+    author: Value,
+    license: Value,
+    tags: Value,
+}
+
+// frontmatter 顶层；`metadata:` 下可以再放一份 Fields
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Frontmatter {
+    #[serde(flatten)]
+    fields: Fields,
+    metadata: Value,
+}
+
+// 从 SKILL.md 内容解析元数据；version/author/tags 同时兼容放在 `metadata:` 下的写法
+pub fn parse_skill_metadata(content: &str) -> SkillMetadata {
+    let Some(block) = extract_block(content) else {
+        return SkillMetadata::default();
+    };
+    if block.trim().is_empty() {
+        return SkillMetadata::default();
+    }
+    let frontmatter: Frontmatter = match serde_yaml::from_str(block) {
+        Ok(frontmatter) => frontmatter,
+        Err(e) => {
+            tracing::debug!("Invalid SKILL.md frontmatter: {}", e);
+            return SkillMetadata::default();
+        }
+    };
+    // metadata 不是映射时当作没有
+    let nested: Fields = serde_yaml::from_value(frontmatter.metadata).unwrap_or_default();
+    let top = frontmatter.fields;
+
+    let pick = |own: &Value, fallback: &Value| text(own).or_else(|| text(fallback));
+    let tags = match list(&top.tags) {
+        tags if tags.is_empty() => list(&nested.tags),
+        tags => tags,
+    };
+
+    SkillMetadata {
+        name: pick(&top.name, &nested.name),
+        description: pick(&top.description, &nested.description),
+        version: pick(&top.version, &nested.version),
+        author: pick(&top.author, &nested.author),
+        license: pick(&top.license, &nested.license),
+        tags,
+    }
+}
+
+// 取出 frontmatter 文本：只认文件开头的 `---` 行，到下一个 `---`（或 `...`）行为止，
+// 正文里的 `---` 分隔线不受影响
+fn extract_block(content: &str) -> Option<&str> {
+    let content = content.trim_start_matches('\u{feff}');
+    let (first, rest) = content.split_once('\n')?;
+    if first.trim_end() != "---" {
+        return None;
+    }
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        let marker = line.trim_end();
+        if marker == "---" || marker == "..." {
+            return Some(&rest[..offset]);
+        }
+        offset += line.len();
+    }
+    None
+}
+
+// 标量转字符串（数字、布尔按字面值），列表用 ", " 拼接；空白值视为缺失
+fn text(value: &Value) -> Option<String> {
+    let s = match value {
+        Value::String(s) => s.trim().to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Sequence(_) => list(value).join(", "),
+        Value::Tagged(tagged) => return text(&tagged.value),
+        Value::Null | Value::Mapping(_) => return None,
+    };
+    (!s.is_empty()).then_some(s)
+}
+
+// 列表字段：兼容 `tags: a, b` 这种逗号分隔写法
+fn list(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => s
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect(),
+        Value::Sequence(items) => items.iter().filter_map(text).collect(),
+        Value::Tagged(tagged) => list(&tagged.value),
+        other => text(other).into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_separator_does_not_end_the_frontmatter_early() {
+        let content = "---\nname: pdf\ndescription: Work with PDFs\n---\n# PDF\n\n---\n\nname: not-metadata\n";
+        let meta = parse_skill_metadata(content);
+        assert_eq!(meta.name.as_deref(), Some("pdf"));
+        assert_eq!(meta.description.as_deref(), Some("Work with PDFs"));
+    }
+
+    #[test]
+    fn quoted_values_keep_their_colons() {
+        let content = "---\nname: \"api: client\"\ndescription: 'Use when: the user asks, it''s fine'\nauthor: a:b\n---\n";
+        let meta = parse_skill_metadata(content);
+        assert_eq!(meta.name.as_deref(), Some("api: client"));
+        assert_eq!(meta.description.as_deref(), Some("Use when: the user asks, it's fine"));
+        assert_eq!(meta.author.as_deref(), Some("a:b"));
+    }
+
+    #[test]
+    fn multi_line_descriptions_are_joined() {
+        let folded = "---\nname: x\ndescription: >\n  First line\n  second line\n\n  Next paragraph\n---\n";
+        assert_eq!(parse_skill_metadata(folded).description.as_deref(), Some("First line second line\nNext paragraph"));
+
+        let literal = "---\ndescription: |\n  one\n  two\nname: x\n---\n";
+        let meta = parse_skill_metadata(literal);
+        assert_eq!(meta.description.as_deref(), Some("one\ntwo"));
+        assert_eq!(meta.name.as_deref(), Some("x"));
+
+        let plain = "---\ndescription: Starts here\n  and continues\nversion: 1.0\n---\n";
+        let meta = parse_skill_metadata(plain);
+        assert_eq!(meta.description.as_deref(), Some("Starts here and continues"));
+        assert_eq!(meta.version.as_deref(), Some("1.0"));
+
+        let quoted = "---\ndescription: \"Spans\nlines: yes\"\n---\n";
+        assert_eq!(parse_skill_metadata(quoted).description.as_deref(), Some("Spans lines: yes"));
+    }
+
+    #[test]
+    fn lists_and_nested_metadata() {
+        let content = "---\nname: x\ntags:\n- a\n- \"b, c\"\nmetadata:\n  version: 2.1\n  author: someone # comment\n---\n";
+        let meta = parse_skill_metadata(content);
+        assert_eq!(meta.tags, vec!["a", "b, c"]);
+        assert_eq!(meta.version.as_deref(), Some("2.1"));
+        assert_eq!(meta.author.as_deref(), Some("someone"));
+        assert_eq!(parse_skill_metadata("---\ntags: [x, 'y']\n---\n").tags, vec!["x", "y"]);
+    }
+
+    #[test]
+    fn multi_line_quoted_values_fold_like_yaml() {
+        let content = "---\nname: x\ndescription: \"First\n  second\n\n  third \\\"quoted\\\"\"\nlicense: 'it''s\n  MIT'\n---\n";
+        let meta = parse_skill_metadata(content);
+        assert_eq!(meta.description.as_deref(), Some("First second\nthird \"quoted\""));
+        assert_eq!(meta.license.as_deref(), Some("it's MIT"));
+        assert_eq!(meta.name.as_deref(), Some("x"));
+
+        let crlf = "---\r\nname: x\r\ndescription: \"a\r\n  b\"\r\n---\r\nbody\r\n";
+        assert_eq!(parse_skill_metadata(crlf).description.as_deref(), Some("a b"));
+    }
+
+    #[test]
+    fn invalid_yaml_yields_nothing() {
+        let meta = parse_skill_metadata("---\nname: x\ndescription: Use when: asked\n---\n");
+        assert!(meta.name.is_none() && meta.description.is_none());
+        assert!(parse_skill_metadata("---\n---\n").name.is_none());
+        // metadata 不是映射时只影响嵌套字段
+        assert_eq!(parse_skill_metadata("---\nname: x\nmetadata: plain\n---\n").name.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn missing_or_unterminated_frontmatter_yields_nothing() {
+        assert!(parse_skill_metadata("# Title\nname: x\n").name.is_none());
+        assert!(parse_skill_metadata("---\nname: x\n").name.is_none());
+        assert_eq!(parse_skill_metadata("\u{feff}---\nname: x\n---\n").name.as_deref(), Some("x"));
+    }
+}
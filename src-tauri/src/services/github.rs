@@ -0,0 +1,95 @@
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, USER_AGENT};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+/// Attempts for a GitHub request that keeps failing with a 5xx
+const MAX_ATTEMPTS: u32 = 3;
+/// Delay before the first retry; doubled for each further attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Load the optional GitHub token used to raise API limits and access private repos
+pub async fn load_token(db: &SqlitePool) -> Option<String> {
+    sqlx::query_scalar::<_, Option<String>>("SELECT token FROM github_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+        .filter(|t| !t.trim().is_empty())
+}
+
+/// HTTP client for GitHub requests, authenticated when a token is configured
+pub async fn client(db: &SqlitePool) -> Result<reqwest::Client, String> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_static("ccg-gateway"));
+    if let Some(token) = load_token(db).await {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token.trim()))
+            .map_err(|_| "GitHub token contains invalid characters".to_string())?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Clear message for an exhausted rate limit, or None if the response isn't rate limited.
+/// GitHub answers 403 (or 429) with `X-RateLimit-Remaining: 0` and a unix `X-RateLimit-Reset`.
+pub fn rate_limit_error(response: &Response) -> Option<String> {
+    let status = response.status();
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<i64>().ok())
+    };
+    if header("x-ratelimit-remaining") != Some(0) {
+        return None;
+    }
+
+    let until = header("x-ratelimit-reset")
+        .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+        .map(|t| t.with_timezone(&chrono::Local).format("%H:%M").to_string());
+    Some(match until {
+        Some(until) => format!("GitHub 请求已被限流，请在 {} 后重试（可配置 GitHub Token 提高限额）", until),
+        None => "GitHub 请求已被限流，请稍后重试（可配置 GitHub Token 提高限额）".to_string(),
+    })
+}
+
+/// Send a GitHub request, retrying transient 5xx responses with exponential backoff.
+/// `build` is called once per attempt. Rate-limited responses become an error.
+pub async fn send<F>(build: F) -> Result<Response, String>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let response = build().send().await.map_err(|e| format!("网络请求失败: {}", e))?;
+
+        if let Some(message) = rate_limit_error(&response) {
+            return Err(message);
+        }
+        if !response.status().is_server_error() || attempt >= MAX_ATTEMPTS {
+            return Ok(response);
+        }
+
+        tracing::warn!(
+            status = %response.status(),
+            attempt,
+            "GitHub returned a server error, retrying in {:?}",
+            backoff
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
@@ -0,0 +1,472 @@
+//! 增量备份：只导出自上次备份以来发生变化的配置表
+//!
+//! 增量文件是 JSON，记录所依赖的全量基线文件名与序号，每个变化的表整表保存（可覆盖删除操作）。
+//! 恢复时先导入基线 `.db`，再按序号依次应用该基线之后的增量。
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::get_data_dir;
+
+/// 增量文件格式标识
+pub const INCREMENT_FORMAT: &str = "ccg_gateway_increment";
+/// 增量文件格式版本
+pub const INCREMENT_VERSION: u32 = 1;
+/// 连续增量达到该数量后强制生成新的全量基线
+pub const MAX_INCREMENTS_PER_BASELINE: u32 = 20;
+/// 增量文件名前缀
+pub const INCREMENT_PREFIX: &str = "ccg_gateway_inc_";
+/// 备份文件名中的时间戳格式（按字符串排序即按时间排序）
+pub const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+// 不参与增量的内部表
+const EXCLUDED_TABLES: [&str; 2] = ["_schema_version", "_schema_migrations"];
+
+/// 单个表的整表快照
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableSnapshot {
+    pub columns: Vec<String>,
+    /// 每行一个 JSON 对象（列名 -> 值）
+    pub rows: serde_json::Value,
+}
+
+/// 增量备份文件内容
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupIncrement {
+    pub format: String,
+    pub version: u32,
+    /// 所依赖的全量备份文件名
+    pub baseline: String,
+    /// 在该基线之后的序号，从 1 开始
+    pub sequence: u32,
+    pub created_at: i64,
+    /// 上次备份时的 max(updated_at) 水位
+    pub since: i64,
+    /// 本次备份时的 max(updated_at) 水位
+    pub watermark: i64,
+    /// 发生变化的表（整表替换）
+    pub tables: BTreeMap<String, TableSnapshot>,
+}
+
+/// 本地记录的增量备份状态（存放在数据目录，不随数据库一起备份/恢复）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupState {
+    pub baseline: String,
+    pub sequence: u32,
+    pub watermark: i64,
+    /// 表名 -> 上次备份时的内容指纹
+    pub table_hashes: BTreeMap<String, String>,
+}
+
+/// 当前数据库的配置表快照
+pub struct DatabaseSnapshot {
+    pub tables: BTreeMap<String, TableSnapshot>,
+    pub hashes: BTreeMap<String, String>,
+    pub watermark: i64,
+}
+
+fn state_path() -> PathBuf {
+    get_data_dir().join("webdav_backup_state.json")
+}
+
+// 状态文件按远端目录（WebDAV URL + 文件夹）分别记录，切换备份目标不会沿用其他目标的基线；
+// 旧版本写入的单一状态无法解析，视为没有基线
+fn load_states() -> BTreeMap<String, BackupState> {
+    std::fs::read_to_string(state_path())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// 读取指定远端目录的增量状态，不存在或损坏时视为没有基线
+pub fn load_state(target: &str) -> Option<BackupState> {
+    load_states().remove(target)
+}
+
+pub fn save_state(target: &str, state: &BackupState) -> Result<(), String> {
+    let mut states = load_states();
+    states.insert(target.to_string(), state.clone());
+    let content = serde_json::to_string_pretty(&states).map_err(|e| e.to_string())?;
+    std::fs::write(state_path(), content).map_err(|e| format!("Failed to save backup state: {}", e))
+}
+
+/// 清除所有远端目录的增量状态（导入备份后调用，本地数据已与各基线不一致，下次备份将重新生成全量基线）
+pub fn clear_state() {
+    let _ = std::fs::remove_file(state_path());
+}
+
+// FNV-1a 64 位指纹，跨版本稳定
+fn fingerprint(content: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// JSON 路径中的键名需要加引号，避免列名中的特殊字符
+fn json_path(column: &str) -> String {
+    format!("$.\"{}\"", column.replace('"', "\\\""))
+}
+
+async fn table_columns(db: &SqlitePool, table: &str) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT name FROM pragma_table_info(?, 'main') ORDER BY cid")
+        .bind(table)
+        .fetch_all(db)
+        .await
+}
+
+// 整表内容序列化为 JSON 数组（按 rowid 排序，保证指纹稳定）
+async fn table_rows_json(db: &SqlitePool, table: &str, columns: &[String]) -> Result<String, String> {
+    let pairs: Vec<String> = columns
+        .iter()
+        .map(|c| format!("'{}', {}", c.replace('\'', "''"), quote_ident(c)))
+        .collect();
+    sqlx::query_scalar(&format!(
+        "SELECT COALESCE(json_group_array(json_object({})), '[]') FROM (SELECT * FROM {} ORDER BY rowid)",
+        pairs.join(", "),
+        quote_ident(table)
+    ))
+    .fetch_one(db)
+    .await
+    .map_err(|e| format!("Failed to read table {}: {}", table, e))
+}
+
+/// 读取单个表的整表快照，表不存在时返回 None
+pub async fn read_table(db: &SqlitePool, table: &str) -> Result<Option<TableSnapshot>, String> {
+    let columns = table_columns(db, table).await.map_err(|e| e.to_string())?;
+    if columns.is_empty() {
+        return Ok(None);
+    }
+    let rows_json = table_rows_json(db, table, &columns).await?;
+    let rows = serde_json::from_str(&rows_json).map_err(|e| e.to_string())?;
+    Ok(Some(TableSnapshot { columns, rows }))
+}
+
+/// 读取所有配置表的整表内容、指纹，以及 max(updated_at) 水位
+pub async fn snapshot(db: &SqlitePool) -> Result<DatabaseSnapshot, String> {
+    let names: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut tables = BTreeMap::new();
+    let mut hashes = BTreeMap::new();
+    let mut watermark = 0i64;
+
+    for name in names {
+        if EXCLUDED_TABLES.contains(&name.as_str()) {
+            continue;
+        }
+        let columns = table_columns(db, &name).await.map_err(|e| e.to_string())?;
+        if columns.is_empty() {
+            continue;
+        }
+
+        let rows_json = table_rows_json(db, &name, &columns).await?;
+
+        if columns.iter().any(|c| c == "updated_at") {
+            let max: Option<i64> = sqlx::query_scalar(&format!("SELECT MAX(updated_at) FROM {}", quote_ident(&name)))
+                .fetch_one(db)
+                .await
+                .map_err(|e| e.to_string())?;
+            watermark = watermark.max(max.unwrap_or(0));
+        }
+
+        let rows: serde_json::Value = serde_json::from_str(&rows_json).map_err(|e| e.to_string())?;
+        hashes.insert(name.clone(), fingerprint(&rows_json));
+        tables.insert(name, TableSnapshot { columns, rows });
+    }
+
+    Ok(DatabaseSnapshot { tables, hashes, watermark })
+}
+
+/// 根据上次状态构建增量；没有任何表变化时返回 None。
+/// 指纹对比可以发现删除和没有 updated_at 列的关联表变化，水位仅作为记录。
+pub fn build_increment(state: &BackupState, snapshot: DatabaseSnapshot) -> Option<BackupIncrement> {
+    let mut tables = BTreeMap::new();
+    for (name, table) in snapshot.tables {
+        if state.table_hashes.get(&name) != snapshot.hashes.get(&name) {
+            tables.insert(name, table);
+        }
+    }
+    if tables.is_empty() {
+        return None;
+    }
+
+    Some(BackupIncrement {
+        format: INCREMENT_FORMAT.to_string(),
+        version: INCREMENT_VERSION,
+        baseline: state.baseline.clone(),
+        sequence: state.sequence + 1,
+        created_at: chrono::Utc::now().timestamp(),
+        since: state.watermark,
+        watermark: snapshot.watermark,
+        tables,
+    })
+}
+
+/// 解析并校验增量文件
+pub fn parse_increment(content: &[u8]) -> Result<BackupIncrement, String> {
+    let increment: BackupIncrement = serde_json::from_slice(content)
+        .map_err(|e| format!("Invalid incremental backup: {}", e))?;
+    if increment.format != INCREMENT_FORMAT {
+        return Err("Invalid incremental backup: unknown format".to_string());
+    }
+    if increment.version > INCREMENT_VERSION {
+        return Err(format!("Unsupported incremental backup version: {}", increment.version));
+    }
+    Ok(increment)
+}
+
+/// 校验一组增量属于同一基线且序号从 1 开始连续，返回按序号排序后的列表
+pub fn order_chain(baseline: &str, mut increments: Vec<BackupIncrement>) -> Result<Vec<BackupIncrement>, String> {
+    increments.retain(|inc| inc.baseline == baseline);
+    increments.sort_by_key(|inc| inc.sequence);
+    for (i, inc) in increments.iter().enumerate() {
+        let expected = i as u32 + 1;
+        if inc.sequence != expected {
+            return Err(format!(
+                "Incremental backup chain for {} is incomplete: expected #{}, found #{}",
+                baseline, expected, inc.sequence
+            ));
+        }
+    }
+    Ok(increments)
+}
+
+/// 在一个事务中按顺序应用增量：每个表整表替换，只写入两边都存在的列
+pub async fn apply_increments(db: &SqlitePool, increments: &[BackupIncrement]) -> Result<(), String> {
+    let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+
+    for increment in increments {
+        for (name, table) in &increment.tables {
+            apply_table(&mut tx, name, table, true).await?;
+        }
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+/// 在独立的库文件上还原增量链：基线先升级到当前结构，再依次应用增量。
+/// 当前库在全部成功之前不受影响，失败时丢弃该文件即可
+pub async fn restore_chain(path: &Path, increments: &[BackupIncrement]) -> Result<(), String> {
+    let pool = crate::db::init_db(path)
+        .await
+        .map_err(|e| format!("Failed to open backup: {}", e))?;
+    let result = apply_increments(&pool, increments).await;
+    pool.close().await;
+    result
+}
+
+/// 将表快照写入当前库，只写入两边都存在的列。
+/// `replace` 为 true 时先清空该表；否则按主键/唯一约束覆盖同名行，其余行保留。
+pub async fn apply_table(
+    conn: &mut sqlx::SqliteConnection,
+    name: &str,
+    table: &TableSnapshot,
+    replace: bool,
+) -> Result<(), String> {
+    if EXCLUDED_TABLES.contains(&name) {
+        return Ok(());
+    }
+    let main_columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?, 'main')")
+        .bind(name)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    // 当前版本已不存在的表直接跳过
+    if main_columns.is_empty() {
+        return Ok(());
+    }
+    let columns: Vec<&String> = main_columns.iter().filter(|c| table.columns.contains(c)).collect();
+
+    if replace {
+        sqlx::query(&format!("DELETE FROM main.{}", quote_ident(name)))
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| format!("Failed to apply table {}: {}", name, e))?;
+    }
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    let column_list: Vec<String> = columns.iter().map(|c| quote_ident(c)).collect();
+    let extracts: Vec<String> = columns
+        .iter()
+        .map(|c| format!("json_extract(value, '{}')", json_path(c).replace('\'', "''")))
+        .collect();
+    sqlx::query(&format!(
+        "INSERT OR REPLACE INTO main.{} ({}) SELECT {} FROM json_each(?) ORDER BY key",
+        quote_ident(name),
+        column_list.join(", "),
+        extracts.join(", ")
+    ))
+    .bind(table.rows.to_string())
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| format!("Failed to apply table {}: {}", name, e))?;
+    Ok(())
+}
+
+/// 从备份文件名中取出时间戳部分（用于确定增量链的范围）；时间戳不合法的文件名返回 None
+pub fn backup_timestamp(filename: &str) -> Option<&str> {
+    let ts = match filename.strip_prefix(INCREMENT_PREFIX) {
+        Some(rest) => rest.strip_suffix(".json")?,
+        None => filename.strip_prefix("ccg_gateway_")?.strip_suffix(".db")?,
+    };
+    chrono::NaiveDateTime::parse_from_str(ts, BACKUP_TIMESTAMP_FORMAT).ok()?;
+    Some(ts)
+}
+
+pub fn is_increment_file(filename: &str) -> bool {
+    filename.starts_with(INCREMENT_PREFIX) && filename.ends_with(".json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn increment(baseline: &str, sequence: u32) -> BackupIncrement {
+        BackupIncrement {
+            format: INCREMENT_FORMAT.to_string(),
+            version: INCREMENT_VERSION,
+            baseline: baseline.to_string(),
+            sequence,
+            created_at: 0,
+            since: 0,
+            watermark: 0,
+            tables: BTreeMap::new(),
+        }
+    }
+
+    async fn prompt_names(db: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar("SELECT name FROM prompt_presets ORDER BY id").fetch_all(db).await.unwrap()
+    }
+
+    #[test]
+    fn increment_format_round_trips_and_rejects_foreign_files() {
+        let inc = increment("ccg_gateway_20240101_000000.db", 1);
+        let parsed = parse_increment(&serde_json::to_vec(&inc).unwrap()).unwrap();
+        assert_eq!(parsed.baseline, inc.baseline);
+        assert_eq!(parsed.sequence, 1);
+
+        let mut foreign = inc.clone();
+        foreign.format = "something_else".to_string();
+        assert!(parse_increment(&serde_json::to_vec(&foreign).unwrap()).is_err());
+        let mut newer = inc;
+        newer.version = INCREMENT_VERSION + 1;
+        assert!(parse_increment(&serde_json::to_vec(&newer).unwrap()).is_err());
+        assert!(parse_increment(b"not json").is_err());
+    }
+
+    #[test]
+    fn chain_must_be_contiguous_for_its_baseline() {
+        let chain = order_chain("base.db", vec![increment("base.db", 2), increment("other.db", 1), increment("base.db", 1)]).unwrap();
+        assert_eq!(chain.iter().map(|i| i.sequence).collect::<Vec<_>>(), vec![1, 2]);
+
+        let err = order_chain("base.db", vec![increment("base.db", 1), increment("base.db", 3)]).unwrap_err();
+        assert!(err.contains("expected #2"), "{}", err);
+    }
+
+    #[test]
+    fn file_names_map_to_timestamps() {
+        assert_eq!(backup_timestamp("ccg_gateway_inc_20240101_120000.json"), Some("20240101_120000"));
+        assert_eq!(backup_timestamp("ccg_gateway_20240101_120000.db"), Some("20240101_120000"));
+        assert_eq!(backup_timestamp("notes.txt"), None);
+        assert_eq!(backup_timestamp("ccg_gateway_manual.db"), None);
+        assert_eq!(backup_timestamp("ccg_gateway_inc_20241301_120000.json"), None);
+        assert!(is_increment_file("ccg_gateway_inc_20240101_120000.json"));
+        assert!(!is_increment_file("ccg_gateway_20240101_120000.db"));
+    }
+
+    #[tokio::test]
+    async fn increment_holds_only_changed_tables_and_replays_them() {
+        let source = crate::db::test_pool(false).await;
+        let baseline = snapshot(&source).await.unwrap();
+        let state = BackupState {
+            baseline: "base.db".to_string(),
+            sequence: 0,
+            watermark: baseline.watermark,
+            table_hashes: baseline.hashes.clone(),
+        };
+        assert!(build_increment(&state, snapshot(&source).await.unwrap()).is_none());
+
+        sqlx::query("INSERT INTO prompt_presets (name, content, updated_at) VALUES ('a', 'x', 10), ('b', 'y', 11)")
+            .execute(&source)
+            .await
+            .unwrap();
+        let inc = build_increment(&state, snapshot(&source).await.unwrap()).unwrap();
+        assert_eq!(inc.sequence, 1);
+        assert_eq!(inc.tables.keys().collect::<Vec<_>>(), vec!["prompt_presets"]);
+        assert_eq!(inc.since, state.watermark);
+
+        // Replaying replaces the whole table, including rows deleted at the source
+        let target = crate::db::test_pool(false).await;
+        sqlx::query("INSERT INTO prompt_presets (name, content, updated_at) VALUES ('stale', 'z', 1)")
+            .execute(&target)
+            .await
+            .unwrap();
+        apply_increments(&target, &[inc]).await.unwrap();
+        assert_eq!(prompt_names(&target).await, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn chain_is_rebuilt_in_the_scratch_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ccg_gateway.import.db");
+        let baseline = crate::db::init_db(&path).await.unwrap();
+        sqlx::query("INSERT INTO prompt_presets (name, content, updated_at) VALUES ('stale', 'z', 1)")
+            .execute(&baseline)
+            .await
+            .unwrap();
+        baseline.close().await;
+
+        let source = crate::db::test_pool(false).await;
+        sqlx::query("INSERT INTO prompt_presets (name, content, updated_at) VALUES ('a', 'x', 10)")
+            .execute(&source)
+            .await
+            .unwrap();
+        let mut inc = increment("base.db", 1);
+        inc.tables.insert("prompt_presets".to_string(), read_table(&source, "prompt_presets").await.unwrap().unwrap());
+        restore_chain(&path, std::slice::from_ref(&inc)).await.unwrap();
+
+        let restored = crate::db::init_db(&path).await.unwrap();
+        assert_eq!(prompt_names(&restored).await, vec!["a"]);
+        restored.close().await;
+
+        // A failing increment rolls back the whole chain
+        let mut broken = increment("base.db", 2);
+        broken.tables.insert(
+            "prompt_presets".to_string(),
+            TableSnapshot { columns: vec!["name".to_string()], rows: serde_json::json!([{ "name": null }]) },
+        );
+        assert!(restore_chain(&path, &[inc, broken]).await.is_err());
+        let restored = crate::db::init_db(&path).await.unwrap();
+        assert_eq!(prompt_names(&restored).await, vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn apply_table_skips_unknown_tables_and_columns() {
+        let db = crate::db::test_pool(false).await;
+        let table = TableSnapshot {
+            columns: vec!["name".to_string(), "content".to_string(), "updated_at".to_string(), "removed_column".to_string()],
+            rows: serde_json::json!([{ "name": "p", "content": "c", "updated_at": 1, "removed_column": 5 }]),
+        };
+        let mut conn = db.acquire().await.unwrap();
+        apply_table(&mut conn, "prompt_presets", &table, false).await.unwrap();
+        apply_table(&mut conn, "dropped_table", &table, true).await.unwrap();
+        apply_table(&mut conn, "_schema_version", &table, true).await.unwrap();
+        drop(conn);
+        assert_eq!(prompt_names(&db).await, vec!["p"]);
+    }
+}
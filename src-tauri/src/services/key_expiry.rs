@@ -0,0 +1,183 @@
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+use crate::db::retry_on_locked;
+use crate::services::{stats, webhook};
+
+/// Upper bound for gateway_settings.key_expiry_warn_days
+pub const MAX_KEY_EXPIRY_WARN_DAYS: i64 = 365;
+
+/// How often key expiry is checked while the app is running
+const CHECK_INTERVAL: Duration = Duration::from_secs(86400);
+
+/// Warn about enabled providers whose key expires within key_expiry_warn_days, and about
+/// keys that already expired; expired keys are paused when auto_pause_expired_keys is on.
+/// Returns the number of providers paused.
+pub async fn check(db: &SqlitePool, log_db: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let (warn_days, auto_pause): (i64, i64) = sqlx::query_as(
+        "SELECT key_expiry_warn_days, auto_pause_expired_keys FROM gateway_settings WHERE id = 1",
+    )
+    .fetch_optional(db)
+    .await?
+    .unwrap_or((0, 0));
+
+    let now = chrono::Utc::now().timestamp();
+    let providers: Vec<(i64, String, i64, i64)> = sqlx::query_as(
+        "SELECT id, name, key_expires_at, paused FROM providers WHERE enabled = 1 AND key_expires_at IS NOT NULL AND key_expires_at <= ?",
+    )
+    .bind(now + warn_days.max(0) * 86400)
+    .fetch_all(db)
+    .await?;
+
+    let mut paused_count = 0;
+    for (id, name, expires_at, paused) in providers {
+        if expires_at > now {
+            let days = (expires_at - now) / 86400;
+            tracing::warn!(provider = %name, expires_at, "Provider API key expires soon");
+            let message = format!("服务商 {} 的 API Key 将在 {} 天内过期", name, days.max(1));
+            let _ = stats::record_system_log(log_db, "provider_key_expiring", &message).await;
+            webhook::notify(db, "provider_key_expiring", Some(&name), &message);
+            continue;
+        }
+
+        if auto_pause != 0 && paused == 0 {
+            retry_on_locked(|| {
+                sqlx::query("UPDATE providers SET paused = 1, updated_at = ? WHERE id = ?")
+                    .bind(now)
+                    .bind(id)
+                    .execute(db)
+            })
+            .await?;
+            paused_count += 1;
+            tracing::warn!(provider = %name, expires_at, "Provider API key expired, provider paused");
+            let message = format!("服务商 {} 的 API Key 已过期，已自动暂停路由", name);
+            let _ = stats::record_system_log(log_db, "provider_key_expired", &message).await;
+            webhook::notify(db, "provider_key_expired", Some(&name), &message);
+        } else if paused == 0 {
+            tracing::warn!(provider = %name, expires_at, "Provider API key expired");
+            let message = format!("服务商 {} 的 API Key 已过期", name);
+            let _ = stats::record_system_log(log_db, "provider_key_expired", &message).await;
+            webhook::notify(db, "provider_key_expired", Some(&name), &message);
+        }
+    }
+
+    Ok(paused_count)
+}
+
+/// Check key expiry at startup and then daily in the background
+pub fn spawn(db: SqlitePool, log_db: SqlitePool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = check(&db, &log_db).await {
+                tracing::warn!(error = %e, "Failed to check provider key expiry");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn setup(auto_pause: bool) -> (SqlitePool, SqlitePool) {
+        let db = crate::db::test_pool(false).await;
+        let log_db = crate::db::test_pool(true).await;
+        sqlx::query("UPDATE gateway_settings SET key_expiry_warn_days = 7, auto_pause_expired_keys = ? WHERE id = 1")
+            .bind(auto_pause as i64)
+            .execute(&db)
+            .await
+            .unwrap();
+        (db, log_db)
+    }
+
+    async fn insert_provider(db: &SqlitePool, id: i64, expires_in_days: i64, enabled: bool, paused: bool) {
+        let expires_at = chrono::Utc::now().timestamp() + expires_in_days * 86400;
+        sqlx::query(
+            "INSERT INTO providers (id, cli_type, name, base_url, api_key, enabled, paused, key_expires_at, created_at, updated_at) VALUES (?, 'claude_code', ?, 'https://api.example.com', 'sk', ?, ?, ?, 0, 0)",
+        )
+        .bind(id)
+        .bind(format!("p{}", id))
+        .bind(enabled as i64)
+        .bind(paused as i64)
+        .bind(expires_at)
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    async fn paused(db: &SqlitePool, id: i64) -> bool {
+        sqlx::query_scalar::<_, i64>("SELECT paused FROM providers WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .unwrap()
+            != 0
+    }
+
+    async fn system_logs(log_db: &SqlitePool) -> Vec<(String, String)> {
+        sqlx::query_as("SELECT event_type, message FROM system_logs ORDER BY id")
+            .fetch_all(log_db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn keys_expiring_soon_are_reported_but_not_paused() {
+        let (db, log_db) = setup(true).await;
+        insert_provider(&db, 1, 3, true, false).await;
+        // Outside the warning window, or disabled: nothing to report
+        insert_provider(&db, 2, 30, true, false).await;
+        insert_provider(&db, 3, 1, false, false).await;
+
+        assert_eq!(check(&db, &log_db).await.unwrap(), 0);
+        assert!(!paused(&db, 1).await);
+        let logs = system_logs(&log_db).await;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].0, "provider_key_expiring");
+        assert!(logs[0].1.contains("p1") && logs[0].1.contains("天内过期"), "{}", logs[0].1);
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_paused_when_auto_pause_is_on() {
+        let (db, log_db) = setup(true).await;
+        insert_provider(&db, 1, -1, true, false).await;
+
+        assert_eq!(check(&db, &log_db).await.unwrap(), 1);
+        assert!(paused(&db, 1).await);
+        let logs = system_logs(&log_db).await;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].0, "provider_key_expired");
+        assert!(logs[0].1.contains("已自动暂停"), "{}", logs[0].1);
+
+        // The next daily check finds it already paused and stays quiet
+        assert_eq!(check(&db, &log_db).await.unwrap(), 0);
+        assert_eq!(system_logs(&log_db).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_keys_only_warn_when_auto_pause_is_off() {
+        let (db, log_db) = setup(false).await;
+        insert_provider(&db, 1, -1, true, false).await;
+
+        assert_eq!(check(&db, &log_db).await.unwrap(), 0);
+        assert!(!paused(&db, 1).await);
+        let logs = system_logs(&log_db).await;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].0, "provider_key_expired");
+        assert!(!logs[0].1.contains("暂停"), "{}", logs[0].1);
+    }
+
+    #[tokio::test]
+    async fn already_paused_providers_are_left_alone() {
+        for auto_pause in [true, false] {
+            let (db, log_db) = setup(auto_pause).await;
+            insert_provider(&db, 1, -1, true, true).await;
+
+            assert_eq!(check(&db, &log_db).await.unwrap(), 0);
+            assert!(paused(&db, 1).await);
+            assert!(system_logs(&log_db).await.is_empty());
+        }
+    }
+}
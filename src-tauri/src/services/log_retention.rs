@@ -0,0 +1,69 @@
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+use crate::db::models::LogSettings;
+use crate::db::retry_on_locked;
+
+/// How often retention is enforced while the app is running
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Load log retention settings (0 days keeps logs forever)
+pub async fn load_settings(db: &SqlitePool) -> Result<LogSettings, sqlx::Error> {
+    let settings = sqlx::query_as::<_, LogSettings>(
+        "SELECT request_log_retention_days, system_log_retention_days FROM log_settings WHERE id = 1",
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(settings.unwrap_or_default())
+}
+
+/// Delete request and system logs older than their configured retention.
+/// Returns (request_logs_deleted, system_logs_deleted).
+pub async fn prune(db: &SqlitePool, log_db: &SqlitePool) -> Result<(u64, u64), sqlx::Error> {
+    let settings = load_settings(db).await?;
+    let now = chrono::Utc::now().timestamp();
+
+    let mut deleted = (0, 0);
+    if settings.request_log_retention_days > 0 {
+        let cutoff = now - settings.request_log_retention_days * 86400;
+        deleted.0 = retry_on_locked(|| {
+            sqlx::query("DELETE FROM request_logs WHERE created_at < ?")
+                .bind(cutoff)
+                .execute(log_db)
+        })
+        .await?
+        .rows_affected();
+    }
+    if settings.system_log_retention_days > 0 {
+        let cutoff = now - settings.system_log_retention_days * 86400;
+        deleted.1 = retry_on_locked(|| {
+            sqlx::query("DELETE FROM system_logs WHERE created_at < ?")
+                .bind(cutoff)
+                .execute(log_db)
+        })
+        .await?
+        .rows_affected();
+    }
+
+    if deleted != (0, 0) {
+        tracing::info!(
+            request_logs = deleted.0,
+            system_logs = deleted.1,
+            "Pruned logs past their retention"
+        );
+    }
+    Ok(deleted)
+}
+
+/// Enforce retention at startup and then periodically in the background
+pub fn spawn(db: SqlitePool, log_db: SqlitePool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = prune(&db, &log_db).await {
+                tracing::warn!(error = %e, "Failed to prune logs");
+            }
+        }
+    });
+}
@@ -1,4 +1,18 @@
+pub mod active_requests;
+pub mod app_log;
+pub mod concurrency;
+pub mod config_bundle;
+pub mod encryption;
+pub mod frontmatter;
+pub mod github;
+pub mod incremental_backup;
+pub mod key_expiry;
+pub mod log_retention;
 pub mod provider;
 pub mod proxy;
 pub mod routing;
+pub mod settings;
+pub mod shutdown;
+pub mod skill_installs;
 pub mod stats;
+pub mod webhook;
@@ -1,4 +1,8 @@
 use sqlx::SqlitePool;
+use std::collections::HashSet;
+
+use crate::db::retry_on_locked;
+use crate::error::{AppError, AppResult};
 
 /// Record a successful request for a provider
 /// Resets consecutive_failures to 0
@@ -16,17 +20,19 @@ pub async fn record_success(db: &SqlitePool, provider_id: i64) -> Result<bool, s
 
     let had_previous_failures = had_failures.map(|(cf,)| cf > 0).unwrap_or(false);
 
-    sqlx::query(
-        r#"
-        UPDATE providers
-        SET consecutive_failures = 0,
-            updated_at = ?
-        WHERE id = ?
-        "#,
-    )
-    .bind(now)
-    .bind(provider_id)
-    .execute(db)
+    retry_on_locked(|| {
+        sqlx::query(
+            r#"
+            UPDATE providers
+            SET consecutive_failures = 0,
+                updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(now)
+        .bind(provider_id)
+        .execute(db)
+    })
     .await?;
 
     Ok(had_previous_failures)
@@ -55,20 +61,22 @@ pub async fn record_failure(db: &SqlitePool, provider_id: i64) -> Result<(bool,
     // Check if we should blacklist
     let was_blacklisted = if new_failures >= failure_threshold {
         let blacklist_until = now + (blacklist_minutes * 60);
-        sqlx::query(
-            r#"
-            UPDATE providers
-            SET consecutive_failures = ?,
-                blacklisted_until = ?,
-                updated_at = ?
-            WHERE id = ?
-            "#,
-        )
-        .bind(new_failures)
-        .bind(blacklist_until)
-        .bind(now)
-        .bind(provider_id)
-        .execute(db)
+        retry_on_locked(|| {
+            sqlx::query(
+                r#"
+                UPDATE providers
+                SET consecutive_failures = ?,
+                    blacklisted_until = ?,
+                    updated_at = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(new_failures)
+            .bind(blacklist_until)
+            .bind(now)
+            .bind(provider_id)
+            .execute(db)
+        })
         .await?;
 
         tracing::warn!(
@@ -79,42 +87,240 @@ pub async fn record_failure(db: &SqlitePool, provider_id: i64) -> Result<(bool,
         );
         true
     } else {
+        retry_on_locked(|| {
+            sqlx::query(
+                r#"
+                UPDATE providers
+                SET consecutive_failures = ?,
+                    updated_at = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(new_failures)
+            .bind(now)
+            .bind(provider_id)
+            .execute(db)
+        })
+        .await?;
+        false
+    };
+
+    Ok((was_blacklisted, provider_name))
+}
+
+/// Upper bound for gateway_settings.retry_after_max_secs
+pub const MAX_RETRY_AFTER_SECS: i64 = 86400;
+
+/// Seconds until the time named by a `Retry-After` header, given either as delay-seconds or
+/// as an HTTP date. None when the value is unreadable or already in the past.
+pub fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<i64> {
+    let value = value.trim();
+    let secs = match value.parse::<i64>() {
+        Ok(secs) => secs,
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            let delay = at.with_timezone(&chrono::Utc) - now;
+            // Round up so the provider is never retried before the stated time
+            (delay.num_milliseconds() + 999).div_euclid(1000)
+        }
+    };
+    (secs > 0).then_some(secs)
+}
+
+/// Record a 429 that told us when to retry: blacklist the provider until exactly then,
+/// regardless of the failure threshold and blacklist_minutes
+/// Returns the provider name
+pub async fn record_rate_limited(db: &SqlitePool, provider_id: i64, retry_after_secs: i64) -> Result<String, sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let blacklist_until = now + retry_after_secs;
+
+    retry_on_locked(|| {
         sqlx::query(
             r#"
             UPDATE providers
-            SET consecutive_failures = ?,
+            SET consecutive_failures = consecutive_failures + 1,
+                blacklisted_until = ?,
                 updated_at = ?
             WHERE id = ?
             "#,
         )
-        .bind(new_failures)
+        .bind(blacklist_until)
         .bind(now)
         .bind(provider_id)
         .execute(db)
+    })
+    .await?;
+
+    let name: Option<String> = sqlx::query_scalar("SELECT name FROM providers WHERE id = ?")
+        .bind(provider_id)
+        .fetch_optional(db)
         .await?;
-        false
-    };
 
-    Ok((was_blacklisted, provider_name))
+    tracing::warn!(
+        provider_id = provider_id,
+        retry_after_secs = retry_after_secs,
+        blacklist_until = blacklist_until,
+        "Provider rate limited, blacklisted until Retry-After"
+    );
+    Ok(name.unwrap_or_default())
 }
 
 /// Reset provider failures and remove blacklist
 pub async fn reset_failures(db: &SqlitePool, provider_id: i64) -> Result<(), sqlx::Error> {
     let now = chrono::Utc::now().timestamp();
 
-    sqlx::query(
-        r#"
-        UPDATE providers
-        SET consecutive_failures = 0,
-            blacklisted_until = NULL,
-            updated_at = ?
-        WHERE id = ?
-        "#,
-    )
-    .bind(now)
-    .bind(provider_id)
-    .execute(db)
+    retry_on_locked(|| {
+        sqlx::query(
+            r#"
+            UPDATE providers
+            SET consecutive_failures = 0,
+                blacklisted_until = NULL,
+                updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(now)
+        .bind(provider_id)
+        .execute(db)
+    })
     .await?;
 
     Ok(())
 }
+
+/// Persist a new provider display order in one transaction.
+/// `ids` must list exactly the providers shown for `cli_type` (or all providers when None),
+/// so a stale list can't leave gaps or drop providers from the ordering.
+pub async fn reorder(db: &SqlitePool, ids: &[i64], cli_type: Option<&str>) -> AppResult<()> {
+    write_order(db, ids, cli_type, "UPDATE providers SET sort_order = ? WHERE id = ?").await
+}
+
+/// Persist a new failover priority order, with the same completeness checks as `reorder`
+pub async fn reorder_priority(db: &SqlitePool, ids: &[i64], cli_type: Option<&str>) -> AppResult<()> {
+    write_order(db, ids, cli_type, "UPDATE providers SET priority = ? WHERE id = ?").await
+}
+
+async fn write_order(db: &SqlitePool, ids: &[i64], cli_type: Option<&str>, update_sql: &str) -> AppResult<()> {
+    let mut tx = db.begin().await?;
+
+    let existing: Vec<i64> = match cli_type {
+        Some(ct) => sqlx::query_scalar(
+            "SELECT id FROM providers WHERE cli_type = ? OR id IN (SELECT provider_id FROM provider_cli_types WHERE cli_type = ?)",
+        )
+        .bind(ct)
+        .bind(ct)
+        .fetch_all(&mut *tx)
+        .await,
+        None => sqlx::query_scalar("SELECT id FROM providers")
+            .fetch_all(&mut *tx)
+            .await,
+    }?;
+
+    let requested: HashSet<i64> = ids.iter().copied().collect();
+    if requested.len() != ids.len() {
+        return Err(AppError::Validation("Provider order contains duplicate ids".to_string()));
+    }
+    let existing: HashSet<i64> = existing.into_iter().collect();
+    if requested != existing {
+        let mut missing: Vec<i64> = existing.difference(&requested).copied().collect();
+        let mut unknown: Vec<i64> = requested.difference(&existing).copied().collect();
+        missing.sort_unstable();
+        unknown.sort_unstable();
+        return Err(AppError::Conflict(format!(
+            "Provider order is out of date (missing: {:?}, unknown: {:?}); reload and try again",
+            missing, unknown
+        )));
+    }
+
+    for (idx, id) in ids.iter().enumerate() {
+        sqlx::query(update_sql)
+            .bind(idx as i64)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_provider(db: &SqlitePool, name: &str, cli_type: &str) -> i64 {
+        sqlx::query(
+            "INSERT INTO providers (cli_type, name, base_url, api_key, created_at, updated_at) VALUES (?, ?, 'https://api.example.com', 'sk-test', 0, 0)",
+        )
+        .bind(cli_type)
+        .bind(name)
+        .execute(db)
+        .await
+        .unwrap()
+        .last_insert_rowid()
+    }
+
+    async fn sort_orders(db: &SqlitePool) -> Vec<(i64, i64)> {
+        sqlx::query_as("SELECT id, sort_order FROM providers ORDER BY id").fetch_all(db).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reorder_rejects_mismatched_id_sets() {
+        let db = crate::db::test_pool(false).await;
+        let a = insert_provider(&db, "a", "claude_code").await;
+        let b = insert_provider(&db, "b", "claude_code").await;
+        let c = insert_provider(&db, "c", "codex").await;
+        let before = sort_orders(&db).await;
+
+        // Missing one, unknown one, duplicate, and an id from another CLI
+        assert!(matches!(reorder(&db, &[a], Some("claude_code")).await, Err(AppError::Conflict(_))));
+        assert!(matches!(reorder(&db, &[a, b, 999], Some("claude_code")).await, Err(AppError::Conflict(_))));
+        assert!(matches!(reorder(&db, &[a, a], Some("claude_code")).await, Err(AppError::Validation(_))));
+        assert!(matches!(reorder(&db, &[a, b, c], Some("claude_code")).await, Err(AppError::Conflict(_))));
+        assert!(matches!(reorder_priority(&db, &[b, a], None).await, Err(AppError::Conflict(_))));
+        assert_eq!(sort_orders(&db).await, before);
+    }
+
+    #[tokio::test]
+    async fn reorder_writes_the_requested_order() {
+        let db = crate::db::test_pool(false).await;
+        let a = insert_provider(&db, "a", "claude_code").await;
+        let b = insert_provider(&db, "b", "claude_code").await;
+
+        reorder(&db, &[b, a], Some("claude_code")).await.unwrap();
+        assert_eq!(sort_orders(&db).await, vec![(a, 1), (b, 0)]);
+    }
+
+    #[test]
+    fn retry_after_accepts_delay_seconds_and_http_dates() {
+        let now = chrono::DateTime::parse_from_rfc2822("Wed, 21 Oct 2026 07:28:00 GMT").unwrap().with_timezone(&chrono::Utc);
+
+        assert_eq!(parse_retry_after("120", now), Some(120));
+        assert_eq!(parse_retry_after(" 5 ", now), Some(5));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2026 07:30:00 GMT", now), Some(120));
+        // Sub-second remainders round up so the provider is not retried early
+        let now_plus = now + chrono::Duration::milliseconds(500);
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2026 07:28:02 GMT", now_plus), Some(2));
+
+        // Already due, or unreadable
+        for value in ["0", "-3", "Wed, 21 Oct 2026 07:27:00 GMT", "Wed, 21 Oct 2026 07:28:00 GMT", "soon", ""] {
+            assert_eq!(parse_retry_after(value, now), None, "{:?}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_provider_is_blacklisted_until_retry_after() {
+        let db = crate::db::test_pool(false).await;
+        let id = insert_provider(&db, "a", "claude_code").await;
+        let before = chrono::Utc::now().timestamp();
+        assert_eq!(record_rate_limited(&db, id, 90).await.unwrap(), "a");
+
+        let until: Option<i64> = sqlx::query_scalar("SELECT blacklisted_until FROM providers WHERE id = ?")
+            .bind(id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let until = until.unwrap();
+        assert!((before + 90..=chrono::Utc::now().timestamp() + 90).contains(&until));
+    }
+}
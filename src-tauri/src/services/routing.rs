@@ -1,6 +1,211 @@
 use sqlx::SqlitePool;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::RwLock;
 
-use crate::db::models::{Provider, ProviderModelMap};
+use crate::db::models::{Provider, ProviderModelMap, ProviderScore, RoutingCandidate, RoutingExplanation};
+use crate::services::concurrency::ConcurrencyLimiter;
+
+/// Smoothing factor for the rolling success rate / latency averages
+const EWMA_ALPHA: f64 = 0.2;
+/// Chance that adaptive routing tries a random provider instead of the best one
+const EXPLORATION_RATE: f64 = 0.1;
+/// Latency at which a provider's score is halved
+const LATENCY_SCALE_MS: f64 = 10_000.0;
+/// Enabled model maps for a provider that apply to a CLI type; CLI-specific maps win over
+/// ones scoped to all types (cli_type NULL)
+const MODEL_MAPS_QUERY: &str = "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 AND (cli_type IS NULL OR cli_type = ?) ORDER BY cli_type IS NULL, id";
+
+/// Provider selection strategy (gateway_settings.routing_strategy)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoutingStrategy {
+    /// Always use the first available provider by sort order
+    #[default]
+    Ordered,
+    /// Pick randomly, weighted by provider score
+    Weighted,
+    /// Pick the best scoring provider, occasionally exploring others
+    Adaptive,
+}
+
+impl RoutingStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoutingStrategy::Ordered => "ordered",
+            RoutingStrategy::Weighted => "weighted",
+            RoutingStrategy::Adaptive => "adaptive",
+        }
+    }
+}
+
+impl std::str::FromStr for RoutingStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ordered" => Ok(RoutingStrategy::Ordered),
+            "weighted" => Ok(RoutingStrategy::Weighted),
+            "adaptive" => Ok(RoutingStrategy::Adaptive),
+            _ => Err(format!("Invalid routing strategy: {} (expected ordered, weighted or adaptive)", s)),
+        }
+    }
+}
+
+/// In-memory rolling success rate and latency per provider.
+/// Scores are not persisted; every provider starts fresh on restart.
+#[derive(Debug, Default)]
+pub struct ProviderScores {
+    scores: RwLock<HashMap<i64, ProviderScore>>,
+}
+
+impl ProviderScores {
+    /// Fold the outcome of one request into the provider's averages
+    pub fn record(&self, provider_id: i64, success: bool, latency_ms: i64) {
+        let outcome = if success { 1.0 } else { 0.0 };
+        let latency = latency_ms.max(0) as f64;
+        let mut scores = self.scores.write().unwrap_or_else(|e| e.into_inner());
+        let entry = scores.entry(provider_id).or_insert(ProviderScore {
+            success_rate: outcome,
+            avg_latency_ms: latency,
+            samples: 0,
+            score: 0.0,
+        });
+        if entry.samples > 0 {
+            entry.success_rate += EWMA_ALPHA * (outcome - entry.success_rate);
+            entry.avg_latency_ms += EWMA_ALPHA * (latency - entry.avg_latency_ms);
+        }
+        entry.samples += 1;
+        entry.score = entry.success_rate / (1.0 + entry.avg_latency_ms / LATENCY_SCALE_MS);
+    }
+
+    pub fn get(&self, provider_id: i64) -> Option<ProviderScore> {
+        self.scores.read().unwrap_or_else(|e| e.into_inner()).get(&provider_id).copied()
+    }
+
+    /// Score used for selection; providers without samples are treated optimistically
+    fn score_of(&self, provider_id: i64) -> f64 {
+        self.get(provider_id).map(|s| s.score).unwrap_or(1.0)
+    }
+}
+
+/// Uniform random number in [0, 1) without pulling in a RNG crate
+pub fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Why a provider was picked for a request, recorded with its log entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionReason {
+    /// Only one provider was available
+    OnlyCandidate,
+    /// First available provider in sort order
+    OrderedFirst,
+    /// Random draw weighted by health score
+    WeightedDraw,
+    /// Highest health score
+    AdaptiveBest,
+    /// Random exploration pick under the adaptive strategy
+    AdaptiveExplore,
+    /// Mirrored copy sent to the shadow provider
+    Shadow,
+    /// Chosen by the client's `x-ccg-provider` header
+    Pinned,
+    /// Pinned with `x-ccg-force`, ignoring pause and blacklist
+    PinnedForced,
+}
+
+impl SelectionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelectionReason::OnlyCandidate => "only_candidate",
+            SelectionReason::OrderedFirst => "ordered_first",
+            SelectionReason::WeightedDraw => "weighted_draw",
+            SelectionReason::AdaptiveBest => "adaptive_best",
+            SelectionReason::AdaptiveExplore => "adaptive_explore",
+            SelectionReason::Shadow => "shadow",
+            SelectionReason::Pinned => "pinned",
+            SelectionReason::PinnedForced => "pinned_forced",
+        }
+    }
+}
+
+/// Choose the index of the provider to use among the sorted candidates
+fn pick_index(providers: &[Provider], strategy: RoutingStrategy, scores: &ProviderScores) -> (usize, SelectionReason) {
+    if providers.len() == 1 {
+        return (0, SelectionReason::OnlyCandidate);
+    }
+    match strategy {
+        RoutingStrategy::Ordered => (0, SelectionReason::OrderedFirst),
+        RoutingStrategy::Weighted => {
+            // Keep a small floor so failing providers still get an occasional retry
+            let weights: Vec<f64> = providers.iter().map(|p| scores.score_of(p.id).max(0.01)).collect();
+            let mut target = random_unit() * weights.iter().sum::<f64>();
+            for (i, w) in weights.iter().enumerate() {
+                if target < *w {
+                    return (i, SelectionReason::WeightedDraw);
+                }
+                target -= w;
+            }
+            (providers.len() - 1, SelectionReason::WeightedDraw)
+        }
+        RoutingStrategy::Adaptive => {
+            if random_unit() < EXPLORATION_RATE {
+                let index = ((random_unit() * providers.len() as f64) as usize).min(providers.len() - 1);
+                return (index, SelectionReason::AdaptiveExplore);
+            }
+            // Ties keep sort order
+            let mut best = 0;
+            let mut best_score = f64::MIN;
+            for (i, p) in providers.iter().enumerate() {
+                let score = scores.score_of(p.id);
+                if score > best_score {
+                    best = i;
+                    best_score = score;
+                }
+            }
+            (best, SelectionReason::AdaptiveBest)
+        }
+    }
+}
+
+/// Chance of each sorted candidate being picked by `pick_index`
+fn selection_probabilities(providers: &[Provider], strategy: RoutingStrategy, scores: &ProviderScores) -> Vec<f64> {
+    let n = providers.len();
+    let mut probabilities = vec![0.0; n];
+    if n == 0 {
+        return probabilities;
+    }
+    match strategy {
+        RoutingStrategy::Ordered => probabilities[0] = 1.0,
+        RoutingStrategy::Weighted => {
+            let weights: Vec<f64> = providers.iter().map(|p| scores.score_of(p.id).max(0.01)).collect();
+            let total: f64 = weights.iter().sum();
+            for (p, w) in probabilities.iter_mut().zip(&weights) {
+                *p = w / total;
+            }
+        }
+        RoutingStrategy::Adaptive => {
+            let explore = if n > 1 { EXPLORATION_RATE } else { 0.0 };
+            for p in probabilities.iter_mut() {
+                *p = explore / n as f64;
+            }
+            let mut best = 0;
+            for (i, p) in providers.iter().enumerate() {
+                if scores.score_of(p.id) > scores.score_of(providers[best].id) {
+                    best = i;
+                }
+            }
+            probabilities[best] += 1.0 - explore;
+        }
+    }
+    probabilities
+}
 
 /// Provider with its model mappings
 #[derive(Debug, Clone)]
@@ -9,42 +214,165 @@ pub struct ProviderWithMaps {
     pub model_maps: Vec<ProviderModelMap>,
 }
 
-/// Select an available provider for the given CLI type
+/// Every provider in failover priority order, each with the reason it can't take a request for
+/// `cli_type` right now (None when eligible). select_provider and explain_routing both use this,
+/// so the dry-run sees exactly the filter real requests go through.
+async fn route_candidates(
+    db: &SqlitePool,
+    cli_type: &str,
+    limiter: &ConcurrencyLimiter,
+) -> Result<Vec<(Provider, Option<&'static str>)>, sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+
+    let providers = sqlx::query_as::<_, Provider>("SELECT * FROM providers ORDER BY priority, sort_order, id")
+        .fetch_all(db)
+        .await?;
+    let served: Vec<i64> = sqlx::query_scalar(
+        "SELECT id FROM providers WHERE cli_type = ? OR id IN (SELECT provider_id FROM provider_cli_types WHERE cli_type = ?)",
+    )
+    .bind(cli_type)
+    .bind(cli_type)
+    .fetch_all(db)
+    .await?;
+
+    let mut candidates: Vec<(Provider, Option<&'static str>)> = providers
+        .into_iter()
+        .map(|p| {
+            let skip_reason = if !served.contains(&p.id) {
+                Some("cli_type_not_served")
+            } else if p.enabled == 0 {
+                Some("disabled")
+            } else if p.paused != 0 {
+                Some("paused")
+            } else if p.blacklist_remaining_secs(now).is_some() {
+                Some("blacklisted")
+            } else {
+                None
+            };
+            (p, skip_reason)
+        })
+        .collect();
+
+    // Prefer providers with a free concurrency slot; if all are saturated the caller queues
+    let saturated = |p: &Provider| limiter.is_saturated(p.id, p.max_concurrency);
+    if candidates.iter().any(|(p, skip)| skip.is_none() && !saturated(p)) {
+        for (p, skip) in candidates.iter_mut() {
+            if skip.is_none() && saturated(p) {
+                *skip = Some("concurrency_saturated");
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// Select an available provider for the given CLI type, with the reason it was picked
 /// Returns None if all providers are blacklisted or none are configured
 pub async fn select_provider(
     db: &SqlitePool,
     cli_type: &str,
+    strategy: RoutingStrategy,
+    scores: &ProviderScores,
+    limiter: &ConcurrencyLimiter,
+) -> Result<Option<(ProviderWithMaps, SelectionReason)>, sqlx::Error> {
+    let mut providers: Vec<Provider> = route_candidates(db, cli_type, limiter)
+        .await?
+        .into_iter()
+        .filter_map(|(p, skip)| skip.is_none().then_some(p))
+        .collect();
+
+    if providers.is_empty() {
+        return Ok(None);
+    }
+
+    // Return the chosen provider with its model maps
+    let (index, reason) = pick_index(&providers, strategy, scores);
+    let provider = providers.swap_remove(index);
+    let model_maps = sqlx::query_as::<_, ProviderModelMap>(
+        MODEL_MAPS_QUERY,
+    )
+    .bind(provider.id)
+    .bind(cli_type)
+    .fetch_all(db)
+    .await?;
+
+    Ok(Some((ProviderWithMaps { provider, model_maps }, reason)))
+}
+
+/// Load a specific provider with its model maps, if it serves the CLI type.
+/// Ignores enabled/paused/blacklist state (used for shadow traffic, not routing).
+pub async fn get_provider_for_cli(
+    db: &SqlitePool,
+    provider_id: i64,
+    cli_type: &str,
+) -> Result<Option<ProviderWithMaps>, sqlx::Error> {
+    let provider = sqlx::query_as::<_, Provider>(
+        "SELECT * FROM providers WHERE id = ? AND (cli_type = ? OR id IN (SELECT provider_id FROM provider_cli_types WHERE cli_type = ?))",
+    )
+    .bind(provider_id)
+    .bind(cli_type)
+    .bind(cli_type)
+    .fetch_optional(db)
+    .await?;
+
+    let Some(provider) = provider else {
+        return Ok(None);
+    };
+    let model_maps = sqlx::query_as::<_, ProviderModelMap>(
+        MODEL_MAPS_QUERY,
+    )
+    .bind(provider.id)
+    .bind(cli_type)
+    .fetch_all(db)
+    .await?;
+
+    Ok(Some(ProviderWithMaps { provider, model_maps }))
+}
+
+/// Load the provider a client pinned by name (or id), if it is enabled and serves the CLI type.
+/// Paused and blacklisted providers are skipped unless `force` is set.
+pub async fn select_pinned_provider(
+    db: &SqlitePool,
+    cli_type: &str,
+    pin: &str,
+    force: bool,
 ) -> Result<Option<ProviderWithMaps>, sqlx::Error> {
     let now = chrono::Utc::now().timestamp();
 
-    // Query enabled providers ordered by sort_order, excluding blacklisted ones
-    let providers = sqlx::query_as::<_, Provider>(
+    // A name match wins over an id match when a provider is named like a number
+    let provider = sqlx::query_as::<_, Provider>(
         r#"
         SELECT * FROM providers
-        WHERE cli_type = ?
+        WHERE (name = ? OR id = ?)
+          AND (cli_type = ? OR id IN (SELECT provider_id FROM provider_cli_types WHERE cli_type = ?))
           AND enabled = 1
-          AND (blacklisted_until IS NULL OR blacklisted_until <= ?)
-        ORDER BY sort_order, id
+        ORDER BY name = ? DESC
+        LIMIT 1
         "#,
     )
+    .bind(pin)
+    .bind(pin.parse::<i64>().ok())
     .bind(cli_type)
-    .bind(now)
-    .fetch_all(db)
+    .bind(cli_type)
+    .bind(pin)
+    .fetch_optional(db)
     .await?;
 
-    // Return the first available provider with its model maps
-    if let Some(provider) = providers.into_iter().next() {
-        let model_maps = sqlx::query_as::<_, ProviderModelMap>(
-            "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY id",
-        )
-        .bind(provider.id)
-        .fetch_all(db)
-        .await?;
-
-        Ok(Some(ProviderWithMaps { provider, model_maps }))
-    } else {
-        Ok(None)
+    let Some(provider) = provider else {
+        return Ok(None);
+    };
+    let available = provider.paused == 0 && provider.blacklisted_until.is_none_or(|until| until <= now);
+    if !available && !force {
+        return Ok(None);
     }
+    let model_maps = sqlx::query_as::<_, ProviderModelMap>(
+        MODEL_MAPS_QUERY,
+    )
+    .bind(provider.id)
+    .bind(cli_type)
+    .fetch_all(db)
+    .await?;
+
+    Ok(Some(ProviderWithMaps { provider, model_maps }))
 }
 
 /// Get all available providers for a CLI type (for fallback scenarios)
@@ -57,13 +385,15 @@ pub async fn get_available_providers(
     let providers = sqlx::query_as::<_, Provider>(
         r#"
         SELECT * FROM providers
-        WHERE cli_type = ?
+        WHERE (cli_type = ? OR id IN (SELECT provider_id FROM provider_cli_types WHERE cli_type = ?))
           AND enabled = 1
+          AND paused = 0
           AND (blacklisted_until IS NULL OR blacklisted_until <= ?)
-        ORDER BY sort_order, id
+        ORDER BY priority, sort_order, id
         "#,
     )
     .bind(cli_type)
+    .bind(cli_type)
     .bind(now)
     .fetch_all(db)
     .await?;
@@ -71,9 +401,10 @@ pub async fn get_available_providers(
     let mut result = Vec::new();
     for provider in providers {
         let model_maps = sqlx::query_as::<_, ProviderModelMap>(
-            "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY id",
+            MODEL_MAPS_QUERY,
         )
         .bind(provider.id)
+        .bind(cli_type)
         .fetch_all(db)
         .await?;
 
@@ -82,3 +413,289 @@ pub async fn get_available_providers(
 
     Ok(result)
 }
+
+/// The request a routing dry-run traces
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoutingQuery<'a> {
+    pub method: &'a str,
+    /// Used for Gemini's model-in-URL and the upstream URL
+    pub path: Option<&'a str>,
+    /// Falls back to the body's `model`, then to the Gemini path
+    pub model: Option<&'a str>,
+    /// Request body, for size-based model maps
+    pub body: Option<&'a [u8]>,
+    /// Provider named by `x-ccg-provider`, and whether `x-ccg-force` applies
+    pub pin: Option<&'a str>,
+    pub force: bool,
+}
+
+/// Trace how a request would be routed right now without sending it: every provider with the
+/// reason it would be skipped, the selection odds of the eligible ones under `strategy`, and
+/// the model each would be sent. Uses the same candidate filter, pinning and model mapping as
+/// the proxy.
+pub async fn explain_routing(
+    db: &SqlitePool,
+    cli_type: crate::services::proxy::CliType,
+    query: RoutingQuery<'_>,
+    strategy: RoutingStrategy,
+    scores: &ProviderScores,
+    limiter: &ConcurrencyLimiter,
+) -> Result<RoutingExplanation, sqlx::Error> {
+    use crate::services::proxy::{find_model_map, gemini_path_model, map_target_model, CliType};
+
+    let now = chrono::Utc::now().timestamp();
+    let cli = cli_type.as_str();
+    let body = query.body.unwrap_or_default();
+
+    let source_model = query
+        .model
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+        .or_else(|| {
+            serde_json::from_slice::<serde_json::Value>(body)
+                .ok()
+                .and_then(|v| v.get("model")?.as_str().map(str::to_string))
+        })
+        .or_else(|| match cli_type {
+            CliType::Gemini => query.path.and_then(gemini_path_model),
+            _ => None,
+        });
+
+    let routed = route_candidates(db, cli, limiter).await?;
+    let eligible: Vec<Provider> = routed
+        .iter()
+        .filter(|(_, skip)| skip.is_none())
+        .map(|(p, _)| p.clone())
+        .collect();
+
+    // A pinned provider that is available takes every request; otherwise routing is as usual
+    let pinned = match query.pin {
+        Some(pin) => select_pinned_provider(db, cli, pin, query.force).await?.map(|p| p.provider.id),
+        None => None,
+    };
+    let probabilities = match pinned {
+        Some(id) => eligible.iter().map(|p| if p.id == id { 1.0 } else { 0.0 }).collect(),
+        None => selection_probabilities(&eligible, strategy, scores),
+    };
+
+    let mut candidates = Vec::with_capacity(routed.len());
+    for (provider, skip_reason) in &routed {
+        let mut candidate = RoutingCandidate {
+            provider_id: provider.id,
+            provider_name: provider.name.clone(),
+            sort_order: provider.sort_order,
+            priority: provider.priority,
+            eligible: skip_reason.is_none(),
+            skip_reason: skip_reason.map(str::to_string),
+            blacklist_remaining_secs: provider.blacklist_remaining_secs(now),
+            score: scores.get(provider.id).map(|s| s.score),
+            selection_probability: 0.0,
+            target_model: None,
+            matched_model_map: None,
+        };
+        if let Some(index) = eligible.iter().position(|p| p.id == provider.id) {
+            candidate.selection_probability = probabilities.get(index).copied().unwrap_or_default();
+        }
+        // A forced pin can select a provider that routing would skip
+        if pinned == Some(provider.id) {
+            candidate.selection_probability = 1.0;
+        }
+        if candidate.eligible || pinned == Some(provider.id) {
+            if let Some(ref model) = source_model {
+                let model_maps = sqlx::query_as::<_, ProviderModelMap>(MODEL_MAPS_QUERY)
+                    .bind(provider.id)
+                    .bind(cli)
+                    .fetch_all(db)
+                    .await?;
+                let map = find_model_map(&model_maps, model);
+                candidate.target_model = Some(map.map(|m| map_target_model(m, body).to_string()).unwrap_or_else(|| model.clone()));
+                candidate.matched_model_map = map.map(|m| m.source_model.clone());
+            }
+        }
+        candidates.push(candidate);
+    }
+
+    // The pin, or else the highest odds; ties keep sort order
+    let selected = match pinned {
+        Some(id) => candidates.iter().find(|c| c.provider_id == id),
+        None => candidates
+            .iter()
+            .filter(|c| c.eligible)
+            .fold(None::<&RoutingCandidate>, |best, c| match best {
+                Some(b) if b.selection_probability >= c.selection_probability => Some(b),
+                _ => Some(c),
+            }),
+    };
+
+    let upstream_url = selected.and_then(|c| {
+        let (provider, _) = routed.iter().find(|(p, _)| p.id == c.provider_id)?;
+        let mut path = query.path.unwrap_or_default().to_string();
+        if let (CliType::Gemini, Some(source), Some(target)) = (cli_type, &source_model, &c.target_model) {
+            path = path.replace(&format!("/models/{}", source), &format!("/models/{}", target));
+        }
+        Some(format!("{}{}", provider.base_url.trim_end_matches('/'), path))
+    });
+
+    Ok(RoutingExplanation {
+        cli_type: cli.to_string(),
+        method: query.method.to_string(),
+        routing_strategy: strategy.as_str().to_string(),
+        pinned: pinned.is_some(),
+        selected_provider_id: selected.map(|c| c.provider_id),
+        selected_provider_name: selected.map(|c| c.provider_name.clone()),
+        target_model: selected.and_then(|c| c.target_model.clone()),
+        matched_model_map: selected.and_then(|c| c.matched_model_map.clone()),
+        upstream_url,
+        source_model,
+        candidates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::proxy::CliType;
+
+    async fn insert_provider(db: &SqlitePool, name: &str, priority: i64) -> i64 {
+        sqlx::query(
+            "INSERT INTO providers (cli_type, name, base_url, api_key, sort_order, priority, created_at, updated_at) VALUES ('claude_code', ?, 'https://api.example.com', 'sk-test', ?, ?, 0, 0)",
+        )
+        .bind(name)
+        .bind(priority)
+        .bind(priority)
+        .execute(db)
+        .await
+        .unwrap()
+        .last_insert_rowid()
+    }
+
+    #[tokio::test]
+    async fn adaptive_routing_deprioritizes_failing_provider_without_blacklisting() {
+        let db = crate::db::test_pool(false).await;
+        let flaky = insert_provider(&db, "flaky", 0).await;
+        let healthy = insert_provider(&db, "healthy", 1).await;
+
+        let scores = ProviderScores::default();
+        for _ in 0..5 {
+            scores.record(flaky, false, 200);
+            scores.record(healthy, true, 200);
+        }
+
+        let explanation = explain_routing(&db, CliType::ClaudeCode, RoutingQuery::default(), RoutingStrategy::Adaptive, &scores, &ConcurrencyLimiter::default())
+            .await
+            .unwrap();
+        let candidate = |id: i64| explanation.candidates.iter().find(|c| c.provider_id == id).unwrap();
+
+        // Still eligible (only exploration reaches it), while the healthy one is preferred
+        assert!(candidate(flaky).eligible);
+        assert!(candidate(flaky).skip_reason.is_none());
+        assert!(candidate(flaky).selection_probability > 0.0);
+        assert!(candidate(healthy).selection_probability > 0.9);
+        assert_eq!(explanation.selected_provider_id, Some(healthy));
+
+        let (picked, _) = select_provider(&db, "claude_code", RoutingStrategy::Ordered, &scores, &ConcurrencyLimiter::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(picked.provider.id, flaky, "ordered routing ignores scores");
+    }
+
+    #[tokio::test]
+    async fn failover_follows_priority_not_display_order() {
+        let db = crate::db::test_pool(false).await;
+        let mut ids = Vec::new();
+        // Listed first in the UI, but last in failover priority
+        for (name, sort_order, priority) in [("first-listed", 0, 2), ("second-listed", 1, 0), ("third-listed", 2, 1)] {
+            let id = sqlx::query(
+                "INSERT INTO providers (cli_type, name, base_url, api_key, sort_order, priority, created_at, updated_at) VALUES ('claude_code', ?, 'https://api.example.com', 'sk-test', ?, ?, 0, 0)",
+            )
+            .bind(name)
+            .bind(sort_order)
+            .bind(priority)
+            .execute(&db)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+            ids.push(id);
+        }
+
+        let (scores, limiter) = (ProviderScores::default(), ConcurrencyLimiter::default());
+        let mut picked = Vec::new();
+        // Blacklist each pick in turn, as failover does
+        while let Some((provider, _)) = select_provider(&db, "claude_code", RoutingStrategy::Ordered, &scores, &limiter)
+            .await
+            .unwrap()
+        {
+            picked.push(provider.provider.id);
+            sqlx::query("UPDATE providers SET blacklisted_until = ? WHERE id = ?")
+                .bind(chrono::Utc::now().timestamp() + 600)
+                .bind(provider.provider.id)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        assert_eq!(picked, [ids[1], ids[2], ids[0]]);
+    }
+
+    #[tokio::test]
+    async fn explanation_matches_selection_for_saturation_size_rules_and_pins() {
+        let db = crate::db::test_pool(false).await;
+        let busy = insert_provider(&db, "busy", 0).await;
+        let free = insert_provider(&db, "free", 1).await;
+        let paused = insert_provider(&db, "paused", 2).await;
+        sqlx::query("UPDATE providers SET max_concurrency = 1 WHERE id = ?").bind(busy).execute(&db).await.unwrap();
+        sqlx::query("UPDATE providers SET paused = 1 WHERE id = ?").bind(paused).execute(&db).await.unwrap();
+        sqlx::query(
+            "INSERT INTO provider_model_map (provider_id, source_model, target_model, large_input_tokens, large_target_model, enabled) VALUES (?, 'claude-*', 'small', 2, 'large', 1)",
+        )
+        .bind(free)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let scores = ProviderScores::default();
+        let limiter = std::sync::Arc::new(ConcurrencyLimiter::default());
+        let _permit = limiter.try_acquire(busy, 1, 0).unwrap();
+
+        let body = br#"{"model":"claude-x","messages":[{"role":"user","content":"long enough"}]}"#;
+        let query = RoutingQuery { method: "POST", path: Some("/v1/messages"), body: Some(body), ..Default::default() };
+        let explanation = explain_routing(&db, CliType::ClaudeCode, query, RoutingStrategy::Ordered, &scores, &limiter)
+            .await
+            .unwrap();
+        let candidate = |id: i64| explanation.candidates.iter().find(|c| c.provider_id == id).unwrap();
+        assert_eq!(candidate(busy).skip_reason.as_deref(), Some("concurrency_saturated"));
+        assert_eq!(explanation.selected_provider_id, Some(free));
+        assert_eq!(explanation.source_model.as_deref(), Some("claude-x"));
+        assert_eq!(explanation.target_model.as_deref(), Some("large"));
+        assert_eq!(explanation.method, "POST");
+        let (picked, _) = select_provider(&db, "claude_code", RoutingStrategy::Ordered, &scores, &limiter).await.unwrap().unwrap();
+        assert_eq!(picked.provider.id, free);
+
+        // A pin takes over; a paused pin only with force
+        let pinned = RoutingQuery { pin: Some("paused"), force: true, ..query };
+        let explanation = explain_routing(&db, CliType::ClaudeCode, pinned, RoutingStrategy::Ordered, &scores, &limiter)
+            .await
+            .unwrap();
+        assert!(explanation.pinned);
+        assert_eq!(explanation.selected_provider_id, Some(paused));
+        let unforced = RoutingQuery { force: false, ..pinned };
+        let explanation = explain_routing(&db, CliType::ClaudeCode, unforced, RoutingStrategy::Ordered, &scores, &limiter)
+            .await
+            .unwrap();
+        assert!(!explanation.pinned);
+        assert_eq!(explanation.selected_provider_id, Some(free));
+    }
+
+    #[test]
+    fn failures_lower_the_rolling_score() {
+        let scores = ProviderScores::default();
+        scores.record(1, true, 100);
+        let before = scores.get(1).unwrap().score;
+        scores.record(1, false, 100);
+        scores.record(1, false, 100);
+        let after = scores.get(1).unwrap();
+        assert!(after.score < before);
+        assert!(after.success_rate > 0.0, "one bad streak must not zero out the provider");
+    }
+}
@@ -0,0 +1,228 @@
+use sqlx::SqlitePool;
+
+use crate::db::models::{BodyCapturePolicy, GatewaySettingsUpdate, LogBodyFormat, UpstreamCompression};
+use crate::services::key_expiry::MAX_KEY_EXPIRY_WARN_DAYS;
+use crate::services::provider::MAX_RETRY_AFTER_SECS;
+use crate::services::routing::RoutingStrategy;
+use crate::services::shutdown::MAX_SHUTDOWN_GRACE_SECS;
+
+/// Shortest accepted admin API token
+pub const MIN_ADMIN_TOKEN_LEN: usize = 16;
+
+/// Check the provided gateway setting fields without touching the database
+fn validate_gateway_update(input: &GatewaySettingsUpdate) -> Result<(), String> {
+    if input.max_timeout_override_ms.is_some_and(|max| max <= 0) {
+        return Err("max_timeout_override_ms must be positive".to_string());
+    }
+    if input.sse_keepalive_ms.is_some_and(|ms| ms < 0) {
+        return Err("sse_keepalive_ms must not be negative".to_string());
+    }
+    if let Some(ref policy) = input.body_capture_policy {
+        policy.parse::<BodyCapturePolicy>()?;
+    }
+    if let Some(ref strategy) = input.routing_strategy {
+        strategy.parse::<RoutingStrategy>()?;
+    }
+    if input.shadow_sample_rate.is_some_and(|rate| !(0.0..=1.0).contains(&rate)) {
+        return Err("shadow_sample_rate must be between 0 and 1".to_string());
+    }
+    if input.log_sample_rate.is_some_and(|rate| !(0.0..=1.0).contains(&rate)) {
+        return Err("log_sample_rate must be between 0 and 1".to_string());
+    }
+    if let Some(ref format) = input.log_body_format {
+        format.parse::<LogBodyFormat>()?;
+    }
+    if let Some(ref compression) = input.upstream_compression {
+        compression.parse::<UpstreamCompression>()?;
+    }
+    if input
+        .shutdown_grace_secs
+        .is_some_and(|secs| !(0..=MAX_SHUTDOWN_GRACE_SECS).contains(&secs))
+    {
+        return Err(format!("shutdown_grace_secs must be between 0 and {}", MAX_SHUTDOWN_GRACE_SECS));
+    }
+    if input
+        .retry_after_max_secs
+        .is_some_and(|secs| !(0..=MAX_RETRY_AFTER_SECS).contains(&secs))
+    {
+        return Err(format!("retry_after_max_secs must be between 0 and {}", MAX_RETRY_AFTER_SECS));
+    }
+    if input
+        .key_expiry_warn_days
+        .is_some_and(|days| !(0..=MAX_KEY_EXPIRY_WARN_DAYS).contains(&days))
+    {
+        return Err(format!("key_expiry_warn_days must be between 0 and {}", MAX_KEY_EXPIRY_WARN_DAYS));
+    }
+    if let Some(ref token) = input.admin_token {
+        let token = token.trim();
+        if !token.is_empty() && token.chars().count() < MIN_ADMIN_TOKEN_LEN {
+            return Err(format!("admin_token must be at least {} characters", MIN_ADMIN_TOKEN_LEN));
+        }
+    }
+    for (name, limit) in [
+        ("log_body_max_depth", input.log_body_max_depth),
+        ("log_body_max_array_items", input.log_body_max_array_items),
+        ("log_body_max_string_chars", input.log_body_max_string_chars),
+        ("max_concurrency", input.max_concurrency),
+    ] {
+        if limit.is_some_and(|n| n < 0) {
+            return Err(format!("{} must not be negative", name));
+        }
+    }
+    Ok(())
+}
+
+/// Apply only the provided gateway setting fields; omitted fields keep their current value.
+/// Returns whether anything was updated.
+pub async fn update_gateway(db: &SqlitePool, input: &GatewaySettingsUpdate) -> Result<bool, String> {
+    validate_gateway_update(input)?;
+
+    // shadow_provider_id = 0 clears the shadow provider
+    if let Some(id) = input.shadow_provider_id.filter(|&id| id != 0) {
+        let exists: Option<i64> = sqlx::query_scalar("SELECT id FROM providers WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        if exists.is_none() {
+            return Err("Shadow provider not found".to_string());
+        }
+    }
+
+    // Build dynamic update query
+    let mut updates = vec!["updated_at = ?"];
+    if input.debug_log.is_some() {
+        updates.push("debug_log = ?");
+    }
+    if input.max_timeout_override_ms.is_some() {
+        updates.push("max_timeout_override_ms = ?");
+    }
+    if input.sse_keepalive_ms.is_some() {
+        updates.push("sse_keepalive_ms = ?");
+    }
+    if input.body_capture_policy.is_some() {
+        updates.push("body_capture_policy = ?");
+    }
+    if input.routing_strategy.is_some() {
+        updates.push("routing_strategy = ?");
+    }
+    if input.shadow_provider_id.is_some() {
+        updates.push("shadow_provider_id = NULLIF(?, 0)");
+    }
+    if input.shadow_sample_rate.is_some() {
+        updates.push("shadow_sample_rate = ?");
+    }
+    if input.log_sample_rate.is_some() {
+        updates.push("log_sample_rate = ?");
+    }
+    if input.log_body_format.is_some() {
+        updates.push("log_body_format = ?");
+    }
+    if input.log_body_max_depth.is_some() {
+        updates.push("log_body_max_depth = ?");
+    }
+    if input.log_body_max_array_items.is_some() {
+        updates.push("log_body_max_array_items = ?");
+    }
+    if input.log_body_max_string_chars.is_some() {
+        updates.push("log_body_max_string_chars = ?");
+    }
+    if input.validate_on_enable.is_some() {
+        updates.push("validate_on_enable = ?");
+    }
+    if input.max_concurrency.is_some() {
+        updates.push("max_concurrency = ?");
+    }
+    if input.shutdown_grace_secs.is_some() {
+        updates.push("shutdown_grace_secs = ?");
+    }
+    if input.allow_force_provider.is_some() {
+        updates.push("allow_force_provider = ?");
+    }
+    if input.admin_token.is_some() {
+        updates.push("admin_token = ?");
+    }
+    if input.upstream_compression.is_some() {
+        updates.push("upstream_compression = ?");
+    }
+    if input.retry_after_max_secs.is_some() {
+        updates.push("retry_after_max_secs = ?");
+    }
+    if input.key_expiry_warn_days.is_some() {
+        updates.push("key_expiry_warn_days = ?");
+    }
+    if input.auto_pause_expired_keys.is_some() {
+        updates.push("auto_pause_expired_keys = ?");
+    }
+    if updates.len() == 1 {
+        return Ok(false);
+    }
+
+    let query = format!("UPDATE gateway_settings SET {} WHERE id = 1", updates.join(", "));
+    let mut q = sqlx::query(&query).bind(chrono::Utc::now().timestamp());
+    if let Some(debug_log) = input.debug_log {
+        q = q.bind(debug_log as i64);
+    }
+    if let Some(max) = input.max_timeout_override_ms {
+        q = q.bind(max);
+    }
+    if let Some(ms) = input.sse_keepalive_ms {
+        q = q.bind(ms);
+    }
+    if let Some(ref policy) = input.body_capture_policy {
+        q = q.bind(policy);
+    }
+    if let Some(ref strategy) = input.routing_strategy {
+        q = q.bind(strategy);
+    }
+    if let Some(id) = input.shadow_provider_id {
+        q = q.bind(id);
+    }
+    if let Some(rate) = input.shadow_sample_rate {
+        q = q.bind(rate);
+    }
+    if let Some(rate) = input.log_sample_rate {
+        q = q.bind(rate);
+    }
+    if let Some(ref format) = input.log_body_format {
+        q = q.bind(format);
+    }
+    for limit in [input.log_body_max_depth, input.log_body_max_array_items, input.log_body_max_string_chars]
+        .into_iter()
+        .flatten()
+    {
+        q = q.bind(limit);
+    }
+    if let Some(validate) = input.validate_on_enable {
+        q = q.bind(validate as i64);
+    }
+    if let Some(max) = input.max_concurrency {
+        q = q.bind(max);
+    }
+    if let Some(secs) = input.shutdown_grace_secs {
+        q = q.bind(secs);
+    }
+    if let Some(allow) = input.allow_force_provider {
+        q = q.bind(allow as i64);
+    }
+    // Empty token turns the admin API off
+    if let Some(ref token) = input.admin_token {
+        let token = token.trim();
+        q = q.bind((!token.is_empty()).then(|| token.to_string()));
+    }
+    if let Some(ref compression) = input.upstream_compression {
+        q = q.bind(compression);
+    }
+    if let Some(secs) = input.retry_after_max_secs {
+        q = q.bind(secs);
+    }
+    if let Some(days) = input.key_expiry_warn_days {
+        q = q.bind(days);
+    }
+    if let Some(auto_pause) = input.auto_pause_expired_keys {
+        q = q.bind(auto_pause as i64);
+    }
+
+    q.execute(db).await.map_err(|e| e.to_string())?;
+    Ok(true)
+}
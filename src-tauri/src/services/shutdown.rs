@@ -0,0 +1,65 @@
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Manager;
+use tokio::sync::Notify;
+
+use crate::services::active_requests::ActiveRequests;
+use crate::LogDb;
+
+/// Upper bound for gateway_settings.shutdown_grace_secs
+pub const MAX_SHUTDOWN_GRACE_SECS: i64 = 300;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tells the HTTP server to stop accepting connections
+static STOP_ACCEPTING: Notify = Notify::const_new();
+
+/// Resolves once shutdown has begun; passed to `with_graceful_shutdown`
+pub async fn stop_signal() {
+    STOP_ACCEPTING.notified().await;
+}
+
+/// Stop accepting new requests, wait up to the configured grace period for in-flight ones
+/// to finish, then exit the process
+pub async fn exit_gracefully(app: &tauri::AppHandle) -> ! {
+    // A permit is stored if the server is not waiting yet, so the signal is never lost
+    STOP_ACCEPTING.notify_one();
+
+    // State is managed once the databases are open; before that there is nothing to drain
+    if let (Some(db), Some(log_db), Some(active)) = (
+        app.try_state::<SqlitePool>(),
+        app.try_state::<LogDb>(),
+        app.try_state::<Arc<ActiveRequests>>(),
+    ) {
+        drain(db.inner(), &log_db.0, &active).await;
+    }
+
+    std::process::exit(0);
+}
+
+async fn drain(db: &SqlitePool, log_db: &SqlitePool, active: &ActiveRequests) {
+    let grace_secs: i64 = sqlx::query_scalar("SELECT shutdown_grace_secs FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(0)
+        .clamp(0, MAX_SHUTDOWN_GRACE_SECS);
+    let grace = Duration::from_secs(grace_secs as u64);
+
+    let at_start = active.list().len();
+    let deadline = tokio::time::Instant::now() + grace;
+    let mut remaining = at_start;
+    while remaining > 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        remaining = active.list().len();
+    }
+
+    tracing::info!(in_flight = at_start, dropped = remaining, grace_secs, "Shutting down");
+    let message = if remaining > 0 {
+        format!("应用退出：退出时有 {} 个请求进行中，等待 {} 秒后仍有 {} 个未完成，已中断", at_start, grace_secs, remaining)
+    } else {
+        format!("应用退出：退出时有 {} 个请求进行中，均已完成", at_start)
+    };
+    let _ = crate::services::stats::record_system_log(log_db, "app_shutdown", &message).await;
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
+
+/// 取消安装时返回的错误信息，前端据此区分取消与失败
+pub const INSTALL_CANCELLED: &str = "Skill installation cancelled";
+
+/// 进行中的 Skill 安装，按 skill key 索引；guard 释放时移除
+#[derive(Default)]
+pub struct SkillInstalls {
+    installs: RwLock<HashMap<String, watch::Sender<bool>>>,
+}
+
+impl SkillInstalls {
+    /// 登记一次安装，直到返回的 guard 被释放；同一 skill 已在安装时返回错误
+    pub fn register(self: &Arc<Self>, key: &str) -> Result<SkillInstallGuard, String> {
+        let mut installs = self.installs.write().unwrap_or_else(|e| e.into_inner());
+        if installs.contains_key(key) {
+            return Err(format!("Skill '{}' is already being installed", key));
+        }
+        let (cancel, cancelled) = watch::channel(false);
+        installs.insert(key.to_string(), cancel);
+        Ok(SkillInstallGuard {
+            registry: self.clone(),
+            key: key.to_string(),
+            cancelled,
+        })
+    }
+
+    /// 通知安装中止；没有进行中的安装时返回 false
+    pub fn cancel(&self, key: &str) -> bool {
+        let installs = self.installs.read().unwrap_or_else(|e| e.into_inner());
+        match installs.get(key) {
+            Some(cancel) => {
+                cancel.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        self.installs.write().unwrap_or_else(|e| e.into_inner()).remove(key);
+    }
+}
+
+/// 保持安装处于登记状态，并传递取消信号
+pub struct SkillInstallGuard {
+    registry: Arc<SkillInstalls>,
+    key: String,
+    cancelled: watch::Receiver<bool>,
+}
+
+impl SkillInstallGuard {
+    /// 是否已请求取消（用于同步的解压循环）
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// 调用 `cancel_skill_install` 后完成
+    pub async fn cancelled(&self) {
+        let mut rx = self.cancelled.clone();
+        // 发送端在 guard 释放前一直保留在登记表中，只有移除后才会出错
+        if rx.wait_for(|c| *c).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for SkillInstallGuard {
+    fn drop(&mut self) {
+        self.registry.remove(&self.key);
+    }
+}
@@ -1,7 +1,17 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveTime, TimeZone};
 use sqlx::SqlitePool;
-use crate::db::models::RequestLogInfo;
+use std::collections::BTreeMap;
+use crate::db::models::{DailyStats, LatencyBucket, LogBodyFormat, LogBodyShape, ProviderStatsResponse, ProviderStatsRow, RequestLogInfo};
+use crate::db::retry_on_locked;
+use crate::services::routing::random_unit;
 
-/// Record a request in the daily usage statistics
+/// Width of a usage_buckets row in seconds.
+/// 15 minutes lines up with every real-world UTC offset (e.g. +05:30, +05:45).
+const USAGE_BUCKET_SECS: i64 = 900;
+
+/// Record a request in the usage statistics.
+/// `usage_daily` is bucketed by UTC date; `usage_buckets` keeps 15-minute UTC buckets
+/// so daily totals can be regrouped for any timezone.
 pub async fn record_request(
     log_db: &SqlitePool,
     provider_name: &str,
@@ -10,35 +20,380 @@ pub async fn record_request(
     input_tokens: i64,
     output_tokens: i64,
 ) -> Result<(), sqlx::Error> {
-    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    let now = chrono::Utc::now();
+    let today = now.format("%Y-%m-%d").to_string();
+    let bucket_start = now.timestamp() - now.timestamp().rem_euclid(USAGE_BUCKET_SECS);
+
+    retry_on_locked(|| {
+        sqlx::query(
+            r#"
+            INSERT INTO usage_buckets (bucket_start, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens)
+            VALUES (?, ?, ?, 1, ?, ?, ?, ?)
+            ON CONFLICT(bucket_start, provider_name, cli_type) DO UPDATE SET
+                request_count = request_count + 1,
+                success_count = success_count + excluded.success_count,
+                failure_count = failure_count + excluded.failure_count,
+                input_tokens = input_tokens + excluded.input_tokens,
+                output_tokens = output_tokens + excluded.output_tokens
+            "#,
+        )
+        .bind(bucket_start)
+        .bind(provider_name)
+        .bind(cli_type)
+        .bind(if success { 1 } else { 0 })
+        .bind(if success { 0 } else { 1 })
+        .bind(input_tokens)
+        .bind(output_tokens)
+        .execute(log_db)
+    })
+    .await?;
 
     // Upsert into usage_daily table
-    sqlx::query(
+    retry_on_locked(|| {
+        sqlx::query(
+            r#"
+            INSERT INTO usage_daily (usage_date, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens)
+            VALUES (?, ?, ?, 1, ?, ?, ?, ?)
+            ON CONFLICT(usage_date, provider_name, cli_type) DO UPDATE SET
+                request_count = request_count + 1,
+                success_count = success_count + excluded.success_count,
+                failure_count = failure_count + excluded.failure_count,
+                input_tokens = input_tokens + excluded.input_tokens,
+                output_tokens = output_tokens + excluded.output_tokens
+            "#,
+        )
+        .bind(&today)
+        .bind(provider_name)
+        .bind(cli_type)
+        .bind(if success { 1 } else { 0 })
+        .bind(if success { 0 } else { 1 })
+        .bind(input_tokens)
+        .bind(output_tokens)
+        .execute(log_db)
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Timezone used to group stats into calendar days.
+/// Offsets are resolved per timestamp, so DST transitions inside a range are honoured.
+#[derive(Debug, Clone, Copy)]
+pub enum StatsTimezone {
+    Local,
+    Fixed(FixedOffset),
+    Named(chrono_tz::Tz),
+}
+
+impl StatsTimezone {
+    /// Parse a stats timezone.
+    ///
+    /// Accepts `local` (or nothing) for the system timezone, `UTC`/`Z`, IANA names such as
+    /// `Asia/Shanghai` or `America/New_York`, and fixed offsets such as `+08:00`, `-0530`,
+    /// `+8` or `UTC+8`.
+    pub fn parse(timezone: Option<&str>) -> Result<Self, String> {
+        let tz = timezone.map(str::trim).unwrap_or("");
+        if tz.is_empty() || tz.eq_ignore_ascii_case("local") {
+            return Ok(Self::Local);
+        }
+        if ["utc", "z", "gmt"].iter().any(|n| tz.eq_ignore_ascii_case(n)) {
+            return Ok(Self::Fixed(FixedOffset::east_opt(0).unwrap()));
+        }
+        if let Ok(named) = tz.parse::<chrono_tz::Tz>() {
+            return Ok(Self::Named(named));
+        }
+
+        let invalid = || format!("Unsupported timezone: {} (use local, UTC, an IANA name or an offset like +08:00)", tz);
+        let offset = tz
+            .strip_prefix("UTC")
+            .or_else(|| tz.strip_prefix("GMT"))
+            .unwrap_or(tz);
+        let (sign, rest) = match offset.as_bytes().first() {
+            Some(b'+') => (1, &offset[1..]),
+            Some(b'-') => (-1, &offset[1..]),
+            _ => return Err(invalid()),
+        };
+        let (hours, minutes) = match rest.split_once(':') {
+            Some((h, m)) => (h, m),
+            None if rest.len() == 4 => rest.split_at(2),
+            None => (rest, "0"),
+        };
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 14 || minutes >= 60 {
+            return Err(invalid());
+        }
+
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Self::Fixed)
+            .ok_or_else(invalid)
+    }
+
+    /// Calendar date of a unix timestamp in this timezone
+    pub fn date_of(&self, ts: i64) -> NaiveDate {
+        let utc = DateTime::from_timestamp(ts, 0).unwrap_or_default();
+        match self {
+            Self::Local => utc.with_timezone(&Local).date_naive(),
+            Self::Fixed(offset) => utc.with_timezone(offset).date_naive(),
+            Self::Named(tz) => utc.with_timezone(tz).date_naive(),
+        }
+    }
+
+    /// Unix timestamp at which `date` starts in this timezone
+    pub fn day_start(&self, date: NaiveDate) -> i64 {
+        match self {
+            Self::Local => local_midnight(&Local, date),
+            Self::Fixed(offset) => local_midnight(offset, date),
+            Self::Named(tz) => local_midnight(tz, date),
+        }
+    }
+}
+
+/// First instant of `date` in `tz`. Zones that skip midnight on a DST change start the day
+/// at the first local time that exists.
+fn local_midnight<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> i64 {
+    let midnight = date.and_time(NaiveTime::MIN);
+    (0..=24 * 4)
+        .find_map(|quarter| {
+            tz.from_local_datetime(&(midnight + chrono::Duration::minutes(quarter * 15)))
+                .earliest()
+        })
+        .map(|t| t.timestamp())
+        .unwrap_or_else(|| midnight.and_utc().timestamp())
+}
+
+/// Parse an optional `YYYY-MM-DD` stats date
+pub fn parse_stats_date(date: Option<&str>) -> Result<Option<NaiveDate>, String> {
+    date.map(|d| {
+        NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").map_err(|_| format!("Invalid date: {} (expected YYYY-MM-DD)", d))
+    })
+    .transpose()
+}
+
+/// Daily usage grouped by calendar day in `tz`; both `start_date` and `end_date` are inclusive.
+///
+/// Days come from `usage_buckets`, each bucket dated with the offset in effect at its own start.
+/// Days recorded before buckets existed fall back to the UTC-dated `usage_daily` rows so older
+/// history is still reported.
+pub async fn query_daily_stats(
+    log_db: &SqlitePool,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    cli_type: Option<&str>,
+    tz: StatsTimezone,
+) -> Result<Vec<DailyStats>, sqlx::Error> {
+    let start_ts = start_date.map(|d| tz.day_start(d));
+    let end_ts = end_date.and_then(|d| d.succ_opt()).map(|d| tz.day_start(d));
+
+    let mut sql = r#"
+        SELECT bucket_start, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens
+        FROM usage_buckets
+        WHERE 1=1
+    "#.to_string();
+    if start_ts.is_some() {
+        sql.push_str(" AND bucket_start >= ?");
+    }
+    if end_ts.is_some() {
+        sql.push_str(" AND bucket_start < ?");
+    }
+    if cli_type.is_some() {
+        sql.push_str(" AND cli_type = ?");
+    }
+    let mut q = sqlx::query_as::<_, (i64, String, String, i64, i64, i64, i64, i64)>(&sql);
+    if let Some(ts) = start_ts {
+        q = q.bind(ts);
+    }
+    if let Some(ts) = end_ts {
+        q = q.bind(ts);
+    }
+    if let Some(ct) = cli_type {
+        q = q.bind(ct);
+    }
+
+    let mut days: BTreeMap<(String, String, String), DailyStats> = BTreeMap::new();
+    for (bucket_start, provider_name, cli_type, requests, success, failure, input, output) in q.fetch_all(log_db).await? {
+        let usage_date = tz.date_of(bucket_start).format("%Y-%m-%d").to_string();
+        let day = days
+            .entry((usage_date.clone(), provider_name.clone(), cli_type.clone()))
+            .or_insert_with(|| DailyStats {
+                usage_date,
+                provider_name,
+                cli_type,
+                request_count: 0,
+                success_count: 0,
+                failure_count: 0,
+                input_tokens: 0,
+                output_tokens: 0,
+            });
+        day.request_count += requests;
+        day.success_count += success;
+        day.failure_count += failure;
+        day.input_tokens += input;
+        day.output_tokens += output;
+    }
+
+    let mut sql = r#"
+        SELECT usage_date, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens
+        FROM usage_daily
+        WHERE usage_date < (SELECT COALESCE(date(MIN(bucket_start), 'unixepoch'), '9999-12-31') FROM usage_buckets)
+    "#.to_string();
+    if start_date.is_some() {
+        sql.push_str(" AND usage_date >= ?");
+    }
+    if end_date.is_some() {
+        sql.push_str(" AND usage_date <= ?");
+    }
+    if cli_type.is_some() {
+        sql.push_str(" AND cli_type = ?");
+    }
+    let mut q = sqlx::query_as::<_, DailyStats>(&sql);
+    if let Some(sd) = start_date {
+        q = q.bind(sd.format("%Y-%m-%d").to_string());
+    }
+    if let Some(ed) = end_date {
+        q = q.bind(ed.format("%Y-%m-%d").to_string());
+    }
+    if let Some(ct) = cli_type {
+        q = q.bind(ct);
+    }
+
+    let mut rows = q.fetch_all(log_db).await?;
+    rows.extend(days.into_values());
+    rows.sort_by(|a, b| b.usage_date.cmp(&a.usage_date));
+    Ok(rows)
+}
+
+/// Per provider and model totals from request_logs between the start of `start_date` and the end
+/// of `end_date` in `tz`, both inclusive. Log sampling drops only successes, so every row is
+/// weighted by its `sample_weight`; while sampling is active the counts, success rate and
+/// token totals are estimates rather than exact figures.
+pub async fn query_provider_stats(
+    log_db: &SqlitePool,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    cli_type: Option<&str>,
+    provider_name: Option<&str>,
+    tz: StatsTimezone,
+) -> Result<Vec<ProviderStatsResponse>, sqlx::Error> {
+    let start_ts = start_date.map(|d| tz.day_start(d));
+    let end_ts = end_date.and_then(|d| d.succ_opt()).map(|d| tz.day_start(d));
+
+    let mut query = r#"
+        SELECT
+            cli_type,
+            provider_name,
+            model_id,
+            CAST(ROUND(SUM(sample_weight)) AS INTEGER) as total_requests,
+            CAST(ROUND(SUM(CASE WHEN status_code >= 200 AND status_code < 300 THEN sample_weight ELSE 0 END)) AS INTEGER) as total_success,
+            CAST(ROUND(SUM((input_tokens + output_tokens) * sample_weight)) AS INTEGER) as total_tokens,
+            CAST(ROUND(SUM(elapsed_ms * sample_weight)) AS INTEGER) as total_elapsed_ms
+        FROM request_logs
+        WHERE is_shadow = 0
+    "#.to_string();
+
+    if start_ts.is_some() {
+        query.push_str(" AND created_at >= ?");
+    }
+    if end_ts.is_some() {
+        query.push_str(" AND created_at < ?");
+    }
+    if cli_type.is_some() {
+        query.push_str(" AND cli_type = ?");
+    }
+    if provider_name.is_some() {
+        query.push_str(" AND provider_name = ?");
+    }
+    query.push_str(" GROUP BY cli_type, provider_name, model_id ORDER BY total_requests DESC");
+
+    let mut q = sqlx::query_as::<_, ProviderStatsRow>(&query);
+    if let Some(ts) = start_ts {
+        q = q.bind(ts);
+    }
+    if let Some(ts) = end_ts {
+        q = q.bind(ts);
+    }
+    if let Some(ct) = cli_type {
+        q = q.bind(ct);
+    }
+    if let Some(pn) = provider_name {
+        q = q.bind(pn);
+    }
+
+    let rows = q.fetch_all(log_db).await?;
+
+    Ok(rows.into_iter().map(|row| ProviderStatsResponse {
+        cli_type: row.cli_type,
+        provider_name: row.provider_name,
+        model_id: row.model_id,
+        total_requests: row.total_requests,
+        total_success: row.total_success,
+        total_tokens: row.total_tokens,
+        total_elapsed_ms: row.total_elapsed_ms,
+        success_rate: if row.total_requests > 0 {
+            (row.total_success as f64 / row.total_requests as f64) * 100.0
+        } else {
+            0.0
+        },
+    }).collect())
+}
+
+/// Most buckets a single latency series query may return
+const MAX_LATENCY_BUCKETS: i64 = 10_000;
+
+/// Average and p95 latency of a provider's successful requests per time bucket in [start, end).
+/// Buckets without requests are omitted. Reads request_logs, so sampled-out requests are not
+/// counted; the latency figures stay representative.
+pub async fn query_latency_series(
+    log_db: &SqlitePool,
+    provider_name: &str,
+    bucket_secs: i64,
+    start: i64,
+    end: i64,
+) -> Result<Vec<LatencyBucket>, String> {
+    if end <= start {
+        return Err("end must be after start".to_string());
+    }
+    if (end - start) / bucket_secs > MAX_LATENCY_BUCKETS {
+        return Err(format!("Time range too large for this bucket size (max {} buckets)", MAX_LATENCY_BUCKETS));
+    }
+
+    // Sorted by bucket, then latency, so each bucket's percentile is a direct index
+    let rows = sqlx::query_as::<_, (i64, i64)>(
         r#"
-        INSERT INTO usage_daily (usage_date, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens)
-        VALUES (?, ?, ?, 1, ?, ?, ?, ?)
-        ON CONFLICT(usage_date, provider_name, cli_type) DO UPDATE SET
-            request_count = request_count + 1,
-            success_count = success_count + excluded.success_count,
-            failure_count = failure_count + excluded.failure_count,
-            input_tokens = input_tokens + excluded.input_tokens,
-            output_tokens = output_tokens + excluded.output_tokens
+        SELECT (created_at / ?) * ? AS bucket_start, elapsed_ms
+        FROM request_logs
+        WHERE provider_name = ? AND is_shadow = 0
+          AND status_code >= 200 AND status_code < 300
+          AND created_at >= ? AND created_at < ?
+        ORDER BY bucket_start, elapsed_ms
         "#,
     )
-    .bind(&today)
+    .bind(bucket_secs)
+    .bind(bucket_secs)
     .bind(provider_name)
-    .bind(cli_type)
-    .bind(if success { 1 } else { 0 })
-    .bind(if success { 0 } else { 1 })
-    .bind(input_tokens)
-    .bind(output_tokens)
-    .execute(log_db)
-    .await?;
+    .bind(start)
+    .bind(end)
+    .fetch_all(log_db)
+    .await
+    .map_err(|e| e.to_string())?;
 
-    Ok(())
+    let mut series = Vec::new();
+    for chunk in rows.chunk_by(|a, b| a.0 == b.0) {
+        let latencies: Vec<i64> = chunk.iter().map(|(_, ms)| *ms).collect();
+        let count = latencies.len();
+        // Nearest-rank p95
+        let p95_index = ((count as f64 * 0.95).ceil() as usize).clamp(1, count) - 1;
+        series.push(LatencyBucket {
+            bucket_start: chunk[0].0,
+            request_count: count as i64,
+            avg_ms: latencies.iter().sum::<i64>() as f64 / count as f64,
+            p95_ms: latencies[p95_index],
+        });
+    }
+    Ok(series)
 }
 
-/// Record a request log entry
+/// Record a request log entry; successful requests are subject to `info.log_sample_rate`
 pub async fn record_request_log(
     log_db: &SqlitePool,
     cli_type: &str,
@@ -53,58 +408,352 @@ pub async fn record_request_log(
     info: Option<RequestLogInfo>,
 ) -> Result<(), sqlx::Error> {
     let now = chrono::Utc::now().timestamp();
-    let info = info.unwrap_or_default();
+    let mut info = info.unwrap_or_default();
 
-    sqlx::query(
-        r#"
-        INSERT INTO request_logs (created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, error_message)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-        "#,
-    )
-    .bind(now)
-    .bind(cli_type)
-    .bind(provider_name)
-    .bind(model_id)
-    .bind(status_code.map(|c| c as i64))
-    .bind(elapsed_ms)
-    .bind(input_tokens)
-    .bind(output_tokens)
-    .bind(client_method)
-    .bind(client_path)
-    .bind(&info.client_headers)
-    .bind(&info.client_body)
-    .bind(&info.forward_url)
-    .bind(&info.forward_headers)
-    .bind(&info.forward_body)
-    .bind(&info.provider_headers)
-    .bind(&info.provider_body)
-    .bind(&info.error_message)
-    .execute(log_db)
+    // Drop bodies the capture policy doesn't want persisted
+    let is_error = info.error_message.is_some()
+        || !status_code.map(|c| (200..300).contains(&c)).unwrap_or(false);
+    if !info.body_capture.should_capture(is_error) {
+        info.client_body = None;
+        info.forward_body = None;
+        info.provider_body = None;
+    }
+
+    // Errors are always logged; successes only at the sample rate. usage_daily / usage_buckets
+    // are recorded separately for every request, so prefer them over request_logs for totals.
+    // Kept successes stand in for the dropped ones through their weight (1 / rate), so
+    // aggregates over request_logs stay unbiased estimates.
+    let mut sample_weight = 1.0;
+    if let Some(rate) = info.log_sample_rate.filter(|rate| !is_error && *rate < 1.0) {
+        if random_unit() >= rate {
+            return Ok(());
+        }
+        sample_weight = 1.0 / rate;
+    }
+
+    // Shape the complete JSON first; cutting it to the size cap beforehand would leave
+    // nothing parseable to shape
+    let shape = info.body_shape;
+    for body in [&mut info.client_body, &mut info.forward_body, &mut info.provider_body] {
+        *body = body.take().map(|b| {
+            let b = if shape.is_raw() { b } else { shape_logged_body(b, &shape) };
+            truncate_logged_body(b)
+        });
+    }
+
+    retry_on_locked(|| {
+        sqlx::query(
+            r#"
+            INSERT INTO request_logs (created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, error_message, effective_timeout_ms, error_kind, is_shadow, selection_reason, client_ip, sample_weight)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(now)
+        .bind(cli_type)
+        .bind(provider_name)
+        .bind(model_id)
+        .bind(status_code.map(|c| c as i64))
+        .bind(elapsed_ms)
+        .bind(input_tokens)
+        .bind(output_tokens)
+        .bind(client_method)
+        .bind(client_path)
+        .bind(&info.client_headers)
+        .bind(&info.client_body)
+        .bind(&info.forward_url)
+        .bind(&info.forward_headers)
+        .bind(&info.forward_body)
+        .bind(&info.provider_headers)
+        .bind(&info.provider_body)
+        .bind(&info.error_message)
+        .bind(info.effective_timeout_ms)
+        .bind(&info.error_kind)
+        .bind(info.is_shadow as i64)
+        .bind(&info.selection_reason)
+        .bind(&info.client_ip)
+        .bind(sample_weight)
+        .execute(log_db)
+    })
     .await?;
 
     Ok(())
 }
 
-/// Record a system log entry
-pub async fn record_system_log(
+/// Size cap for each body stored in request_logs
+const MAX_LOGGED_BODY_BYTES: usize = 100 * 1024;
+
+fn truncate_logged_body(mut body: String) -> String {
+    if body.len() <= MAX_LOGGED_BODY_BYTES {
+        return body;
+    }
+    let mut end = MAX_LOGGED_BODY_BYTES;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    body.truncate(end);
+    body.push_str("...[truncated]");
+    body
+}
+
+/// Reformat a JSON body for storage per the shape settings. Bodies that are not a single
+/// JSON document (SSE streams) are stored unchanged.
+fn shape_logged_body(body: String, shape: &LogBodyShape) -> String {
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(&body) else {
+        return body;
+    };
+    collapse_json(&mut value, shape, 0);
+    let shaped = match shape.format {
+        LogBodyFormat::Pretty => serde_json::to_string_pretty(&value),
+        _ => serde_json::to_string(&value),
+    };
+    shaped.unwrap_or(body)
+}
+
+/// Apply the depth/array/string limits in place, leaving a marker wherever data was dropped
+fn collapse_json(value: &mut serde_json::Value, shape: &LogBodyShape, depth: usize) {
+    use serde_json::Value;
+
+    let collapsed_at_depth = shape.max_depth > 0 && depth >= shape.max_depth;
+    match value {
+        Value::String(s) if shape.max_string_chars > 0 => {
+            let total = s.chars().count();
+            if total > shape.max_string_chars {
+                let kept: String = s.chars().take(shape.max_string_chars).collect();
+                *s = format!("{}...[+{} chars]", kept, total - shape.max_string_chars);
+            }
+        }
+        Value::Array(items) if collapsed_at_depth => {
+            *value = Value::String(format!("[array: {} items]", items.len()));
+        }
+        Value::Object(map) if collapsed_at_depth => {
+            *value = Value::String(format!("[object: {} keys]", map.len()));
+        }
+        Value::Array(items) => {
+            let dropped = match shape.max_array_items {
+                0 => 0,
+                max => items.len().saturating_sub(max),
+            };
+            items.truncate(items.len() - dropped);
+            for item in items.iter_mut() {
+                collapse_json(item, shape, depth + 1);
+            }
+            if dropped > 0 {
+                items.push(Value::String(format!("[... {} more items]", dropped)));
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                collapse_json(item, shape, depth + 1);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Record a system log entry, tagged with the calling file and line.
+/// Not an `async fn` so `#[track_caller]` sees the call site rather than the future's poll.
+#[track_caller]
+pub fn record_system_log<'a>(
+    log_db: &'a SqlitePool,
+    event_type: &'a str,
+    message: &'a str,
+) -> impl std::future::Future<Output = Result<(), sqlx::Error>> + 'a {
+    let caller = std::panic::Location::caller();
+    let source = format!("{}:{}", caller.file(), caller.line());
+    insert_system_log(log_db, event_type, message, source)
+}
+
+async fn insert_system_log(
     log_db: &SqlitePool,
     event_type: &str,
     message: &str,
+    source: String,
 ) -> Result<(), sqlx::Error> {
     let now = chrono::Utc::now().timestamp();
 
-    sqlx::query(
-        r#"
-        INSERT INTO system_logs (created_at, event_type, message)
-        VALUES (?, ?, ?)
-        "#,
-    )
-    .bind(now)
-    .bind(event_type)
-    .bind(message)
-    .execute(log_db)
+    retry_on_locked(|| {
+        sqlx::query(
+            r#"
+            INSERT INTO system_logs (created_at, event_type, message, source)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(now)
+        .bind(event_type)
+        .bind(message)
+        .bind(&source)
+        .execute(log_db)
+    })
     .await?;
 
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(rfc3339: &str) -> i64 {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().timestamp()
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    async fn insert_bucket(log_db: &SqlitePool, bucket_start: i64, requests: i64) {
+        sqlx::query(
+            "INSERT INTO usage_buckets (bucket_start, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens) VALUES (?, 'p1', 'claude_code', ?, ?, 0, 0, 0)",
+        )
+        .bind(bucket_start)
+        .bind(requests)
+        .bind(requests)
+        .execute(log_db)
+        .await
+        .unwrap();
+    }
+
+    async fn insert_log(log_db: &SqlitePool, created_at: i64) {
+        sqlx::query(
+            "INSERT INTO request_logs (created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, client_method, client_path) VALUES (?, 'claude_code', 'p1', 'm1', 200, 10, 'POST', '/v1/messages')",
+        )
+        .bind(created_at)
+        .execute(log_db)
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn parses_names_and_offsets() {
+        assert!(matches!(StatsTimezone::parse(None), Ok(StatsTimezone::Local)));
+        assert!(matches!(StatsTimezone::parse(Some("Asia/Shanghai")), Ok(StatsTimezone::Named(_))));
+        assert!(matches!(StatsTimezone::parse(Some("UTC+8")), Ok(StatsTimezone::Fixed(o)) if o.local_minus_utc() == 8 * 3600));
+        assert!(matches!(StatsTimezone::parse(Some("-0530")), Ok(StatsTimezone::Fixed(o)) if o.local_minus_utc() == -(5 * 3600 + 1800)));
+        assert!(StatsTimezone::parse(Some("Mars/Olympus")).is_err());
+    }
+
+    #[tokio::test]
+    async fn daily_stats_split_days_at_local_midnight() {
+        let log_db = crate::db::test_pool(true).await;
+        // 23:45 and 00:00 on either side of midnight at +08:00
+        insert_bucket(&log_db, ts("2024-03-10T15:45:00Z"), 1).await;
+        insert_bucket(&log_db, ts("2024-03-10T16:00:00Z"), 2).await;
+
+        let tz = StatsTimezone::parse(Some("+08:00")).unwrap();
+        let rows = query_daily_stats(&log_db, Some(date("2024-03-11")), Some(date("2024-03-11")), None, tz).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].usage_date, "2024-03-11");
+        assert_eq!(rows[0].request_count, 2);
+
+        let rows = query_daily_stats(&log_db, None, None, None, StatsTimezone::parse(Some("UTC")).unwrap()).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].usage_date, "2024-03-10");
+        assert_eq!(rows[0].request_count, 3);
+    }
+
+    #[tokio::test]
+    async fn daily_stats_follow_dst_changes_inside_the_range() {
+        let log_db = crate::db::test_pool(true).await;
+        // New York moves from -05:00 to -04:00 on 2024-03-10
+        insert_bucket(&log_db, ts("2024-03-09T04:30:00Z"), 1).await; // 23:30 EST on the 8th
+        insert_bucket(&log_db, ts("2024-03-11T04:30:00Z"), 1).await; // 00:30 EDT on the 11th
+
+        let tz = StatsTimezone::parse(Some("America/New_York")).unwrap();
+        let rows = query_daily_stats(&log_db, Some(date("2024-03-08")), Some(date("2024-03-11")), None, tz).await.unwrap();
+        let dates: Vec<_> = rows.iter().map(|r| r.usage_date.as_str()).collect();
+        assert_eq!(dates, vec!["2024-03-11", "2024-03-08"]);
+    }
+
+    #[tokio::test]
+    async fn provider_stats_include_the_end_day() {
+        let log_db = crate::db::test_pool(true).await;
+        insert_log(&log_db, ts("2024-03-10T15:59:00Z")).await; // 23:59 on the 10th at +08:00
+        insert_log(&log_db, ts("2024-03-11T15:30:00Z")).await; // 23:30 on the 11th
+        insert_log(&log_db, ts("2024-03-11T16:00:00Z")).await; // 00:00 on the 12th
+
+        let tz = StatsTimezone::parse(Some("Asia/Shanghai")).unwrap();
+        let rows = query_provider_stats(&log_db, Some(date("2024-03-11")), Some(date("2024-03-11")), None, None, tz).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].total_requests, 1);
+
+        let rows = query_provider_stats(&log_db, Some(date("2024-03-10")), Some(date("2024-03-11")), None, None, tz).await.unwrap();
+        assert_eq!(rows[0].total_requests, 2);
+    }
+
+    fn shape(max_depth: usize, max_array_items: usize, max_string_chars: usize) -> LogBodyShape {
+        LogBodyShape { format: LogBodyFormat::Compact, max_depth, max_array_items, max_string_chars }
+    }
+
+    #[test]
+    fn collapse_json_applies_each_limit() {
+        let collapse = |body: serde_json::Value, shape: LogBodyShape| {
+            let mut body = body;
+            collapse_json(&mut body, &shape, 0);
+            body
+        };
+        let nested = serde_json::json!({ "a": { "b": { "c": 1 } }, "list": [[1, 2]] });
+        assert_eq!(
+            collapse(nested, shape(2, 0, 0)),
+            serde_json::json!({ "a": { "b": "[object: 1 keys]" }, "list": ["[array: 2 items]"] })
+        );
+
+        let items = serde_json::json!({ "messages": [1, 2, 3, 4, 5] });
+        assert_eq!(collapse(items, shape(0, 2, 0)), serde_json::json!({ "messages": [1, 2, "[... 3 more items]"] }));
+
+        let text = serde_json::json!({ "content": "héllo world", "short": "ok" });
+        assert_eq!(
+            collapse(text, shape(0, 0, 5)),
+            serde_json::json!({ "content": "héllo...[+6 chars]", "short": "ok" })
+        );
+    }
+
+    #[tokio::test]
+    async fn over_cap_bodies_are_shaped_before_truncation() {
+        let log_db = crate::db::test_pool(true).await;
+        let large = serde_json::json!({ "model": "m", "content": "x".repeat(MAX_LOGGED_BODY_BYTES * 2) }).to_string();
+        let info = |body_shape| RequestLogInfo {
+            client_body: Some(large.clone()),
+            body_shape,
+            ..Default::default()
+        };
+        record_request_log(&log_db, "claude_code", "p1", None, Some(200), 1, 0, 0, "POST", "/v1/messages", Some(info(shape(0, 0, 10))))
+            .await
+            .unwrap();
+        record_request_log(&log_db, "claude_code", "p1", None, Some(200), 1, 0, 0, "POST", "/v1/messages", Some(info(LogBodyShape::default())))
+            .await
+            .unwrap();
+
+        let bodies: Vec<String> = sqlx::query_scalar("SELECT client_body FROM request_logs ORDER BY id")
+            .fetch_all(&log_db)
+            .await
+            .unwrap();
+        let shaped: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(shaped["content"], format!("{}...[+{} chars]", "x".repeat(10), MAX_LOGGED_BODY_BYTES * 2 - 10));
+        // Raw bodies still get the size cap
+        assert!(bodies[1].ends_with("...[truncated]"));
+        assert_eq!(bodies[1].len(), MAX_LOGGED_BODY_BYTES + "...[truncated]".len());
+    }
+
+    #[tokio::test]
+    async fn provider_stats_weight_sampled_successes() {
+        let log_db = crate::db::test_pool(true).await;
+        // One success kept at a 1/3 sample rate stands in for three; failures are always kept
+        for (status, weight) in [(200, 3.0), (500, 1.0)] {
+            sqlx::query(
+                "INSERT INTO request_logs (created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, sample_weight) VALUES (100, 'claude_code', 'p1', 'm1', ?, 10, 5, 5, 'POST', '/v1/messages', ?)",
+            )
+            .bind(status)
+            .bind(weight)
+            .execute(&log_db)
+            .await
+            .unwrap();
+        }
+
+        let rows = query_provider_stats(&log_db, None, None, None, None, StatsTimezone::parse(Some("UTC")).unwrap()).await.unwrap();
+        assert_eq!(rows[0].total_requests, 4);
+        assert_eq!(rows[0].total_success, 3);
+        assert_eq!(rows[0].total_tokens, 40);
+        assert_eq!(rows[0].success_rate, 75.0);
+    }
+}
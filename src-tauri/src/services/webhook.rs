@@ -0,0 +1,141 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Minimum gap between two `notify_once` notifications for the same event and subject
+const NOTIFY_COOLDOWN: Duration = Duration::from_secs(300);
+
+// (event, subject) -> when it was last notified
+static LAST_NOTIFIED: OnceLock<Mutex<HashMap<(String, String), Instant>>> = OnceLock::new();
+
+/// Payload posted to the failure notification webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPayload {
+    pub event: String,
+    pub provider: Option<String>,
+    pub reason: String,
+    pub timestamp: i64,
+}
+
+impl WebhookPayload {
+    pub fn new(event: &str, provider: Option<&str>, reason: &str) -> Self {
+        Self {
+            event: event.to_string(),
+            provider: provider.map(|p| p.to_string()),
+            reason: reason.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Render a custom body template.
+/// Supported placeholders: {{event}}, {{provider}}, {{reason}}, {{timestamp}}.
+/// Values are JSON-escaped so they can be placed inside JSON string literals.
+pub fn render_template(template: &str, payload: &WebhookPayload) -> String {
+    let escape = |s: &str| {
+        let quoted = serde_json::to_string(s).unwrap_or_default();
+        quoted[1..quoted.len() - 1].to_string()
+    };
+
+    template
+        .replace("{{event}}", &escape(&payload.event))
+        .replace("{{provider}}", &escape(payload.provider.as_deref().unwrap_or("")))
+        .replace("{{reason}}", &escape(&payload.reason))
+        .replace("{{timestamp}}", &payload.timestamp.to_string())
+}
+
+/// POST the payload to the webhook URL, using the template as the body when set
+pub async fn send(url: &str, template: Option<&str>, payload: &WebhookPayload) -> Result<(), String> {
+    let body = match template.filter(|t| !t.trim().is_empty()) {
+        Some(t) => render_template(t, payload),
+        None => serde_json::to_string(payload).map_err(|e| e.to_string())?,
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!("Webhook returned HTTP {}", response.status()));
+    }
+
+    Ok(())
+}
+
+/// Fire-and-forget notification.
+/// Runs in a background task; failures are logged and never affect the caller.
+pub fn notify(db: &SqlitePool, event: &str, provider: Option<&str>, reason: &str) {
+    let db = db.clone();
+    let payload = WebhookPayload::new(event, provider, reason);
+
+    tokio::spawn(async move {
+        let settings = sqlx::query_as::<_, (Option<String>, Option<String>, i64)>(
+            "SELECT url, template, enabled FROM webhook_settings WHERE id = 1",
+        )
+        .fetch_optional(&db)
+        .await;
+
+        let (url, template) = match settings {
+            Ok(Some((Some(url), template, enabled))) if enabled != 0 && !url.trim().is_empty() => (url, template),
+            Ok(_) => return,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to load webhook settings");
+                return;
+            }
+        };
+
+        if let Err(e) = send(&url, template.as_deref(), &payload).await {
+            tracing::warn!(error = %e, event = %payload.event, "Failed to send webhook notification");
+        }
+    });
+}
+
+/// Like `notify`, but at most once per cooldown window for the same event and subject (a
+/// provider name or CLI type), so an incident that fails every request notifies once.
+pub fn notify_once(db: &SqlitePool, event: &str, subject: &str, provider: Option<&str>, reason: &str) {
+    if cooldown_elapsed(event, subject, Instant::now()) {
+        notify(db, event, provider, reason);
+    } else {
+        tracing::debug!(event = %event, subject = %subject, "Webhook notification suppressed by cooldown");
+    }
+}
+
+// Whether the event is due for the subject; records the notification when it is
+fn cooldown_elapsed(event: &str, subject: &str, now: Instant) -> bool {
+    let mut last = LAST_NOTIFIED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let key = (event.to_string(), subject.to_string());
+    if last.get(&key).is_some_and(|at| now.duration_since(*at) < NOTIFY_COOLDOWN) {
+        return false;
+    }
+    last.insert(key, now);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown_is_per_event_and_subject() {
+        let now = Instant::now();
+        assert!(cooldown_elapsed("test_cooldown", "claude_code", now));
+        assert!(!cooldown_elapsed("test_cooldown", "claude_code", now + Duration::from_secs(1)));
+        assert!(cooldown_elapsed("test_cooldown", "codex", now));
+        assert!(cooldown_elapsed("test_cooldown_other", "claude_code", now));
+        assert!(cooldown_elapsed("test_cooldown", "claude_code", now + NOTIFY_COOLDOWN));
+    }
+}